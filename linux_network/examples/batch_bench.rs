@@ -0,0 +1,185 @@
+// Compares the per-datagram sendto()/recvfrom() path against
+// the sendmmsg()/recvmmsg() batches over the loopback, for the raw ICMPv6
+// sockets and for the packet sockets with recvpacket_batch().
+// Needs CAP_NET_RAW:
+//
+//     cargo run --release --example batch_bench [packets] [batch size]
+//
+// The defaults are 1000000 packets in batches of 32.  Over the loopback
+// the sending side dominates, so the receive side gains more than
+// the totals reported show.
+
+extern crate linux_network;
+extern crate nix;
+extern crate pnet_packet;
+
+use std::env;
+use std::net::*;
+use std::time::*;
+
+use linux_network::*;
+use nix::libc::IPPROTO_ICMPV6;
+use pnet_packet::ip::IpNextHeaderProtocols;
+
+const PACKET_SIZE: usize = 64;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let packets = args.next().map_or(1_000_000, |x| x.parse().unwrap());
+    let batch = args.next().map_or(32, |x| x.parse().unwrap());
+
+    println!("raw ICMPv6 sockets");
+    report(
+        batch,
+        run_raw(packets, batch, false),
+        run_raw(packets, batch, true)
+    );
+    println!("packet sockets");
+    report(
+        batch,
+        run_packet(packets, batch, false),
+        run_packet(packets, batch, true)
+    );
+}
+
+fn report(batch: usize, single: f64, batched: f64) {
+    println!("  per-datagram calls: {:>10.0} packets/s", single);
+    println!("  batches of {:<5}   {:>10.0} packets/s", batch, batched);
+    println!("  speedup:            {:>10.2}x", batched / single);
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn rate(packets: usize, start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64
+        + f64::from(elapsed.subsec_nanos()) / 1e9;
+    packets as f64 / secs
+}
+
+fn run_raw(packets: usize, batch: usize, use_mmsg: bool) -> f64 {
+    let mut send_sock = IPv6RawSocket::new(IPPROTO_ICMPV6, SockFlag::empty())
+        .expect("cannot create a raw socket, CAP_NET_RAW is required");
+    let mut recv_sock = IPv6RawSocket::new(IPPROTO_ICMPV6, SockFlag::empty())
+        .expect("cannot create a raw socket, CAP_NET_RAW is required");
+
+    // the sender would get a copy of everything too
+    send_sock.setsockopt(&SockOpts::IcmpV6Filter::new(&icmp6_filter::new()))
+        .unwrap();
+    // echo replies nobody asked for are not answered by the kernel
+    let mut filter = icmp6_filter::new();
+    filter.pass(IcmpV6Type::EchoReply);
+    recv_sock.setsockopt(&SockOpts::IcmpV6Filter::new(&filter)).unwrap();
+
+    let dst = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+    let mut payload = [0; PACKET_SIZE];
+    payload[0] = IcmpV6Type::EchoReply.repr();
+    let msgs = vec![(&payload[..], dst); batch];
+    let mut buf = vec![0; PACKET_SIZE * batch];
+
+    let start = Instant::now();
+    let mut done = 0;
+    // a batch at a time, so that the receive buffer never overflows
+    while done < packets {
+        let n = batch.min(packets - done);
+
+        if use_mmsg {
+            let mut sent = 0;
+            while sent < n {
+                sent += send_sock.sendto_batch(
+                    &msgs[sent..n],
+                    SendFlags::empty()
+                ).unwrap();
+            }
+            let mut received = 0;
+            while received < n {
+                let slots = (n - received) * PACKET_SIZE;
+                received += recv_sock.recvfrom_batch(
+                    &mut buf[..slots],
+                    PACKET_SIZE,
+                    RecvFlags::empty()
+                ).unwrap().len();
+            }
+        } else {
+            for _ in 0..n {
+                send_sock.sendto(&payload, dst, SendFlags::empty()).unwrap();
+            }
+            for _ in 0..n {
+                recv_sock.recvfrom(&mut buf[..PACKET_SIZE], RecvFlags::empty())
+                    .unwrap();
+            }
+        }
+
+        done += n;
+    }
+
+    rate(packets, start)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn run_packet(packets: usize, batch: usize, use_mmsg: bool) -> f64 {
+    let proto = raw::ETHERTYPE_IPV6;
+    let mut send_sock = IPv6PacketSocket::new(proto, SockFlag::empty(), "lo")
+        .expect("cannot create a packet socket, CAP_NET_RAW is required");
+    let mut recv_sock = IPv6PacketSocket::new(proto, SockFlag::empty(), "lo")
+        .expect("cannot create a packet socket, CAP_NET_RAW is required");
+
+    // an echo reply with a bad checksum, dropped by the IPv6 stack
+    let make_packet = || {
+        let mut payload = vec![0; PACKET_SIZE];
+        payload[0] = IcmpV6Type::EchoReply.repr();
+        Ipv6 {
+            version: 6,
+            traffic_class: 0,
+            flow_label: 0,
+            payload_length: PACKET_SIZE as u16,
+            next_header: IpNextHeaderProtocols::Icmpv6,
+            hop_limit: 255,
+            source: Ipv6Addr::LOCALHOST,
+            destination: Ipv6Addr::LOCALHOST,
+            payload
+        }
+    };
+    let packet = make_packet();
+    let packets_batch = (0..batch)
+        .map(|_| (make_packet(), None))
+        .collect::<Vec<_>>();
+    let maxsize = 40 + PACKET_SIZE;
+
+    let start = Instant::now();
+    let mut done = 0;
+    // the send socket gets a copy of every frame as well, nobody reads it
+    // and the kernel drops them once its receive buffer is full
+    while done < packets {
+        let n = batch.min(packets - done);
+
+        if use_mmsg {
+            let mut sent = 0;
+            while sent < n {
+                sent += send_sock.sendpacket_batch(
+                    &packets_batch[sent..n],
+                    SendFlags::empty()
+                ).unwrap();
+            }
+            let mut received = 0;
+            while received < n {
+                received += recv_sock.recvpacket_batch(
+                    maxsize,
+                    n - received,
+                    RecvFlags::empty()
+                ).unwrap().len();
+            }
+        } else {
+            for _ in 0..n {
+                send_sock.sendpacket(&packet, None, SendFlags::empty())
+                    .unwrap();
+            }
+            for _ in 0..n {
+                recv_sock.recvpacket(maxsize, RecvFlags::empty()).unwrap();
+            }
+        }
+
+        done += n;
+    }
+
+    rate(packets, start)
+}
@@ -1,73 +1,826 @@
-use ::std::fmt::*;
+// Classic BPF socket filters, see filter(2) and
+// Documentation/networking/filter.txt in the kernel sources
 
+use ::std::fmt;
+use ::std::net::Ipv6Addr;
+use ::std::ptr::null_mut;
+
+use ::nlibc::{c_ushort, IPPROTO_ICMPV6};
+
+use ::constants::raw::*;
+use ::errors::{Error, Result};
 use ::structs::raw::*;
+use ::{BpfCommandFlags, IcmpV6Type};
+
+// the offsets the helpers use, for packets starting at the IPv6 header
+// and without extension headers
+pub const IPV6_NEXT_HEADER_OFFSET: u32 = 6;
+pub const IPV6_HOP_LIMIT_OFFSET: u32 = 7;
+pub const IPV6_SOURCE_OFFSET: u32 = 8;
+pub const IPV6_DESTINATION_OFFSET: u32 = 24;
+pub const IPV6_PAYLOAD_OFFSET: u32 = 40;
 
 pub struct BpfProg {
-    pub filters: Vec<sock_filter>,
-    pub fprog: sock_fprog
+    filters: Vec<sock_filter>,
+    fprog: sock_fprog
 }
 
 impl BpfProg {
+    // checks the program the way the kernel does on attaching
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(filters: Vec<sock_filter>) -> Result<Self> {
+        validate(&filters)?;
+
+        let mut ret = Self {
+            filters,
+            fprog: sock_fprog {
+                len: 0,
+                filter: null_mut()
+            }
+        };
+        ret.fprog.len = ret.filters.len() as c_ushort;
+        ret.fprog.filter = ret.filters.as_mut_ptr();
+        Ok(ret)
+    }
+
     pub fn get(&self) -> &sock_fprog {
         &self.fprog
     }
+
+    pub fn instructions(&self) -> &[sock_filter] {
+        &self.filters
+    }
+
+    // the number of bytes of the packet the kernel would accept, 0 drops it;
+    // the ancillary SKF_AD_* loads are not supported and drop the packet
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn run(&self, packet: &[u8]) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0_u32; BPF_MEMWORDS];
+        let mut pc = 0;
+
+        loop {
+            let insn = self.filters[pc];
+            let k = insn.k;
+            pc += 1;
+
+            match bpf_class(insn.code) {
+                BPF_LD => {
+                    a = match bpf_mode(insn.code) {
+                        BPF_ABS => match load(packet, insn.code, k) {
+                            Some(val) => val,
+                            None => return 0
+                        },
+                        BPF_IND => {
+                            match load(packet, insn.code, x.wrapping_add(k)) {
+                                Some(val) => val,
+                                None => return 0
+                            }
+                        },
+                        BPF_LEN => packet.len() as u32,
+                        BPF_MEM => mem[k as usize],
+                        _ => k
+                    };
+                },
+                BPF_LDX => {
+                    x = match bpf_mode(insn.code) {
+                        BPF_MEM => mem[k as usize],
+                        BPF_LEN => packet.len() as u32,
+                        BPF_MSH => match packet.get(k as usize) {
+                            Some(val) => u32::from(val & 0xf) << 2,
+                            None => return 0
+                        },
+                        _ => k
+                    };
+                },
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                BPF_ALU => {
+                    let src = if insn.code & BPF_X == 0 { k } else { x };
+                    a = match bpf_op(insn.code) {
+                        BPF_ADD => a.wrapping_add(src),
+                        BPF_SUB => a.wrapping_sub(src),
+                        BPF_MUL => a.wrapping_mul(src),
+                        BPF_DIV | BPF_MOD if src == 0 => return 0,
+                        BPF_DIV => a / src,
+                        BPF_MOD => a % src,
+                        BPF_AND => a & src,
+                        BPF_OR => a | src,
+                        BPF_XOR => a ^ src,
+                        BPF_LSH => a.checked_shl(src).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(src).unwrap_or(0),
+                        _ => a.wrapping_neg()
+                    };
+                },
+                BPF_JMP => {
+                    let src = if insn.code & BPF_X == 0 { k } else { x };
+                    let taken = match bpf_op(insn.code) {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        },
+                        BPF_JEQ => a == src,
+                        BPF_JGT => a > src,
+                        BPF_JGE => a >= src,
+                        _ => a & src != 0
+                    };
+                    pc += usize::from(if taken { insn.jt } else { insn.jf });
+                },
+                BPF_RET => {
+                    return if bpf_rval(insn.code) == BPF_A { a } else { k };
+                },
+                _ => if insn.code & 0xf8 == BPF_TXA {
+                    a = x;
+                } else {
+                    x = a;
+                }
+            }
+        }
+    }
 }
 
-impl Debug for BpfProg {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+impl fmt::Debug for BpfProg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self.filters)
     }
 }
 
-#[macro_export]
-macro_rules! bpf_stmt {
-    ( $code:expr, $k:expr ) => (
-        $crate::structs::raw::sock_filter {
-            code: $code.bits(),
-            jt: 0 as u8,
-            jf: 0 as u8,
-            k: $k as u32
+fn bpf_class(code: u16) -> u16 {
+    code & 0x07
+}
+
+fn bpf_size(code: u16) -> u16 {
+    code & 0x18
+}
+
+fn bpf_mode(code: u16) -> u16 {
+    code & 0xe0
+}
+
+fn bpf_op(code: u16) -> u16 {
+    code & 0xf0
+}
+
+fn bpf_rval(code: u16) -> u16 {
+    code & 0x18
+}
+
+// network byte order
+fn load(packet: &[u8], code: u16, offset: u32) -> Option<u32> {
+    let len = match bpf_size(code) {
+        BPF_W => 4,
+        BPF_H => 2,
+        _ => 1
+    };
+    let start = offset as usize;
+    let bytes = packet.get(start .. start.checked_add(len)?)?;
+    Some(bytes.iter().fold(0, |acc, &x| (acc << 8) | u32::from(x)))
+}
+
+const VALID_CODES: [u16; 49] = [
+    BPF_ALU | BPF_ADD | BPF_K,
+    BPF_ALU | BPF_ADD | BPF_X,
+    BPF_ALU | BPF_SUB | BPF_K,
+    BPF_ALU | BPF_SUB | BPF_X,
+    BPF_ALU | BPF_MUL | BPF_K,
+    BPF_ALU | BPF_MUL | BPF_X,
+    BPF_ALU | BPF_DIV | BPF_K,
+    BPF_ALU | BPF_DIV | BPF_X,
+    BPF_ALU | BPF_MOD | BPF_K,
+    BPF_ALU | BPF_MOD | BPF_X,
+    BPF_ALU | BPF_AND | BPF_K,
+    BPF_ALU | BPF_AND | BPF_X,
+    BPF_ALU | BPF_OR | BPF_K,
+    BPF_ALU | BPF_OR | BPF_X,
+    BPF_ALU | BPF_XOR | BPF_K,
+    BPF_ALU | BPF_XOR | BPF_X,
+    BPF_ALU | BPF_LSH | BPF_K,
+    BPF_ALU | BPF_LSH | BPF_X,
+    BPF_ALU | BPF_RSH | BPF_K,
+    BPF_ALU | BPF_RSH | BPF_X,
+    BPF_ALU | BPF_NEG,
+    BPF_LD | BPF_W | BPF_ABS,
+    BPF_LD | BPF_H | BPF_ABS,
+    BPF_LD | BPF_B | BPF_ABS,
+    BPF_LD | BPF_W | BPF_LEN,
+    BPF_LD | BPF_W | BPF_IND,
+    BPF_LD | BPF_H | BPF_IND,
+    BPF_LD | BPF_B | BPF_IND,
+    BPF_LD | BPF_IMM,
+    BPF_LD | BPF_MEM,
+    BPF_LDX | BPF_W | BPF_IMM,
+    BPF_LDX | BPF_W | BPF_LEN,
+    BPF_LDX | BPF_B | BPF_MSH,
+    BPF_LDX | BPF_MEM,
+    BPF_ST,
+    BPF_STX,
+    BPF_MISC | BPF_TAX,
+    BPF_MISC | BPF_TXA,
+    BPF_RET | BPF_K,
+    BPF_RET | BPF_A,
+    BPF_JMP | BPF_JA,
+    BPF_JMP | BPF_JEQ | BPF_K,
+    BPF_JMP | BPF_JEQ | BPF_X,
+    BPF_JMP | BPF_JGE | BPF_K,
+    BPF_JMP | BPF_JGE | BPF_X,
+    BPF_JMP | BPF_JGT | BPF_K,
+    BPF_JMP | BPF_JGT | BPF_X,
+    BPF_JMP | BPF_JSET | BPF_K,
+    BPF_JMP | BPF_JSET | BPF_X
+];
+
+fn invalid(pc: usize, reason: &str) -> ::failure::Error {
+    Error::InvalidBpfProgram {
+        pc,
+        reason: reason.to_string()
+    }.into()
+}
+
+// mirrors bpf_check_classic() and check_load_and_stores() of the kernel
+pub fn validate(filters: &[sock_filter]) -> Result<()> {
+    let len = filters.len();
+    if len == 0 || len > BPF_MAXINSNS {
+        return Err(invalid(0, "program length out of range"));
+    }
+
+    // the memory slots written on every path to the instruction
+    let mut written = vec![u32::max_value(); len];
+    written[0] = 0;
+
+    for (pc, insn) in filters.iter().enumerate() {
+        if !VALID_CODES.contains(&insn.code) {
+            return Err(invalid(pc, "unknown instruction"));
+        }
+
+        let k = insn.k as usize;
+        let mut slots = written[pc];
+        let mut next: [Option<usize>; 2] = [Some(pc + 1), None];
+
+        match bpf_class(insn.code) {
+            BPF_ALU => match bpf_op(insn.code) {
+                BPF_DIV | BPF_MOD if insn.code & BPF_X == 0 && k == 0 => {
+                    return Err(invalid(pc, "division by zero"));
+                },
+                BPF_LSH | BPF_RSH if insn.code & BPF_X == 0 && k >= 32 => {
+                    return Err(invalid(pc, "shift out of range"));
+                },
+                _ => ()
+            },
+            BPF_LD | BPF_LDX if bpf_mode(insn.code) == BPF_MEM => {
+                if k >= BPF_MEMWORDS {
+                    return Err(invalid(pc, "memory slot out of range"));
+                }
+                if slots & (1 << k) == 0 {
+                    return Err(invalid(pc, "memory slot read before written"));
+                }
+            },
+            BPF_ST | BPF_STX => {
+                if k >= BPF_MEMWORDS {
+                    return Err(invalid(pc, "memory slot out of range"));
+                }
+                slots |= 1 << k;
+            },
+            BPF_JMP => {
+                let remaining = len - pc - 1;
+                if bpf_op(insn.code) == BPF_JA {
+                    if k >= remaining {
+                        return Err(invalid(pc, "jump out of range"));
+                    }
+                    next = [Some(pc + 1 + k), None];
+                } else {
+                    let jt = usize::from(insn.jt);
+                    let jf = usize::from(insn.jf);
+                    if jt >= remaining || jf >= remaining {
+                        return Err(invalid(pc, "jump out of range"));
+                    }
+                    next = [Some(pc + 1 + jt), Some(pc + 1 + jf)];
+                }
+            },
+            BPF_RET => next = [None, None],
+            _ => ()
+        }
+
+        for i in next.iter().filter_map(|&x| x).filter(|&x| x < len) {
+            written[i] &= slots;
+        }
+    }
+
+    if bpf_class(filters[len - 1].code) != BPF_RET {
+        return Err(invalid(len - 1, "program does not end with a return"));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BpfLabel(usize);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BpfSize {
+    Byte,
+    Half,
+    Word
+}
+
+impl BpfSize {
+    fn bits(self) -> u16 {
+        match self {
+            BpfSize::Byte => BPF_B,
+            BpfSize::Half => BPF_H,
+            BpfSize::Word => BPF_W
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BpfAluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Lsh,
+    Rsh
+}
+
+impl BpfAluOp {
+    fn bits(self) -> u16 {
+        match self {
+            BpfAluOp::Add => BPF_ADD,
+            BpfAluOp::Sub => BPF_SUB,
+            BpfAluOp::Mul => BPF_MUL,
+            BpfAluOp::Div => BPF_DIV,
+            BpfAluOp::Mod => BPF_MOD,
+            BpfAluOp::And => BPF_AND,
+            BpfAluOp::Or => BPF_OR,
+            BpfAluOp::Xor => BPF_XOR,
+            BpfAluOp::Lsh => BPF_LSH,
+            BpfAluOp::Rsh => BPF_RSH
         }
-    )
+    }
+}
+
+// compares the accumulator, Set tests for any common bits
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BpfCond {
+    Eq,
+    Gt,
+    Ge,
+    Set
 }
 
-#[macro_export]
-macro_rules! bpf_jump {
-    ( $code:expr, $k:expr, $jt:expr, $jf:expr ) => (
-        $crate::structs::raw::sock_filter {
-            code: $code.bits(),
-            jt: $jt as u8,
-            jf: $jf as u8,
-            k: $k as u32
+impl BpfCond {
+    fn bits(self) -> u16 {
+        match self {
+            BpfCond::Eq => BPF_JEQ,
+            BpfCond::Gt => BPF_JGT,
+            BpfCond::Ge => BPF_JGE,
+            BpfCond::Set => BPF_JSET
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Next,
+    Label(BpfLabel)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PendingInsn {
+    code: u16,
+    k: u32,
+    jt: Target,
+    jf: Target
+}
+
+// resolves the jump offsets from the labels placed;
+// only the forward jumps are possible in classic BPF.
+// The misplaced labels are reported by build().
+#[derive(Debug, Default)]
+pub struct BpfBuilder {
+    insns: Vec<PendingInsn>,
+    labels: Vec<Option<usize>>,
+    misplaced: Option<(usize, &'static str)>
+}
+
+impl BpfBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self) -> BpfLabel {
+        self.labels.push(None);
+        BpfLabel(self.labels.len() - 1)
+    }
+
+    // the label refers to the next instruction added
+    pub fn place(&mut self, label: BpfLabel) -> &mut Self {
+        let pc = self.insns.len();
+        let error = match self.labels.get_mut(label.0) {
+            Some(&mut Some(_)) => "label placed twice",
+            Some(x) => {
+                *x = Some(pc);
+                return self;
+            },
+            None => "label of another builder placed"
+        };
+        if self.misplaced.is_none() {
+            self.misplaced = Some((pc, error));
         }
-    )
-}
-
-#[macro_export]
-macro_rules! bpf_filter {
-    ( bpf_stmt!( $( $arg:tt )+ ); $( $o:tt )* ) => (
-        bpf_filter!((bpf_stmt!( $( $arg )* ));;; (1);;; $( $o )* )
-    );
-    ( bpf_jump!( $( $arg:tt )+ ); $( $o:tt )* ) => (
-        bpf_filter!((bpf_jump!( $( $arg )* ));;; (1);;; $( $o )* )
-    );
-
-    ( ( $( $acc:tt )+ );;; ($len:expr);;; bpf_stmt!( $( $arg:tt )+ ); $( $o:tt )* ) => (
-        bpf_filter!( ( $( $acc )*, bpf_stmt!( $( $arg )* ) );;; ($len + 1);;; $( $o )* )
-    );
-    ( ( $( $acc:tt )+ );;; ($len:expr);;; bpf_jump!( $( $arg:tt )+ ); $( $o:tt )* ) => (
-        bpf_filter!( ( $( $acc )*, bpf_jump!( $( $arg )* ) );;; ($len + 1);;; $( $o )* )
-    );
-
-    ( ( $( $acc:tt )+ );;; ($len:expr);;; ) => ({
-        let mut ret = Box::new(BpfProg {
-            filters: vec![ $( $acc )* ],
-            fprog: $crate::structs::raw::sock_fprog {
-                len: $len,
-                filter: ::std::ptr::null_mut()
+        self
+    }
+
+    fn push(&mut self, code: u16, k: u32, jt: Target, jf: Target)
+            -> &mut Self {
+        self.insns.push(PendingInsn { code, k, jt, jf });
+        self
+    }
+
+    pub fn stmt(&mut self, code: BpfCommandFlags, k: u32) -> &mut Self {
+        self.push(code.bits(), k, Target::Next, Target::Next)
+    }
+
+    pub fn jump(
+        &mut self,
+        code: BpfCommandFlags,
+        k: u32,
+        jt: BpfLabel,
+        jf: BpfLabel
+    ) -> &mut Self {
+        self.push(code.bits(), k, Target::Label(jt), Target::Label(jf))
+    }
+
+    pub fn load_abs(&mut self, size: BpfSize, offset: u32) -> &mut Self {
+        self.push(BPF_LD | size.bits() | BPF_ABS, offset,
+            Target::Next, Target::Next)
+    }
+
+    // from X + offset
+    pub fn load_ind(&mut self, size: BpfSize, offset: u32) -> &mut Self {
+        self.push(BPF_LD | size.bits() | BPF_IND, offset,
+            Target::Next, Target::Next)
+    }
+
+    pub fn load_len(&mut self) -> &mut Self {
+        self.push(BPF_LD | BPF_W | BPF_LEN, 0, Target::Next, Target::Next)
+    }
+
+    pub fn load_imm(&mut self, k: u32) -> &mut Self {
+        self.push(BPF_LD | BPF_IMM, k, Target::Next, Target::Next)
+    }
+
+    pub fn load_mem(&mut self, slot: u32) -> &mut Self {
+        self.push(BPF_LD | BPF_MEM, slot, Target::Next, Target::Next)
+    }
+
+    pub fn load_x_imm(&mut self, k: u32) -> &mut Self {
+        self.push(BPF_LDX | BPF_W | BPF_IMM, k, Target::Next, Target::Next)
+    }
+
+    pub fn load_x_len(&mut self) -> &mut Self {
+        self.push(BPF_LDX | BPF_W | BPF_LEN, 0, Target::Next, Target::Next)
+    }
+
+    pub fn load_x_mem(&mut self, slot: u32) -> &mut Self {
+        self.push(BPF_LDX | BPF_MEM, slot, Target::Next, Target::Next)
+    }
+
+    // the IPv4 header length from the byte at offset
+    pub fn load_x_ipv4_header_len(&mut self, offset: u32) -> &mut Self {
+        self.push(BPF_LDX | BPF_B | BPF_MSH, offset,
+            Target::Next, Target::Next)
+    }
+
+    pub fn store(&mut self, slot: u32) -> &mut Self {
+        self.push(BPF_ST, slot, Target::Next, Target::Next)
+    }
+
+    pub fn store_x(&mut self, slot: u32) -> &mut Self {
+        self.push(BPF_STX, slot, Target::Next, Target::Next)
+    }
+
+    pub fn alu(&mut self, op: BpfAluOp, k: u32) -> &mut Self {
+        self.push(BPF_ALU | op.bits() | BPF_K, k, Target::Next, Target::Next)
+    }
+
+    pub fn alu_x(&mut self, op: BpfAluOp) -> &mut Self {
+        self.push(BPF_ALU | op.bits() | BPF_X, 0, Target::Next, Target::Next)
+    }
+
+    pub fn neg(&mut self) -> &mut Self {
+        self.push(BPF_ALU | BPF_NEG, 0, Target::Next, Target::Next)
+    }
+
+    pub fn tax(&mut self) -> &mut Self {
+        self.push(BPF_MISC | BPF_TAX, 0, Target::Next, Target::Next)
+    }
+
+    pub fn txa(&mut self) -> &mut Self {
+        self.push(BPF_MISC | BPF_TXA, 0, Target::Next, Target::Next)
+    }
+
+    pub fn jump_to(&mut self, target: BpfLabel) -> &mut Self {
+        self.push(BPF_JMP | BPF_JA, 0, Target::Label(target), Target::Next)
+    }
+
+    pub fn jump_if(
+        &mut self,
+        cond: BpfCond,
+        k: u32,
+        jt: BpfLabel,
+        jf: BpfLabel
+    ) -> &mut Self {
+        self.push(BPF_JMP | cond.bits() | BPF_K, k,
+            Target::Label(jt), Target::Label(jf))
+    }
+
+    // compares with X
+    pub fn jump_if_x(&mut self, cond: BpfCond, jt: BpfLabel, jf: BpfLabel)
+            -> &mut Self {
+        self.push(BPF_JMP | cond.bits() | BPF_X, 0,
+            Target::Label(jt), Target::Label(jf))
+    }
+
+    // falls through otherwise
+    pub fn jump_when(&mut self, cond: BpfCond, k: u32, target: BpfLabel)
+            -> &mut Self {
+        self.push(BPF_JMP | cond.bits() | BPF_K, k,
+            Target::Label(target), Target::Next)
+    }
+
+    // falls through when the condition holds
+    pub fn jump_unless(&mut self, cond: BpfCond, k: u32, target: BpfLabel)
+            -> &mut Self {
+        self.push(BPF_JMP | cond.bits() | BPF_K, k,
+            Target::Next, Target::Label(target))
+    }
+
+    pub fn ret(&mut self, k: u32) -> &mut Self {
+        self.push(BPF_RET | BPF_K, k, Target::Next, Target::Next)
+    }
+
+    pub fn ret_a(&mut self) -> &mut Self {
+        self.push(BPF_RET | BPF_A, 0, Target::Next, Target::Next)
+    }
+
+    pub fn accept(&mut self) -> &mut Self {
+        self.ret(u32::max_value())
+    }
+
+    pub fn reject(&mut self) -> &mut Self {
+        self.ret(0)
+    }
+
+    pub fn match_ipv6_next_header(&mut self, next_header: u8,
+            mismatch: BpfLabel) -> &mut Self {
+        self.load_abs(BpfSize::Byte, IPV6_NEXT_HEADER_OFFSET)
+            .jump_unless(BpfCond::Eq, u32::from(next_header), mismatch)
+    }
+
+    pub fn match_ipv6_hop_limit(&mut self, hop_limit: u8,
+            mismatch: BpfLabel) -> &mut Self {
+        self.load_abs(BpfSize::Byte, IPV6_HOP_LIMIT_OFFSET)
+            .jump_unless(BpfCond::Eq, u32::from(hop_limit), mismatch)
+    }
+
+    pub fn match_ipv6_source(&mut self, addr: Ipv6Addr,
+            mismatch: BpfLabel) -> &mut Self {
+        self.match_ipv6_addr(IPV6_SOURCE_OFFSET, addr, mismatch)
+    }
+
+    pub fn match_ipv6_destination(&mut self, addr: Ipv6Addr,
+            mismatch: BpfLabel) -> &mut Self {
+        self.match_ipv6_addr(IPV6_DESTINATION_OFFSET, addr, mismatch)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn match_ipv6_addr(&mut self, offset: u32, addr: Ipv6Addr,
+            mismatch: BpfLabel) -> &mut Self {
+        for (i, word) in addr.octets().chunks(4).enumerate() {
+            let val = word.iter()
+                .fold(0, |acc, &x| (acc << 8) | u32::from(x));
+            self.load_abs(BpfSize::Word, offset + 4 * i as u32)
+                .jump_unless(BpfCond::Eq, val, mismatch);
+        }
+        self
+    }
+
+    // an ICMPv6 message directly after the IPv6 header
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn match_icmpv6_type(&mut self, icmp_type: IcmpV6Type,
+            mismatch: BpfLabel) -> &mut Self {
+        self.match_ipv6_next_header(IPPROTO_ICMPV6 as u8, mismatch)
+            .load_abs(BpfSize::Byte, IPV6_PAYLOAD_OFFSET)
+            .jump_unless(BpfCond::Eq, u32::from(icmp_type.repr()), mismatch)
+    }
+
+    pub fn build(&self) -> Result<BpfProg> {
+        if let Some((pc, error)) = self.misplaced {
+            return Err(invalid(pc, error));
+        }
+        let mut filters = Vec::with_capacity(self.insns.len());
+
+        for (pc, insn) in self.insns.iter().enumerate() {
+            let mut filter = sock_filter {
+                code: insn.code,
+                jt: 0,
+                jf: 0,
+                k: insn.k
+            };
+
+            if insn.code == BPF_JMP | BPF_JA {
+                filter.k = self.resolve(pc, insn.jt)?;
+            } else if bpf_class(insn.code) == BPF_JMP {
+                filter.jt = self.resolve_short(pc, insn.jt)?;
+                filter.jf = self.resolve_short(pc, insn.jf)?;
             }
-        });
-        ret.fprog.filter = ret.filters.as_mut_ptr();
-        ret
-    })
+
+            filters.push(filter);
+        }
+
+        BpfProg::new(filters)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn resolve(&self, pc: usize, target: Target) -> Result<u32> {
+        let label = match target {
+            Target::Next => return Ok(0),
+            Target::Label(x) => x
+        };
+        let dest = self.labels.get(label.0)
+            .and_then(|&x| x)
+            .ok_or_else(|| invalid(pc, "jump to a label never placed"))?;
+        if dest <= pc {
+            return Err(invalid(pc, "backward jump"));
+        }
+        Ok((dest - pc - 1) as u32)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn resolve_short(&self, pc: usize, target: Target) -> Result<u8> {
+        let offset = self.resolve(pc, target)?;
+        if offset > u32::from(u8::max_value()) {
+            return Err(invalid(pc, "conditional jump too far"));
+        }
+        Ok(offset as u8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn neighbor_solicit_filter() -> BpfProg {
+        let mut builder = BpfBuilder::new();
+        let reject = builder.label();
+        builder
+            .match_icmpv6_type(IcmpV6Type::NdNeighborSolicit, reject)
+            .match_ipv6_hop_limit(255, reject)
+            .accept()
+            .place(reject)
+            .reject();
+        builder.build().unwrap()
+    }
+
+    fn make_packet(next_header: u8, hop_limit: u8, icmp_type: u8)
+            -> Vec<u8> {
+        let mut packet = vec![0; 64];
+        packet[0] = 0x60;
+        packet[6] = next_header;
+        packet[7] = hop_limit;
+        packet[40] = icmp_type;
+        packet
+    }
+
+    #[test]
+    fn builder_resolves_labels() {
+        let prog = neighbor_solicit_filter();
+        let insns = prog.instructions();
+        assert_eq!(insns.len(), 8);
+        // the mismatch jumps all land on the final return
+        for (pc, insn) in insns.iter().enumerate() {
+            if bpf_class(insn.code) == BPF_JMP {
+                assert_eq!(pc + 1 + usize::from(insn.jf), insns.len() - 1);
+                assert_eq!(insn.jt, 0);
+            }
+        }
+        assert_eq!(prog.get().len, 8);
+    }
+
+    #[test]
+    fn interpreter_matches() {
+        let prog = neighbor_solicit_filter();
+        assert_eq!(prog.run(&make_packet(58, 255, 135)), u32::max_value());
+        assert_eq!(prog.run(&make_packet(58, 255, 136)), 0);
+        assert_eq!(prog.run(&make_packet(58, 64, 135)), 0);
+        assert_eq!(prog.run(&make_packet(17, 255, 135)), 0);
+        // out of bounds loads drop the packet
+        assert_eq!(prog.run(&make_packet(58, 255, 135)[..40]), 0);
+    }
+
+    #[test]
+    fn interpreter_arithmetic_and_memory() {
+        let mut builder = BpfBuilder::new();
+        builder
+            .load_len()
+            .store(3)
+            .load_x_imm(2)
+            .load_mem(3)
+            .alu_x(BpfAluOp::Mul)
+            .alu(BpfAluOp::Sub, 1)
+            .ret_a();
+        let prog = builder.build().unwrap();
+        assert_eq!(prog.run(&[0; 10]), 19);
+    }
+
+    #[test]
+    fn address_match() {
+        let addr: Ipv6Addr = "ff02::1:ff00:1".parse().unwrap();
+        let mut builder = BpfBuilder::new();
+        let reject = builder.label();
+        builder
+            .match_ipv6_destination(addr, reject)
+            .accept()
+            .place(reject)
+            .reject();
+        let prog = builder.build().unwrap();
+
+        let mut packet = make_packet(58, 255, 135);
+        packet[24..40].copy_from_slice(&addr.octets());
+        assert_eq!(prog.run(&packet), u32::max_value());
+        packet[39] = 2;
+        assert_eq!(prog.run(&packet), 0);
+    }
+
+    #[test]
+    fn validation() {
+        let stmt = |code, k| sock_filter { code, jt: 0, jf: 0, k };
+
+        assert!(BpfProg::new(vec![]).is_err());
+        assert!(BpfProg::new(vec![stmt(BPF_LD | BPF_IMM, 1)]).is_err());
+        assert!(BpfProg::new(vec![
+            stmt(BPF_LD | BPF_MEM, 0),
+            stmt(BPF_RET | BPF_A, 0)
+        ]).is_err());
+        assert!(BpfProg::new(vec![
+            stmt(BPF_ST, 16),
+            stmt(BPF_RET | BPF_A, 0)
+        ]).is_err());
+        assert!(BpfProg::new(vec![
+            stmt(BPF_ALU | BPF_DIV | BPF_K, 0),
+            stmt(BPF_RET | BPF_A, 0)
+        ]).is_err());
+        assert!(BpfProg::new(vec![
+            stmt(BPF_JMP | BPF_JA, 1),
+            stmt(BPF_RET | BPF_A, 0)
+        ]).is_err());
+        assert!(BpfProg::new(vec![stmt(0xffff, 0)]).is_err());
+
+        // written on one path only
+        assert!(BpfProg::new(vec![
+            sock_filter {
+                code: BPF_JMP | BPF_JEQ | BPF_K,
+                jt: 1,
+                jf: 0,
+                k: 0
+            },
+            stmt(BPF_ST, 0),
+            stmt(BPF_LD | BPF_MEM, 0),
+            stmt(BPF_RET | BPF_A, 0)
+        ]).is_err());
+    }
+
+    #[test]
+    fn builder_errors() {
+        let mut builder = BpfBuilder::new();
+        let nowhere = builder.label();
+        builder.jump_to(nowhere).accept();
+        assert!(builder.build().is_err());
+
+        let mut builder = BpfBuilder::new();
+        let back = builder.label();
+        builder.place(back).load_len().jump_to(back).accept();
+        assert!(builder.build().is_err());
+
+        let mut builder = BpfBuilder::new();
+        let twice = builder.label();
+        builder.jump_to(twice).place(twice).accept().place(twice).reject();
+        assert!(builder.build().is_err());
+
+        // the labels of another builder
+        let mut other = BpfBuilder::new();
+        other.label();
+        let foreign = other.label();
+        let mut builder = BpfBuilder::new();
+        builder.jump_to(foreign).accept();
+        assert!(builder.build().is_err());
+        let mut builder = BpfBuilder::new();
+        builder.accept().place(foreign);
+        assert!(builder.build().is_err());
+    }
 }
@@ -0,0 +1,180 @@
+// Fixed-size slots the batch receives write the packets into, reused from
+// one batch to the next.  The packets are read through borrowed views, which
+// stay valid until the pool is received into again.
+
+use ::std::mem::zeroed;
+use ::std::net::SocketAddrV6;
+
+use ::pnet_packet::icmpv6::Icmpv6Packet;
+use ::pnet_packet::ipv6::Ipv6Packet;
+
+use ::HwAddr;
+use ::errors::{Error, Result};
+
+// the message headers of a batch live on the stack
+pub const MAX_POOL_SLOTS: usize = 64;
+
+// the source address of a received packet, converted from the sockaddr
+// the kernel fills in
+pub trait PoolAddr: Copy {
+    type Raw: Copy;
+
+    fn from_raw(raw: &Self::Raw) -> Result<Self>;
+}
+
+pub struct BufferPool<A> where A: PoolAddr {
+    buf: Vec<u8>,
+    slot_size: usize,
+    raw_addrs: Vec<A::Raw>,
+    // the slot, the length and the source address of each packet
+    filled: Vec<(usize, usize, A)>
+}
+
+impl<A> BufferPool<A> where A: PoolAddr {
+    pub fn new(slot_size: usize, slots: usize) -> Result<Self> {
+        if slot_size == 0 {
+            return Err(Error::BufferTooSmall { len: slot_size }.into());
+        }
+        if slots == 0 || slots > MAX_POOL_SLOTS {
+            return Err(Error::InvalidPoolSize {
+                slots,
+                max: MAX_POOL_SLOTS
+            }.into());
+        }
+
+        Ok(Self {
+            buf: vec![0; slot_size * slots],
+            slot_size,
+            raw_addrs: vec![unsafe { zeroed() }; slots],
+            filled: Vec::with_capacity(slots)
+        })
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    pub fn slots(&self) -> usize {
+        self.raw_addrs.len()
+    }
+
+    // the number of packets from the last receive
+    pub fn len(&self) -> usize {
+        self.filled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filled.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.filled.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<(&[u8], A)> {
+        self.filled.get(index).map(|&x| self.view(x))
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (&'a [u8], A)> + 'a {
+        self.filled.iter().map(move |&x| self.view(x))
+    }
+
+    fn view(&self, (slot, len, addr): (usize, usize, A)) -> (&[u8], A) {
+        let start = slot * self.slot_size;
+        (&self.buf[start .. start + len], addr)
+    }
+
+    // the slots and the sockaddrs for the kernel to fill, emptying the pool
+    pub(crate) fn prepare(&mut self) -> (&mut [u8], &mut [A::Raw]) {
+        self.filled.clear();
+        (&mut self.buf, &mut self.raw_addrs)
+    }
+
+    // the lengths of the messages received into the first slots;
+    // the ones with a source address that can not be converted are skipped
+    pub(crate) fn set_received<I>(&mut self, lens: I)
+            where I: IntoIterator<Item = usize> {
+        let slot_size = self.slot_size;
+        let received = lens.into_iter()
+            .zip(&self.raw_addrs)
+            .enumerate()
+            .filter_map(|(i, (len, raw))| {
+                A::from_raw(raw).ok().map(|addr| (i, len.min(slot_size), addr))
+            });
+        self.filled.extend(received);
+    }
+}
+
+impl BufferPool<SocketAddrV6> {
+    // the raw ICMPv6 sockets receive no IPv6 header;
+    // the packets too short for the ICMPv6 header are skipped
+    pub fn icmpv6_packets<'a>(&'a self)
+            -> impl Iterator<Item = (Icmpv6Packet<'a>, SocketAddrV6)> + 'a {
+        self.iter()
+            .filter_map(|(x, addr)| Icmpv6Packet::new(x).map(|p| (p, addr)))
+    }
+}
+
+impl BufferPool<Option<HwAddr>> {
+    // the runts are skipped
+    pub fn ipv6_packets<'a>(&'a self)
+            -> impl Iterator<Item = (Ipv6Packet<'a>, Option<HwAddr>)> + 'a {
+        self.iter()
+            .filter_map(|(x, addr)| Ipv6Packet::new(x).map(|p| (p, addr)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pool_size_is_validated() {
+        type Pool = BufferPool<Option<HwAddr>>;
+        assert!(Pool::new(0, 4).is_err());
+        assert!(Pool::new(1500, 0).is_err());
+        assert!(Pool::new(1500, MAX_POOL_SLOTS + 1).is_err());
+        assert!(Pool::new(1500, MAX_POOL_SLOTS).is_ok());
+    }
+
+    #[test]
+    fn views_skip_runts() {
+        let mut pool = BufferPool::<Option<HwAddr>>::new(128, 4).unwrap();
+        {
+            let (buf, _) = pool.prepare();
+            buf[0] = 0x60;
+            buf[7] = 255;
+        }
+        pool.set_received(vec![64, 10]);
+
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.get(1).unwrap().0.len(), 10);
+        let packets = pool.ipv6_packets().collect::<Vec<_>>();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].0.get_hop_limit(), 255);
+        assert_eq!(packets[0].1, None);
+
+        pool.clear();
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn bad_addresses_are_skipped() {
+        let mut pool = BufferPool::<Option<HwAddr>>::new(128, 4).unwrap();
+        {
+            let (buf, addrs) = pool.prepare();
+            buf[128] = 1;
+            // longer than sll_addr
+            addrs[0].sll_halen = 9;
+            addrs[1].sll_halen = 6;
+        }
+        pool.set_received(vec![64, 10]);
+
+        assert_eq!(pool.len(), 1);
+        let (packet, addr) = pool.get(0).unwrap();
+        assert_eq!(packet.len(), 10);
+        assert_eq!(packet[0], 1);
+        assert_eq!(addr.unwrap().len(), 6);
+        assert_eq!(pool.iter().count(), 1);
+    }
+}
@@ -0,0 +1,317 @@
+// Typed ancillary data of recvmsg() and sendmsg(), see cmsg(3) and ipv6(7)
+
+use ::std::mem::*;
+use ::std::net::{Ipv6Addr, SocketAddrV6};
+use ::std::ptr;
+use ::std::slice;
+use ::std::time::*;
+
+use ::nlibc::*;
+
+use ::errors::{Error, Result};
+use ::errqueue::ExtendedErr;
+use ::raw::{in6_pktinfo, IPV6_HOPLIMIT, IPV6_PKTINFO, IPV6_TCLASS};
+use ::raw::{ip6_mtuinfo, sock_extended_err, IPV6_PATHMTU, IPV6_RECVERR};
+use ::raw::{PACKET_TX_TIMESTAMP, SCM_TIMESTAMPING, SOL_PACKET, SO_TIMESTAMPNS};
+use ::raw::SCM_TXTIME;
+use ::socket::{Family, Inet6};
+use ::timestamping::Timestamps;
+use ::util::*;
+
+// enough for every kind of Cmsg at once
+pub const CMSG_BUF_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Ipv6PktInfo {
+    pub addr: Ipv6Addr,
+    // 0 lets the kernel pick the interface when sending
+    pub if_index: c_uint
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Cmsg {
+    // the destination and the incoming interface on receipt,
+    // the source and the outgoing interface when sending
+    PktInfo(Ipv6PktInfo),
+    HopLimit(c_int),
+    TClass(c_int),
+    // the launch time in nanoseconds, of the clock of SockOpts::TxTime
+    TxTime(u64),
+    // receive only
+    Timestamp(SystemTime),
+    // receive only, SO_TIMESTAMPING
+    Timestamping(Timestamps),
+    // receive only, from the error queue
+    ExtendedErr(ExtendedErr),
+    // receive only, dst is the destination the path MTU applies to
+    PathMtu {
+        dst: SocketAddrV6,
+        mtu: u32
+    },
+    Other {
+        level: c_int,
+        kind: c_int,
+        data: Vec<u8>
+    }
+}
+
+impl Cmsg {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn parse(level: c_int, kind: c_int, data: &[u8]) -> Self { unsafe {
+        match (level, kind) {
+            (IPPROTO_IPV6, IPV6_PKTINFO)
+                    if data.len() >= size_of::<in6_pktinfo>() => {
+                let info: in6_pktinfo = read_data(data);
+                Cmsg::PktInfo(Ipv6PktInfo {
+                    addr: addr_from_segments(&info.ipi6_addr.s6_addr),
+                    if_index: info.ipi6_ifindex
+                })
+            },
+            (IPPROTO_IPV6, IPV6_HOPLIMIT)
+                    if data.len() >= size_of::<c_int>() => {
+                Cmsg::HopLimit(read_data(data))
+            },
+            (IPPROTO_IPV6, IPV6_TCLASS)
+                    if data.len() >= size_of::<c_int>() => {
+                Cmsg::TClass(read_data(data))
+            },
+            (SOL_SOCKET, SO_TIMESTAMPNS)
+                    if data.len() >= size_of::<timespec>() => {
+                let ts: timespec = read_data(data);
+                Cmsg::Timestamp(UNIX_EPOCH + Duration::new(
+                    ts.tv_sec as u64,
+                    ts.tv_nsec as u32
+                ))
+            },
+            (IPPROTO_IPV6, IPV6_RECVERR)
+                    | (SOL_PACKET, PACKET_TX_TIMESTAMP)
+                    if data.len() >= size_of::<sock_extended_err>() => {
+                Cmsg::ExtendedErr(parse_extended_err(data))
+            },
+            (IPPROTO_IPV6, IPV6_PATHMTU)
+                    if data.len() >= size_of::<ip6_mtuinfo>() => {
+                let info: ip6_mtuinfo = read_data(data);
+                match Inet6::from_raw(&info.ip6m_addr) {
+                    Ok(dst) => Cmsg::PathMtu { dst, mtu: info.ip6m_mtu },
+                    Err(_) => Cmsg::Other {
+                        level,
+                        kind,
+                        data: data.to_vec()
+                    }
+                }
+            },
+            (SOL_SOCKET, SCM_TXTIME) if data.len() >= size_of::<u64>() => {
+                Cmsg::TxTime(read_data(data))
+            },
+            (SOL_SOCKET, SCM_TIMESTAMPING)
+                    if data.len() >= 3 * size_of::<timespec>() => {
+                // the second one is deprecated and always zero
+                let ts: [timespec; 3] = read_data(data);
+                Cmsg::Timestamping(Timestamps {
+                    software: timespec_to_time(&ts[0]),
+                    hardware: timespec_to_time(&ts[2])
+                })
+            },
+            _ => Cmsg::Other {
+                level,
+                kind,
+                data: data.to_vec()
+            }
+        }
+    }}
+
+    fn to_raw(&self) -> Result<(c_int, c_int, Vec<u8>)> { unsafe {
+        Ok(match *self {
+            Cmsg::PktInfo(ref info) => {
+                let mut raw: in6_pktinfo = zeroed();
+                raw.ipi6_addr.s6_addr = info.addr.octets();
+                raw.ipi6_ifindex = info.if_index;
+                (IPPROTO_IPV6, IPV6_PKTINFO, data_to_vec(&raw))
+            },
+            Cmsg::HopLimit(x) =>
+                (IPPROTO_IPV6, IPV6_HOPLIMIT, data_to_vec(&x)),
+            Cmsg::TClass(x) =>
+                (IPPROTO_IPV6, IPV6_TCLASS, data_to_vec(&x)),
+            Cmsg::TxTime(x) =>
+                (SOL_SOCKET, SCM_TXTIME, data_to_vec(&x)),
+            Cmsg::Timestamp(_)
+                    | Cmsg::Timestamping(_)
+                    | Cmsg::ExtendedErr(_)
+                    | Cmsg::PathMtu { .. } =>
+                return Err(Error::UnsendableCmsg.into()),
+            Cmsg::Other { level, kind, ref data } =>
+                (level, kind, data.clone())
+        })
+    }}
+}
+
+// CMSG_SPACE(), the buffer space taken by data_len bytes of ancillary data
+pub fn cmsg_space(data_len: usize) -> usize {
+    cmsg_header_space() + cmsg_align(data_len)
+}
+
+fn cmsg_align(len: usize) -> usize {
+    let align = size_of::<usize>();
+    (len + align - 1) & !(align - 1)
+}
+
+fn cmsg_header_space() -> usize {
+    cmsg_align(size_of::<cmsghdr>())
+}
+
+// usize elements keep the headers aligned
+pub(crate) struct CmsgBuffer(Vec<usize>);
+
+impl CmsgBuffer {
+    pub fn new(size: usize) -> Self {
+        CmsgBuffer(vec![0; cmsg_align(size) / size_of::<usize>()])
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() * size_of::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.0.as_mut_ptr() as *mut c_void
+    }
+
+    pub fn bytes(&self) -> &[u8] { unsafe {
+        slice::from_raw_parts(self.0.as_ptr() as *const u8, self.len())
+    }}
+
+    fn bytes_mut(&mut self) -> &mut [u8] { unsafe {
+        slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, self.len())
+    }}
+}
+
+pub(crate) fn encode_cmsgs(cmsgs: &[Cmsg]) -> Result<CmsgBuffer> {
+    let raw = cmsgs.iter()
+        .map(Cmsg::to_raw)
+        .collect::<Result<Vec<_>>>()?;
+    let size = raw.iter()
+        .map(|&(_, _, ref data)| cmsg_space(data.len()))
+        .sum();
+
+    let mut buf = CmsgBuffer::new(size);
+    {
+        let bytes = buf.bytes_mut();
+        let mut offset = 0;
+        for (level, kind, data) in raw {
+            let mut hdr: cmsghdr = unsafe { zeroed() };
+            // the field type differs between glibc and musl
+            hdr.cmsg_len = (cmsg_header_space() + data.len()) as _;
+            hdr.cmsg_level = level;
+            hdr.cmsg_type = kind;
+            unsafe {
+                ptr::write_unaligned(
+                    bytes[offset..].as_mut_ptr() as *mut cmsghdr,
+                    hdr
+                );
+            }
+
+            let start = offset + cmsg_header_space();
+            bytes[start .. start + data.len()].copy_from_slice(&data);
+            offset += cmsg_space(data.len());
+        }
+    }
+
+    Ok(buf)
+}
+
+// buf is the part of the control buffer filled by the kernel
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(crate) fn parse_cmsgs(buf: &[u8]) -> Vec<Cmsg> {
+    let mut ret = Vec::new();
+    let mut offset = 0;
+
+    while offset + cmsg_header_space() <= buf.len() {
+        let hdr: cmsghdr = unsafe { read_data(&buf[offset..]) };
+        let len = hdr.cmsg_len as usize;
+        if len < cmsg_header_space() || offset + len > buf.len() {
+            break;
+        }
+
+        ret.push(Cmsg::parse(
+            hdr.cmsg_level,
+            hdr.cmsg_type,
+            &buf[offset + cmsg_header_space() .. offset + len]
+        ));
+        offset += cmsg_align(len);
+    }
+
+    ret
+}
+
+// the sock_extended_err may be followed by the sockaddr of the offender
+unsafe fn parse_extended_err(data: &[u8]) -> ExtendedErr {
+    let err: sock_extended_err = read_data(data);
+    let off = &data[size_of::<sock_extended_err>()..];
+    let offender = if off.len() >= size_of::<sockaddr_in6>() {
+        let addr: sockaddr_in6 = read_data(off);
+        if c_int::from(addr.sin6_family) == AF_INET6 {
+            Inet6::from_raw(&addr).ok()
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    ExtendedErr::new(&err, offender)
+}
+
+// None for the zero timestamps of the kinds not generated
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn timespec_to_time(ts: &timespec) -> Option<SystemTime> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+unsafe fn read_data<T>(data: &[u8]) -> T where T: Copy {
+    ptr::read_unaligned(data.as_ptr() as *const T)
+}
+
+unsafe fn data_to_vec<T>(x: &T) -> Vec<u8> where T: Copy {
+    slice::from_raw_parts(x as *const T as *const u8, size_of::<T>()).to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_parse_roundtrip() {
+        let cmsgs = vec![
+            Cmsg::PktInfo(Ipv6PktInfo {
+                addr: "fe80::1".parse().unwrap(),
+                if_index: 3
+            }),
+            Cmsg::HopLimit(255),
+            Cmsg::TClass(0x20),
+            Cmsg::TxTime(1_000_000_000),
+            Cmsg::Other { level: 1, kind: 2, data: vec![1, 2, 3] }
+        ];
+
+        let buf = encode_cmsgs(&cmsgs).unwrap();
+        assert_eq!(buf.len() % size_of::<usize>(), 0);
+
+        assert_eq!(parse_cmsgs(buf.bytes()), cmsgs);
+    }
+
+    #[test]
+    fn timestamp_not_sendable() {
+        assert!(encode_cmsgs(&[Cmsg::Timestamp(SystemTime::now())]).is_err());
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        let buf = encode_cmsgs(&[Cmsg::HopLimit(64)]).unwrap();
+        let bytes = buf.bytes();
+        assert_eq!(parse_cmsgs(&bytes[..bytes.len() - 8]), vec![]);
+    }
+}
@@ -19,6 +19,26 @@ pub mod raw {
 
     pub const ICMPV6_FILTER: c_int = 1;
 
+    pub const SOL_RAW: c_int = 255;
+
+    pub const IPV6_ADD_MEMBERSHIP: c_int = 20;
+    pub const IPV6_DROP_MEMBERSHIP: c_int = 21;
+
+    pub const PACKET_ADD_MEMBERSHIP: c_int = 1;
+    pub const PACKET_DROP_MEMBERSHIP: c_int = 2;
+    pub const PACKET_MR_MULTICAST: c_ushort = 0;
+    pub const ICMP_FILTER: c_int = 1;
+
+    pub const ICMP_ECHOREPLY: uint8_t = 0;
+    pub const ICMP_DEST_UNREACH: uint8_t = 3;
+    pub const ICMP_SOURCE_QUENCH: uint8_t = 4;
+    pub const ICMP_REDIRECT: uint8_t = 5;
+    pub const ICMP_ECHO: uint8_t = 8;
+    pub const ICMP_TIME_EXCEEDED: uint8_t = 11;
+    pub const ICMP_PARAMETERPROB: uint8_t = 12;
+    pub const ICMP_TIMESTAMP: uint8_t = 13;
+    pub const ICMP_TIMESTAMPREPLY: uint8_t = 14;
+
     pub const ICMP6_ECHO_REQUEST: uint8_t = 128;
     pub const ICMP6_ECHO_REPLY: uint8_t = 129;
     pub const MLD_LISTENER_QUERY: uint8_t = 130;
@@ -38,6 +58,10 @@ pub mod raw {
     pub const SIOCGIFINDEX: c_ulong = 0x8933;
     #[cfg(target_env = "gnu")]
     pub const SIOCGIFMTU: c_ulong = 0x8921;
+    #[cfg(target_env = "gnu")]
+    pub const SIOCGIFHWADDR: c_ulong = 0x8927;
+    #[cfg(target_env = "gnu")]
+    pub const SIOCSHWTSTAMP: c_ulong = 0x89b0;
 
     #[cfg(target_env = "musl")]
     pub const SIOCGIFFLAGS: c_int = 0x8913;
@@ -47,6 +71,10 @@ pub mod raw {
     pub const SIOCGIFINDEX: c_int = 0x8933;
     #[cfg(target_env = "musl")]
     pub const SIOCGIFMTU: c_int = 0x8921;
+    #[cfg(target_env = "musl")]
+    pub const SIOCGIFHWADDR: c_int = 0x8927;
+    #[cfg(target_env = "musl")]
+    pub const SIOCSHWTSTAMP: c_int = 0x89b0;
 
     pub const BPF_LD: u16 = 0x00;
     pub const BPF_LDX: u16 = 0x01;
@@ -88,10 +116,75 @@ pub mod raw {
 
     pub const BPF_K: u16 = 0x00;
     pub const BPF_X: u16 = 0x08;
+    pub const BPF_A: u16 = 0x10;
+
+    pub const BPF_TAX: u16 = 0x00;
+    pub const BPF_TXA: u16 = 0x80;
 
+    pub const BPF_MEMWORDS: usize = 16;
+    pub const BPF_MAXINSNS: usize = 4096;
+
+    pub const ETHERTYPE_IP: u16 = 0x0800;
+    pub const ETHERTYPE_ARP: u16 = 0x0806;
     pub const ETHERTYPE_IPV6: u16 = 0x86dd;
 
+    pub const ARPHRD_RAWIP: u16 = 519;
+    pub const ARPHRD_IP6GRE: u16 = 823;
+
     pub const IPV6_MTU_DISCOVER: c_int = 23;
+    pub const IPV6_MTU: c_int = 24;
+    pub const IPV6_MIN_MTU: u16 = 1280;
+    pub const IPV6_RECVERR: c_int = 25;
+
+    pub const IPV6_RECVPKTINFO: c_int = 49;
+    pub const IPV6_PKTINFO: c_int = 50;
+    pub const IPV6_RECVHOPLIMIT: c_int = 51;
+    pub const IPV6_HOPLIMIT: c_int = 52;
+    pub const IPV6_RECVPATHMTU: c_int = 60;
+    pub const IPV6_PATHMTU: c_int = 61;
+    pub const IPV6_RECVTCLASS: c_int = 66;
+    pub const IPV6_TCLASS: c_int = 67;
+
+    pub const SO_EE_ORIGIN_NONE: u8 = 0;
+    pub const SO_EE_ORIGIN_LOCAL: u8 = 1;
+    pub const SO_EE_ORIGIN_ICMP: u8 = 2;
+    pub const SO_EE_ORIGIN_ICMP6: u8 = 3;
+    pub const SO_EE_ORIGIN_TIMESTAMPING: u8 = 4;
+
+    pub const SO_TIMESTAMPNS: c_int = 35;
+    pub const SO_TIMESTAMPING: c_int = 37;
+    pub const SCM_TIMESTAMPING: c_int = SO_TIMESTAMPING;
+    pub const SO_MARK: c_int = 36;
+    pub const SO_ATTACH_BPF: c_int = 50;
+    pub const SO_ATTACH_REUSEPORT_EBPF: c_int = 52;
+    pub const SO_TXTIME: c_int = 61;
+    pub const SCM_TXTIME: c_int = SO_TXTIME;
+
+    pub const SOF_TXTIME_DEADLINE_MODE: u32 = 1 << 0;
+    pub const SOF_TXTIME_REPORT_ERRORS: u32 = 1 << 1;
+
+    pub const CLOCK_TAI: c_int = 11;
+
+    pub const SOF_TIMESTAMPING_TX_HARDWARE: u32 = 1 << 0;
+    pub const SOF_TIMESTAMPING_TX_SOFTWARE: u32 = 1 << 1;
+    pub const SOF_TIMESTAMPING_RX_HARDWARE: u32 = 1 << 2;
+    pub const SOF_TIMESTAMPING_RX_SOFTWARE: u32 = 1 << 3;
+    pub const SOF_TIMESTAMPING_SOFTWARE: u32 = 1 << 4;
+    pub const SOF_TIMESTAMPING_RAW_HARDWARE: u32 = 1 << 6;
+    pub const SOF_TIMESTAMPING_OPT_ID: u32 = 1 << 7;
+    pub const SOF_TIMESTAMPING_TX_SCHED: u32 = 1 << 8;
+    pub const SOF_TIMESTAMPING_OPT_CMSG: u32 = 1 << 10;
+    pub const SOF_TIMESTAMPING_OPT_TSONLY: u32 = 1 << 11;
+
+    pub const SCM_TSTAMP_SND: u32 = 0;
+    pub const SCM_TSTAMP_SCHED: u32 = 1;
+    pub const SCM_TSTAMP_ACK: u32 = 2;
+
+    pub const HWTSTAMP_TX_OFF: c_int = 0;
+    pub const HWTSTAMP_TX_ON: c_int = 1;
+    pub const HWTSTAMP_FILTER_NONE: c_int = 0;
+    pub const HWTSTAMP_FILTER_ALL: c_int = 1;
+    pub const HWTSTAMP_FILTER_PTP_V2_EVENT: c_int = 12;
 
     pub const IPV6_PMTUDISC_DONT: c_int = 0;
     pub const IPV6_PMTUDISC_WANT: c_int = 1;
@@ -101,6 +194,8 @@ pub mod raw {
     #[cfg(target_env = "musl")]
     pub const SO_ATTACH_FILTER: c_int = 26;
     #[cfg(target_env = "musl")]
+    pub const SO_DETACH_FILTER: c_int = 27;
+    #[cfg(target_env = "musl")]
     pub const SO_LOCK_FILTER: c_int = 44;
 
     #[cfg(target_env = "musl")]
@@ -111,6 +206,14 @@ use ::nlibc::*;
 
 use self::raw::*;
 use self::raw::{S_ISUID, S_ISGID, S_ISVTX};
+// newer libc versions have these too
+use self::raw::{IPV6_RECVPKTINFO, IPV6_RECVHOPLIMIT, IPV6_RECVTCLASS};
+use self::raw::{IPV6_RECVERR, IPV6_RECVPATHMTU};
+use self::raw::{SO_TIMESTAMPING, SO_TIMESTAMPNS};
+use self::raw::{CLOCK_TAI, SO_MARK, SO_TXTIME};
+use self::raw::{SO_ATTACH_BPF, SO_ATTACH_REUSEPORT_EBPF};
+use self::raw::{IPV6_ADD_MEMBERSHIP, IPV6_DROP_MEMBERSHIP};
+use ::ring::raw::SOL_PACKET;
 
 bitflags!(
     pub struct SecBits: c_int {
@@ -187,6 +290,8 @@ bitflags!(
 #[EnumRepr(type = "c_int")]
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum IpProto {
+    IPv4 = IPPROTO_IP,
+    Icmp = IPPROTO_ICMP,
     IPv6 = IPPROTO_IPV6,
     IcmpV6 = IPPROTO_ICMPV6
 }
@@ -196,14 +301,31 @@ pub enum IpProto {
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum SockOptLevel {
     Socket = SOL_SOCKET,
+    IPv4 = IPPROTO_IP,
     IPv6 = IPPROTO_IPV6,
-    IcmpV6 = IPPROTO_ICMPV6
+    IcmpV6 = IPPROTO_ICMPV6,
+    Raw = SOL_RAW,
+    Packet = SOL_PACKET
 }
 
 pub trait SockOptLevelGetter {
     fn get_sock_opt_level(self) -> SockOptLevel;
 }
 
+// not exhaustive
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SockOptIPv4 {
+    HdrIncl = IP_HDRINCL,
+    Ttl = IP_TTL
+}
+
+impl SockOptLevelGetter for SockOptIPv4 {
+    fn get_sock_opt_level(self) -> SockOptLevel {
+        SockOptLevel::IPv4
+    }
+}
+
 // not exhaustive
 #[EnumRepr(type = "c_int")]
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -211,7 +333,19 @@ pub enum SockOptIPv6 {
     IpHdrIncl = IP_HDRINCL,
     V6Only = IPV6_V6ONLY,
     UnicastHops = IPV6_UNICAST_HOPS,
-    V6MtuDiscover = IPV6_MTU_DISCOVER
+    V6MtuDiscover = IPV6_MTU_DISCOVER,
+    Mtu = IPV6_MTU,
+    RecvPktInfo = IPV6_RECVPKTINFO,
+    RecvHopLimit = IPV6_RECVHOPLIMIT,
+    RecvTClass = IPV6_RECVTCLASS,
+    RecvErr = IPV6_RECVERR,
+    RecvPathMtu = IPV6_RECVPATHMTU,
+    TClass = IPV6_TCLASS,
+    MulticastIf = IPV6_MULTICAST_IF,
+    MulticastHops = IPV6_MULTICAST_HOPS,
+    MulticastLoop = IPV6_MULTICAST_LOOP,
+    AddMembership = IPV6_ADD_MEMBERSHIP,
+    DropMembership = IPV6_DROP_MEMBERSHIP
 }
 
 impl SockOptLevelGetter for SockOptIPv6 {
@@ -233,6 +367,33 @@ impl SockOptLevelGetter for SockOptICMPv6 {
     }
 }
 
+// not exhaustive
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SockOptRaw {
+    IcmpFilter = ICMP_FILTER
+}
+
+impl SockOptLevelGetter for SockOptRaw {
+    fn get_sock_opt_level(self) -> SockOptLevel {
+        SockOptLevel::Raw
+    }
+}
+
+// not exhaustive
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SockOptPacket {
+    AddMembership = PACKET_ADD_MEMBERSHIP,
+    DropMembership = PACKET_DROP_MEMBERSHIP
+}
+
+impl SockOptLevelGetter for SockOptPacket {
+    fn get_sock_opt_level(self) -> SockOptLevel {
+        SockOptLevel::Packet
+    }
+}
+
 // not exhaustive
 #[EnumRepr(type = "c_int")]
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -240,7 +401,20 @@ pub enum SockOptSocket {
     BindToDevice = SO_BINDTODEVICE,
     DontRoute = SO_DONTROUTE,
     AttachFilter = SO_ATTACH_FILTER,
-    LockFilter = SO_LOCK_FILTER
+    LockFilter = SO_LOCK_FILTER,
+    DetachFilter = SO_DETACH_FILTER,
+    AttachBpf = SO_ATTACH_BPF,
+    AttachReuseportEbpf = SO_ATTACH_REUSEPORT_EBPF,
+    Error = SO_ERROR,
+    SndBuf = SO_SNDBUF,
+    RcvBuf = SO_RCVBUF,
+    SndBufForce = SO_SNDBUFFORCE,
+    RcvBufForce = SO_RCVBUFFORCE,
+    Priority = SO_PRIORITY,
+    Mark = SO_MARK,
+    TimestampNs = SO_TIMESTAMPNS,
+    Timestamping = SO_TIMESTAMPING,
+    TxTime = SO_TXTIME
 }
 
 impl SockOptLevelGetter for SockOptSocket {
@@ -258,6 +432,48 @@ pub enum V6PmtuType {
     Probe = IPV6_PMTUDISC_PROBE
 }
 
+// the clocks of the SO_TXTIME launch times,
+// the etf qdisc only takes Tai
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TxTimeClock {
+    Realtime = CLOCK_REALTIME,
+    Monotonic = CLOCK_MONOTONIC,
+    Tai = CLOCK_TAI
+}
+
+// the hwtstamp_config of SIOCSHWTSTAMP, see the kernel
+// Documentation/networking/timestamping.txt
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HwTxType {
+    Off = HWTSTAMP_TX_OFF,
+    On = HWTSTAMP_TX_ON
+}
+
+// not exhaustive
+#[EnumRepr(type = "c_int")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HwRxFilter {
+    None = HWTSTAMP_FILTER_NONE,
+    All = HWTSTAMP_FILTER_ALL,
+    PtpV2Event = HWTSTAMP_FILTER_PTP_V2_EVENT
+}
+
+#[EnumRepr(type = "uint8_t")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum IcmpType {
+    EchoReply = ICMP_ECHOREPLY,
+    DestUnreachable = ICMP_DEST_UNREACH,
+    SourceQuench = ICMP_SOURCE_QUENCH,
+    Redirect = ICMP_REDIRECT,
+    EchoRequest = ICMP_ECHO,
+    TimeExceeded = ICMP_TIME_EXCEEDED,
+    ParameterProblem = ICMP_PARAMETERPROB,
+    Timestamp = ICMP_TIMESTAMP,
+    TimestampReply = ICMP_TIMESTAMPREPLY
+}
+
 #[EnumRepr(type = "uint8_t")]
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum IcmpV6Type {
@@ -273,6 +489,34 @@ pub enum IcmpV6Type {
     NdRedirect = ND_REDIRECT
 }
 
+bitflags!(
+    pub struct TimestampingFlags: u32 {
+        const TxHardware = SOF_TIMESTAMPING_TX_HARDWARE;
+        const TxSoftware = SOF_TIMESTAMPING_TX_SOFTWARE;
+        const RxHardware = SOF_TIMESTAMPING_RX_HARDWARE;
+        const RxSoftware = SOF_TIMESTAMPING_RX_SOFTWARE;
+        // report the software timestamps generated
+        const Software = SOF_TIMESTAMPING_SOFTWARE;
+        // report the hardware timestamps generated
+        const RawHardware = SOF_TIMESTAMPING_RAW_HARDWARE;
+        // number the sent packets, see TxTimestamp::key
+        const OptId = SOF_TIMESTAMPING_OPT_ID;
+        const TxSched = SOF_TIMESTAMPING_TX_SCHED;
+        const OptCmsg = SOF_TIMESTAMPING_OPT_CMSG;
+        // queue the timestamps without the packets
+        const OptTsonly = SOF_TIMESTAMPING_OPT_TSONLY;
+    }
+);
+
+bitflags!(
+    pub struct TxTimeFlags: u32 {
+        // the launch time is a deadline, the packets may leave earlier
+        const DeadlineMode = SOF_TXTIME_DEADLINE_MODE;
+        // the dropped packets are reported on the error queue
+        const ReportErrors = SOF_TXTIME_REPORT_ERRORS;
+    }
+);
+
 bitflags!(
     pub struct RecvFlags: c_int {
         const CmsgCloexec = MSG_CMSG_CLOEXEC;
@@ -339,6 +583,10 @@ bitflags!(
 
         const K = BPF_K;
         const X = BPF_X;
+        const A = BPF_A;
+
+        const TAX = BPF_TAX;
+        const TXA = BPF_TXA;
     }
 );
 
@@ -0,0 +1,875 @@
+// eBPF socket filters loaded through bpf(2), and the maps they share with
+// the userspace.  The programs are either raw bytecode or the sections of
+// an ELF object as built by clang -target bpf, with the maps declared in
+// its "maps" section as struct bpf_map_def.  The kernels before 5.11
+// charge the maps and programs to RLIMIT_MEMLOCK.
+
+use ::std::collections::HashMap;
+use ::std::ffi::CString;
+use ::std::fs;
+use ::std::mem::size_of;
+use ::std::os::unix::prelude::*;
+use ::std::path::Path;
+use ::std::ptr::null_mut;
+
+use ::nlibc::{
+    c_int, c_long, syscall, EACCES, EINTR, EINVAL, ENOENT, ENOSPC, SYS_bpf
+};
+
+use ::errors::{Error, Result};
+use ::util::*;
+use self::raw::*;
+
+pub mod raw {
+    use ::nlibc::c_int;
+
+    pub const BPF_MAP_CREATE: c_int = 0;
+    pub const BPF_MAP_LOOKUP_ELEM: c_int = 1;
+    pub const BPF_MAP_UPDATE_ELEM: c_int = 2;
+    pub const BPF_MAP_DELETE_ELEM: c_int = 3;
+    pub const BPF_MAP_GET_NEXT_KEY: c_int = 4;
+    pub const BPF_PROG_LOAD: c_int = 5;
+
+    pub const BPF_MAP_TYPE_HASH: u32 = 1;
+    pub const BPF_MAP_TYPE_ARRAY: u32 = 2;
+    pub const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+
+    pub const BPF_PROG_TYPE_SOCKET_FILTER: u32 = 1;
+    pub const BPF_PROG_TYPE_SK_REUSEPORT: u32 = 21;
+
+    pub const BPF_ANY: u64 = 0;
+    pub const BPF_NOEXIST: u64 = 1;
+    pub const BPF_EXIST: u64 = 2;
+
+    // BPF_LD | BPF_IMM | BPF_DW, the 16 byte load taking the map fds
+    pub const BPF_LD_IMM64: u8 = 0x18;
+    pub const BPF_PSEUDO_MAP_FD: u8 = 1;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    pub struct bpf_insn {
+        pub code: u8,
+        // dst_reg in the low nibble, src_reg in the high one
+        pub regs: u8,
+        pub off: i16,
+        pub imm: i32
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct bpf_map_create_attr {
+        pub map_type: u32,
+        pub key_size: u32,
+        pub value_size: u32,
+        pub max_entries: u32,
+        pub map_flags: u32
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct bpf_map_elem_attr {
+        pub map_fd: u32,
+        pub key: u64,
+        // or next_key
+        pub value: u64,
+        pub flags: u64
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct bpf_prog_load_attr {
+        pub prog_type: u32,
+        pub insn_cnt: u32,
+        pub insns: u64,
+        pub license: u64,
+        pub log_level: u32,
+        pub log_size: u32,
+        pub log_buf: u64,
+        pub kern_version: u32,
+        pub prog_flags: u32
+    }
+}
+
+const VERIFIER_LOG_SIZE: usize = 1 << 16;
+
+const EM_BPF: u16 = 247;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_REL: u32 = 9;
+const SHF_EXECINSTR: u64 = 0x4;
+
+#[EnumRepr(type = "u32")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EbpfMapType {
+    Hash = BPF_MAP_TYPE_HASH,
+    Array = BPF_MAP_TYPE_ARRAY,
+    LruHash = BPF_MAP_TYPE_LRU_HASH
+}
+
+#[EnumRepr(type = "u32")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EbpfProgType {
+    // for SO_ATTACH_BPF and SO_ATTACH_REUSEPORT_EBPF
+    SocketFilter = BPF_PROG_TYPE_SOCKET_FILTER,
+    SkReuseport = BPF_PROG_TYPE_SK_REUSEPORT
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EbpfUpdate {
+    Any,
+    NoExist,
+    Exist
+}
+
+impl EbpfUpdate {
+    fn flags(self) -> u64 {
+        match self {
+            EbpfUpdate::Any => BPF_ANY,
+            EbpfUpdate::NoExist => BPF_NOEXIST,
+            EbpfUpdate::Exist => BPF_EXIST
+        }
+    }
+}
+
+// The types read and written as the keys and values of the maps.
+// Implementing it asserts the type has no padding and every bit
+// pattern is valid, as it is shared with the eBPF program as is.
+pub unsafe trait EbpfPod: Copy {}
+
+unsafe impl EbpfPod for u8 {}
+unsafe impl EbpfPod for u16 {}
+unsafe impl EbpfPod for u32 {}
+unsafe impl EbpfPod for u64 {}
+unsafe impl EbpfPod for i32 {}
+unsafe impl EbpfPod for i64 {}
+unsafe impl EbpfPod for [u8; 4] {}
+unsafe impl EbpfPod for [u8; 6] {}
+unsafe impl EbpfPod for [u8; 16] {}
+
+#[allow(clippy::cast_sign_loss)]
+fn ptr_to_u64<T>(x: *const T) -> u64 {
+    x as usize as u64
+}
+
+#[allow(clippy::cast_possible_truncation)]
+unsafe fn bpf<T>(cmd: c_int, attr: &mut T) -> c_long {
+    syscall(SYS_bpf, cmd, attr as *mut T, size_of::<T>() as u32)
+}
+
+fn as_bytes<T>(x: &T) -> &[u8] where T: EbpfPod {
+    unsafe {
+        ::std::slice::from_raw_parts(x as *const T as *const u8, size_of::<T>())
+    }
+}
+
+pub struct EbpfMap {
+    fd: RawFd,
+    key_size: usize,
+    value_size: usize
+}
+
+impl EbpfMap {
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(
+        map_type: EbpfMapType,
+        key_size: u32,
+        value_size: u32,
+        max_entries: u32
+    ) -> Result<Self> {
+        let mut attr = bpf_map_create_attr {
+            map_type: map_type.repr(),
+            key_size,
+            value_size,
+            max_entries,
+            map_flags: 0
+        };
+        let fd = unsafe { n1try!(bpf(BPF_MAP_CREATE, &mut attr)) };
+
+        Ok(EbpfMap {
+            fd: fd as RawFd,
+            key_size: key_size as usize,
+            value_size: value_size as usize
+        })
+    }
+
+    pub fn key_size(&self) -> usize {
+        self.key_size
+    }
+
+    pub fn value_size(&self) -> usize {
+        self.value_size
+    }
+
+    // None if there is no such key
+    pub fn lookup(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.check_key(key)?;
+        let mut value = vec![0; self.value_size];
+        let mut attr = self.elem_attr(key, value.as_mut_ptr(), 0);
+
+        let ret = unsafe { bpf(BPF_MAP_LOOKUP_ELEM, &mut attr) };
+        if ret == -1 {
+            return not_found_or_err();
+        }
+        Ok(Some(value))
+    }
+
+    pub fn update(&self, key: &[u8], value: &[u8], how: EbpfUpdate)
+            -> Result<()> {
+        self.check_key(key)?;
+        if value.len() != self.value_size {
+            return Err(Error::WrongSize.into());
+        }
+        let mut attr = self.elem_attr(
+            key,
+            value.as_ptr() as *mut u8,
+            how.flags()
+        );
+
+        unsafe {
+            n1try!(bpf(BPF_MAP_UPDATE_ELEM, &mut attr));
+        }
+        Ok(())
+    }
+
+    // false if there was no such key
+    pub fn delete(&self, key: &[u8]) -> Result<bool> {
+        self.check_key(key)?;
+        let mut attr = self.elem_attr(key, null_mut(), 0);
+
+        let ret = unsafe { bpf(BPF_MAP_DELETE_ELEM, &mut attr) };
+        if ret == -1 {
+            return not_found_or_err().map(|_: Option<()>| false);
+        }
+        Ok(true)
+    }
+
+    // a snapshot, the program may add or remove keys meanwhile
+    #[allow(clippy::cast_sign_loss)]
+    pub fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        loop {
+            let mut next = vec![0; self.key_size];
+            let mut attr = bpf_map_elem_attr {
+                map_fd: self.fd as u32,
+                // the first key for a null one
+                key: keys.last().map_or(0, |x| ptr_to_u64(x.as_ptr())),
+                value: ptr_to_u64(next.as_mut_ptr()),
+                flags: 0
+            };
+
+            let ret = unsafe { bpf(BPF_MAP_GET_NEXT_KEY, &mut attr) };
+            if ret == -1 {
+                return not_found_or_err().map(|_: Option<()>| keys);
+            }
+            keys.push(next);
+        }
+    }
+
+    pub fn get<K, V>(&self, key: &K) -> Result<Option<V>> where
+            K: EbpfPod,
+            V: EbpfPod {
+        if size_of::<V>() != self.value_size {
+            return Err(Error::WrongSize.into());
+        }
+        Ok(self.lookup(as_bytes(key))?.map(|x| unsafe {
+            ::std::ptr::read_unaligned(x.as_ptr() as *const V)
+        }))
+    }
+
+    pub fn set<K, V>(&self, key: &K, value: &V) -> Result<()> where
+            K: EbpfPod,
+            V: EbpfPod {
+        self.update(as_bytes(key), as_bytes(value), EbpfUpdate::Any)
+    }
+
+    fn check_key(&self, key: &[u8]) -> Result<()> {
+        if key.len() == self.key_size {
+            Ok(())
+        } else {
+            Err(Error::WrongSize.into())
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn elem_attr(&self, key: &[u8], value: *mut u8, flags: u64)
+            -> bpf_map_elem_attr {
+        bpf_map_elem_attr {
+            map_fd: self.fd as u32,
+            key: ptr_to_u64(key.as_ptr()),
+            value: ptr_to_u64(value),
+            flags
+        }
+    }
+}
+
+impl AsRawFd for EbpfMap {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for EbpfMap {
+    fn drop(&mut self) {
+        log_if_err(::nix::unistd::close(self.fd).map_err(|e| e.into()));
+    }
+}
+
+fn not_found_or_err<T>() -> Result<Option<T>> {
+    let err = ::std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(ENOENT) => Ok(None),
+        _ => Err(Error::IoError(err).into())
+    }
+}
+
+// attached with SockOpts::AttachBpf or SockOpts::AttachReuseportEbpf,
+// the sockets keep it loaded after it is dropped
+pub struct EbpfProg {
+    fd: RawFd
+}
+
+impl EbpfProg {
+    // the verifier log is only asked for when the program is rejected
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn load(prog_type: EbpfProgType, insns: &[bpf_insn], license: &str)
+            -> Result<Self> {
+        let license = CString::new(license)?;
+        let mut attr = bpf_prog_load_attr {
+            prog_type: prog_type.repr(),
+            insn_cnt: insns.len() as u32,
+            insns: ptr_to_u64(insns.as_ptr()),
+            license: ptr_to_u64(license.as_ptr()),
+            ..Default::default()
+        };
+
+        let fd = unsafe { bpf(BPF_PROG_LOAD, &mut attr) };
+        if fd != -1 {
+            return Ok(EbpfProg { fd: fd as RawFd });
+        }
+        let err = ::std::io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(EACCES) | Some(EINVAL) => (),
+            _ => return Err(Error::IoError(err).into())
+        }
+
+        let mut log = vec![0u8; VERIFIER_LOG_SIZE];
+        attr.log_level = 1;
+        attr.log_size = log.len() as u32;
+        attr.log_buf = ptr_to_u64(log.as_mut_ptr());
+        let fd = unsafe { bpf(BPF_PROG_LOAD, &mut attr) };
+        if fd != -1 {
+            return Ok(EbpfProg { fd: fd as RawFd });
+        }
+        let retry_err = ::std::io::Error::last_os_error();
+        if retry_err.raw_os_error() == Some(ENOSPC) {
+            // the log did not fit, what is there is still of use
+        } else if !log.iter().any(|&x| x != 0) {
+            return Err(Error::IoError(err).into());
+        }
+
+        let len = log.iter().position(|&x| x == 0).unwrap_or(log.len());
+        Err(Error::EbpfVerifierError {
+            log: String::from_utf8_lossy(&log[..len]).into_owned()
+        }.into())
+    }
+
+    // the instructions in the host byte order, e.g. a dump of a section
+    pub fn from_bytecode(
+        prog_type: EbpfProgType,
+        bytecode: &[u8],
+        license: &str
+    ) -> Result<Self> {
+        Self::load(prog_type, &parse_bytecode(bytecode)?, license)
+    }
+}
+
+impl AsRawFd for EbpfProg {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for EbpfProg {
+    fn drop(&mut self) {
+        log_if_err(::nix::unistd::close(self.fd).map_err(|e| e.into()));
+    }
+}
+
+pub fn parse_bytecode(bytecode: &[u8]) -> Result<Vec<bpf_insn>> {
+    if bytecode.len() % size_of::<bpf_insn>() != 0 {
+        return Err(invalid_object("bytecode not a multiple of 8 bytes"));
+    }
+    Ok(bytecode.chunks(size_of::<bpf_insn>()).map(|x| unsafe {
+        ::std::ptr::read_unaligned(x.as_ptr() as *const bpf_insn)
+    }).collect())
+}
+
+fn invalid_object<T>(reason: T) -> ::failure::Error where T: Into<String> {
+    Error::InvalidEbpfObject { reason: reason.into() }.into()
+}
+
+// a struct bpf_map_def of the "maps" section
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EbpfMapDef {
+    pub name: String,
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32
+}
+
+struct EbpfProgSection {
+    name: String,
+    insns: Vec<bpf_insn>,
+    // the ld_imm64 instructions to be given the fds of the maps
+    map_refs: Vec<(usize, String)>
+}
+
+// The parsed ELF object, 64-bit little endian only.
+pub struct EbpfObject {
+    license: String,
+    maps: Vec<EbpfMapDef>,
+    progs: Vec<EbpfProgSection>
+}
+
+// the maps are all created, whether the program uses them or not
+pub struct LoadedEbpfObject {
+    pub prog: EbpfProg,
+    pub maps: HashMap<String, EbpfMap>
+}
+
+impl EbpfObject {
+    pub fn from_file<T>(path: T) -> Result<Self> where T: AsRef<Path> {
+        Self::parse(&fs::read(path).map_err(Error::IoError)?)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let elf = Elf::parse(data)?;
+
+        let mut license = None;
+        let mut maps_index = None;
+        let mut symtab_index = None;
+        for (i, sh) in elf.sections.iter().enumerate() {
+            match (sh.kind, &*elf.section_name(sh)?) {
+                (SHT_PROGBITS, "license") => {
+                    let x = elf.section_data(sh)?;
+                    let len = x.iter().position(|&c| c == 0)
+                        .unwrap_or(x.len());
+                    license = Some(String::from_utf8_lossy(&x[..len])
+                        .into_owned());
+                },
+                (SHT_PROGBITS, "maps") => maps_index = Some(i),
+                (SHT_SYMTAB, _) => symtab_index = Some(i),
+                _ => ()
+            }
+        }
+
+        let symbols = match symtab_index {
+            Some(i) => elf.symbols(&elf.sections[i])?,
+            None => Vec::new()
+        };
+
+        let mut maps = Vec::new();
+        if let Some(i) = maps_index {
+            let data = elf.section_data(&elf.sections[i])?;
+            for sym in symbols.iter()
+                    .filter(|x| x.section == i && !x.name.is_empty()) {
+                let off = sym.value as usize;
+                let def = off.checked_add(20)
+                    .and_then(|end| data.get(off..end))
+                    .ok_or_else(|| invalid_object("map definition cut"))?;
+                maps.push(EbpfMapDef {
+                    name: sym.name.clone(),
+                    map_type: read_u32(def, 0)?,
+                    key_size: read_u32(def, 4)?,
+                    value_size: read_u32(def, 8)?,
+                    max_entries: read_u32(def, 12)?,
+                    map_flags: read_u32(def, 16)?
+                });
+            }
+        }
+
+        let mut progs = Vec::new();
+        for (i, sh) in elf.sections.iter().enumerate() {
+            if sh.kind != SHT_PROGBITS || sh.flags & SHF_EXECINSTR == 0
+                    || sh.size == 0 {
+                continue;
+            }
+            let mut map_refs = Vec::new();
+            for rel in elf.sections.iter()
+                    .filter(|x| x.kind == SHT_REL && x.info as usize == i) {
+                for (offset, sym) in elf.relocations(rel)? {
+                    let sym = symbols.get(sym)
+                        .ok_or_else(|| invalid_object("no such symbol"))?;
+                    if Some(sym.section) != maps_index {
+                        return Err(invalid_object(format!(
+                            "relocation to {}, not a map",
+                            sym.name
+                        )));
+                    }
+                    map_refs.push((offset as usize / 8, sym.name.clone()));
+                }
+            }
+            progs.push(EbpfProgSection {
+                name: elf.section_name(sh)?,
+                insns: parse_bytecode(elf.section_data(sh)?)?,
+                map_refs
+            });
+        }
+
+        Ok(EbpfObject {
+            license: license.unwrap_or_else(|| "GPL".to_string()),
+            maps,
+            progs
+        })
+    }
+
+    pub fn license(&self) -> &str {
+        &self.license
+    }
+
+    pub fn maps(&self) -> &[EbpfMapDef] {
+        &self.maps
+    }
+
+    // the names of the sections with code, e.g. "socket"
+    pub fn programs(&self) -> Vec<&str> {
+        self.progs.iter().map(|x| &*x.name).collect()
+    }
+
+    pub fn load(&self, section: &str, prog_type: EbpfProgType)
+            -> Result<LoadedEbpfObject> {
+        let mut maps = HashMap::new();
+        for def in &self.maps {
+            let map_type = EbpfMapType::from_repr(def.map_type)
+                .ok_or_else(|| invalid_object(format!(
+                    "map {} of unsupported type {}",
+                    def.name,
+                    def.map_type
+                )))?;
+            let map = EbpfMap::new(
+                map_type,
+                def.key_size,
+                def.value_size,
+                def.max_entries
+            )?;
+            maps.insert(def.name.clone(), map);
+        }
+
+        let fds = maps.iter()
+            .map(|(name, map)| (name.clone(), map.as_raw_fd()))
+            .collect();
+        let insns = self.relocated(section, &fds)?;
+        let prog = EbpfProg::load(prog_type, &insns, &self.license)?;
+        Ok(LoadedEbpfObject { prog, maps })
+    }
+
+    fn relocated(&self, section: &str, fds: &HashMap<String, RawFd>)
+            -> Result<Vec<bpf_insn>> {
+        let prog = self.progs.iter()
+            .find(|x| x.name == section)
+            .ok_or_else(|| invalid_object(format!("no section {}", section)))?;
+
+        let mut insns = prog.insns.clone();
+        for &(i, ref name) in &prog.map_refs {
+            let fd = *fds.get(name)
+                .ok_or_else(|| invalid_object(format!("no map {}", name)))?;
+            let insn = insns.get_mut(i)
+                .filter(|x| x.code == BPF_LD_IMM64)
+                .ok_or_else(|| invalid_object(format!(
+                    "map {} not referenced by a 64-bit load",
+                    name
+                )))?;
+            insn.regs = (insn.regs & 0x0f) | (BPF_PSEUDO_MAP_FD << 4);
+            insn.imm = fd;
+        }
+        Ok(insns)
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32
+}
+
+struct Symbol {
+    name: String,
+    section: usize,
+    value: u64
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    sections: Vec<SectionHeader>,
+    shstrndx: usize
+}
+
+impl<'a> Elf<'a> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.get(..6) != Some(&b"\x7fELF\x02\x01"[..]) {
+            return Err(invalid_object("not a 64-bit little endian ELF"));
+        }
+        if read_u16(data, 18)? != EM_BPF {
+            return Err(invalid_object("not an eBPF ELF"));
+        }
+
+        let shoff = read_u64(data, 40)? as usize;
+        let shentsize = usize::from(read_u16(data, 58)?);
+        let shnum = usize::from(read_u16(data, 60)?);
+        let shstrndx = usize::from(read_u16(data, 62)?);
+        if shentsize < 64 || shstrndx >= shnum {
+            return Err(invalid_object("bad section header table"));
+        }
+
+        let sections = (0..shnum).map(|i| -> Result<SectionHeader> {
+            let x = i.checked_mul(shentsize)
+                .and_then(|x| x.checked_add(shoff))
+                .ok_or_else(|| invalid_object("truncated"))?;
+            let x = read_bytes(data, x, 64)?;
+            Ok(SectionHeader {
+                name: read_u32(x, 0)?,
+                kind: read_u32(x, 4)?,
+                flags: read_u64(x, 8)?,
+                offset: read_u64(x, 24)?,
+                size: read_u64(x, 32)?,
+                link: read_u32(x, 40)?,
+                info: read_u32(x, 44)?
+            })
+        }).collect::<Result<Vec<_>>>()?;
+
+        Ok(Elf { data, sections, shstrndx })
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn section_data(&self, sh: &SectionHeader) -> Result<&'a [u8]> {
+        let start = sh.offset as usize;
+        start.checked_add(sh.size as usize)
+            .and_then(|end| self.data.get(start..end))
+            .ok_or_else(|| invalid_object("section out of the file"))
+    }
+
+    fn string(&self, strtab: usize, off: u32) -> Result<String> {
+        let sh = self.sections.get(strtab)
+            .ok_or_else(|| invalid_object("no string table"))?;
+        let data = self.section_data(sh)?
+            .get(off as usize..)
+            .ok_or_else(|| invalid_object("string out of the table"))?;
+        let len = data.iter().position(|&c| c == 0)
+            .ok_or_else(|| invalid_object("unterminated string"))?;
+        Ok(String::from_utf8_lossy(&data[..len]).into_owned())
+    }
+
+    fn section_name(&self, sh: &SectionHeader) -> Result<String> {
+        self.string(self.shstrndx, sh.name)
+    }
+
+    fn symbols(&self, symtab: &SectionHeader) -> Result<Vec<Symbol>> {
+        self.section_data(symtab)?.chunks(24).map(|x| -> Result<Symbol> {
+            Ok(Symbol {
+                name: self.string(symtab.link as usize, read_u32(x, 0)?)?,
+                section: usize::from(read_u16(x, 6)?),
+                value: read_u64(x, 8)?
+            })
+        }).collect()
+    }
+
+    // the offsets and the symbol indices
+    #[allow(clippy::cast_possible_truncation)]
+    fn relocations(&self, rel: &SectionHeader) -> Result<Vec<(u64, usize)>> {
+        self.section_data(rel)?.chunks(16).map(|x| -> Result<(u64, usize)> {
+            Ok((read_u64(x, 0)?, (read_u64(x, 8)? >> 32) as usize))
+        }).collect()
+    }
+}
+
+fn read_bytes(data: &[u8], off: usize, len: usize) -> Result<&[u8]> {
+    off.checked_add(len)
+        .and_then(|end| data.get(off..end))
+        .ok_or_else(|| invalid_object("truncated"))
+}
+
+fn read_u16(data: &[u8], off: usize) -> Result<u16> {
+    let x = read_bytes(data, off, 2)?;
+    Ok(u16::from(x[0]) | u16::from(x[1]) << 8)
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32> {
+    let x = read_bytes(data, off, 4)?;
+    Ok(u32::from(read_u16(x, 0)?) | u32::from(read_u16(x, 2)?) << 16)
+}
+
+fn read_u64(data: &[u8], off: usize) -> Result<u64> {
+    let x = read_bytes(data, off, 8)?;
+    Ok(u64::from(read_u32(x, 0)?) | u64::from(read_u32(x, 4)?) << 32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_u16(v: &mut Vec<u8>, x: u16) {
+        v.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn push_u32(v: &mut Vec<u8>, x: u32) {
+        v.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn push_u64(v: &mut Vec<u8>, x: u64) {
+        v.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn insn_bytes(insns: &[bpf_insn]) -> Vec<u8> {
+        let mut v = Vec::new();
+        for i in insns {
+            v.push(i.code);
+            v.push(i.regs);
+            v.extend_from_slice(&i.off.to_le_bytes());
+            v.extend_from_slice(&i.imm.to_le_bytes());
+        }
+        v
+    }
+
+    // r1 = map ll; r0 = 0; exit
+    fn prog() -> Vec<bpf_insn> {
+        vec![
+            bpf_insn { code: BPF_LD_IMM64, regs: 1, off: 0, imm: 0 },
+            bpf_insn { code: 0, regs: 0, off: 0, imm: 0 },
+            bpf_insn { code: 0xb7, regs: 0, off: 0, imm: 0 },
+            bpf_insn { code: 0x95, regs: 0, off: 0, imm: 0 }
+        ]
+    }
+
+    // null, socket, maps, license, .symtab, .strtab, .relsocket, .shstrtab
+    fn make_object() -> Vec<u8> {
+        let shstrtab = b"\0socket\0maps\0license\0.symtab\0.strtab\0\
+            .relsocket\0.shstrtab\0".to_vec();
+        let strtab = b"\0counters\0".to_vec();
+
+        let code = insn_bytes(&prog());
+        let mut maps = Vec::new();
+        for x in &[BPF_MAP_TYPE_HASH, 16, 8, 1024, 0] {
+            push_u32(&mut maps, *x);
+        }
+        let license = b"GPL\0".to_vec();
+        let mut symtab = vec![0; 24];
+        push_u32(&mut symtab, 1);
+        symtab.extend_from_slice(&[0x11, 0]);
+        push_u16(&mut symtab, 2);
+        push_u64(&mut symtab, 0);
+        push_u64(&mut symtab, 20);
+        let mut rel = Vec::new();
+        push_u64(&mut rel, 0);
+        push_u64(&mut rel, 1 << 32 | 1);
+
+        // name, type, flags, link, info, data
+        let sections: Vec<(u32, u32, u64, u32, u32, Vec<u8>)> = vec![
+            (0, 0, 0, 0, 0, Vec::new()),
+            (1, SHT_PROGBITS, SHF_EXECINSTR | 2, 0, 0, code),
+            (8, SHT_PROGBITS, 3, 0, 0, maps),
+            (13, SHT_PROGBITS, 3, 0, 0, license),
+            (21, SHT_SYMTAB, 0, 5, 1, symtab),
+            (29, 3, 0, 0, 0, strtab),
+            (37, SHT_REL, 0, 4, 1, rel),
+            (48, 3, 0, 0, 0, shstrtab)
+        ];
+
+        let mut data = vec![0; 64];
+        let mut headers = Vec::new();
+        for (name, kind, flags, link, info, bytes) in sections {
+            let offset = data.len() as u64;
+            data.extend_from_slice(&bytes);
+            push_u32(&mut headers, name);
+            push_u32(&mut headers, kind);
+            push_u64(&mut headers, flags);
+            push_u64(&mut headers, 0);
+            push_u64(&mut headers, offset);
+            push_u64(&mut headers, bytes.len() as u64);
+            push_u32(&mut headers, link);
+            push_u32(&mut headers, info);
+            push_u64(&mut headers, 8);
+            push_u64(&mut headers, 0);
+        }
+        let shoff = data.len() as u64;
+        data.extend_from_slice(&headers);
+
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        data[6] = 1;
+        data[16..18].copy_from_slice(&1u16.to_le_bytes());
+        data[18..20].copy_from_slice(&EM_BPF.to_le_bytes());
+        data[40..48].copy_from_slice(&shoff.to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[58..60].copy_from_slice(&64u16.to_le_bytes());
+        data[60..62].copy_from_slice(&8u16.to_le_bytes());
+        data[62..64].copy_from_slice(&7u16.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parse_object() {
+        let obj = EbpfObject::parse(&make_object()).unwrap();
+        assert_eq!(obj.license(), "GPL");
+        assert_eq!(obj.programs(), vec!["socket"]);
+        assert_eq!(obj.maps(), &[EbpfMapDef {
+            name: "counters".to_string(),
+            map_type: BPF_MAP_TYPE_HASH,
+            key_size: 16,
+            value_size: 8,
+            max_entries: 1024,
+            map_flags: 0
+        }][..]);
+
+        let mut fds = HashMap::new();
+        assert!(obj.relocated("socket", &fds).is_err());
+        fds.insert("counters".to_string(), 42);
+        let insns = obj.relocated("socket", &fds).unwrap();
+        assert_eq!(insns[0].regs, 1 | BPF_PSEUDO_MAP_FD << 4);
+        assert_eq!(insns[0].imm, 42);
+        assert_eq!(&insns[1..], &prog()[1..]);
+        assert!(obj.relocated("nosuchsection", &fds).is_err());
+    }
+
+    #[test]
+    fn bytecode() {
+        let insns = prog();
+        assert_eq!(parse_bytecode(&insn_bytes(&insns)).unwrap(), insns);
+        assert!(parse_bytecode(&[0; 12]).is_err());
+        assert!(EbpfObject::parse(&[0; 64]).is_err());
+    }
+
+    #[test]
+    fn offsets_overflowing() {
+        let mut obj = make_object();
+        obj[40..48].copy_from_slice(&u64::max_value().to_le_bytes());
+        assert!(EbpfObject::parse(&obj).is_err());
+
+        let mut obj = make_object();
+        let shoff = read_u64(&obj, 40).unwrap() as usize;
+        // the offset of the socket section
+        let x = shoff + 64 + 24;
+        obj[x..x + 8].copy_from_slice(&u64::max_value().to_le_bytes());
+        assert!(EbpfObject::parse(&obj).is_err());
+
+        assert!(read_u64(&[0; 8], usize::max_value() - 3).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs bpf(2), see kernel.unprivileged_bpf_disabled"]
+    fn array_map() {
+        let map = EbpfMap::new(EbpfMapType::Array, 4, 8, 4).unwrap();
+        assert_eq!(map.get::<u32, u64>(&1).unwrap(), Some(0));
+        map.set(&1u32, &7u64).unwrap();
+        assert_eq!(map.get::<u32, u64>(&1).unwrap(), Some(7));
+        assert_eq!(map.get::<u32, u64>(&4).unwrap(), None);
+        assert!(map.get::<u32, u32>(&1).is_err());
+        assert_eq!(map.keys().unwrap().len(), 4);
+    }
+}
@@ -18,21 +18,17 @@ pub enum Error {
     #[fail(display = "system call would block")]
     Again(#[cause] io::Error),
 
-    #[fail(display = "error getting address {} info: {}", addr, explanation)]
-    AddrError {
-        addr: String,
-        explanation: String
-    },
+    #[fail(display = "cannot resolve the address")]
+    AddrError(#[cause] AddrError),
 
     #[fail(display = "provided buffer of length {} is too small", len)]
     BufferTooSmall {
         len: usize
     },
 
-    #[fail(display = "cannot get the network interface info")]
-    GetInterfaceError {
-        name: String,
-        #[cause] cause: ::interfaces::InterfacesError
+    #[fail(display = "the eBPF verifier rejected the program:\n{}", log)]
+    EbpfVerifierError {
+        log: String
     },
 
     #[fail(display = "interface name \"{}\" is too long", _0)]
@@ -40,23 +36,99 @@ pub enum Error {
         if_name: String
     },
 
+    #[fail(display = "invalid BPF program at instruction {}: {}", pc, reason)]
+    InvalidBpfProgram {
+        pc: usize,
+        reason: String
+    },
+
+    #[fail(display = "invalid eBPF object: {}", reason)]
+    InvalidEbpfObject {
+        reason: String
+    },
+
+    #[fail(display = "a buffer pool holds 1 to {} slots, not {}", max, slots)]
+    InvalidPoolSize {
+        slots: usize,
+        max: usize
+    },
+
+    #[fail(display = "invalid packet ring configuration: {}", reason)]
+    InvalidRingConfig {
+        reason: &'static str
+    },
+
     #[fail(display = "system call was interrupted")]
     Interrupted(#[cause] io::Error),
 
     #[fail(display = "io error")]
     IoError(#[cause] io::Error),
 
+    #[fail(display = "malformed netlink message")]
+    MalformedNetlinkMessage,
+
     #[fail(display = "no \"{}\" network interface", name)]
     NoInterface {
         name: String
     },
 
+    #[fail(display = "no source address to reach {}", dst)]
+    NoSourceAddress {
+        dst: String
+    },
+
     #[fail(display = "io error ocurred on a socket")]
     SocketError(#[cause] ::failure::Compat<::failure::Error>),
 
+    #[fail(display = "unexpected socket option value {}", value)]
+    UnexpectedSockOptValue {
+        value: i32
+    },
+
     #[fail(display = "io error ocurred in tokio")]
     TokioError(#[cause] io::Error),
 
+    #[fail(display = "this kind of ancillary data cannot be sent")]
+    UnsendableCmsg,
+
+    #[fail(display = "interface \"{}\" has unsupported link type {}",
+        if_name, link_type)]
+    UnsupportedLinkType {
+        if_name: String,
+        link_type: u16
+    },
+
     #[fail(display = "wrong buffer length")]
     WrongSize
 }
+
+#[derive(Debug, Fail)]
+pub enum AddrError {
+    #[fail(display = "\"{}\" is not a numeric IPv6 address", addr)]
+    NotNumeric {
+        addr: String
+    },
+
+    #[fail(display = "no IPv6 address for \"{}\"", addr)]
+    NoAddress {
+        addr: String
+    },
+
+    #[fail(display = "temporary failure resolving \"{}\"", addr)]
+    TemporaryFailure {
+        addr: String
+    },
+
+    #[fail(display = "no \"{}\" interface for the zone of {}", zone, addr)]
+    UnknownZone {
+        addr: String,
+        zone: String
+    },
+
+    #[fail(display = "error resolving \"{}\": {}", addr, explanation)]
+    Other {
+        addr: String,
+        code: i32,
+        explanation: String
+    }
+}
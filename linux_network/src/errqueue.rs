@@ -0,0 +1,351 @@
+// The socket error queue of ipv6(7) IPV6_RECVERR: the ICMPv6 errors caused
+// by the packets sent, and the local errors like a send over the known
+// path MTU.  The path MTU of a destination is learned from either,
+// or from the IPV6_PATHMTU ancillary data.
+
+use ::std::collections::HashMap;
+use ::std::net::*;
+use ::std::time::*;
+
+use ::nlibc::EMSGSIZE;
+
+use ::*;
+use ::errors::Result;
+use ::raw::{
+    sock_extended_err, IPV6_MIN_MTU, SO_EE_ORIGIN_ICMP, SO_EE_ORIGIN_ICMP6,
+    SO_EE_ORIGIN_LOCAL, SO_EE_ORIGIN_NONE, SO_EE_ORIGIN_TIMESTAMPING
+};
+
+// RFC 8201 section 4: an increase may be probed for after 10 minutes
+pub const PATH_MTU_LIFETIME: Duration = Duration::from_secs(600);
+
+const ICMP6_DST_UNREACH: u8 = 1;
+const ICMP6_PACKET_TOO_BIG: u8 = 2;
+const ICMP6_TIME_EXCEEDED: u8 = 3;
+const ICMP6_PARAM_PROB: u8 = 4;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrOrigin {
+    None,
+    Local,
+    Icmp,
+    Icmp6,
+    // not an error, see TxTimestamp
+    Timestamping,
+    Other(u8)
+}
+
+impl From<u8> for ErrOrigin {
+    fn from(x: u8) -> Self {
+        match x {
+            SO_EE_ORIGIN_NONE => ErrOrigin::None,
+            SO_EE_ORIGIN_LOCAL => ErrOrigin::Local,
+            SO_EE_ORIGIN_ICMP => ErrOrigin::Icmp,
+            SO_EE_ORIGIN_ICMP6 => ErrOrigin::Icmp6,
+            SO_EE_ORIGIN_TIMESTAMPING => ErrOrigin::Timestamping,
+            x => ErrOrigin::Other(x)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtendedErr {
+    pub errno: i32,
+    pub origin: ErrOrigin,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    // the MTU for EMSGSIZE, the pointer of the parameter problems
+    pub info: u32,
+    pub data: u32,
+    // the node that sent the ICMPv6 error
+    pub offender: Option<SocketAddrV6>
+}
+
+impl ExtendedErr {
+    #[allow(clippy::cast_possible_wrap)]
+    pub(crate) fn new(raw: &sock_extended_err, offender: Option<SocketAddrV6>)
+            -> Self {
+        ExtendedErr {
+            errno: raw.ee_errno as i32,
+            origin: raw.ee_origin.into(),
+            icmp_type: raw.ee_type,
+            icmp_code: raw.ee_code,
+            info: raw.ee_info,
+            data: raw.ee_data,
+            offender
+        }
+    }
+}
+
+// dst is the destination of the packet that caused the error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorEvent {
+    // an ICMPv6 Packet Too Big, or a send over the known path MTU
+    PacketTooBig {
+        dst: SocketAddrV6,
+        mtu: u32,
+        offender: Option<SocketAddrV6>
+    },
+    DestUnreachable {
+        dst: SocketAddrV6,
+        code: u8,
+        offender: Option<SocketAddrV6>
+    },
+    TimeExceeded {
+        dst: SocketAddrV6,
+        code: u8,
+        offender: Option<SocketAddrV6>
+    },
+    ParamProblem {
+        dst: SocketAddrV6,
+        code: u8,
+        pointer: u32,
+        offender: Option<SocketAddrV6>
+    },
+    Other {
+        dst: SocketAddrV6,
+        err: ExtendedErr
+    }
+}
+
+impl ErrorEvent {
+    // from the ancillary data of a recvmsg() with RecvFlags::ErrQueue,
+    // None if it has no extended error or is a TxTimestamp
+    pub fn from_cmsgs(dst: SocketAddrV6, cmsgs: &[Cmsg]) -> Option<Self> {
+        cmsgs.iter().filter_map(|x| match *x {
+            Cmsg::ExtendedErr(ref err)
+                if err.origin != ErrOrigin::Timestamping =>
+                    Some(Self::new(dst, err)),
+            _ => None
+        }).next()
+    }
+
+    fn new(dst: SocketAddrV6, err: &ExtendedErr) -> Self {
+        let offender = err.offender;
+        match (err.origin, err.icmp_type) {
+            (ErrOrigin::Icmp6, ICMP6_PACKET_TOO_BIG) =>
+                ErrorEvent::PacketTooBig { dst, mtu: err.info, offender },
+            (ErrOrigin::Local, _) if err.errno == EMSGSIZE =>
+                ErrorEvent::PacketTooBig { dst, mtu: err.info, offender },
+            (ErrOrigin::Icmp6, ICMP6_DST_UNREACH) =>
+                ErrorEvent::DestUnreachable {
+                    dst,
+                    code: err.icmp_code,
+                    offender
+                },
+            (ErrOrigin::Icmp6, ICMP6_TIME_EXCEEDED) =>
+                ErrorEvent::TimeExceeded {
+                    dst,
+                    code: err.icmp_code,
+                    offender
+                },
+            (ErrOrigin::Icmp6, ICMP6_PARAM_PROB) =>
+                ErrorEvent::ParamProblem {
+                    dst,
+                    code: err.icmp_code,
+                    pointer: err.info,
+                    offender
+                },
+            _ => ErrorEvent::Other { dst, err: *err }
+        }
+    }
+
+    pub fn dst(&self) -> SocketAddrV6 {
+        match *self {
+            ErrorEvent::PacketTooBig { dst, .. }
+                | ErrorEvent::DestUnreachable { dst, .. }
+                | ErrorEvent::TimeExceeded { dst, .. }
+                | ErrorEvent::ParamProblem { dst, .. }
+                | ErrorEvent::Other { dst, .. } => dst
+        }
+    }
+}
+
+// The ICMPv6 errors and the transmit timestamps share the error queue.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrQueueEntry {
+    Error(ErrorEvent),
+    TxTimestamp(TxTimestamp)
+}
+
+impl ErrQueueEntry {
+    // from the ancillary data of a recvmsg() with RecvFlags::ErrQueue,
+    // None if it is neither
+    pub fn from_cmsgs(dst: SocketAddrV6, cmsgs: &[Cmsg]) -> Option<Self> {
+        TxTimestamp::from_cmsgs(cmsgs)
+            .map(ErrQueueEntry::TxTimestamp)
+            .or_else(|| {
+                ErrorEvent::from_cmsgs(dst, cmsgs).map(ErrQueueEntry::Error)
+            })
+    }
+}
+
+impl<P> RawSocket<Inet6, P> where P: Protocol {
+    // IPV6_RECVERR and IPV6_RECVPATHMTU, the sockets need V6MtuDiscover
+    // set to Do for the path MTU to be reported
+    pub fn enable_error_queue(&mut self) -> Result<()> {
+        self.setsockopt(&SockOpts::RecvErr::new(&true))?;
+        self.setsockopt(&SockOpts::RecvPathMtu::new(&true))
+    }
+
+    // the oldest queued error or transmit timestamp, None if the queue
+    // is empty; reading the error queue never blocks
+    pub fn recv_errqueue(&mut self) -> Result<Option<ErrQueueEntry>> {
+        while let Some((dst, cmsgs)) = self.recvmsg_errqueue()? {
+            if let Some(x) = ErrQueueEntry::from_cmsgs(dst, &cmsgs) {
+                return Ok(Some(x));
+            }
+        }
+        Ok(None)
+    }
+
+    // the transmit timestamps queued along are dropped
+    pub fn recv_error(&mut self) -> Result<Option<ErrorEvent>> {
+        while let Some(x) = self.recv_errqueue()? {
+            if let ErrQueueEntry::Error(x) = x {
+                return Ok(Some(x));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The path MTUs learned per destination, forgotten after
+// PATH_MTU_LIFETIME so that the senders try the default again.
+pub struct PathMtuCache {
+    default_mtu: u32,
+    entries: HashMap<Ipv6Addr, (u32, Instant)>
+}
+
+impl PathMtuCache {
+    // default_mtu is for the destinations nothing is known about,
+    // e.g. the MTU of the outgoing interface
+    pub fn new(default_mtu: u32) -> Self {
+        PathMtuCache {
+            default_mtu,
+            entries: HashMap::new()
+        }
+    }
+
+    pub fn get(&mut self, dst: &Ipv6Addr) -> u32 {
+        let expired = match self.entries.get(dst) {
+            Some(&(mtu, learned)) if learned.elapsed() < PATH_MTU_LIFETIME =>
+                return mtu,
+            Some(_) => true,
+            None => false
+        };
+        if expired {
+            self.entries.remove(dst);
+        }
+        self.default_mtu
+    }
+
+    // the MTUs below the IPv6 minimum are raised to it, RFC 8201 section 4
+    pub fn set(&mut self, dst: Ipv6Addr, mtu: u32) {
+        let mtu = mtu.max(u32::from(IPV6_MIN_MTU)).min(self.default_mtu);
+        self.entries.insert(dst, (mtu, Instant::now()));
+    }
+
+    // true if the event changed the path MTU of its destination
+    pub fn update(&mut self, event: &ErrorEvent) -> bool {
+        match *event {
+            ErrorEvent::PacketTooBig { dst, mtu, .. } if mtu != 0 =>
+                self.learn(*dst.ip(), mtu),
+            _ => false
+        }
+    }
+
+    // the IPV6_PATHMTU data received along with the datagrams
+    pub fn update_from_cmsgs(&mut self, cmsgs: &[Cmsg]) -> bool {
+        let mut changed = false;
+        for i in cmsgs {
+            if let Cmsg::PathMtu { dst, mtu } = *i {
+                changed |= self.learn(*dst.ip(), mtu);
+            }
+        }
+        changed
+    }
+
+    fn learn(&mut self, dst: Ipv6Addr, mtu: u32) -> bool {
+        let prev = self.get(&dst);
+        self.set(dst, mtu);
+        self.get(&dst) != prev
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_err(origin: ErrOrigin, icmp_type: u8, info: u32) -> ExtendedErr {
+        ExtendedErr {
+            errno: EMSGSIZE,
+            origin,
+            icmp_type,
+            icmp_code: 0,
+            info,
+            data: 0,
+            offender: None
+        }
+    }
+
+    #[test]
+    fn packet_too_big_updates_cache() {
+        let dst = SocketAddrV6::new("2001:db8::1".parse().unwrap(), 0, 0, 0);
+        let cmsgs = vec![
+            Cmsg::HopLimit(64),
+            Cmsg::ExtendedErr(make_err(
+                ErrOrigin::Icmp6,
+                ICMP6_PACKET_TOO_BIG,
+                1400
+            ))
+        ];
+        let event = ErrorEvent::from_cmsgs(dst, &cmsgs).unwrap();
+        assert_eq!(
+            event,
+            ErrorEvent::PacketTooBig { dst, mtu: 1400, offender: None }
+        );
+
+        let mut cache = PathMtuCache::new(1500);
+        assert_eq!(cache.get(dst.ip()), 1500);
+        assert!(cache.update(&event));
+        assert!(!cache.update(&event));
+        assert_eq!(cache.get(dst.ip()), 1400);
+
+        cache.set(*dst.ip(), 1000);
+        assert_eq!(cache.get(dst.ip()), u32::from(IPV6_MIN_MTU));
+    }
+
+    #[test]
+    fn errqueue_entry_from_cmsgs() {
+        let dst = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        let stamps = Timestamps {
+            software: Some(UNIX_EPOCH),
+            hardware: None
+        };
+        let cmsgs = vec![
+            Cmsg::Timestamping(stamps),
+            Cmsg::ExtendedErr(make_err(ErrOrigin::Timestamping, 0, 0))
+        ];
+        match ErrQueueEntry::from_cmsgs(dst, &cmsgs) {
+            Some(ErrQueueEntry::TxTimestamp(x)) => assert_eq!(x.stamps, stamps),
+            x => panic!("{:?}", x)
+        }
+
+        let cmsgs = vec![Cmsg::ExtendedErr(
+            make_err(ErrOrigin::Icmp6, ICMP6_TIME_EXCEEDED, 0)
+        )];
+        match ErrQueueEntry::from_cmsgs(dst, &cmsgs) {
+            Some(ErrQueueEntry::Error(x)) => assert_eq!(x.dst(), dst),
+            x => panic!("{:?}", x)
+        }
+
+        assert_eq!(ErrQueueEntry::from_cmsgs(dst, &[Cmsg::HopLimit(1)]), None);
+    }
+
+    #[test]
+    fn no_extended_err() {
+        let dst = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        assert_eq!(ErrorEvent::from_cmsgs(dst, &[Cmsg::HopLimit(1)]), None);
+    }
+}
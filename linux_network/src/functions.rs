@@ -1,10 +1,8 @@
-use ::std::ffi::*;
 use ::std::mem::*;
 use ::std::ptr::*;
 use ::std::os::unix::prelude::*;
 
 use ::nlibc::*;
-use ::nix::sys::socket::SockType;
 
 use ::*;
 use ::raw::*;
@@ -27,6 +25,8 @@ pub mod raw {
     ioctl!(set_interface_flags; SIOCSIFFLAGS; ifreq);
     ioctl!(get_interface_index; SIOCGIFINDEX; ifreq);
     ioctl!(get_interface_mtu; SIOCGIFMTU; ifreq);
+    ioctl!(get_interface_hwaddr; SIOCGIFHWADDR; ifreq);
+    ioctl!(set_hw_timestamping; SIOCSHWTSTAMP; ifreq);
 }
 
 pub fn get_securebits() -> Result<SecBits> { unsafe {
@@ -131,51 +131,57 @@ pub fn get_interface_mtu<F,T>(fd: &F, ifname: T) -> Result<c_int> where
     Ok(ifr.un.ifr_mtu)
 }}
 
-#[allow(clippy::transmute_ptr_to_ptr, clippy::cast_sign_loss)]
-#[allow(clippy::cast_possible_truncation)]
-pub fn make_sockaddr_in6_v6_dgram<T>(
-    addr_str: T,
-    socktype: Option<SockType>,
-    proto: c_int,
-    port: in_port_t,
-    flags: AddrInfoFlags
-) -> Result<sockaddr_in6> where T: AsRef<str> { unsafe {
-    let mut ai: addrinfo = zeroed();
-    ai.ai_family = AF_INET6;
-    ai.ai_socktype = socktype.map_or(0, |x| x as c_int);
-    ai.ai_protocol = proto;
-    ai.ai_flags = flags.bits();
-
-    let mut res: *mut addrinfo = null_mut();
-
-    let err = getaddrinfo(
-        CString::new(addr_str.as_ref())?.as_ptr(),
-        null(),
-        &ai,
-        &mut res
-    );
-    if err != 0 {
-        match err {
-            EAI_SYSTEM => return Err(::std::io::Error::last_os_error().into()),
-            _ => return Err(Error::AddrError {
-                    addr: addr_str.as_ref().to_string(),
-                    explanation: CStr::from_ptr(gai_strerror(err))
-                        .to_string_lossy()
-                        .into_owned()
-                }.into())
-        }
-    }
-
-    assert_eq!(((*res).ai_addrlen) as usize, size_of::<sockaddr_in6>());
-    let mut sa = ::std::ptr::read(
-        transmute::<*mut sockaddr, *mut sockaddr_in6>((*res).ai_addr)
-    );
-    assert_eq!(sa.sin6_family, AF_INET6 as sa_family_t);
-    sa.sin6_port = port;
+// needs CAP_NET_ADMIN, fails with EOPNOTSUPP or ERANGE when the driver
+// cannot timestamp, see TimestampingFlags::RawHardware for the reporting
+pub fn set_hw_timestamping<F,T>(
+    fd: &F,
+    ifname: T,
+    tx_type: HwTxType,
+    rx_filter: HwRxFilter
+) -> Result<()> where
+        F: AsRawFd + ?Sized,
+        T: AsRef<str> { unsafe {
+    let mut config = hwtstamp_config {
+        flags: 0,
+        tx_type: tx_type.repr(),
+        rx_filter: rx_filter.repr()
+    };
+    let mut ifr = ifreq_with_ifname(ifname)?;
+    ifr.un.ifr_data = &mut config as *mut hwtstamp_config as *mut c_char;
+    self::raw::set_hw_timestamping(fd, &mut ifr)?;
+    Ok(())
+}}
 
-    freeaddrinfo(res);
+// Ok(None) for the links without link-layer addresses (tun, tunnels, etc.),
+// decided by the link type alone: an ethernet link with arp turned off
+// still has its address
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn get_interface_hwaddr<F,T>(fd: &F, ifname: T)
+        -> Result<Option<HwAddr>> where
+        F: AsRawFd + ?Sized,
+        T: AsRef<str> { unsafe {
+    let name = ifname.as_ref();
+    let mut ifr = ifreq_with_ifname(name)?;
+    self::raw::get_interface_hwaddr(fd, &mut ifr)?;
+    let hwaddr = &ifr.un.ifr_hwaddr;
+
+    let len = match hwaddr.sa_family {
+        ARPHRD_ETHER | ARPHRD_EETHER | ARPHRD_IEEE802 | ARPHRD_FDDI
+            | ARPHRD_IEEE80211 => 6,
+        ARPHRD_EUI64 | ARPHRD_IEEE802154 => 8,
+        ARPHRD_NONE | ARPHRD_VOID | ARPHRD_LOOPBACK | ARPHRD_PPP
+            | ARPHRD_TUNNEL | ARPHRD_TUNNEL6 | ARPHRD_SIT | ARPHRD_IPGRE
+            | ARPHRD_IP6GRE | ARPHRD_RAWIP => return Ok(None),
+        link_type => return Err(Error::UnsupportedLinkType {
+            if_name: name.to_string(),
+            link_type
+        }.into())
+    };
 
-    Ok(sa)
+    let bytes = hwaddr.sa_data[.. len].iter()
+        .map(|&x| x as u8)
+        .collect::<Vec<u8>>();
+    Ok(Some(HwAddr::from_bytes(bytes)?))
 }}
 
 fn get_fd_flags<F>(fd: &F)
@@ -199,6 +205,12 @@ pub fn get_fd_nonblock<F>(fd: &F) -> Result<bool> where F: AsRawFd + ?Sized {
     Ok(get_fd_flags(fd)?.contains(FileOpenFlags::Nonblock))
 }
 
+// the new descriptor shares the open file description,
+// so the flags set with set_fd_nonblock() apply to both
+pub fn dup_fd<F>(fd: &F) -> Result<RawFd> where F: AsRawFd + ?Sized { unsafe {
+    Ok(n1try!(fcntl(fd.as_raw_fd(), F_DUPFD_CLOEXEC, 0)))
+}}
+
 gen_boolean_enum!(pub Nonblock);
 
 pub fn set_fd_nonblock<F>(fd: &F, nonblock: Nonblock)
@@ -0,0 +1,285 @@
+// The network interfaces with their IPv6 addresses, read from a single
+// rtnetlink(7) dump of the links and the addresses.
+
+use ::std::net::*;
+
+use ::nlibc::{
+    IFF_BROADCAST, IFF_LOOPBACK, IFF_MULTICAST, IFF_NOARP, IFF_POINTOPOINT,
+    IFF_PROMISC, IFF_RUNNING, IFF_UP
+};
+
+use ::*;
+use ::errors::{Error, Result};
+use ::rtnetlink::*;
+use ::util::rfc6724_scope;
+use self::raw::*;
+
+pub mod raw {
+    pub const IFF_LOWER_UP: u32 = 0x10000;
+
+    pub const IFA_F_TEMPORARY: u32 = 0x01;
+    pub const IFA_F_NODAD: u32 = 0x02;
+    pub const IFA_F_OPTIMISTIC: u32 = 0x04;
+    pub const IFA_F_DADFAILED: u32 = 0x08;
+    pub const IFA_F_HOMEADDRESS: u32 = 0x10;
+    pub const IFA_F_DEPRECATED: u32 = 0x20;
+    pub const IFA_F_TENTATIVE: u32 = 0x40;
+    pub const IFA_F_PERMANENT: u32 = 0x80;
+    pub const IFA_F_MANAGETEMPADDR: u32 = 0x100;
+    pub const IFA_F_NOPREFIXROUTE: u32 = 0x200;
+
+    pub const RT_SCOPE_UNIVERSE: u8 = 0;
+    pub const RT_SCOPE_SITE: u8 = 200;
+    pub const RT_SCOPE_LINK: u8 = 253;
+    pub const RT_SCOPE_HOST: u8 = 254;
+}
+
+bitflags!(
+    pub struct InterfaceFlags: u32 {
+        const Up = IFF_UP as u32;
+        const Broadcast = IFF_BROADCAST as u32;
+        const Loopback = IFF_LOOPBACK as u32;
+        const PointToPoint = IFF_POINTOPOINT as u32;
+        const Running = IFF_RUNNING as u32;
+        const NoArp = IFF_NOARP as u32;
+        const Promisc = IFF_PROMISC as u32;
+        const Multicast = IFF_MULTICAST as u32;
+        const LowerUp = IFF_LOWER_UP;
+    }
+);
+
+bitflags!(
+    pub struct AddrFlags: u32 {
+        const Temporary = IFA_F_TEMPORARY;
+        const NoDad = IFA_F_NODAD;
+        const Optimistic = IFA_F_OPTIMISTIC;
+        const DadFailed = IFA_F_DADFAILED;
+        const HomeAddress = IFA_F_HOMEADDRESS;
+        const Deprecated = IFA_F_DEPRECATED;
+        const Tentative = IFA_F_TENTATIVE;
+        const Permanent = IFA_F_PERMANENT;
+        const ManageTempAddr = IFA_F_MANAGETEMPADDR;
+        const NoPrefixRoute = IFA_F_NOPREFIXROUTE;
+    }
+);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddrScope {
+    Global,
+    Site,
+    Link,
+    Host,
+    Other(u8)
+}
+
+impl From<u8> for AddrScope {
+    fn from(x: u8) -> Self {
+        match x {
+            RT_SCOPE_UNIVERSE => AddrScope::Global,
+            RT_SCOPE_SITE => AddrScope::Site,
+            RT_SCOPE_LINK => AddrScope::Link,
+            RT_SCOPE_HOST => AddrScope::Host,
+            x => AddrScope::Other(x)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterfaceAddr {
+    pub addr: Ipv6Addr,
+    pub prefix_len: u8,
+    pub scope: AddrScope,
+    pub flags: AddrFlags
+}
+
+impl InterfaceAddr {
+    // the tentative addresses cannot be bound until DAD completes
+    pub fn is_usable(&self) -> bool {
+        !self.flags.intersects(AddrFlags::Tentative | AddrFlags::DadFailed)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    pub flags: InterfaceFlags,
+    pub mtu: Option<u32>,
+    pub hwaddr: Option<HwAddr>,
+    pub addrs: Vec<InterfaceAddr>
+}
+
+impl Interface {
+    pub fn list() -> Result<Vec<Self>> {
+        let mut sock = RtNetlinkSocket::new()?;
+        let addrs = sock.addresses()?;
+
+        Ok(sock.links()?.into_iter().map(|link| {
+            let index = link.index;
+            Self {
+                index,
+                name: link.name,
+                flags: InterfaceFlags::from_bits_truncate(link.flags),
+                mtu: link.mtu,
+                hwaddr: link.hwaddr,
+                addrs: addrs.iter()
+                    .filter(|x| x.index == index)
+                    .filter_map(|x| match x.addr {
+                        IpAddr::V6(addr) => Some(InterfaceAddr {
+                            addr,
+                            prefix_len: x.prefix_len,
+                            scope: x.scope.into(),
+                            flags: AddrFlags::from_bits_truncate(x.flags)
+                        }),
+                        IpAddr::V4(_) => None
+                    })
+                    .collect()
+            }
+        }).collect())
+    }
+
+    pub fn by_index(index: u32) -> Result<Self> {
+        Self::find(|x| x.index == index, || format!("#{}", index))
+    }
+
+    pub fn by_name<T>(name: T) -> Result<Self> where T: AsRef<str> {
+        let name = name.as_ref();
+        Self::find(|x| x.name == name, || name.to_string())
+    }
+
+    // the interface the address is assigned to
+    pub fn by_address(addr: &Ipv6Addr) -> Result<Self> {
+        Self::find(
+            |x| x.addrs.iter().any(|a| a.addr == *addr),
+            || addr.to_string()
+        )
+    }
+
+    fn find<P, N>(pred: P, name: N) -> Result<Self> where
+            P: Fn(&Self) -> bool,
+            N: FnOnce() -> String {
+        Self::list()?.into_iter()
+            .find(pred)
+            .ok_or_else(|| Error::NoInterface { name: name() }.into())
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.flags.contains(InterfaceFlags::Up)
+    }
+
+    // Of the usable addresses with the scope of dst, prefers the ones
+    // not deprecated, then the longest prefix shared with dst.
+    pub fn source_address(&self, dst: &Ipv6Addr) -> Option<Ipv6Addr> {
+        let scope = ipv6_scope(dst);
+        self.addrs.iter()
+            .filter(|x| x.is_usable() && ipv6_scope(&x.addr) == scope)
+            .max_by_key(|x| (
+                !x.flags.contains(AddrFlags::Deprecated),
+                common_prefix_len(&x.addr, dst)
+            ))
+            .map(|x| x.addr)
+    }
+}
+
+// The source the kernel would most likely pick for dst: on the interface
+// given, or else the one of the scope id, or else the one routing to dst.
+// The link-local sources carry the scope id.
+pub fn select_source_address(dst: &SocketAddrV6, index: Option<u32>)
+        -> Result<SocketAddrV6> {
+    let index = match index {
+        Some(x) => x,
+        None if dst.scope_id() != 0 => dst.scope_id(),
+        None => RtNetlinkSocket::new()?
+            .route_to(IpAddr::V6(*dst.ip()), None)?
+            .oif
+            .ok_or_else(|| Error::NoSourceAddress {
+                dst: dst.ip().to_string()
+            })?
+    };
+
+    let addr = Interface::by_index(index)?
+        .source_address(dst.ip())
+        .ok_or_else(|| Error::NoSourceAddress {
+            dst: dst.ip().to_string()
+        })?;
+    let scope_id = match ipv6_scope(&addr) {
+        AddrScope::Link => index,
+        _ => 0
+    };
+    Ok(SocketAddrV6::new(addr, 0, 0, scope_id))
+}
+
+// the kernel gives the loopback address the host scope
+fn ipv6_scope(addr: &Ipv6Addr) -> AddrScope {
+    if addr.is_loopback() {
+        return AddrScope::Host;
+    }
+    match rfc6724_scope(addr) {
+        1 => AddrScope::Host,
+        2 => AddrScope::Link,
+        5 => AddrScope::Site,
+        _ => AddrScope::Global
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn common_prefix_len(a: &Ipv6Addr, b: &Ipv6Addr) -> u8 {
+    let x = u128::from(*a) ^ u128::from(*b);
+    x.leading_zeros() as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_addr(addr: &str, flags: AddrFlags) -> InterfaceAddr {
+        InterfaceAddr {
+            addr: addr.parse().unwrap(),
+            prefix_len: 64,
+            scope: AddrScope::Global,
+            flags
+        }
+    }
+
+    #[test]
+    fn loopback_interface() {
+        let lo = Interface::by_name("lo").unwrap();
+        assert!(lo.flags.contains(InterfaceFlags::Loopback));
+        assert_eq!(Interface::by_index(lo.index).unwrap().name, "lo");
+        assert!(Interface::by_name("no-such-interface0").is_err());
+    }
+
+    #[test]
+    fn source_address_preference() {
+        let ifc = Interface {
+            index: 2,
+            name: "eth0".to_string(),
+            flags: InterfaceFlags::Up,
+            mtu: Some(1500),
+            hwaddr: None,
+            addrs: vec![
+                make_addr("fe80::1", AddrFlags::Permanent),
+                make_addr("2001:db8:1::1", AddrFlags::Tentative),
+                make_addr("2001:db8:1::2", AddrFlags::Deprecated),
+                make_addr("2001:db8:2::1", AddrFlags::Permanent)
+            ]
+        };
+
+        let src = |x: &str| ifc.source_address(&x.parse().unwrap())
+            .map(|x| x.to_string());
+        assert_eq!(src("fe80::42"), Some("fe80::1".to_string()));
+        assert_eq!(src("2001:db8:1::42"), Some("2001:db8:2::1".to_string()));
+        assert_eq!(src("::1"), None);
+    }
+
+    #[test]
+    fn scopes() {
+        let scope = |x: &str| ipv6_scope(&x.parse().unwrap());
+        assert_eq!(scope("::1"), AddrScope::Host);
+        assert_eq!(scope("fe80::1"), AddrScope::Link);
+        assert_eq!(scope("ff02::1"), AddrScope::Link);
+        assert_eq!(scope("fec0::1"), AddrScope::Site);
+        assert_eq!(scope("2001:db8::1"), AddrScope::Global);
+        assert_eq!(scope("ff0e::1"), AddrScope::Global);
+    }
+}
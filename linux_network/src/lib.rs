@@ -7,45 +7,75 @@
 #[cfg(feature = "async")] extern crate bytes;
 #[cfg(feature = "async")] #[macro_use] extern crate enum_kinds;
 #[macro_use] extern crate failure;
-extern crate interfaces;
+#[cfg(feature = "std-future")] extern crate futures_core;
+#[cfg(feature = "std-future")] extern crate futures_sink;
 #[macro_use] extern crate log;
 #[cfg(feature = "async")] extern crate mio;
 extern crate nix;
 #[cfg(feature = "async")] extern crate owning_ref;
 extern crate pnet_packet;
 #[cfg(feature = "seccomp")] extern crate seccomp;
-#[cfg(feature = "async")] extern crate spin;
 #[cfg(feature = "async")] extern crate tokio;
+#[cfg(feature = "std-future")] extern crate tokio1;
 
 #[macro_use] extern crate boolean_enums;
 #[macro_use] extern crate enum_repr;
 
 #[macro_use] mod util;
-#[macro_use] pub mod bpf;
+pub mod bpf;
+pub mod buffer_pool;
+pub mod cmsg;
+pub mod ebpf;
 pub mod errors;
+pub mod errqueue;
 pub mod constants;
 pub mod functions;
+pub mod interface;
+pub mod resolver;
+pub mod ring;
+pub mod rtnetlink;
+pub mod sandbox;
+#[cfg(feature = "seccomp")] pub mod seccomp_policy;
 pub mod socket;
+#[cfg(feature = "std-future")] pub mod std_future;
 pub mod structs;
+#[cfg(any(test, feature = "test-util"))] pub mod test_util;
+pub mod timestamping;
 
 pub mod raw {
     pub use constants::raw::*;
+    pub use ebpf::raw::*;
     pub use functions::raw::*;
+    pub use interface::raw::*;
+    pub use ring::raw::*;
+    pub use rtnetlink::raw::*;
+    pub use sandbox::raw::*;
     pub use structs::raw::*;
 }
 
 #[cfg(feature = "async")]
 pub mod futures {
+    pub use ring::futures::*;
+    pub use rtnetlink::futures::*;
     pub use socket::futures::*;
 }
 
 use nix::libc as nlibc;
 
 pub use self::bpf::*;
+pub use self::buffer_pool::*;
+pub use self::cmsg::*;
 pub use self::constants::*;
+pub use self::ebpf::*;
+pub use self::errqueue::*;
 pub use self::functions::*;
+pub use self::interface::*;
+pub use self::resolver::*;
+pub use self::sandbox::*;
+#[cfg(feature = "seccomp")] pub use self::seccomp_policy::*;
 pub use self::socket::*;
 pub use self::structs::*;
+pub use self::timestamping::*;
 use self::util::check_for_eagain;
 
 #[cfg(feature = "async")]
@@ -0,0 +1,295 @@
+// The IPv6 addresses of a host from getaddrinfo(3), all the candidates
+// in the order of RFC 6724 section 6 as far as it goes without knowing
+// the source addresses.  The zone of "fe80::1%eth0" is taken off first
+// and becomes the scope id of the link-local results.
+
+use ::std::ffi::{CStr, CString};
+use ::std::mem::{size_of, zeroed};
+use ::std::net::*;
+use ::std::ptr::{null, null_mut};
+
+use ::nlibc::{
+    addrinfo, c_int, freeaddrinfo, gai_strerror, getaddrinfo, if_nametoindex,
+    sockaddr_in6, AF_INET6, EAI_AGAIN, EAI_FAMILY, EAI_NONAME, EAI_SYSTEM
+};
+use ::nix::sys::socket::SockType;
+
+use ::*;
+use ::errors::{AddrError, Error, Result};
+use ::util::rfc6724_scope;
+
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    numeric_only: bool,
+    port: u16,
+    socktype: Option<SockType>,
+    protocol: c_int,
+    flags: AddrInfoFlags
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            numeric_only: false,
+            port: 0,
+            socktype: None,
+            protocol: 0,
+            flags: AddrInfoFlags::empty()
+        }
+    }
+
+    // the address literals only, no name lookups
+    pub fn numeric_only(&mut self) -> &mut Self {
+        self.numeric_only = true;
+        self
+    }
+
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = port;
+        self
+    }
+
+    pub fn socktype(&mut self, socktype: SockType) -> &mut Self {
+        self.socktype = Some(socktype);
+        self
+    }
+
+    pub fn protocol(&mut self, protocol: c_int) -> &mut Self {
+        self.protocol = protocol;
+        self
+    }
+
+    // e.g. AddrConfig, NumericHost is implied by numeric_only()
+    pub fn flags(&mut self, flags: AddrInfoFlags) -> &mut Self {
+        self.flags |= flags;
+        self
+    }
+
+    // the candidates without duplicates, the preferred first
+    pub fn resolve<T>(&self, addr: T) -> Result<Vec<SocketAddrV6>>
+            where T: AsRef<str> {
+        let addr = addr.as_ref();
+        let (host, zone) = split_zone(addr)?;
+
+        let mut addrs = if self.numeric_only {
+            let ip = host.parse::<Ipv6Addr>().map_err(|_| {
+                Error::AddrError(AddrError::NotNumeric {
+                    addr: addr.to_string()
+                })
+            })?;
+            vec![SocketAddrV6::new(ip, self.port, 0, 0)]
+        } else {
+            self.getaddrinfo(host, addr)?
+        };
+
+        if let Some(scope_id) = zone {
+            for i in &mut addrs {
+                if i.scope_id() == 0 && rfc6724_scope(i.ip()) <= 2 {
+                    i.set_scope_id(scope_id);
+                }
+            }
+        }
+        sort_by_rfc6724(&mut addrs);
+        Ok(addrs)
+    }
+
+    pub fn resolve_one<T>(&self, addr: T) -> Result<SocketAddrV6>
+            where T: AsRef<str> {
+        Ok(self.resolve(addr)?.remove(0))
+    }
+
+    #[allow(clippy::cast_ptr_alignment)]
+    fn getaddrinfo(&self, host: &str, addr: &str)
+            -> Result<Vec<SocketAddrV6>> { unsafe {
+        let mut hints: addrinfo = zeroed();
+        hints.ai_family = AF_INET6;
+        hints.ai_socktype = self.socktype.map_or(0, |x| x as c_int);
+        hints.ai_protocol = self.protocol;
+        hints.ai_flags = self.flags.bits();
+
+        let mut res: *mut addrinfo = null_mut();
+        let err = getaddrinfo(
+            CString::new(host)?.as_ptr(),
+            null(),
+            &hints,
+            &mut res
+        );
+        if err != 0 {
+            let addr = addr.to_string();
+            return Err(match err {
+                EAI_SYSTEM => Error::IoError(::std::io::Error::last_os_error()),
+                EAI_NONAME | EAI_FAMILY =>
+                    Error::AddrError(AddrError::NoAddress { addr }),
+                EAI_AGAIN =>
+                    Error::AddrError(AddrError::TemporaryFailure { addr }),
+                code => Error::AddrError(AddrError::Other {
+                    addr,
+                    code,
+                    explanation: CStr::from_ptr(gai_strerror(code))
+                        .to_string_lossy()
+                        .into_owned()
+                })
+            }.into());
+        }
+
+        let mut addrs: Vec<SocketAddrV6> = Vec::new();
+        let mut ai = res;
+        while !ai.is_null() {
+            if (*ai).ai_family == AF_INET6
+                    && (*ai).ai_addrlen as usize == size_of::<sockaddr_in6>() {
+                let sa = &*((*ai).ai_addr as *const sockaddr_in6);
+                let x = SocketAddrV6::new(
+                    sa.sin6_addr.s6_addr.into(),
+                    self.port,
+                    sa.sin6_flowinfo,
+                    sa.sin6_scope_id
+                );
+                // one per socket type otherwise
+                if !addrs.contains(&x) {
+                    addrs.push(x);
+                }
+            }
+            ai = (*ai).ai_next;
+        }
+        freeaddrinfo(res);
+
+        if addrs.is_empty() {
+            return Err(Error::AddrError(AddrError::NoAddress {
+                addr: addr.to_string()
+            }).into());
+        }
+        Ok(addrs)
+    }}
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the interface name or index after the '%'
+fn split_zone(addr: &str) -> Result<(&str, Option<u32>)> {
+    let mut parts = addr.splitn(2, '%');
+    let host = parts.next().unwrap_or("");
+    let zone = match parts.next() {
+        Some(x) => x,
+        None => return Ok((host, None))
+    };
+
+    match zone_to_scope_id(zone) {
+        Some(x) => Ok((host, Some(x))),
+        None => Err(Error::AddrError(AddrError::UnknownZone {
+            addr: addr.to_string(),
+            zone: zone.to_string()
+        }).into())
+    }
+}
+
+pub fn zone_to_scope_id(zone: &str) -> Option<u32> {
+    if let Ok(x) = zone.parse::<u32>() {
+        return Some(x);
+    }
+    let name = CString::new(zone).ok()?;
+    match unsafe { if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        x => Some(x)
+    }
+}
+
+// Rule 6, the higher precedence of the default policy table first,
+// then rule 8, the smaller scope first; the order of getaddrinfo() is
+// kept otherwise.  The rules needing the source addresses are left out.
+pub fn sort_by_rfc6724(addrs: &mut [SocketAddrV6]) {
+    addrs.sort_by_key(|x| (
+        ::std::cmp::Reverse(rfc6724_precedence(x.ip())),
+        rfc6724_scope(x.ip())
+    ));
+}
+
+// RFC 6724 section 2.1
+fn rfc6724_precedence(addr: &Ipv6Addr) -> u8 {
+    let x = u128::from(*addr);
+    let matches = |prefix: u128, len: u32|
+        x >> (128 - len) == prefix >> (128 - len);
+
+    if *addr == Ipv6Addr::LOCALHOST {
+        50
+    } else if matches(0xffff << 32, 96) {
+        35
+    } else if matches(0x2002 << 112, 16) {
+        30
+    } else if matches(0x2001 << 112, 32) {
+        5
+    } else if matches(0xfc << 120, 7) {
+        3
+    } else if matches(0, 96) || matches(0xfec0 << 112, 10)
+            || matches(0x3ffe << 112, 16) {
+        1
+    } else {
+        40
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr_error(e: ::failure::Error) -> AddrError {
+        match e.downcast::<Error>() {
+            Ok(Error::AddrError(x)) => x,
+            x => panic!("not an address error: {:?}", x)
+        }
+    }
+
+    #[test]
+    fn zones() {
+        let mut resolver = Resolver::new();
+        resolver.numeric_only().port(7);
+
+        let addr = resolver.resolve_one("fe80::1%3").unwrap();
+        assert_eq!(addr.scope_id(), 3);
+        assert_eq!(addr.port(), 7);
+
+        let lo = zone_to_scope_id("lo").unwrap();
+        assert_eq!(resolver.resolve_one("fe80::1%lo").unwrap().scope_id(), lo);
+        let global = resolver.resolve_one("2001:db8::1%lo").unwrap();
+        assert_eq!(global.scope_id(), 0);
+
+        match addr_error(resolver.resolve("fe80::1%nosuchif0").unwrap_err()) {
+            AddrError::UnknownZone { zone, .. } =>
+                assert_eq!(zone, "nosuchif0"),
+            x => panic!("{:?}", x)
+        }
+        match addr_error(resolver.resolve("localhost").unwrap_err()) {
+            AddrError::NotNumeric { .. } => (),
+            x => panic!("{:?}", x)
+        }
+    }
+
+    #[test]
+    fn getaddrinfo_literal() {
+        let addrs = Resolver::new().resolve("::1").unwrap();
+        let lo = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        assert_eq!(addrs, vec![lo]);
+    }
+
+    #[test]
+    fn rfc6724_order() {
+        let mut addrs = [
+            "2001:0:4136:e378::1", "::ffff:192.0.2.1", "2a00::1", "fe80::1",
+            "::1"
+        ].iter()
+            .map(|x| SocketAddrV6::new(x.parse().unwrap(), 0, 0, 0))
+            .collect::<Vec<_>>();
+        sort_by_rfc6724(&mut addrs);
+
+        let order = addrs.iter()
+            .map(|x| x.ip().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![
+            "::1", "fe80::1", "2a00::1", "::ffff:192.0.2.1",
+            "2001:0:4136:e378::1"
+        ]);
+    }
+}
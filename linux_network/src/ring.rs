@@ -0,0 +1,623 @@
+// A memory-mapped PACKET_RX_RING in the TPACKET_V3 block mode for the
+// packet sockets, see Documentation/networking/packet_mmap.txt.
+// The kernel fills a block with frames and hands it over to the user space,
+// which gives it back once every frame in it is processed.
+
+use ::std::io;
+use ::std::mem::*;
+use ::std::os::unix::prelude::*;
+use ::std::ptr;
+use ::std::slice;
+use ::std::sync::atomic::{fence, Ordering};
+use ::std::time::*;
+
+use ::nlibc::{
+    c_int, c_uint, c_void, mmap, munmap, poll, pollfd, setsockopt,
+    sockaddr_ll, socklen_t, EINTR, MAP_FAILED, MAP_SHARED, POLLERR, POLLIN,
+    PROT_READ, PROT_WRITE
+};
+use ::pnet_packet::ipv6::Ipv6Packet;
+
+use ::*;
+use ::errors::{Error, Result};
+use ::util::*;
+use self::raw::*;
+
+pub mod raw {
+    use ::nlibc::{c_int, c_uint};
+
+    pub const SOL_PACKET: c_int = 263;
+    pub const PACKET_RX_RING: c_int = 5;
+    pub const PACKET_STATISTICS: c_int = 6;
+    pub const PACKET_VERSION: c_int = 10;
+    pub const PACKET_TX_TIMESTAMP: c_int = 16;
+
+    pub const TPACKET_V3: c_int = 2;
+    pub const TPACKET_ALIGNMENT: usize = 16;
+
+    pub const TP_STATUS_KERNEL: u32 = 0;
+    pub const TP_STATUS_USER: u32 = 1;
+
+    #[repr(C)]
+    pub struct tpacket_req3 {
+        pub tp_block_size: c_uint,
+        pub tp_block_nr: c_uint,
+        pub tp_frame_size: c_uint,
+        pub tp_frame_nr: c_uint,
+        pub tp_retire_blk_tov: c_uint,
+        pub tp_sizeof_priv: c_uint,
+        pub tp_feature_req_word: c_uint
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct tpacket_bd_ts {
+        pub ts_sec: c_uint,
+        pub ts_nsec: c_uint
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct tpacket_hdr_v1 {
+        pub block_status: u32,
+        pub num_pkts: u32,
+        pub offset_to_first_pkt: u32,
+        pub blk_len: u32,
+        pub seq_num: u64,
+        pub ts_first_pkt: tpacket_bd_ts,
+        pub ts_last_pkt: tpacket_bd_ts
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct tpacket_block_desc {
+        pub version: u32,
+        pub offset_to_priv: u32,
+        pub hdr: tpacket_hdr_v1
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct tpacket_hdr_variant1 {
+        pub tp_rxhash: u32,
+        pub tp_vlan_tci: u32,
+        pub tp_vlan_tpid: u16,
+        pub tp_padding: u16
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct tpacket3_hdr {
+        pub tp_next_offset: u32,
+        pub tp_sec: u32,
+        pub tp_nsec: u32,
+        pub tp_snaplen: u32,
+        pub tp_len: u32,
+        pub tp_status: u32,
+        pub tp_mac: u16,
+        pub tp_net: u16,
+        pub hv1: tpacket_hdr_variant1,
+        pub tp_padding: [u8; 8]
+    }
+
+    #[derive(Clone, Copy, Default)]
+    #[repr(C)]
+    pub struct tpacket_stats_v3 {
+        pub tp_packets: c_uint,
+        pub tp_drops: c_uint,
+        pub tp_freeze_q_cnt: c_uint
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RxRingConfig {
+    // a multiple of the page size
+    pub block_size: u32,
+    pub block_count: u32,
+    // the largest frame with its tpacket3_hdr,
+    // a multiple of TPACKET_ALIGNMENT
+    pub frame_size: u32,
+    // a block is handed over this long after its first frame at the latest
+    pub retire_timeout: Duration
+}
+
+impl RxRingConfig {
+    // the page size of block_size is left to the kernel
+    fn validate(&self) -> Result<()> {
+        let reason = if self.frame_size == 0 {
+            "frame_size is 0"
+        } else if self.frame_size as usize % TPACKET_ALIGNMENT != 0 {
+            "frame_size is not a multiple of TPACKET_ALIGNMENT"
+        } else if self.block_size < self.frame_size {
+            "block_size is smaller than frame_size"
+        } else if self.block_count == 0 {
+            "block_count is 0"
+        } else {
+            return Ok(());
+        };
+        Err(Error::InvalidRingConfig { reason }.into())
+    }
+}
+
+impl Default for RxRingConfig {
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 16,
+            block_count: 64,
+            frame_size: 2048,
+            retire_timeout: Duration::from_millis(10)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RingStats {
+    pub packets: u32,
+    pub drops: u32,
+    pub freezes: u32
+}
+
+pub struct IPv6PacketRing {
+    sock: IPv6PacketSocket,
+    map: *mut u8,
+    block_size: usize,
+    block_count: usize,
+    current: usize
+}
+
+impl IPv6PacketRing {
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(sock: IPv6PacketSocket, config: &RxRingConfig)
+            -> Result<Self> { unsafe {
+        config.validate()?;
+        let fd = sock.as_raw_fd();
+
+        let version = TPACKET_V3;
+        n1try!(setsockopt(
+            fd,
+            SOL_PACKET,
+            PACKET_VERSION,
+            ref_to_cvoid(&version),
+            size_of_val(&version) as socklen_t
+        ));
+
+        let timeout = config.retire_timeout;
+        let req = tpacket_req3 {
+            tp_block_size: config.block_size,
+            tp_block_nr: config.block_count,
+            tp_frame_size: config.frame_size,
+            tp_frame_nr: config.block_size / config.frame_size
+                * config.block_count,
+            tp_retire_blk_tov: (timeout.as_secs() * 1000) as c_uint
+                + timeout.subsec_millis(),
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0
+        };
+        n1try!(setsockopt(
+            fd,
+            SOL_PACKET,
+            PACKET_RX_RING,
+            ref_to_cvoid(&req),
+            size_of_val(&req) as socklen_t
+        ));
+
+        let block_size = config.block_size as usize;
+        let block_count = config.block_count as usize;
+        let map = mmap(
+            ptr::null_mut(),
+            block_size * block_count,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED,
+            fd,
+            0
+        );
+        if map == MAP_FAILED {
+            return Err(Error::IoError(io::Error::last_os_error()).into());
+        }
+
+        Ok(Self {
+            sock,
+            map: map as *mut u8,
+            block_size,
+            block_count,
+            current: 0
+        })
+    }}
+
+    // for sending, or the socket options
+    pub fn get_ref(&self) -> &IPv6PacketSocket {
+        &self.sock
+    }
+
+    pub fn get_mut(&mut self) -> &mut IPv6PacketSocket {
+        &mut self.sock
+    }
+
+    fn block_desc(&self) -> *mut tpacket_block_desc { unsafe {
+        self.map.add(self.current * self.block_size)
+            as *mut tpacket_block_desc
+    }}
+
+    pub fn block_ready(&self) -> bool { unsafe {
+        let desc = self.block_desc();
+        let status = ptr::read_volatile(&(*desc).hdr.block_status);
+        // the frames are read after the status
+        fence(Ordering::Acquire);
+        status & TP_STATUS_USER != 0
+    }}
+
+    // the blocks come in order, the next one is only available
+    // once the returned block is dropped
+    pub fn next_block(&mut self) -> Option<RingBlock> {
+        if self.block_ready() {
+            Some(RingBlock { ring: self })
+        } else {
+            None
+        }
+    }
+
+    // waits for a block in poll(2), indefinitely on None;
+    // false on the timeout
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool> { unsafe {
+        if self.block_ready() {
+            return Ok(true);
+        }
+
+        let mut fds = pollfd {
+            fd: self.as_raw_fd(),
+            events: POLLIN | POLLERR,
+            revents: 0
+        };
+        let timeout_ms = timeout.map_or(-1, |x|
+            (x.as_secs() * 1000) as c_int + x.subsec_millis() as c_int
+        );
+        n1try!(poll(&mut fds, 1, timeout_ms));
+
+        Ok(self.block_ready())
+    }}
+
+    // the kernel resets the counters on every read
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn statistics(&self) -> Result<RingStats> { unsafe {
+        let mut stats = tpacket_stats_v3::default();
+        let mut len = size_of_val(&stats) as socklen_t;
+        n1try!(::nlibc::getsockopt(
+            self.as_raw_fd(),
+            SOL_PACKET,
+            PACKET_STATISTICS,
+            ref_to_mut_cvoid(&mut stats),
+            &mut len
+        ));
+        Ok(RingStats {
+            packets: stats.tp_packets,
+            drops: stats.tp_drops,
+            freezes: stats.tp_freeze_q_cnt
+        })
+    }}
+}
+
+impl Drop for IPv6PacketRing {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(
+                self.map as *mut c_void,
+                self.block_size * self.block_count
+            );
+        }
+    }
+}
+
+impl AsRawFd for IPv6PacketRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sock.as_raw_fd()
+    }
+}
+
+impl SocketCommon for IPv6PacketRing {}
+
+// gives the block back to the kernel when dropped
+pub struct RingBlock<'a> {
+    ring: &'a mut IPv6PacketRing
+}
+
+impl<'a> RingBlock<'a> {
+    fn bytes(&self) -> &[u8] { unsafe {
+        slice::from_raw_parts(
+            self.ring.block_desc() as *const u8,
+            self.ring.block_size
+        )
+    }}
+
+    pub fn len(&self) -> usize { unsafe {
+        (*self.ring.block_desc()).hdr.num_pkts as usize
+    }}
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn frames(&self) -> RingFrames {
+        RingFrames::new(self.bytes())
+    }
+}
+
+impl<'a> Drop for RingBlock<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            // the frames are read before the block is given back
+            fence(Ordering::Release);
+            ptr::write_volatile(
+                &mut (*self.ring.block_desc()).hdr.block_status,
+                TP_STATUS_KERNEL
+            );
+        }
+        self.ring.current = (self.ring.current + 1) % self.ring.block_count;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RingFrame<'a> {
+    // the IPv6 packet, possibly cut short to the frame size
+    pub data: &'a [u8],
+    // the length of the whole packet
+    pub len: usize,
+    pub source: Option<HwAddr>,
+    pub timestamp: SystemTime
+}
+
+impl<'a> RingFrame<'a> {
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.len
+    }
+
+    pub fn packet(&self) -> Option<Ipv6Packet<'a>> {
+        Ipv6Packet::new(self.data)
+    }
+}
+
+pub struct RingFrames<'a> {
+    block: &'a [u8],
+    offset: usize,
+    remaining: u32
+}
+
+impl<'a> RingFrames<'a> {
+    fn new(block: &'a [u8]) -> Self {
+        if block.len() < size_of::<tpacket_block_desc>() {
+            return Self { block, offset: 0, remaining: 0 };
+        }
+        let desc = unsafe { read_data::<tpacket_block_desc>(block) };
+
+        Self {
+            block,
+            offset: desc.hdr.offset_to_first_pkt as usize,
+            remaining: desc.hdr.num_pkts
+        }
+    }
+
+    fn parse_frame(&self) -> Option<(RingFrame<'a>, usize)> {
+        let frame = self.block.get(self.offset..)?;
+        if frame.len() < size_of::<tpacket3_hdr>() {
+            return None;
+        }
+        let hdr = unsafe { read_data::<tpacket3_hdr>(frame) };
+
+        let start = usize::from(hdr.tp_net);
+        let data = frame.get(start .. start + hdr.tp_snaplen as usize)?;
+
+        // the address follows the header
+        let addr_offset = align(size_of::<tpacket3_hdr>(), TPACKET_ALIGNMENT);
+        let addr_bytes = frame.get(
+            addr_offset .. addr_offset + size_of::<sockaddr_ll>()
+        )?;
+        let addr = unsafe { read_data::<sockaddr_ll>(addr_bytes) };
+        let halen = usize::from(addr.sll_halen).min(addr.sll_addr.len());
+        let source = match halen {
+            0 => None,
+            x => HwAddr::from_bytes(&addr.sll_addr[..x]).ok()
+        };
+
+        Some((
+            RingFrame {
+                data,
+                len: hdr.tp_len as usize,
+                source,
+                timestamp: UNIX_EPOCH + Duration::new(
+                    u64::from(hdr.tp_sec),
+                    hdr.tp_nsec
+                )
+            },
+            hdr.tp_next_offset as usize
+        ))
+    }
+}
+
+impl<'a> Iterator for RingFrames<'a> {
+    type Item = RingFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        match self.parse_frame() {
+            Some((frame, next_offset)) => {
+                self.offset += next_offset;
+                self.remaining -= 1;
+                Some(frame)
+            },
+            None => {
+                warn!("malformed frame in the packet ring block");
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+}
+
+fn align(x: usize, alignment: usize) -> usize {
+    (x + alignment - 1) & !(alignment - 1)
+}
+
+unsafe fn read_data<T>(data: &[u8]) -> T where T: Copy {
+    ptr::read_unaligned(data.as_ptr() as *const T)
+}
+
+#[cfg(feature = "async")]
+pub mod futures {
+    use super::*;
+
+    use ::mio;
+    use ::mio::*;
+    use ::mio::event::Evented;
+    use ::mio::unix::EventedFd;
+    use ::tokio::prelude::*;
+    use ::tokio::prelude::Poll;
+    use ::tokio::reactor::*;
+
+    gen_evented_eventedfd!(IPv6PacketRing);
+
+    pub struct IPv6PacketRingAdapter(PollEvented2<IPv6PacketRing>);
+
+    impl IPv6PacketRingAdapter {
+        pub fn new(handle: &Handle, inner: IPv6PacketRing) -> Result<Self> {
+            set_fd_nonblock(&inner, Nonblock::Yes)?;
+            Ok(IPv6PacketRingAdapter(
+                PollEvented2::new_with_handle(inner, handle)?
+            ))
+        }
+
+        pub fn get_ref(&self) -> &IPv6PacketRing {
+            self.0.get_ref()
+        }
+
+        pub fn get_mut(&mut self) -> &mut IPv6PacketRing {
+            self.0.get_mut()
+        }
+
+        // to be called from a task, like Future::poll()
+        pub fn poll_block(&mut self) -> Poll<RingBlock, Error> {
+            let ready = Ready::readable();
+
+            if !self.0.get_ref().block_ready() {
+                if let Async::NotReady = self.0.poll_read_ready(ready)
+                        .map_err(Error::TokioError)? {
+                    return Ok(Async::NotReady);
+                }
+
+                self.0.clear_read_ready(ready).map_err(Error::TokioError)?;
+                // a block may have been retired in the meantime
+                if !self.0.get_ref().block_ready() {
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            Ok(Async::Ready(
+                self.0.get_mut().next_block().expect("a ready block")
+            ))
+        }
+    }
+
+    impl AsRawFd for IPv6PacketRingAdapter {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.get_ref().as_raw_fd()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::nlibc::AF_PACKET;
+
+    fn put<T>(buf: &mut [u8], offset: usize, x: &T) {
+        let bytes = unsafe {
+            slice::from_raw_parts(x as *const T as *const u8, size_of::<T>())
+        };
+        buf[offset .. offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn put_frame(buf: &mut [u8], offset: usize, next: u32, payload: &[u8]) {
+        let addr_offset = align(size_of::<tpacket3_hdr>(), TPACKET_ALIGNMENT);
+        let net = align(addr_offset + size_of::<sockaddr_ll>(), 16);
+
+        let mut hdr: tpacket3_hdr = unsafe { zeroed() };
+        hdr.tp_next_offset = next;
+        hdr.tp_sec = 1;
+        hdr.tp_nsec = 500;
+        hdr.tp_snaplen = payload.len() as u32;
+        hdr.tp_len = payload.len() as u32 + 10;
+        hdr.tp_net = net as u16;
+        put(buf, offset, &hdr);
+
+        let mut addr: sockaddr_ll = unsafe { zeroed() };
+        addr.sll_family = AF_PACKET as u16;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&[2, 0, 0, 0, 0, 1]);
+        put(buf, offset + addr_offset, &addr);
+
+        buf[offset + net .. offset + net + payload.len()]
+            .copy_from_slice(payload);
+    }
+
+    #[test]
+    fn block_frames() {
+        let mut block = vec![0; 4096];
+        let first = align(size_of::<tpacket_block_desc>(), TPACKET_ALIGNMENT);
+
+        let mut desc: tpacket_block_desc = unsafe { zeroed() };
+        desc.hdr.num_pkts = 2;
+        desc.hdr.offset_to_first_pkt = first as u32;
+        desc.hdr.block_status = TP_STATUS_USER;
+        put(&mut block, 0, &desc);
+
+        put_frame(&mut block, first, 256, &[0x60, 1, 2, 3]);
+        put_frame(&mut block, first + 256, 0, &[0x60, 4, 5]);
+
+        let frames: Vec<RingFrame> = RingFrames::new(&block).collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, &[0x60, 1, 2, 3]);
+        assert_eq!(frames[1].data, &[0x60, 4, 5]);
+        assert!(frames[1].is_truncated());
+        assert_eq!(
+            frames[0].source,
+            Some(HwAddr::from_bytes(&[2, 0, 0, 0, 0, 1]).unwrap())
+        );
+        assert_eq!(
+            frames[0].timestamp,
+            UNIX_EPOCH + Duration::new(1, 500)
+        );
+    }
+
+    #[test]
+    fn malformed_block() {
+        let mut block = vec![0; 128];
+        let mut desc: tpacket_block_desc = unsafe { zeroed() };
+        desc.hdr.num_pkts = 3;
+        desc.hdr.offset_to_first_pkt = 120;
+        put(&mut block, 0, &desc);
+
+        assert_eq!(RingFrames::new(&block).count(), 0);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(RxRingConfig::default().validate().is_ok());
+
+        let invalid = [
+            RxRingConfig { frame_size: 0, ..Default::default() },
+            RxRingConfig { frame_size: 2047, ..Default::default() },
+            RxRingConfig { block_size: 1024, ..Default::default() },
+            RxRingConfig { block_count: 0, ..Default::default() }
+        ];
+        for config in &invalid {
+            let err = config.validate().unwrap_err();
+            match err.downcast_ref::<Error>() {
+                Some(&Error::InvalidRingConfig { .. }) => (),
+                _ => panic!("unexpected error: {}", err)
+            }
+        }
+    }
+}
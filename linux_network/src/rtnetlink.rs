@@ -0,0 +1,820 @@
+// A minimal rtnetlink(7) client: dumps and watches the links, addresses,
+// routes and neighbours, and manages the neighbour and proxy entries.
+// Netlink messages are in the host byte order.
+
+use ::std::io;
+use ::std::mem::*;
+use ::std::net::*;
+use ::std::os::unix::prelude::*;
+use ::std::ptr;
+
+use ::nlibc::{
+    bind, c_int, c_void, recv, sendto, sockaddr, sockaddr_nl, socket,
+    socklen_t, AF_INET, AF_INET6, AF_NETLINK, EINTR, SOCK_CLOEXEC,
+    SOCK_RAW
+};
+
+use ::*;
+use ::errors::{Error, Result};
+use ::util::*;
+use self::raw::*;
+
+pub mod raw {
+    pub const NETLINK_ROUTE: i32 = 0;
+
+    pub const NLMSG_HDRLEN: usize = 16;
+    pub const NLMSG_ERROR: u16 = 2;
+    pub const NLMSG_DONE: u16 = 3;
+
+    pub const NLM_F_REQUEST: u16 = 0x1;
+    pub const NLM_F_MULTI: u16 = 0x2;
+    pub const NLM_F_ACK: u16 = 0x4;
+    pub const NLM_F_REPLACE: u16 = 0x100;
+    pub const NLM_F_DUMP: u16 = 0x300;
+    pub const NLM_F_CREATE: u16 = 0x400;
+
+    pub const RTM_NEWLINK: u16 = 16;
+    pub const RTM_DELLINK: u16 = 17;
+    pub const RTM_GETLINK: u16 = 18;
+    pub const RTM_NEWADDR: u16 = 20;
+    pub const RTM_DELADDR: u16 = 21;
+    pub const RTM_GETADDR: u16 = 22;
+    pub const RTM_NEWROUTE: u16 = 24;
+    pub const RTM_DELROUTE: u16 = 25;
+    pub const RTM_GETROUTE: u16 = 26;
+    pub const RTM_NEWNEIGH: u16 = 28;
+    pub const RTM_DELNEIGH: u16 = 29;
+    pub const RTM_GETNEIGH: u16 = 30;
+
+    pub const IFLA_ADDRESS: u16 = 1;
+    pub const IFLA_IFNAME: u16 = 3;
+    pub const IFLA_MTU: u16 = 4;
+
+    pub const IFA_ADDRESS: u16 = 1;
+    pub const IFA_LOCAL: u16 = 2;
+    pub const IFA_FLAGS: u16 = 8;
+
+    pub const RTA_DST: u16 = 1;
+    pub const RTA_OIF: u16 = 4;
+    pub const RTA_GATEWAY: u16 = 5;
+    pub const RTA_PRIORITY: u16 = 6;
+    pub const RTA_TABLE: u16 = 15;
+
+    pub const NDA_DST: u16 = 1;
+    pub const NDA_LLADDR: u16 = 2;
+
+    pub const NTF_PROXY: u8 = 0x08;
+
+    pub const NUD_INCOMPLETE: u16 = 0x01;
+    pub const NUD_REACHABLE: u16 = 0x02;
+    pub const NUD_STALE: u16 = 0x04;
+    pub const NUD_DELAY: u16 = 0x08;
+    pub const NUD_PROBE: u16 = 0x10;
+    pub const NUD_FAILED: u16 = 0x20;
+    pub const NUD_NOARP: u16 = 0x40;
+    pub const NUD_PERMANENT: u16 = 0x80;
+
+    pub const RTMGRP_LINK: u32 = 0x1;
+    pub const RTMGRP_NEIGH: u32 = 0x4;
+    pub const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+    pub const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+    pub const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+    pub const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+    pub const IFINFOMSG_SIZE: usize = 16;
+    pub const IFADDRMSG_SIZE: usize = 8;
+    pub const RTMSG_SIZE: usize = 12;
+    pub const NDMSG_SIZE: usize = 12;
+}
+
+// big enough for a page-sized dump chunk of any kind
+const RECV_BUF_SIZE: usize = 65536;
+
+bitflags!(
+    pub struct RtGroups: u32 {
+        const Link = RTMGRP_LINK;
+        const Neighbour = RTMGRP_NEIGH;
+        const IPv4Address = RTMGRP_IPV4_IFADDR;
+        const IPv4Route = RTMGRP_IPV4_ROUTE;
+        const IPv6Address = RTMGRP_IPV6_IFADDR;
+        const IPv6Route = RTMGRP_IPV6_ROUTE;
+    }
+);
+
+bitflags!(
+    pub struct NeighbourState: u16 {
+        const Incomplete = NUD_INCOMPLETE;
+        const Reachable = NUD_REACHABLE;
+        const Stale = NUD_STALE;
+        const Delay = NUD_DELAY;
+        const Probe = NUD_PROBE;
+        const Failed = NUD_FAILED;
+        const NoArp = NUD_NOARP;
+        const Permanent = NUD_PERMANENT;
+    }
+);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Link {
+    pub index: u32,
+    pub name: String,
+    pub link_type: u16,
+    pub flags: u32,
+    pub mtu: Option<u32>,
+    pub hwaddr: Option<HwAddr>
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Address {
+    pub index: u32,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    pub scope: u8,
+    // IFA_F_*, see AddrFlags
+    pub flags: u32
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Route {
+    pub dst: Option<IpAddr>,
+    pub dst_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub oif: Option<u32>,
+    pub priority: Option<u32>,
+    pub table: u32,
+    pub protocol: u8,
+    pub scope: u8,
+    pub kind: u8
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Neighbour {
+    pub index: u32,
+    pub addr: IpAddr,
+    pub hwaddr: Option<HwAddr>,
+    pub state: NeighbourState,
+    pub proxy: bool
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    NewLink(Link),
+    DelLink(Link),
+    NewAddress(Address),
+    DelAddress(Address),
+    NewRoute(Route),
+    DelRoute(Route),
+    NewNeighbour(Neighbour),
+    DelNeighbour(Neighbour)
+}
+
+// Requests and events should use separate sockets: the events arriving
+// while waiting for a reply are dropped.
+pub struct RtNetlinkSocket {
+    fd: RawFd,
+    seq: u32
+}
+
+impl RtNetlinkSocket {
+    pub fn new() -> Result<Self> {
+        Self::with_groups(RtGroups::empty())
+    }
+
+    // subscribes to the multicast groups, see recv_events()
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn with_groups(groups: RtGroups) -> Result<Self> { unsafe {
+        let fd = n1try!(socket(
+            AF_NETLINK,
+            SOCK_RAW | SOCK_CLOEXEC,
+            NETLINK_ROUTE
+        ));
+        let ret = Self { fd, seq: 0 };
+
+        let mut addr: sockaddr_nl = zeroed();
+        addr.nl_family = AF_NETLINK as u16;
+        addr.nl_groups = groups.bits();
+        n1try!(bind(
+            fd,
+            &addr as *const sockaddr_nl as *const sockaddr,
+            size_of_val(&addr) as socklen_t
+        ));
+
+        Ok(ret)
+    }}
+
+    pub fn links(&mut self) -> Result<Vec<Link>> {
+        let mut ret = Vec::new();
+        self.dump(RTM_GETLINK, &[0; IFINFOMSG_SIZE], |msg_type, payload| {
+            if msg_type == RTM_NEWLINK {
+                ret.push(parse_link(payload)?);
+            }
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    pub fn addresses(&mut self) -> Result<Vec<Address>> {
+        let mut ret = Vec::new();
+        self.dump(RTM_GETADDR, &[0; IFADDRMSG_SIZE], |msg_type, payload| {
+            if msg_type == RTM_NEWADDR {
+                ret.push(parse_address(payload)?);
+            }
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    pub fn routes(&mut self) -> Result<Vec<Route>> {
+        let mut ret = Vec::new();
+        self.dump(RTM_GETROUTE, &[0; RTMSG_SIZE], |msg_type, payload| {
+            if msg_type == RTM_NEWROUTE {
+                ret.push(parse_route(payload)?);
+            }
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    // the route the kernel picks for the destination, as ip-route(8) "get";
+    // the link-local destinations need the outgoing interface
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn route_to(&mut self, dst: IpAddr, oif: Option<u32>)
+            -> Result<Route> {
+        let mut req = vec![0; RTMSG_SIZE];
+        req[0] = match dst {
+            IpAddr::V4(_) => AF_INET as u8,
+            IpAddr::V6(_) => AF_INET6 as u8
+        };
+        let octets = ip_octets(dst);
+        req[1] = (octets.len() * 8) as u8;
+        put_attr(&mut req, RTA_DST, &octets);
+        if let Some(x) = oif {
+            let mut data = Vec::with_capacity(4);
+            put_u32(&mut data, x);
+            put_attr(&mut req, RTA_OIF, &data);
+        }
+
+        let mut ret = None;
+        let seq = self.send_request(RTM_GETROUTE, 0, &req)?;
+        self.recv_reply(seq, |msg_type, payload| {
+            if msg_type == RTM_NEWROUTE {
+                ret = Some(parse_route(payload)?);
+            }
+            Ok(())
+        })?;
+        ret.ok_or_else(|| Error::MalformedNetlinkMessage.into())
+    }
+
+    pub fn neighbours(&mut self) -> Result<Vec<Neighbour>> {
+        self.dump_neighbours(0)
+    }
+
+    // the proxy NDP/ARP entries, see ip-neighbour(8) "proxy"
+    pub fn proxies(&mut self) -> Result<Vec<Neighbour>> {
+        self.dump_neighbours(NTF_PROXY)
+    }
+
+    fn dump_neighbours(&mut self, flags: u8) -> Result<Vec<Neighbour>> {
+        let mut req = [0; NDMSG_SIZE];
+        req[10] = flags;
+
+        let mut ret = Vec::new();
+        self.dump(RTM_GETNEIGH, &req, |msg_type, payload| {
+            if msg_type == RTM_NEWNEIGH {
+                ret.push(parse_neighbour(payload)?);
+            }
+            Ok(())
+        })?;
+        Ok(ret)
+    }
+
+    // creates or replaces the entry
+    pub fn add_neighbour(
+        &mut self,
+        index: u32,
+        addr: IpAddr,
+        hwaddr: HwAddr,
+        state: NeighbourState
+    ) -> Result<()> {
+        let mut req = make_ndmsg(index, addr, state, 0);
+        put_attr(&mut req, NDA_DST, &ip_octets(addr));
+        put_attr(&mut req, NDA_LLADDR, hwaddr.as_bytes());
+        self.change(RTM_NEWNEIGH, NLM_F_CREATE | NLM_F_REPLACE, &req)
+    }
+
+    pub fn del_neighbour(&mut self, index: u32, addr: IpAddr) -> Result<()> {
+        let mut req = make_ndmsg(index, addr, NeighbourState::empty(), 0);
+        put_attr(&mut req, NDA_DST, &ip_octets(addr));
+        self.change(RTM_DELNEIGH, 0, &req)
+    }
+
+    // makes the kernel answer the solicitations for addr on the interface,
+    // given the proxy_ndp (or proxy_arp) sysctl is on
+    pub fn add_proxy(&mut self, index: u32, addr: IpAddr) -> Result<()> {
+        let mut req = make_ndmsg(
+            index,
+            addr,
+            NeighbourState::Permanent,
+            NTF_PROXY
+        );
+        put_attr(&mut req, NDA_DST, &ip_octets(addr));
+        self.change(RTM_NEWNEIGH, NLM_F_CREATE | NLM_F_REPLACE, &req)
+    }
+
+    pub fn del_proxy(&mut self, index: u32, addr: IpAddr) -> Result<()> {
+        let mut req = make_ndmsg(index, addr, NeighbourState::empty(),
+            NTF_PROXY);
+        put_attr(&mut req, NDA_DST, &ip_octets(addr));
+        self.change(RTM_DELNEIGH, 0, &req)
+    }
+
+    // Receives a datagram worth of the events for the subscribed groups.
+    // ENOBUFS means some events were lost and the state must be re-read.
+    #[allow(clippy::cast_sign_loss)]
+    pub fn recv_events(&mut self) -> Result<Vec<Event>> { unsafe {
+        let mut buf = vec![0_u8; RECV_BUF_SIZE];
+        let len = n1try!(recv(
+            self.fd,
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len(),
+            0
+        )) as usize;
+
+        let mut ret = Vec::new();
+        for (msg_type, _, _, payload) in parse_messages(&buf[..len])? {
+            if let Some(event) = parse_event(msg_type, payload)? {
+                ret.push(event);
+            }
+        }
+        Ok(ret)
+    }}
+
+    fn dump<F>(&mut self, msg_type: u16, req: &[u8], f: F) -> Result<()>
+            where F: FnMut(u16, &[u8]) -> Result<()> {
+        let seq = self.send_request(msg_type, NLM_F_DUMP, req)?;
+        self.recv_reply(seq, f)
+    }
+
+    fn change(&mut self, msg_type: u16, flags: u16, req: &[u8])
+            -> Result<()> {
+        let seq = self.send_request(msg_type, flags | NLM_F_ACK, req)?;
+        self.recv_reply(seq, |_, _| Ok(()))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn send_request(&mut self, msg_type: u16, flags: u16, payload: &[u8])
+            -> Result<u32> { unsafe {
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut buf = Vec::with_capacity(NLMSG_HDRLEN + payload.len());
+        put_u32(&mut buf, (NLMSG_HDRLEN + payload.len()) as u32);
+        put_u16(&mut buf, msg_type);
+        put_u16(&mut buf, flags | NLM_F_REQUEST);
+        put_u32(&mut buf, self.seq);
+        put_u32(&mut buf, 0);
+        buf.extend_from_slice(payload);
+
+        // the kernel is port 0
+        let mut addr: sockaddr_nl = zeroed();
+        addr.nl_family = AF_NETLINK as u16;
+        n1try!(sendto(
+            self.fd,
+            buf.as_ptr() as *const c_void,
+            buf.len(),
+            0,
+            &addr as *const sockaddr_nl as *const sockaddr,
+            size_of_val(&addr) as socklen_t
+        ));
+
+        Ok(self.seq)
+    }}
+
+    // rtnetlink requests are processed synchronously, so the reply
+    // is already queued when sendto() returns
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+    fn recv_reply<F>(&mut self, seq: u32, mut f: F) -> Result<()>
+            where F: FnMut(u16, &[u8]) -> Result<()> { unsafe {
+        let mut buf = vec![0_u8; RECV_BUF_SIZE];
+        loop {
+            let len = n1try!(recv(
+                self.fd,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len(),
+                0
+            )) as usize;
+
+            for (msg_type, flags, msg_seq, payload)
+                    in parse_messages(&buf[..len])? {
+                if msg_seq != seq {
+                    continue;
+                }
+
+                match msg_type {
+                    NLMSG_DONE => return Ok(()),
+                    NLMSG_ERROR => {
+                        // a zero error is the acknowledgement
+                        return match get_u32(payload, 0)? as i32 {
+                            0 => Ok(()),
+                            e => Err(Error::IoError(
+                                io::Error::from_raw_os_error(-e)
+                            ).into())
+                        };
+                    },
+                    _ => {
+                        f(msg_type, payload)?;
+                        if flags & NLM_F_MULTI == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }}
+}
+
+impl Drop for RtNetlinkSocket {
+    fn drop(&mut self) {
+        log_if_err(::nix::unistd::close(self.fd).map_err(|e| e.into()));
+    }
+}
+
+impl AsRawFd for RtNetlinkSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+fn parse_event(msg_type: u16, payload: &[u8]) -> Result<Option<Event>> {
+    Ok(Some(match msg_type {
+        RTM_NEWLINK => Event::NewLink(parse_link(payload)?),
+        RTM_DELLINK => Event::DelLink(parse_link(payload)?),
+        RTM_NEWADDR => Event::NewAddress(parse_address(payload)?),
+        RTM_DELADDR => Event::DelAddress(parse_address(payload)?),
+        RTM_NEWROUTE => Event::NewRoute(parse_route(payload)?),
+        RTM_DELROUTE => Event::DelRoute(parse_route(payload)?),
+        RTM_NEWNEIGH => Event::NewNeighbour(parse_neighbour(payload)?),
+        RTM_DELNEIGH => Event::DelNeighbour(parse_neighbour(payload)?),
+        _ => return Ok(None)
+    }))
+}
+
+// (type, flags, sequence number, payload)
+fn parse_messages(buf: &[u8]) -> Result<Vec<(u16, u16, u32, &[u8])>> {
+    let mut ret = Vec::new();
+    let mut off = 0;
+    while buf.len() - off >= NLMSG_HDRLEN {
+        let len = get_u32(buf, off)? as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() - off {
+            return Err(Error::MalformedNetlinkMessage.into());
+        }
+        ret.push((
+            get_u16(buf, off + 4)?,
+            get_u16(buf, off + 6)?,
+            get_u32(buf, off + 8)?,
+            &buf[off + NLMSG_HDRLEN .. off + len]
+        ));
+        off += align(len).min(buf.len() - off);
+    }
+    Ok(ret)
+}
+
+fn parse_attrs(buf: &[u8]) -> Result<Vec<(u16, &[u8])>> {
+    let mut ret = Vec::new();
+    let mut off = 0;
+    while buf.len() - off >= 4 {
+        let len = get_u16(buf, off)? as usize;
+        if len < 4 || len > buf.len() - off {
+            return Err(Error::MalformedNetlinkMessage.into());
+        }
+        // the top bits are the nested and byte order flags
+        let attr_type = get_u16(buf, off + 2)? & 0x3fff;
+        ret.push((attr_type, &buf[off + 4 .. off + len]));
+        off += align(len).min(buf.len() - off);
+    }
+    Ok(ret)
+}
+
+fn parse_link(payload: &[u8]) -> Result<Link> {
+    if payload.len() < IFINFOMSG_SIZE {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+
+    let mut ret = Link {
+        index: get_u32(payload, 4)?,
+        name: String::new(),
+        link_type: get_u16(payload, 2)?,
+        flags: get_u32(payload, 8)?,
+        mtu: None,
+        hwaddr: None
+    };
+
+    for (attr_type, data) in parse_attrs(&payload[IFINFOMSG_SIZE..])? {
+        match attr_type {
+            IFLA_IFNAME => {
+                let len = data.iter().position(|&x| x == 0)
+                    .unwrap_or(data.len());
+                ret.name = String::from_utf8_lossy(&data[..len]).into_owned();
+            },
+            IFLA_MTU => ret.mtu = Some(get_u32(data, 0)?),
+            // all-zero for the links without addresses, like loopback
+            IFLA_ADDRESS if data.iter().any(|&x| x != 0) =>
+                ret.hwaddr = HwAddr::from_bytes(data).ok(),
+            _ => ()
+        }
+    }
+
+    Ok(ret)
+}
+
+fn parse_address(payload: &[u8]) -> Result<Address> {
+    if payload.len() < IFADDRMSG_SIZE {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+    let family = payload[0];
+
+    let mut local = None;
+    let mut address = None;
+    let mut flags = u32::from(payload[2]);
+    for (attr_type, data) in parse_attrs(&payload[IFADDRMSG_SIZE..])? {
+        match attr_type {
+            IFA_LOCAL => local = Some(parse_ip(family, data)?),
+            IFA_ADDRESS => address = Some(parse_ip(family, data)?),
+            // the flags above 0x80 do not fit in ifaddrmsg
+            IFA_FLAGS => flags = get_u32(data, 0)?,
+            _ => ()
+        }
+    }
+
+    // IFA_ADDRESS is the peer's on the point-to-point links
+    Ok(Address {
+        index: get_u32(payload, 4)?,
+        addr: local.or(address).ok_or(Error::MalformedNetlinkMessage)?,
+        prefix_len: payload[1],
+        flags,
+        scope: payload[3]
+    })
+}
+
+fn parse_route(payload: &[u8]) -> Result<Route> {
+    if payload.len() < RTMSG_SIZE {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+    let family = payload[0];
+
+    let mut ret = Route {
+        dst: None,
+        dst_len: payload[1],
+        gateway: None,
+        oif: None,
+        priority: None,
+        table: u32::from(payload[4]),
+        protocol: payload[5],
+        scope: payload[6],
+        kind: payload[7]
+    };
+
+    for (attr_type, data) in parse_attrs(&payload[RTMSG_SIZE..])? {
+        match attr_type {
+            RTA_DST => ret.dst = Some(parse_ip(family, data)?),
+            RTA_GATEWAY => ret.gateway = Some(parse_ip(family, data)?),
+            RTA_OIF => ret.oif = Some(get_u32(data, 0)?),
+            RTA_PRIORITY => ret.priority = Some(get_u32(data, 0)?),
+            // the tables above 255 do not fit in rtmsg
+            RTA_TABLE => ret.table = get_u32(data, 0)?,
+            _ => ()
+        }
+    }
+
+    Ok(ret)
+}
+
+fn parse_neighbour(payload: &[u8]) -> Result<Neighbour> {
+    if payload.len() < NDMSG_SIZE {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+    let family = payload[0];
+
+    let mut addr = None;
+    let mut hwaddr = None;
+    for (attr_type, data) in parse_attrs(&payload[NDMSG_SIZE..])? {
+        match attr_type {
+            NDA_DST => addr = Some(parse_ip(family, data)?),
+            NDA_LLADDR => hwaddr = HwAddr::from_bytes(data).ok(),
+            _ => ()
+        }
+    }
+
+    Ok(Neighbour {
+        index: get_u32(payload, 4)?,
+        addr: addr.ok_or(Error::MalformedNetlinkMessage)?,
+        hwaddr,
+        state: NeighbourState::from_bits_truncate(get_u16(payload, 8)?),
+        proxy: payload[10] & NTF_PROXY != 0
+    })
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn parse_ip(family: u8, data: &[u8]) -> Result<IpAddr> {
+    if c_int::from(family) == AF_INET && data.len() == 4 {
+        let mut octets = [0; 4];
+        octets.copy_from_slice(data);
+        Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if c_int::from(family) == AF_INET6 && data.len() == 16 {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(data);
+        Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        Err(Error::MalformedNetlinkMessage.into())
+    }
+}
+
+fn ip_octets(addr: IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(x) => x.octets().to_vec(),
+        IpAddr::V6(x) => x.octets().to_vec()
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn make_ndmsg(index: u32, addr: IpAddr, state: NeighbourState, flags: u8)
+        -> Vec<u8> {
+    let family = match addr {
+        IpAddr::V4(_) => AF_INET,
+        IpAddr::V6(_) => AF_INET6
+    };
+
+    let mut ret = Vec::with_capacity(NDMSG_SIZE);
+    ret.push(family as u8);
+    ret.extend_from_slice(&[0; 3]);
+    put_u32(&mut ret, index);
+    put_u16(&mut ret, state.bits());
+    ret.push(flags);
+    // ndm_type, unused on requests
+    ret.push(0);
+    ret
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn put_attr(buf: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+    let len = 4 + data.len();
+    put_u16(buf, len as u16);
+    put_u16(buf, attr_type);
+    buf.extend_from_slice(data);
+    let padding = align(len) - len;
+    buf.extend_from_slice(&[0; 4][..padding]);
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn get_u16(buf: &[u8], off: usize) -> Result<u16> {
+    if buf.len() < off + 2 {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+    Ok(unsafe { ptr::read_unaligned(buf[off..].as_ptr() as *const u16) })
+}
+
+fn get_u32(buf: &[u8], off: usize) -> Result<u32> {
+    if buf.len() < off + 4 {
+        return Err(Error::MalformedNetlinkMessage.into());
+    }
+    Ok(unsafe { ptr::read_unaligned(buf[off..].as_ptr() as *const u32) })
+}
+
+fn put_u16(buf: &mut Vec<u8>, x: u16) {
+    let bytes: [u8; 2] = unsafe { transmute(x) };
+    buf.extend_from_slice(&bytes);
+}
+
+fn put_u32(buf: &mut Vec<u8>, x: u32) {
+    let bytes: [u8; 4] = unsafe { transmute(x) };
+    buf.extend_from_slice(&bytes);
+}
+
+#[cfg(feature = "async")]
+pub mod futures {
+    use super::*;
+
+    use ::std::collections::VecDeque;
+
+    use ::mio;
+    use ::mio::*;
+    use ::mio::event::Evented;
+    use ::mio::unix::EventedFd;
+    use ::tokio::prelude::*;
+    use ::tokio::prelude::Poll;
+    use ::tokio::reactor::*;
+
+    gen_evented_eventedfd!(RtNetlinkSocket);
+
+    // the stream of the events for the groups the socket is subscribed to
+    pub struct RtNetlinkEvents {
+        sock: PollEvented2<RtNetlinkSocket>,
+        pending: VecDeque<Event>
+    }
+
+    impl RtNetlinkEvents {
+        pub fn new(handle: &Handle, inner: RtNetlinkSocket) -> Result<Self> {
+            set_fd_nonblock(&inner, Nonblock::Yes)?;
+            Ok(Self {
+                sock: PollEvented2::new_with_handle(inner, handle)?,
+                pending: VecDeque::new()
+            })
+        }
+    }
+
+    impl AsRawFd for RtNetlinkEvents {
+        fn as_raw_fd(&self) -> RawFd {
+            self.sock.get_ref().as_raw_fd()
+        }
+    }
+
+    impl Stream for RtNetlinkEvents {
+        type Item = Event;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            loop {
+                if let Some(event) = self.pending.pop_front() {
+                    return Ok(Async::Ready(Some(event)));
+                }
+
+                let ready = Ready::readable();
+                if let Async::NotReady = self.sock.poll_read_ready(ready)
+                        .map_err(Error::TokioError)? {
+                    return Ok(Async::NotReady);
+                }
+
+                match self.sock.get_mut().recv_events() {
+                    Ok(events) => self.pending.extend(events),
+                    Err(e) => {
+                        let err = e.downcast::<Error>().unwrap();
+                        if let Again = (&err).into() {
+                            self.sock.clear_read_ready(ready)
+                                .map_err(Error::TokioError)?;
+                            return Ok(Async::NotReady);
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loopback_link() {
+        let mut sock = RtNetlinkSocket::new().unwrap();
+        let links = sock.links().unwrap();
+        let lo = links.iter().find(|x| x.name == "lo")
+            .expect("loopback interface");
+        assert!(lo.flags & ::nlibc::IFF_LOOPBACK as u32 != 0);
+        assert_eq!(lo.hwaddr, None);
+    }
+
+    #[test]
+    fn parse_neighbour_message() {
+        let addr: IpAddr = "2001:db8::1".parse().unwrap();
+        let mut msg = make_ndmsg(42, addr, NeighbourState::Reachable, 0);
+        put_attr(&mut msg, NDA_DST, &ip_octets(addr));
+        put_attr(&mut msg, NDA_LLADDR, &[2, 0, 0, 0, 0, 1]);
+
+        let neigh = parse_neighbour(&msg).unwrap();
+        assert_eq!(neigh.index, 42);
+        assert_eq!(neigh.addr, addr);
+        assert_eq!(
+            neigh.hwaddr,
+            Some(HwAddr::from_bytes(&[2, 0, 0, 0, 0, 1]).unwrap())
+        );
+        assert_eq!(neigh.state, NeighbourState::Reachable);
+        assert!(!neigh.proxy);
+
+        assert!(parse_neighbour(&msg[..NDMSG_SIZE + 3]).is_err());
+    }
+
+    // needs CAP_SYS_ADMIN for a throwaway network namespace
+    #[test]
+    #[ignore]
+    fn proxy_entries() {
+        ::nix::sched::unshare(::nix::sched::CloneFlags::CLONE_NEWNET)
+            .unwrap();
+
+        let mut sock = RtNetlinkSocket::new().unwrap();
+        let lo = sock.links().unwrap().into_iter()
+            .find(|x| x.name == "lo")
+            .unwrap();
+        let addr: IpAddr = "2001:db8::42".parse().unwrap();
+
+        sock.add_proxy(lo.index, addr).unwrap();
+        assert!(sock.proxies().unwrap().iter()
+            .any(|x| x.addr == addr && x.index == lo.index && x.proxy));
+
+        sock.del_proxy(lo.index, addr).unwrap();
+        assert!(!sock.proxies().unwrap().iter().any(|x| x.addr == addr));
+    }
+}
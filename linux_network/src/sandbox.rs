@@ -0,0 +1,478 @@
+// Process hardening beyond the privilege functions: namespaces,
+// an empty root directory, resource limits and Landlock, see namespaces(7),
+// pivot_root(2), setrlimit(2) and Documentation/userspace-api/landlock.rst.
+// Sandbox applies them together with the securebits in an order that works.
+
+use ::std::ffi::CString;
+use ::std::fs;
+use ::std::os::unix::prelude::*;
+use ::std::path::{Path, PathBuf};
+use ::std::ptr::null;
+
+use ::nlibc::{
+    c_int, c_void, chdir, getegid, geteuid, mount, rlim_t, rlimit, setrlimit,
+    syscall, umount2, unshare, CLONE_NEWIPC, CLONE_NEWNET, CLONE_NEWNS,
+    CLONE_NEWUSER, CLONE_NEWUTS, EINTR, ENOSYS, EOPNOTSUPP, MNT_DETACH,
+    MS_BIND, MS_NODEV, MS_NOEXEC, MS_NOSUID, MS_PRIVATE, MS_RDONLY, MS_REC,
+    MS_REMOUNT, O_CLOEXEC, O_PATH, RLIMIT_AS, RLIMIT_CORE, RLIMIT_FSIZE,
+    RLIMIT_MEMLOCK, RLIMIT_NOFILE, RLIMIT_NPROC, SYS_pivot_root
+};
+
+use ::*;
+use ::errors::{Error, Result};
+use ::util::*;
+use self::raw::*;
+
+pub mod raw {
+    use ::nlibc::{c_int, c_long};
+
+    pub const SYS_landlock_create_ruleset: c_long = 444;
+    pub const SYS_landlock_add_rule: c_long = 445;
+    pub const SYS_landlock_restrict_self: c_long = 446;
+
+    pub const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+    pub const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+
+    pub const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1;
+    pub const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    pub const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    pub const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    pub const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    pub const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    pub const LANDLOCK_ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    pub const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    pub const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    pub const LANDLOCK_ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    pub const LANDLOCK_ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    pub const LANDLOCK_ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    pub const LANDLOCK_ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+    #[repr(C)]
+    pub struct landlock_ruleset_attr {
+        pub handled_access_fs: u64
+    }
+
+    #[repr(C, packed)]
+    pub struct landlock_path_beneath_attr {
+        pub allowed_access: u64,
+        pub parent_fd: c_int
+    }
+}
+
+bitflags!(
+    pub struct Namespaces: c_int {
+        const Net = CLONE_NEWNET;
+        const Mount = CLONE_NEWNS;
+        const User = CLONE_NEWUSER;
+        const Ipc = CLONE_NEWIPC;
+        const Uts = CLONE_NEWUTS;
+    }
+);
+
+// the first Landlock ABI
+bitflags!(
+    pub struct LandlockAccess: u64 {
+        const Execute = LANDLOCK_ACCESS_FS_EXECUTE;
+        const WriteFile = LANDLOCK_ACCESS_FS_WRITE_FILE;
+        const ReadFile = LANDLOCK_ACCESS_FS_READ_FILE;
+        const ReadDir = LANDLOCK_ACCESS_FS_READ_DIR;
+        const RemoveDir = LANDLOCK_ACCESS_FS_REMOVE_DIR;
+        const RemoveFile = LANDLOCK_ACCESS_FS_REMOVE_FILE;
+        const MakeChar = LANDLOCK_ACCESS_FS_MAKE_CHAR;
+        const MakeDir = LANDLOCK_ACCESS_FS_MAKE_DIR;
+        const MakeReg = LANDLOCK_ACCESS_FS_MAKE_REG;
+        const MakeSock = LANDLOCK_ACCESS_FS_MAKE_SOCK;
+        const MakeFifo = LANDLOCK_ACCESS_FS_MAKE_FIFO;
+        const MakeBlock = LANDLOCK_ACCESS_FS_MAKE_BLOCK;
+        const MakeSym = LANDLOCK_ACCESS_FS_MAKE_SYM;
+    }
+);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Resource {
+    AddressSpace,
+    CoreSize,
+    FileSize,
+    LockedMemory,
+    OpenFiles,
+    Processes
+}
+
+// an unprivileged user namespace maps the current ids to themselves
+pub fn unshare_namespaces(namespaces: Namespaces) -> Result<()> {
+    let (uid, gid) = unsafe { (geteuid(), getegid()) };
+    unsafe { n1try!(unshare(namespaces.bits())); }
+
+    if namespaces.contains(Namespaces::User) {
+        write_proc_self("setgroups", "deny")?;
+        write_proc_self("uid_map", &format!("{} {} 1", uid, uid))?;
+        write_proc_self("gid_map", &format!("{} {} 1", gid, gid))?;
+    }
+    Ok(())
+}
+
+fn write_proc_self(file: &str, contents: &str) -> Result<()> {
+    fs::write(Path::new("/proc/self").join(file), contents)
+        .map_err(Error::IoError)?;
+    Ok(())
+}
+
+// The root becomes a read-only empty tmpfs, the old one is detached.
+// Needs a mount namespace of its own, the mounts are not seen outside.
+pub fn enter_empty_root() -> Result<()> {
+    // a mount point that exists on any system, hidden by the new root
+    let new_root = cstr("/tmp")?;
+    let tmpfs = cstr("tmpfs")?;
+    let options = cstr("size=4k,nr_inodes=8,mode=0755")?;
+
+    unsafe {
+        n1try!(mount(
+            null(),
+            cstr("/")?.as_ptr(),
+            null(),
+            MS_REC | MS_PRIVATE,
+            null()
+        ));
+        n1try!(mount(
+            tmpfs.as_ptr(),
+            new_root.as_ptr(),
+            tmpfs.as_ptr(),
+            MS_NODEV | MS_NOEXEC | MS_NOSUID,
+            options.as_ptr() as *const c_void
+        ));
+    }
+
+    fs::create_dir("/tmp/old").map_err(Error::IoError)?;
+    unsafe {
+        n1try!(syscall(
+            SYS_pivot_root,
+            new_root.as_ptr(),
+            cstr("/tmp/old")?.as_ptr()
+        ));
+        n1try!(chdir(cstr("/")?.as_ptr()));
+        n1try!(umount2(cstr("/old")?.as_ptr(), MNT_DETACH));
+    }
+    fs::remove_dir("/old").map_err(Error::IoError)?;
+
+    unsafe {
+        n1try!(mount(
+            null(),
+            cstr("/")?.as_ptr(),
+            null(),
+            MS_REMOUNT | MS_BIND | MS_RDONLY | MS_NODEV | MS_NOEXEC
+                | MS_NOSUID,
+            null()
+        ));
+    }
+    Ok(())
+}
+
+// the same for the soft and the hard limit, so it can not be raised back
+pub fn limit_resource(resource: Resource, limit: rlim_t) -> Result<()> {
+    let rl = rlimit {
+        rlim_cur: limit,
+        rlim_max: limit
+    };
+    let raw = match resource {
+        Resource::AddressSpace => RLIMIT_AS,
+        Resource::CoreSize => RLIMIT_CORE,
+        Resource::FileSize => RLIMIT_FSIZE,
+        Resource::LockedMemory => RLIMIT_MEMLOCK,
+        Resource::OpenFiles => RLIMIT_NOFILE,
+        Resource::Processes => RLIMIT_NPROC
+    };
+
+    unsafe { n1try!(setrlimit(raw, &rl)); }
+    Ok(())
+}
+
+// Filesystem access rules, applied to the process and its future children.
+// Access kinds not in handled stay allowed everywhere.
+pub struct LandlockRuleset {
+    fd: RawFd
+}
+
+impl LandlockRuleset {
+    // Ok(None) if the kernel has no Landlock
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(handled: LandlockAccess) -> Result<Option<Self>> {
+        let attr = landlock_ruleset_attr {
+            handled_access_fs: handled.bits()
+        };
+
+        let fd = unsafe {
+            syscall(
+                SYS_landlock_create_ruleset,
+                &attr as *const landlock_ruleset_attr,
+                ::std::mem::size_of::<landlock_ruleset_attr>(),
+                0 as u32
+            )
+        };
+        if fd == -1 {
+            let err = ::std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(ENOSYS) | Some(EOPNOTSUPP) => Ok(None),
+                _ => Err(Error::IoError(err).into())
+            };
+        }
+
+        Ok(Some(LandlockRuleset { fd: fd as RawFd }))
+    }
+
+    // the ABI version, 0 if the kernel has no Landlock
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn abi_version() -> c_int {
+        let ret = unsafe {
+            syscall(
+                SYS_landlock_create_ruleset,
+                null::<c_void>(),
+                0 as usize,
+                LANDLOCK_CREATE_RULESET_VERSION
+            )
+        };
+        if ret < 0 { 0 } else { ret as c_int }
+    }
+
+    pub fn allow<T>(&mut self, path: T, access: LandlockAccess) -> Result<()>
+            where T: AsRef<Path> {
+        let path = open_path(path.as_ref()).map_err(Error::IoError)?;
+        let attr = landlock_path_beneath_attr {
+            allowed_access: access.bits(),
+            parent_fd: path.as_raw_fd()
+        };
+
+        unsafe {
+            n1try!(syscall(
+                SYS_landlock_add_rule,
+                self.fd,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const landlock_path_beneath_attr,
+                0 as u32
+            ));
+        }
+        Ok(())
+    }
+
+    // needs NO_NEW_PRIVS or CAP_SYS_ADMIN
+    pub fn restrict_self(self) -> Result<()> {
+        unsafe {
+            n1try!(syscall(SYS_landlock_restrict_self, self.fd, 0 as u32));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LandlockRuleset {
+    fn drop(&mut self) {
+        log_if_err(::nix::unistd::close(self.fd).map_err(|e| e.into()));
+    }
+}
+
+fn open_path(path: &Path) -> ::std::io::Result<fs::File> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let fd = unsafe {
+        ::nlibc::open(path.as_ptr(), O_PATH | O_CLOEXEC)
+    };
+    if fd == -1 {
+        return Err(::std::io::Error::last_os_error());
+    }
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+fn cstr(s: &str) -> Result<CString> {
+    Ok(CString::new(s)?)
+}
+
+// Collects the hardening steps and applies them at once, in this order:
+// securebits, namespaces, the empty root, resource limits, NO_NEW_PRIVS
+// and Landlock. The caller switches the user and drops the capabilities
+// afterwards, the namespaces and the mounts need CAP_SYS_ADMIN
+// unless a user namespace is unshared too.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    securebits: Option<SecBits>,
+    namespaces: Namespaces,
+    empty_root: bool,
+    limits: Vec<(Resource, rlim_t)>,
+    no_new_privs: bool,
+    landlock: Option<LandlockAccess>,
+    allowed_paths: Vec<(PathBuf, LandlockAccess)>
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Sandbox {
+            securebits: None,
+            namespaces: Namespaces::empty(),
+            empty_root: false,
+            limits: Vec::new(),
+            no_new_privs: false,
+            landlock: None,
+            allowed_paths: Vec::new()
+        }
+    }
+
+    pub fn securebits(&mut self, bits: SecBits) -> &mut Self {
+        self.securebits = Some(bits);
+        self
+    }
+
+    pub fn unshare(&mut self, namespaces: Namespaces) -> &mut Self {
+        self.namespaces |= namespaces;
+        self
+    }
+
+    // implies the mount namespace
+    pub fn empty_root(&mut self) -> &mut Self {
+        self.empty_root = true;
+        self.unshare(Namespaces::Mount)
+    }
+
+    pub fn limit(&mut self, resource: Resource, limit: rlim_t) -> &mut Self {
+        self.limits.push((resource, limit));
+        self
+    }
+
+    pub fn no_new_privs(&mut self) -> &mut Self {
+        self.no_new_privs = true;
+        self
+    }
+
+    // forbids the handled access kinds outside of the allowed paths;
+    // implies NO_NEW_PRIVS, skipped if the kernel has no Landlock
+    pub fn restrict_filesystem(&mut self, handled: LandlockAccess)
+            -> &mut Self {
+        self.no_new_privs = true;
+        self.landlock = Some(handled);
+        self
+    }
+
+    // the paths must exist before the root is changed;
+    // without restrict_filesystem all the access kinds are handled
+    pub fn allow_path<T>(&mut self, path: T, access: LandlockAccess)
+            -> &mut Self where T: AsRef<Path> {
+        if self.landlock.is_none() {
+            self.restrict_filesystem(LandlockAccess::all());
+        }
+        self.allowed_paths.push((path.as_ref().to_path_buf(), access));
+        self
+    }
+
+    // must be called before any threads are started
+    pub fn apply(&self) -> Result<()> {
+        if let Some(bits) = self.securebits {
+            set_securebits(bits)?;
+        }
+
+        // the ruleset holds the paths open across the root change
+        let landlock = match self.landlock {
+            Some(handled) => match LandlockRuleset::new(handled)? {
+                Some(mut ruleset) => {
+                    for &(ref path, access) in &self.allowed_paths {
+                        ruleset.allow(path, access)?;
+                    }
+                    Some(ruleset)
+                },
+                None => {
+                    debug!("no landlock in the kernel, skipping");
+                    None
+                }
+            },
+            None => None
+        };
+
+        if !self.namespaces.is_empty() {
+            unshare_namespaces(self.namespaces)?;
+        }
+        if self.empty_root {
+            enter_empty_root()?;
+        }
+        for &(resource, limit) in &self.limits {
+            limit_resource(resource, limit)?;
+        }
+        if self.no_new_privs {
+            set_no_new_privs()?;
+        }
+        if let Some(ruleset) = landlock {
+            ruleset.restrict_self()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::panic::{catch_unwind, UnwindSafe};
+
+    use ::nix::sys::wait::*;
+    use ::nix::unistd::*;
+
+    // the sandbox is applied to a child, not to the test harness
+    fn in_child<F>(f: F) where F: FnOnce() + UnwindSafe {
+        match fork().unwrap() {
+            ForkResult::Child => {
+                let ret = catch_unwind(f);
+                unsafe { ::nlibc::_exit(if ret.is_ok() { 0 } else { 1 }) }
+            },
+            ForkResult::Parent { child } => assert_eq!(
+                waitpid(child, None).unwrap(),
+                WaitStatus::Exited(child, 0)
+            )
+        }
+    }
+
+    #[test]
+    fn builder_implications() {
+        let mut sandbox = Sandbox::new();
+        sandbox.empty_root()
+            .restrict_filesystem(LandlockAccess::all())
+            .allow_path("/", LandlockAccess::ReadFile);
+
+        assert!(sandbox.namespaces.contains(Namespaces::Mount));
+        assert!(sandbox.no_new_privs);
+        assert_eq!(sandbox.landlock, Some(LandlockAccess::all()));
+        assert_eq!(sandbox.allowed_paths.len(), 1);
+    }
+
+    #[test]
+    fn paths_imply_a_ruleset() {
+        let mut sandbox = Sandbox::new();
+        sandbox.allow_path("/", LandlockAccess::ReadFile)
+            .restrict_filesystem(LandlockAccess::ReadFile);
+
+        assert!(sandbox.no_new_privs);
+        assert_eq!(sandbox.landlock, Some(LandlockAccess::ReadFile));
+        assert_eq!(sandbox.allowed_paths.len(), 1);
+    }
+
+    #[test]
+    fn open_files_limit() {
+        in_child(|| {
+            let next_fd = fs::File::open("/").unwrap().as_raw_fd();
+            Sandbox::new()
+                .limit(Resource::OpenFiles, next_fd as rlim_t)
+                .apply()
+                .unwrap();
+
+            let err = fs::File::open("/").unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(::nlibc::EMFILE));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs Landlock in the kernel"]
+    fn landlock_denies_other_paths() {
+        in_child(|| {
+            assert!(LandlockRuleset::abi_version() > 0);
+            Sandbox::new()
+                .allow_path("/proc", LandlockAccess::ReadDir)
+                .apply()
+                .unwrap();
+
+            assert!(fs::read_dir("/proc").is_ok());
+            let err = fs::read_dir("/").unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(::nlibc::EACCES));
+        });
+    }
+}
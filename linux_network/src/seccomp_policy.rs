@@ -0,0 +1,322 @@
+// A seccomp policy assembled from the sockets a program uses.
+// Each socket type knows the system calls made on its descriptor,
+// the futures adapters add those of the tokio reactor and thread pool.
+
+use ::std::collections::BTreeSet;
+use ::std::fs::read_dir;
+use ::std::os::unix::prelude::*;
+
+use ::nlibc::*;
+use ::seccomp::*;
+
+use ::*;
+use ::errors::Result;
+
+// the process can not run without these
+const PROCESS_SYSCALLS: &[c_long] = &[
+    SYS_brk, SYS_clock_gettime, SYS_close, SYS_exit, SYS_exit_group,
+    SYS_futex, SYS_mmap, SYS_mremap, SYS_munmap, SYS_restart_syscall,
+    SYS_rt_sigreturn, SYS_sigaltstack
+];
+
+// the sockets take options and interface ioctls after the setup too,
+// fcntl(2) duplicates them for the writer tasks
+const SOCKET_SYSCALLS: &[c_long] = &[
+    SYS_fcntl, SYS_getsockname, SYS_getsockopt, SYS_ioctl, SYS_recvfrom,
+    SYS_recvmmsg, SYS_recvmsg, SYS_sendmmsg, SYS_sendmsg, SYS_sendto,
+    SYS_setsockopt
+];
+
+// poll(2) takes an array of descriptors, so it can not be tied to one;
+// the generic system call table of aarch64 and riscv64 has only ppoll(2)
+#[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+const RING_SYSCALLS: &[c_long] = &[SYS_poll, SYS_ppoll];
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const RING_SYSCALLS: &[c_long] = &[SYS_ppoll];
+
+// the reactor, its wakeup pipe or eventfd, the timer and the worker threads
+// of a runtime built before the filter is loaded; glibc 2.34 creates
+// the threads with clone3(2) and registers them with rseq(2)
+const RUNTIME_SYSCALLS: &[c_long] = &[
+    SYS_clock_getres, SYS_clock_nanosleep, SYS_clone, SYS_clone3,
+    SYS_epoll_create1, SYS_epoll_ctl, SYS_epoll_pwait, SYS_eventfd2,
+    SYS_getrandom, SYS_madvise, SYS_mprotect, SYS_nanosleep, SYS_pipe2,
+    SYS_prctl, SYS_rseq, SYS_rt_sigprocmask, SYS_sched_yield,
+    SYS_set_robust_list, SYS_timerfd_create, SYS_timerfd_gettime,
+    SYS_timerfd_settime
+];
+
+// epoll_wait(2) is epoll_pwait(2) in the generic system call table
+#[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
+const RUNTIME_ARCH_SYSCALLS: &[c_long] = &[SYS_epoll_wait];
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const RUNTIME_ARCH_SYSCALLS: &[c_long] = &[];
+
+// read(2) and write(2) on the wakeup descriptors of the reactor,
+// whose numbers are not exposed by tokio, see FdSnapshot
+const RUNTIME_IO_SYSCALLS: &[c_long] = &[SYS_read, SYS_write];
+
+pub trait SyscallSet: AsRawFd {
+    // the system calls taking the descriptor as the first argument
+    fn fd_syscalls(&self) -> &'static [c_long];
+
+    // the system calls made on behalf of the descriptor on any arguments
+    fn process_syscalls(&self) -> &'static [c_long] {
+        &[]
+    }
+
+    fn needs_runtime(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum PolicyRule {
+    Any(c_long),
+    Fd(c_long, RawFd)
+}
+
+// the descriptors open at the moment, to tell the ones a runtime opens
+// while it is built
+#[derive(Clone, Debug)]
+pub struct FdSnapshot(BTreeSet<RawFd>);
+
+#[derive(Clone, Debug)]
+pub struct SeccompPolicy {
+    rules: BTreeSet<PolicyRule>
+}
+
+impl SeccompPolicy {
+    pub fn new() -> Self {
+        let mut ret = SeccompPolicy {
+            rules: BTreeSet::new()
+        };
+        ret.allow_syscalls(PROCESS_SYSCALLS);
+        ret
+    }
+
+    pub fn allow_socket<T>(&mut self, sock: &T) -> &mut Self
+            where T: SyscallSet {
+        let fd = sock.as_raw_fd();
+        for &i in sock.fd_syscalls() {
+            self.rules.insert(PolicyRule::Fd(i, fd));
+        }
+        self.allow_syscalls(sock.process_syscalls());
+        if sock.needs_runtime() {
+            self.allow_runtime();
+        }
+        self
+    }
+
+    // what tokio needs besides the sockets, for the programs that
+    // lock down after building the runtime and before starting it
+    pub fn allow_runtime(&mut self) -> &mut Self {
+        self.allow_syscalls(RUNTIME_SYSCALLS)
+            .allow_syscalls(RUNTIME_ARCH_SYSCALLS)
+    }
+
+    // read(2) and write(2) on the descriptors the runtime opened,
+    // see FdSnapshot::opened_since()
+    pub fn allow_runtime_fds(&mut self, fds: &[RawFd]) -> &mut Self {
+        for &fd in fds {
+            for &i in RUNTIME_IO_SYSCALLS {
+                self.rules.insert(PolicyRule::Fd(i, fd));
+            }
+        }
+        self
+    }
+
+    pub fn allow_syscall(&mut self, syscall: c_long) -> &mut Self {
+        self.rules.insert(PolicyRule::Any(syscall));
+        self
+    }
+
+    pub fn allow_syscalls(&mut self, syscalls: &[c_long]) -> &mut Self {
+        for &i in syscalls {
+            self.allow_syscall(i);
+        }
+        self
+    }
+
+    pub fn allow_fd_syscall(&mut self, fd: RawFd, syscall: c_long)
+            -> &mut Self {
+        self.rules.insert(PolicyRule::Fd(syscall, fd));
+        self
+    }
+
+    // adds the rules to a context made by the caller
+    pub fn add_to(&self, ctx: &mut Context) -> Result<()> {
+        for &i in &self.rules {
+            ctx.add_rule(i.to_rule())?;
+        }
+        Ok(())
+    }
+
+    // the context is yet to be loaded,
+    // anything not allowed gets the default action
+    pub fn build(&self, default: Action) -> Result<Context> {
+        let mut ctx = Context::default(default)?;
+        self.add_to(&mut ctx)?;
+        Ok(ctx)
+    }
+}
+
+impl FdSnapshot {
+    pub fn take() -> Result<Self> {
+        Ok(FdSnapshot(open_fds()?))
+    }
+
+    // the descriptors opened since the snapshot and still open
+    pub fn opened_since(&self) -> Result<Vec<RawFd>> {
+        Ok(open_fds()?.difference(&self.0).cloned().collect())
+    }
+}
+
+fn open_fds() -> Result<BTreeSet<RawFd>> {
+    let mut fds = BTreeSet::new();
+    for i in read_dir("/proc/self/fd")? {
+        if let Ok(fd) = i?.file_name().to_string_lossy().parse() {
+            fds.insert(fd);
+        }
+    }
+
+    // the directory's own descriptor is listed too, and closed by now
+    Ok(fds.into_iter()
+        .filter(|&x| unsafe { fcntl(x, F_GETFD) } != -1)
+        .collect())
+}
+
+impl PolicyRule {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn to_rule(self) -> Rule {
+        let (syscall, op, arg) = match self {
+            // the argument is not looked at, the comparison always holds
+            PolicyRule::Any(syscall) => (syscall, Op::Ge, 0),
+            PolicyRule::Fd(syscall, fd) => (syscall, Op::Eq, fd as u64)
+        };
+
+        Rule::new(
+            syscall as usize,
+            Compare::arg(0)
+                .using(op)
+                .with(arg)
+                .build()
+                .unwrap(),
+            Action::Allow
+        )
+    }
+}
+
+macro_rules! gen_syscall_set {
+    // for a generic type, with the generics in the brackets
+    ([$($gen:tt)*] $typ:ty, $fd_syscalls:expr, $process_syscalls:expr,
+            $runtime:expr) => (
+        impl<$($gen)*> SyscallSet for $typ {
+            fn fd_syscalls(&self) -> &'static [c_long] {
+                $fd_syscalls
+            }
+
+            fn process_syscalls(&self) -> &'static [c_long] {
+                $process_syscalls
+            }
+
+            fn needs_runtime(&self) -> bool {
+                $runtime
+            }
+        }
+    );
+    ($typ:ty, $fd_syscalls:expr, $process_syscalls:expr, $runtime:expr) => (
+        gen_syscall_set!([] $typ, $fd_syscalls, $process_syscalls, $runtime);
+    )
+}
+
+gen_syscall_set!([F: Family, P: Protocol] RawSocket<F, P>,
+    SOCKET_SYSCALLS, &[], false);
+gen_syscall_set!(IPv6PacketSocket, SOCKET_SYSCALLS, &[], false);
+gen_syscall_set!(PacketSocket, SOCKET_SYSCALLS, &[], false);
+gen_syscall_set!(::ring::IPv6PacketRing, SOCKET_SYSCALLS, RING_SYSCALLS,
+    false);
+gen_syscall_set!(::rtnetlink::RtNetlinkSocket, SOCKET_SYSCALLS, &[], false);
+
+#[cfg(feature = "async")]
+mod futures {
+    use super::*;
+    use ::futures::*;
+
+    gen_syscall_set!([F: Family, P: Protocol] RawSocketAdapter<F, P>,
+        SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(IPv6PacketSocketAdapter, SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(PacketSocketAdapter, SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(IPv6PacketRingAdapter, SOCKET_SYSCALLS, RING_SYSCALLS,
+        true);
+    gen_syscall_set!(RtNetlinkEvents, SOCKET_SYSCALLS, &[], true);
+
+    gen_syscall_set!([F: Family, P: Protocol] RawSocketRecvHalf<F, P>,
+        SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!([F: Family, P: Protocol] RawSocketSendHalf<F, P>,
+        SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(IPv6PacketSocketRecvHalf, SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(IPv6PacketSocketSendHalf, SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(PacketSocketRecvHalf, SOCKET_SYSCALLS, &[], true);
+    gen_syscall_set!(PacketSocketSendHalf, SOCKET_SYSCALLS, &[], true);
+}
+
+#[cfg(all(test, feature = "async"))]
+mod test {
+    use super::*;
+
+    use ::std::net::*;
+    use ::std::thread;
+    use ::std::time::Duration;
+
+    use ::bytes::{Bytes, BytesMut};
+    use ::tokio::prelude::*;
+    use ::tokio::runtime::Runtime;
+    use ::tokio::timer::Timeout;
+
+    use ::futures::IPv6PingSocketAdapter;
+
+    // The filter kills on anything not allowed, and the thread pool runtime
+    // is built before it is loaded, as in the stream mode of the ping6-data
+    // tools.  The filter is loaded on a thread of its own and stays there.
+    #[test]
+    #[ignore = "needs the group within net.ipv4.ping_group_range"]
+    fn adapter_under_filter() {
+        let sock = IPv6PingSocket::new(SockFlag::empty()).unwrap();
+
+        thread::spawn(move || {
+            let fds = FdSnapshot::take().unwrap();
+            let mut rt = Runtime::new().unwrap();
+
+            set_no_new_privs().unwrap();
+            SeccompPolicy::new()
+                .allow_socket(&sock)
+                .allow_runtime()
+                .allow_runtime_fds(&fds.opened_since().unwrap())
+                .build(Action::Kill)
+                .unwrap()
+                .load()
+                .unwrap();
+
+            let mut sock = IPv6PingSocketAdapter::new(rt.reactor(), sock)
+                .unwrap();
+
+            let localhost = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+            let request = Bytes::from(&[
+                IcmpV6Type::EchoRequest.repr(), 0, 0, 0,
+                0, 0, 0, 1
+            ][..]);
+            rt.block_on(
+                sock.sendto(request, localhost, SendFlags::empty())
+            ).unwrap();
+
+            let reply = Timeout::new(
+                sock.recvfrom(BytesMut::from(vec![0; 64]), RecvFlags::empty()),
+                Duration::from_secs(5)
+            );
+            let (reply, src) = rt.block_on(reply).unwrap();
+            assert_eq!(*src.ip(), Ipv6Addr::LOCALHOST);
+            assert_eq!(reply[0], IcmpV6Type::EchoReply.repr());
+        }).join().unwrap();
+    }
+}
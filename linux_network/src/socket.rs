@@ -1,7 +1,9 @@
 use ::std::ffi::*;
+use ::std::marker::PhantomData;
 use ::std::mem::*;
 use ::std::net::*;
 use ::std::os::unix::prelude::*;
+use ::std::ptr::null_mut;
 
 use ::nlibc::*;
 use ::nix::sys::socket::{AddressFamily, SockType, socket};
@@ -9,58 +11,315 @@ use ::pnet_packet::*;
 use ::pnet_packet::ipv6::*;
 
 use ::*;
+use ::cmsg::{CmsgBuffer, encode_cmsgs, parse_cmsgs};
 use ::errors::{Error, Result};
 use ::util::*;
 
 pub use ::nix::sys::socket::SockFlag;
 
-pub struct IPv6RawSocket(IPv6RawSocketImpl);
-struct IPv6RawSocketImpl(RawFd);
+// An address family: the address its sockets exchange, and the sockaddr
+// the kernel takes and fills in for it
+pub trait Family {
+    type Addr: Copy;
+    type Raw: Copy;
 
-impl IPv6RawSocket {
+    const DOMAIN: AddressFamily;
+
+    fn to_raw(addr: &Self::Addr) -> Self::Raw;
+    fn from_raw(raw: &Self::Raw) -> Result<Self::Addr>;
+
+    // for the sockets not taking a port, see Protocol::TAKES_PORT
+    fn clear_port(_raw: &mut Self::Raw) {}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Inet6 {}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Inet {}
+
+// AF_PACKET, the frames of a single interface
+#[derive(Clone, Copy, Debug)]
+pub enum LinkLayer {}
+
+impl Family for Inet6 {
+    type Addr = SocketAddrV6;
+    type Raw = sockaddr_in6;
+
+    const DOMAIN: AddressFamily = AddressFamily::Inet6;
+
+    fn to_raw(addr: &SocketAddrV6) -> sockaddr_in6 {
+        make_sockaddr_in6(*addr)
+    }
+
+    fn from_raw(raw: &sockaddr_in6) -> Result<SocketAddrV6> {
+        Ok(make_socketaddrv6(raw))
+    }
+
+    fn clear_port(raw: &mut sockaddr_in6) {
+        raw.sin6_port = 0;
+    }
+}
+
+impl Family for Inet {
+    type Addr = SocketAddrV4;
+    type Raw = sockaddr_in;
+
+    const DOMAIN: AddressFamily = AddressFamily::Inet;
+
+    fn to_raw(addr: &SocketAddrV4) -> sockaddr_in {
+        make_sockaddr_in(*addr)
+    }
+
+    fn from_raw(raw: &sockaddr_in) -> Result<SocketAddrV4> {
+        Ok(make_socketaddrv4(raw))
+    }
+}
+
+// proto is the ethertype, in the host byte order
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LinkAddr {
+    pub if_index: c_int,
+    pub proto: u16,
+    pub hwaddr: Option<HwAddr>
+}
+
+impl Family for LinkLayer {
+    type Addr = LinkAddr;
+    type Raw = sockaddr_ll;
+
+    const DOMAIN: AddressFamily = AddressFamily::Packet;
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn to_raw(addr: &LinkAddr) -> sockaddr_ll { unsafe {
+        let mut addr_ll: sockaddr_ll = zeroed();
+
+        addr_ll.sll_family = AF_PACKET as c_ushort;
+        addr_ll.sll_protocol = addr.proto.to_be();
+        addr_ll.sll_ifindex = addr.if_index;
+        if let Some(hwaddr) = addr.hwaddr {
+            let len = hwaddr.len();
+            addr_ll.sll_halen = len as c_uchar;
+            addr_ll.sll_addr[0..len].copy_from_slice(hwaddr.as_bytes());
+        }
+
+        addr_ll
+    }}
+
+    fn from_raw(raw: &sockaddr_ll) -> Result<LinkAddr> {
+        Ok(LinkAddr {
+            if_index: raw.sll_ifindex,
+            proto: u16::from_be(raw.sll_protocol),
+            hwaddr: hwaddr_from_sockaddr_ll(raw)?
+        })
+    }
+}
+
+// the socket type of a kind of sockets
+pub trait Protocol {
+    const SOCK_TYPE: SockType;
+    // whether the port of the addresses given reaches the kernel
+    const TAKES_PORT: bool;
+}
+
+// SOCK_RAW, with the protocol given on creation
+#[derive(Clone, Copy, Debug)]
+pub enum RawProto {}
+
+// SOCK_DGRAM ICMP sockets, see IPv6PingSocket
+#[derive(Clone, Copy, Debug)]
+pub enum PingProto {}
+
+// a nonzero port of an IPv6 raw socket stands for the protocol,
+// sendto(2) fails with EINVAL on any other one
+impl Protocol for RawProto {
+    const SOCK_TYPE: SockType = SockType::Raw;
+    const TAKES_PORT: bool = false;
+}
+
+// the port is the echo identifier
+impl Protocol for PingProto {
+    const SOCK_TYPE: SockType = SockType::Datagram;
+    const TAKES_PORT: bool = true;
+}
+
+// Owns the descriptor, the system calls are made by RawSocketImpl,
+// which the adapters build from the descriptor they poll.
+pub struct RawSocket<F, P> where F: Family, P: Protocol {
+    sock: RawSocketImpl<F, P>,
+    proto: PhantomData<P>
+}
+
+pub type IPv6RawSocket = RawSocket<Inet6, RawProto>;
+
+// received datagrams start with the IPv4 header, unlike with IPv6
+pub type IPv4RawSocket = RawSocket<Inet, RawProto>;
+
+// An unprivileged ICMPv6 datagram socket, for the groups within
+// the net.ipv4.ping_group_range sysctl.  Only the echo requests can be sent,
+// and only the echo replies to them are received.  The kernel computes
+// the checksums and replaces the identifier of the requests
+// with the socket's one, see identifier().
+pub type IPv6PingSocket = RawSocket<Inet6, PingProto>;
+
+struct RawSocketImpl<F, P = RawProto> where F: Family, P: Protocol {
+    fd: RawFd,
+    family: PhantomData<F>,
+    proto: PhantomData<P>
+}
+
+impl<F, P> Clone for RawSocketImpl<F, P> where F: Family, P: Protocol {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<F, P> Copy for RawSocketImpl<F, P> where F: Family, P: Protocol {}
+
+impl<F> RawSocket<F, RawProto> where F: Family {
     pub fn new(proto: c_int, flags: SockFlag) -> Result<Self> {
-        Ok(
-            IPv6RawSocket(IPv6RawSocketImpl(
-                socket(
-                    AddressFamily::Inet6,
-                    SockType::Raw,
-                    flags,
-                    proto
-                )?
-            ))
-        )
+        Self::open(proto, flags)
+    }
+}
+
+impl RawSocket<Inet6, PingProto> {
+    pub fn new(flags: SockFlag) -> Result<Self> {
+        Self::open(IPPROTO_ICMPV6, flags)
+    }
+
+    // the echo identifier in use, 0 until the socket is bound
+    // or sends its first request
+    pub fn identifier(&self) -> Result<u16> {
+        get_identifier(self.as_raw_fd())
+    }
+}
+
+impl<F, P> RawSocket<F, P> where F: Family, P: Protocol {
+    fn open(proto: c_int, flags: SockFlag) -> Result<Self> {
+        let fd = socket(F::DOMAIN, P::SOCK_TYPE, flags, proto)?;
+        Ok(Self::from_impl(RawSocketImpl::from_fd(fd)))
+    }
+
+    fn from_impl(sock: RawSocketImpl<F, P>) -> Self {
+        RawSocket { sock, proto: PhantomData }
+    }
+
+    // for the ping sockets, the port of addr requests an identifier,
+    // 0 lets the kernel choose
+    pub fn bind(&mut self, addr: F::Addr) -> Result<()> {
+        self.sock.bind(addr)
     }
 
-    pub fn bind(&mut self, addr: SocketAddrV6) -> Result<()> {
-        self.0.bind(addr)
+    // another descriptor for the same socket, e.g. for a second task
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self::from_impl(RawSocketImpl::from_fd(dup_fd(self)?)))
     }
 
     pub fn recvfrom<'a>(
         &mut self,
         buf: &'a mut [u8],
         flags: RecvFlags
-    ) -> Result<(&'a mut [u8], SocketAddrV6)> {
-        self.0.recvfrom(buf, flags)
+    ) -> Result<(&'a mut [u8], F::Addr)> {
+        self.sock.recvfrom(buf, flags)
     }
 
     pub fn sendto(
         &mut self,
         buf: &[u8],
-        addr: SocketAddrV6,
+        addr: F::Addr,
+        flags: SendFlags
+    ) -> Result<size_t> {
+        self.sock.sendto(buf, addr, flags)
+    }
+
+    // the ancillary data is only delivered
+    // with the corresponding SockOpts::Recv* options set
+    pub fn recvmsg<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        flags: RecvFlags
+    ) -> Result<(&'a mut [u8], F::Addr, Vec<Cmsg>)> {
+        self.sock.recvmsg(buf, flags)
+    }
+
+    pub fn sendmsg(
+        &mut self,
+        buf: &[u8],
+        addr: F::Addr,
+        cmsgs: &[Cmsg],
         flags: SendFlags
     ) -> Result<size_t> {
-        self.0.sendto(buf, addr, flags)
+        self.sock.sendmsg(buf, addr, cmsgs, flags)
+    }
+
+    // buf is split into slots of slot_size bytes, one datagram per slot;
+    // returns the lengths and the sources of the slots filled
+    pub fn recvfrom_batch(
+        &mut self,
+        buf: &mut [u8],
+        slot_size: usize,
+        flags: RecvFlags
+    ) -> Result<Vec<(usize, F::Addr)>> {
+        self.sock.recvfrom_batch(buf, slot_size, flags)
+    }
+
+    // fills the pool with up to pool.slots() datagrams
+    // in a single system call
+    pub fn recvfrom_pool(
+        &mut self,
+        pool: &mut BufferPool<F::Addr>,
+        flags: RecvFlags
+    ) -> Result<usize> where F::Addr: PoolAddr {
+        recv_into_pool(self.as_raw_fd(), pool, flags)
+    }
+
+    // returns the number of datagrams sent, which may be less than requested
+    pub fn sendto_batch<T>(
+        &mut self,
+        msgs: &[(T, F::Addr)],
+        flags: SendFlags
+    ) -> Result<usize> where T: AsRef<[u8]> {
+        self.sock.sendto_batch(msgs, flags)
+    }
+
+    // the oldest queued transmit timestamp, None if there is none yet;
+    // needs SockOpts::Timestamping with the Tx* flags
+    pub fn recv_tx_timestamp(&mut self) -> Result<Option<TxTimestamp>> {
+        self.sock.recv_tx_timestamp()
+    }
+
+    // the source of ErrQueueEntry, the destination of the offending packet
+    // and the ancillary data describing it
+    pub(crate) fn recvmsg_errqueue(&mut self)
+            -> Result<Option<(F::Addr, Vec<Cmsg>)>> {
+        self.sock.recvmsg_errqueue()
     }
 }
 
 #[allow(clippy::cast_possible_truncation)]
-impl IPv6RawSocketImpl {
-    fn bind(&mut self, addr: SocketAddrV6) -> Result<()> { unsafe {
-        let addr_in = make_sockaddr_in6(addr);
+impl<F, P> RawSocketImpl<F, P> where F: Family, P: Protocol {
+    fn from_fd(fd: RawFd) -> Self {
+        RawSocketImpl {
+            fd,
+            family: PhantomData,
+            proto: PhantomData
+        }
+    }
+
+    fn to_raw(addr: &F::Addr) -> F::Raw {
+        let mut raw = F::to_raw(addr);
+        if !P::TAKES_PORT {
+            F::clear_port(&mut raw);
+        }
+        raw
+    }
+
+    fn bind(&mut self, addr: F::Addr) -> Result<()> { unsafe {
+        let addr_raw = Self::to_raw(&addr);
         n1try!(bind(
-            self.0,
-            as_sockaddr(&addr_in),
-            size_of_val(&addr_in) as socklen_t
+            self.fd,
+            as_sockaddr(&addr_raw),
+            size_of_val(&addr_raw) as socklen_t
         ));
         Ok(())
     }}
@@ -70,12 +329,12 @@ impl IPv6RawSocketImpl {
         &mut self,
         buf: &'a mut [u8],
         flags: RecvFlags
-    ) -> Result<(&'a mut [u8], SocketAddrV6)> { unsafe {
-        let mut addr: sockaddr_in6 = zeroed();
+    ) -> Result<(&'a mut [u8], F::Addr)> { unsafe {
+        let mut addr: F::Raw = zeroed();
 
         let mut addr_size = size_of_val(&addr) as socklen_t;
         let size = n1try!(::nlibc::recvfrom(
-            self.0,
+            self.fd,
             ref_to_mut_cvoid(buf),
             buf.len() as size_t,
             flags.bits(),
@@ -83,53 +342,191 @@ impl IPv6RawSocketImpl {
             &mut addr_size
         ));
 
-        let sockaddr = SocketAddrV6::new(
-            addr_from_segments(&addr.sin6_addr.s6_addr),
-            in_port_t::from_be(addr.sin6_port),
-            addr.sin6_flowinfo,
-            addr.sin6_scope_id
-        );
-
-        Ok((&mut buf[..size as usize], sockaddr))
+        Ok((&mut buf[..size as usize], F::from_raw(&addr)?))
     }}
 
     #[allow(clippy::cast_sign_loss)]
     fn sendto(
         &mut self,
         buf: &[u8],
-        addr: SocketAddrV6,
+        addr: F::Addr,
         flags: SendFlags
     ) -> Result<size_t> { unsafe {
-        let addr_in = make_sockaddr_in6(addr);
-        let addr_size = size_of_val(&addr_in) as socklen_t;
+        let addr_raw = Self::to_raw(&addr);
+        let addr_size = size_of_val(&addr_raw) as socklen_t;
 
         Ok(n1try!(::nlibc::sendto(
-            self.0,
+            self.fd,
             ref_to_cvoid(buf),
             buf.len() as size_t,
             flags.bits(),
-            as_sockaddr(&addr_in),
+            as_sockaddr(&addr_raw),
             addr_size)) as size_t
         )
     }}
+
+    #[allow(clippy::cast_sign_loss)]
+    fn recvmsg<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        flags: RecvFlags
+    ) -> Result<(&'a mut [u8], F::Addr, Vec<Cmsg>)> { unsafe {
+        let mut addr: F::Raw = zeroed();
+        let mut iov = make_iovec(buf);
+        let mut control = CmsgBuffer::new(CMSG_BUF_SIZE);
+        let mut hdr = make_msghdr(&mut iov, &mut addr);
+        hdr.msg_control = control.as_mut_ptr();
+        hdr.msg_controllen = control.len() as _;
+
+        let size = n1try!(::nlibc::recvmsg(self.fd, &mut hdr, flags.bits()));
+
+        if hdr.msg_flags & MSG_CTRUNC != 0 {
+            debug!("ancillary data truncated");
+        }
+        let control_len = (hdr.msg_controllen as usize).min(control.len());
+        let cmsgs = parse_cmsgs(&control.bytes()[..control_len]);
+
+        Ok((&mut buf[..size as usize], F::from_raw(&addr)?, cmsgs))
+    }}
+
+    #[allow(clippy::cast_sign_loss)]
+    fn sendmsg(
+        &mut self,
+        buf: &[u8],
+        addr: F::Addr,
+        cmsgs: &[Cmsg],
+        flags: SendFlags
+    ) -> Result<size_t> { unsafe {
+        let mut addr_raw = Self::to_raw(&addr);
+        let mut iov = make_iovec_const(buf);
+        let mut control = encode_cmsgs(cmsgs)?;
+        let mut hdr = make_msghdr(&mut iov, &mut addr_raw);
+        if !control.is_empty() {
+            hdr.msg_control = control.as_mut_ptr();
+            hdr.msg_controllen = control.len() as _;
+        }
+
+        Ok(n1try!(::nlibc::sendmsg(self.fd, &hdr, flags.bits())) as size_t)
+    }}
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn recvfrom_batch(
+        &mut self,
+        buf: &mut [u8],
+        slot_size: usize,
+        flags: RecvFlags
+    ) -> Result<Vec<(usize, F::Addr)>> { unsafe {
+        if slot_size == 0 || buf.len() < slot_size {
+            return Err(Error::BufferTooSmall { len: buf.len() }.into());
+        }
+        let count = buf.len() / slot_size;
+
+        let mut addrs: Vec<F::Raw> = vec![zeroed(); count];
+        let mut iovs: Vec<iovec> = buf.chunks_mut(slot_size)
+            .take(count)
+            .map(|x| make_iovec(x))
+            .collect();
+        let mut hdrs: Vec<mmsghdr> = iovs.iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| make_mmsghdr(iov, addr))
+            .collect();
+
+        let received = n1try!(recvmmsg(
+            self.fd,
+            hdrs.as_mut_ptr(),
+            count as c_uint,
+            flags.bits() as _,
+            null_mut()
+        )) as usize;
+
+        hdrs[..received].iter()
+            .zip(&addrs)
+            .map(|(hdr, addr)| Ok((hdr.msg_len as usize, F::from_raw(addr)?)))
+            .collect()
+    }}
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn sendto_batch<T>(
+        &mut self,
+        msgs: &[(T, F::Addr)],
+        flags: SendFlags
+    ) -> Result<usize> where T: AsRef<[u8]> { unsafe {
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+
+        let mut addrs: Vec<F::Raw> = msgs.iter()
+            .map(|&(_, ref addr)| Self::to_raw(addr))
+            .collect();
+        let mut iovs: Vec<iovec> = msgs.iter()
+            .map(|&(ref buf, _)| make_iovec_const(buf.as_ref()))
+            .collect();
+        let mut hdrs: Vec<mmsghdr> = iovs.iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| make_mmsghdr(iov, addr))
+            .collect();
+
+        Ok(n1try!(sendmmsg(
+            self.fd,
+            hdrs.as_mut_ptr(),
+            hdrs.len() as c_uint,
+            flags.bits() as _
+        )) as usize)
+    }}
+
+    // the oldest entry of the error queue, None if it is empty;
+    // reading the error queue never blocks
+    fn recvmsg_errqueue(&mut self) -> Result<Option<(F::Addr, Vec<Cmsg>)>> {
+        // only the headers of the offending packet are of interest,
+        // it is looped back with the timestamps too,
+        // unless TimestampingFlags::OptTsonly
+        let mut buf = [0; 128];
+        match self.recvmsg(&mut buf, RecvFlags::ErrQueue) {
+            Ok((_, addr, cmsgs)) => Ok(Some((addr, cmsgs))),
+            Err(e) => match e.downcast::<Error>() {
+                Ok(Error::Again(_)) => Ok(None),
+                Ok(e) => Err(e.into()),
+                Err(e) => Err(e)
+            }
+        }
+    }
+
+    // the ICMPv6 errors queued along are dropped, see recv_errqueue()
+    fn recv_tx_timestamp(&mut self) -> Result<Option<TxTimestamp>> {
+        while let Some((_, cmsgs)) = self.recvmsg_errqueue()? {
+            if let Some(x) = TxTimestamp::from_cmsgs(&cmsgs) {
+                return Ok(Some(x));
+            }
+        }
+        Ok(None)
+    }
 }
 
-impl Drop for IPv6RawSocket {
+#[allow(clippy::cast_possible_truncation)]
+fn get_identifier(fd: RawFd) -> Result<u16> { unsafe {
+    let mut addr: sockaddr_in6 = zeroed();
+    let mut addr_size = size_of_val(&addr) as socklen_t;
+    n1try!(getsockname(fd, as_sockaddr_mut(&mut addr), &mut addr_size));
+    Ok(u16::from_be(addr.sin6_port))
+}}
+
+impl<F, P> Drop for RawSocket<F, P> where F: Family, P: Protocol {
     fn drop(&mut self) {
         log_if_err(::nix::unistd::close(self.as_raw_fd())
             .map_err(|e| e.into()));
     }
 }
 
-impl AsRawFd for IPv6RawSocketImpl {
+impl<F, P> AsRawFd for RawSocketImpl<F, P>
+        where F: Family, P: Protocol {
     fn as_raw_fd(&self) -> RawFd {
-        self.0
+        self.fd
     }
 }
 
-impl AsRawFd for IPv6RawSocket {
+impl<F, P> AsRawFd for RawSocket<F, P> where F: Family, P: Protocol {
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.sock.as_raw_fd()
     }
 }
 
@@ -137,6 +534,7 @@ fn make_sockaddr_in6(addr: SocketAddrV6) -> sockaddr_in6 { unsafe {
     let mut addr_in: sockaddr_in6 = zeroed();
 
     addr_in.sin6_family = AddressFamily::Inet6 as u16;
+    addr_in.sin6_port = addr.port().to_be();
     addr_in.sin6_flowinfo = addr.flowinfo();
     addr_in.sin6_scope_id = addr.scope_id();
 
@@ -147,94 +545,246 @@ fn make_sockaddr_in6(addr: SocketAddrV6) -> sockaddr_in6 { unsafe {
     addr_in
 }}
 
-pub struct IPv6PacketSocket(IPv6PacketSocketImpl);
-struct IPv6PacketSocketImpl {
-    fd: RawFd,
-    if_index: c_int,
-    macaddr: MacAddr,
-    proto: c_ushort
+fn make_socketaddrv6(addr: &sockaddr_in6) -> SocketAddrV6 {
+    SocketAddrV6::new(
+        addr_from_segments(&addr.sin6_addr.s6_addr),
+        in_port_t::from_be(addr.sin6_port),
+        addr.sin6_flowinfo,
+        addr.sin6_scope_id
+    )
 }
 
-impl IPv6PacketSocket {
-    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-    pub fn new<T>(proto: u16, flags: SockFlag, if_name: T)
-            -> Result<Self> where
-            T: AsRef<str> {
-        let name = if_name.as_ref();
-        let iface = ::interfaces::Interface::get_by_name(name)
-            .map_err(|e| Error::GetInterfaceError {
-                name: name.to_string(),
-                cause: e
-            })?.ok_or(Error::NoInterface {
-                name: name.to_string()
-            })?;
-        let if_addr = MacAddr::from_bytes(iface.hardware_addr()?.as_bytes())?;
+unsafe fn make_iovec(buf: &mut [u8]) -> iovec {
+    iovec {
+        iov_base: ref_to_mut_cvoid(buf),
+        iov_len: buf.len()
+    }
+}
 
-        let proto = proto.to_be() as c_ushort;
+// the kernel does not write through iovecs passed to sendmmsg()
+unsafe fn make_iovec_const(buf: &[u8]) -> iovec {
+    iovec {
+        iov_base: ref_to_cvoid(buf) as *mut c_void,
+        iov_len: buf.len()
+    }
+}
 
-        let sock = socket(
-            AddressFamily::Packet,
-            SockType::Datagram,
-            flags,
-            c_int::from(proto)
-        )?;
+#[allow(clippy::cast_possible_truncation)]
+unsafe fn make_msghdr<T>(iov: &mut iovec, addr: &mut T) -> msghdr {
+    let mut hdr: msghdr = zeroed();
+    hdr.msg_name = ref_to_mut_cvoid(addr);
+    hdr.msg_namelen = size_of::<T>() as socklen_t;
+    hdr.msg_iov = iov;
+    hdr.msg_iovlen = 1;
+    hdr
+}
 
-        let mut ret = IPv6PacketSocketImpl {
-            fd: sock,
-            if_index: -1,
-            macaddr: if_addr,
-            proto
-        };
-        ret.if_index = get_interface_index(&ret, name)?;
+unsafe fn make_mmsghdr<T>(iov: &mut iovec, addr: &mut T) -> mmsghdr {
+    let mut hdr: mmsghdr = zeroed();
+    hdr.msg_hdr = make_msghdr(iov, addr);
+    hdr
+}
+
+impl PoolAddr for SocketAddrV6 {
+    type Raw = sockaddr_in6;
+
+    fn from_raw(raw: &sockaddr_in6) -> Result<Self> {
+        Ok(make_socketaddrv6(raw))
+    }
+}
+
+impl PoolAddr for Option<HwAddr> {
+    type Raw = sockaddr_ll;
 
-        unsafe {
-            let mut addr: sockaddr_ll = zeroed();
-            addr.sll_family = AF_PACKET as c_ushort;
-            addr.sll_protocol = proto;
-            addr.sll_ifindex = ret.if_index;
-            n1try!(bind(
-                sock,
-                as_sockaddr(&addr),
-                size_of_val(&addr) as socklen_t
-            ));
+    fn from_raw(raw: &sockaddr_ll) -> Result<Self> {
+        hwaddr_from_sockaddr_ll(raw)
+    }
+}
+
+// a single recvmmsg() into the slots of the pool, allocating nothing
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn recv_into_pool<A>(fd: RawFd, pool: &mut BufferPool<A>, flags: RecvFlags)
+        -> Result<usize> where A: PoolAddr { unsafe {
+    let slot_size = pool.slot_size();
+    let mut iovs: [iovec; MAX_POOL_SLOTS] = zeroed();
+    let mut hdrs: [mmsghdr; MAX_POOL_SLOTS] = zeroed();
+
+    let count = {
+        let (buf, addrs) = pool.prepare();
+        for (i, (slot, addr)) in buf.chunks_mut(slot_size)
+                .zip(addrs.iter_mut())
+                .enumerate() {
+            iovs[i] = make_iovec(slot);
+            hdrs[i] = make_mmsghdr(&mut iovs[i], addr);
         }
+        addrs.len()
+    };
+
+    let received = n1try!(recvmmsg(
+        fd,
+        hdrs.as_mut_ptr(),
+        count as c_uint,
+        flags.bits() as _,
+        null_mut()
+    )) as usize;
+
+    pool.set_received(hdrs[..received].iter().map(|x| x.msg_len as usize));
+    Ok(pool.len())
+}}
+
+pub struct IPv6PacketSocket(PacketSocketImpl);
+struct PacketSocketImpl {
+    sock: RawSocketImpl<LinkLayer>,
+    if_index: c_int,
+    hwaddr: Option<HwAddr>,
+    proto: u16
+}
 
-        Ok(IPv6PacketSocket(ret))
+impl IPv6PacketSocket {
+    pub fn new<T>(proto: u16, flags: SockFlag, if_name: T)
+            -> Result<Self> where
+            T: AsRef<str> {
+        Ok(IPv6PacketSocket(PacketSocketImpl::new(proto, flags, if_name)?))
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(IPv6PacketSocket(self.0.try_clone()?))
     }
 
     pub fn recvpacket(
         &mut self,
         maxsize: size_t,
         flags: RecvFlags
-    ) -> Result<(Ipv6, MacAddr)> {
+    ) -> Result<(Ipv6, Option<HwAddr>)> {
         self.0.recvpacket(maxsize, flags)
     }
 
     pub fn sendpacket(
             &mut self,
             packet: &Ipv6,
-            dest: Option<MacAddr>,
+            dest: Option<HwAddr>,
             flags: SendFlags
     ) -> Result<size_t> {
         self.0.sendpacket(packet, dest, flags)
     }
 
+    // with the receive timestamps, if SockOpts::Timestamping has the Rx*
+    // flags and a reporting one
+    pub fn recvpacket_timestamped(
+        &mut self,
+        maxsize: size_t,
+        flags: RecvFlags
+    ) -> Result<(Ipv6, Option<HwAddr>, Option<Timestamps>)> {
+        self.0.recvpacket_timestamped(maxsize, flags)
+    }
+
+    // the oldest queued transmit timestamp, None if there is none yet
+    pub fn recv_tx_timestamp(&mut self) -> Result<Option<TxTimestamp>> {
+        self.0.sock.recv_tx_timestamp()
+    }
+
+    // receives up to max_packets packets of at most maxsize bytes
+    // in a single system call
+    pub fn recvpacket_batch(
+        &mut self,
+        maxsize: size_t,
+        max_packets: usize,
+        flags: RecvFlags
+    ) -> Result<Vec<(Ipv6, Option<HwAddr>)>> {
+        self.0.recvpacket_batch(maxsize, max_packets, flags)
+    }
+
+    // the frames stay in the pool, see BufferPool::ipv6_packets()
+    pub fn recvpacket_pool(
+        &mut self,
+        pool: &mut BufferPool<Option<HwAddr>>,
+        flags: RecvFlags
+    ) -> Result<usize> {
+        recv_into_pool(self.as_raw_fd(), pool, flags)
+    }
+
+    // returns the number of packets sent, which may be less than requested
+    pub fn sendpacket_batch(
+        &mut self,
+        packets: &[(Ipv6, Option<HwAddr>)],
+        flags: SendFlags
+    ) -> Result<usize> {
+        self.0.sendpacket_batch(packets, flags)
+    }
+
     pub fn get_interface_index(&self) -> c_int {
         self.0.get_interface_index()
     }
 
-    pub fn get_interface_mac(&self) -> MacAddr {
-        self.0.get_interface_mac()
+    // None if the link has no link-layer addresses
+    pub fn get_interface_hwaddr(&self) -> Option<HwAddr> {
+        self.0.get_interface_hwaddr()
     }
 }
 
-impl IPv6PacketSocketImpl {
+impl PacketSocketImpl {
+    fn new<T>(proto: u16, flags: SockFlag, if_name: T)
+            -> Result<Self> where
+            T: AsRef<str> {
+        let name = if_name.as_ref();
+
+        let fd = socket(
+            AddressFamily::Packet,
+            SockType::Datagram,
+            flags,
+            c_int::from(proto.to_be())
+        )?;
+
+        let mut ret = PacketSocketImpl {
+            sock: RawSocketImpl::from_fd(fd),
+            if_index: -1,
+            hwaddr: None,
+            proto
+        };
+        ret.if_index = get_interface_index(&ret, name)?;
+        ret.hwaddr = get_interface_hwaddr(&ret, name)?;
+
+        ret.sock.bind(LinkAddr {
+            if_index: ret.if_index,
+            proto,
+            hwaddr: None
+        })?;
+
+        Ok(ret)
+    }
+
+    fn try_clone(&self) -> Result<Self> {
+        Ok(PacketSocketImpl {
+            sock: RawSocketImpl::from_fd(dup_fd(self)?),
+            ..*self
+        })
+    }
+
+    fn recvfrom<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        flags: RecvFlags
+    ) -> Result<(&'a mut [u8], Option<HwAddr>)> {
+        let (buf, addr) = self.sock.recvfrom(buf, flags)?;
+        Ok((buf, addr.hwaddr))
+    }
+
+    fn sendto(
+        &mut self,
+        buf: &[u8],
+        dest: Option<HwAddr>,
+        flags: SendFlags
+    ) -> Result<size_t> {
+        let addr = self.link_addr(dest);
+        self.sock.sendto(buf, addr, flags)
+    }
+
     #[allow(clippy::cast_possible_truncation)]
     fn recvpacket(
         &mut self,
         maxsize: size_t,
         flags: RecvFlags
-    ) -> Result<(Ipv6, MacAddr)> { unsafe {
+    ) -> Result<(Ipv6, Option<HwAddr>)> { unsafe {
         let mut packet = MutableIpv6Packet::owned(vec![0; maxsize])
             .ok_or(Error::BufferTooSmall {
                 len: maxsize as usize
@@ -243,7 +793,7 @@ impl IPv6PacketSocketImpl {
         let mut addr: sockaddr_ll = zeroed();
         let mut addr_size = size_of_val(&addr) as socklen_t;
         n1try!(::nlibc::recvfrom(
-            self.fd,
+            self.as_raw_fd(),
             ref_to_mut_cvoid(packet.packet_mut()),
             maxsize,
             flags.bits(),
@@ -251,39 +801,48 @@ impl IPv6PacketSocketImpl {
             &mut addr_size
         ));
 
-        let mac = MacAddr::from_bytes(
-            &addr.sll_addr[0..addr.sll_halen as usize]
-        )?;
-
-        Ok((packet.from_packet(), mac))
+        Ok((packet.from_packet(), hwaddr_from_sockaddr_ll(&addr)?))
     }}
 
-    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-    fn sendpacket(
-            &mut self,
+    fn recvpacket_timestamped(
+        &mut self,
+        maxsize: size_t,
+        flags: RecvFlags
+    ) -> Result<(Ipv6, Option<HwAddr>, Option<Timestamps>)> {
+        let mut packet = MutableIpv6Packet::owned(vec![0; maxsize])
+            .ok_or(Error::BufferTooSmall { len: maxsize })?;
+
+        let (addr, cmsgs) = {
+            let (_, addr, cmsgs) = self.sock.recvmsg(
+                packet.packet_mut(),
+                flags
+            )?;
+            (addr, cmsgs)
+        };
+
+        Ok((
+            packet.from_packet(),
+            addr.hwaddr,
+            Timestamps::from_cmsgs(&cmsgs)
+        ))
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn sendpacket(
+            &mut self,
             packet: &Ipv6,
-            dest: Option<MacAddr>,
+            dest: Option<HwAddr>,
             flags: SendFlags
     ) -> Result<size_t> { unsafe {
         let len = Ipv6Packet::packet_size(&packet);
-        let mut buf = MutableIpv6Packet::owned(
-            vec![0; len]).unwrap();
-        buf.populate(&packet);
+        let buf = serialize_ipv6(packet);
 
-        let mut addr_ll: sockaddr_ll = zeroed();
+        let addr_ll = LinkLayer::to_raw(&self.link_addr(dest));
         let addr_size = size_of_val(&addr_ll) as socklen_t;
 
-        addr_ll.sll_family = AF_PACKET as c_ushort;
-        addr_ll.sll_protocol = self.proto;
-        addr_ll.sll_ifindex = self.if_index;
-        addr_ll.sll_halen = 6;
-        addr_ll.sll_addr[0..6].copy_from_slice(
-            dest.as_ref().unwrap_or(&self.macaddr).as_bytes()
-        );
-
         Ok(n1try!(
             ::nlibc::sendto(
-                self.fd,
+                self.as_raw_fd(),
                 ref_to_cvoid(buf.packet()),
                 len as size_t,
                 flags.bits(),
@@ -293,12 +852,124 @@ impl IPv6PacketSocketImpl {
         )
     }}
 
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn recvpacket_batch(
+        &mut self,
+        maxsize: size_t,
+        max_packets: usize,
+        flags: RecvFlags
+    ) -> Result<Vec<(Ipv6, Option<HwAddr>)>> { unsafe {
+        if maxsize < Ipv6Packet::minimum_packet_size() {
+            return Err(Error::BufferTooSmall { len: maxsize }.into());
+        }
+        if max_packets == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0; maxsize * max_packets];
+        let mut addrs: Vec<sockaddr_ll> = vec![zeroed(); max_packets];
+        let mut iovs: Vec<iovec> = buf.chunks_mut(maxsize)
+            .map(|x| make_iovec(x))
+            .collect();
+        let mut hdrs: Vec<mmsghdr> = iovs.iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| make_mmsghdr(iov, addr))
+            .collect();
+
+        let received = n1try!(recvmmsg(
+            self.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            max_packets as c_uint,
+            flags.bits() as _,
+            null_mut()
+        )) as usize;
+
+        let mut ret = Vec::with_capacity(received);
+        for (i, hdr) in hdrs[..received].iter().enumerate() {
+            let start = i * maxsize;
+            let len = (hdr.msg_len as usize).min(maxsize);
+            // runts cannot be parsed, and are not ours anyway,
+            // nor are the frames of the hardware addresses not fitting
+            // in sockaddr_ll
+            let packet = match Ipv6Packet::new(&buf[start .. start + len]) {
+                Some(x) => x,
+                None => continue
+            };
+            let hwaddr = match hwaddr_from_sockaddr_ll(&addrs[i]) {
+                Ok(x) => x,
+                Err(_) => continue
+            };
+            ret.push((packet.from_packet(), hwaddr));
+        }
+
+        Ok(ret)
+    }}
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn sendpacket_batch(
+        &mut self,
+        packets: &[(Ipv6, Option<HwAddr>)],
+        flags: SendFlags
+    ) -> Result<usize> { unsafe {
+        if packets.is_empty() {
+            return Ok(0);
+        }
+
+        let bufs: Vec<MutableIpv6Packet> = packets.iter()
+            .map(|&(ref packet, _)| serialize_ipv6(packet))
+            .collect();
+        let mut addrs: Vec<sockaddr_ll> = packets.iter()
+            .map(|&(_, dest)| LinkLayer::to_raw(&self.link_addr(dest)))
+            .collect();
+        let mut iovs: Vec<iovec> = bufs.iter()
+            .map(|x| make_iovec_const(x.packet()))
+            .collect();
+        let mut hdrs: Vec<mmsghdr> = iovs.iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| make_mmsghdr(iov, addr))
+            .collect();
+
+        Ok(n1try!(sendmmsg(
+            self.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            hdrs.len() as c_uint,
+            flags.bits() as _
+        )) as usize)
+    }}
+
+    fn link_addr(&self, dest: Option<HwAddr>) -> LinkAddr {
+        LinkAddr {
+            if_index: self.if_index,
+            proto: self.proto,
+            hwaddr: dest.or(self.hwaddr)
+        }
+    }
+
     fn get_interface_index(&self) -> c_int {
         self.if_index
     }
 
-    fn get_interface_mac(&self) -> MacAddr {
-        self.macaddr
+    fn get_interface_hwaddr(&self) -> Option<HwAddr> {
+        self.hwaddr
+    }
+}
+
+fn serialize_ipv6(packet: &Ipv6) -> MutableIpv6Packet<'static> {
+    let len = Ipv6Packet::packet_size(packet);
+    let mut buf = MutableIpv6Packet::owned(vec![0; len]).unwrap();
+    buf.populate(packet);
+    buf
+}
+
+// the kernel reports the full length of the longer addresses,
+// e.g. the InfiniBand ones, and cuts them to sll_addr
+fn hwaddr_from_sockaddr_ll(addr: &sockaddr_ll) -> Result<Option<HwAddr>> {
+    match addr.sll_halen as usize {
+        0 => Ok(None),
+        len => match addr.sll_addr.get(..len) {
+            Some(x) => Ok(Some(HwAddr::from_bytes(x)?)),
+            None => Err(Error::WrongSize.into())
+        }
     }
 }
 
@@ -309,9 +980,9 @@ impl Drop for IPv6PacketSocket {
     }
 }
 
-impl AsRawFd for IPv6PacketSocketImpl {
+impl AsRawFd for PacketSocketImpl {
     fn as_raw_fd(&self) -> RawFd {
-        self.fd
+        self.sock.as_raw_fd()
     }
 }
 
@@ -321,6 +992,89 @@ impl AsRawFd for IPv6PacketSocket {
     }
 }
 
+// a packet socket for any ethertype, e.g. ETHERTYPE_IP or ETHERTYPE_ARP;
+// the link-layer header is handled by the kernel
+pub struct PacketSocket(PacketSocketImpl);
+
+impl PacketSocket {
+    pub fn new<T>(proto: u16, flags: SockFlag, if_name: T)
+            -> Result<Self> where
+            T: AsRef<str> {
+        Ok(PacketSocket(PacketSocketImpl::new(proto, flags, if_name)?))
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(PacketSocket(self.0.try_clone()?))
+    }
+
+    // returns the payload and the link-layer source address
+    pub fn recvfrom<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        flags: RecvFlags
+    ) -> Result<(&'a mut [u8], Option<HwAddr>)> {
+        self.0.recvfrom(buf, flags)
+    }
+
+    // the interface's own address is used if dest is None, that is what
+    // the links without link-layer addresses need
+    pub fn sendto(
+        &mut self,
+        buf: &[u8],
+        dest: Option<HwAddr>,
+        flags: SendFlags
+    ) -> Result<size_t> {
+        self.0.sendto(buf, dest, flags)
+    }
+
+    pub fn recvfrom_pool(
+        &mut self,
+        pool: &mut BufferPool<Option<HwAddr>>,
+        flags: RecvFlags
+    ) -> Result<usize> {
+        recv_into_pool(self.as_raw_fd(), pool, flags)
+    }
+
+    pub fn get_interface_index(&self) -> c_int {
+        self.0.get_interface_index()
+    }
+
+    // None if the link has no link-layer addresses
+    pub fn get_interface_hwaddr(&self) -> Option<HwAddr> {
+        self.0.get_interface_hwaddr()
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        log_if_err(::nix::unistd::close(self.as_raw_fd())
+            .map_err(|e| e.into()));
+    }
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+fn make_sockaddr_in(addr: SocketAddrV4) -> sockaddr_in { unsafe {
+    let mut addr_in: sockaddr_in = zeroed();
+
+    addr_in.sin_family = AddressFamily::Inet as u16;
+    addr_in.sin_port = addr.port().to_be();
+    addr_in.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+
+    addr_in
+}}
+
+fn make_socketaddrv4(addr: &sockaddr_in) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+        in_port_t::from_be(addr.sin_port)
+    )
+}
+
 pub trait SocketCommon where
         Self: AsRawFd + Sized {
     fn setsockopt<'a, T: SetSockOpt<'a>>(&mut self, opt: &'a T)
@@ -328,6 +1082,18 @@ pub trait SocketCommon where
         opt.set(&*self)
     }
 
+    fn getsockopt<T: GetSockOpt>(&self) -> Result<T::Val> {
+        T::get(self)
+    }
+
+    // reading the pending error clears it
+    fn take_error(&self) -> Result<Option<::std::io::Error>> {
+        match self.getsockopt::<SockOpts::SockError>()? {
+            0 => Ok(None),
+            x => Ok(Some(::std::io::Error::from_raw_os_error(x)))
+        }
+    }
+
     #[allow(clippy::cast_possible_truncation)]
     fn set_allmulti<T>(&mut self, allmulti: bool, ifname: T)
             -> Result<bool>
@@ -350,17 +1116,121 @@ pub trait SocketCommon where
 
     #[cfg(feature = "seccomp")]
     fn allow_sending(&self, ctx: &mut ::seccomp::Context) -> Result<()> {
-        allow_syscall(ctx, self, SYS_sendto)
+        allow_syscall(ctx, self, SYS_sendto)?;
+        allow_syscall(ctx, self, SYS_sendmsg)?;
+        allow_syscall(ctx, self, SYS_sendmmsg)
     }
 
     #[cfg(feature = "seccomp")]
     fn allow_receiving(&self, ctx: &mut ::seccomp::Context) -> Result<()> {
-        allow_syscall(ctx, self, SYS_recvfrom)
+        allow_syscall(ctx, self, SYS_recvfrom)?;
+        allow_syscall(ctx, self, SYS_recvmsg)?;
+        allow_syscall(ctx, self, SYS_recvmmsg)
     }
 }
 
-impl SocketCommon for IPv6RawSocket {}
+impl<F, P> SocketCommon for RawSocket<F, P> where F: Family, P: Protocol {}
 impl SocketCommon for IPv6PacketSocket {}
+impl SocketCommon for PacketSocket {}
+
+// Membership of an IPv6 multicast group, left when dropped.
+// The kernel sends the MLD reports, so snooping switches forward the group.
+// The guard keeps the socket, usually a clone of an adapter.
+pub struct MulticastMembership<T> where T: SocketCommon {
+    sock: T,
+    group: Ipv6Addr,
+    if_index: c_int
+}
+
+impl<T> MulticastMembership<T> where T: SocketCommon {
+    // if_index 0 lets the kernel pick the interface by the routing table
+    pub fn join(mut sock: T, group: Ipv6Addr, if_index: c_int)
+            -> Result<Self> {
+        let mreq = make_ipv6_mreq(group, if_index);
+        sock.setsockopt(&SockOpts::AddMembership::new(&mreq))?;
+
+        Ok(MulticastMembership {
+            sock,
+            group,
+            if_index
+        })
+    }
+
+    pub fn group(&self) -> Ipv6Addr {
+        self.group
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+}
+
+impl<T> Drop for MulticastMembership<T> where T: SocketCommon {
+    fn drop(&mut self) {
+        let mreq = make_ipv6_mreq(self.group, self.if_index);
+        log_if_err(
+            self.sock.setsockopt(&SockOpts::DropMembership::new(&mreq))
+        );
+    }
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn make_ipv6_mreq(group: Ipv6Addr, if_index: c_int) -> ipv6_mreq {
+    let mut mreq: ipv6_mreq = unsafe { zeroed() };
+    mreq.ipv6mr_multiaddr.s6_addr = group.octets();
+    mreq.ipv6mr_interface = if_index as c_uint;
+    mreq
+}
+
+// Link-layer multicast membership of a packet socket, left when dropped.
+// The interface accepts the frames sent to the address without entering
+// the all-multicast mode; no MLD reports are sent.
+pub struct PacketMembership<T> where T: SocketCommon {
+    sock: T,
+    hwaddr: HwAddr,
+    if_index: c_int
+}
+
+impl<T> PacketMembership<T> where T: SocketCommon {
+    pub fn join(mut sock: T, hwaddr: HwAddr, if_index: c_int)
+            -> Result<Self> {
+        let mreq = make_packet_mreq(hwaddr, if_index);
+        sock.setsockopt(&SockOpts::AddPacketMembership::new(&mreq))?;
+
+        Ok(PacketMembership {
+            sock,
+            hwaddr,
+            if_index
+        })
+    }
+
+    pub fn hwaddr(&self) -> HwAddr {
+        self.hwaddr
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.sock
+    }
+}
+
+impl<T> Drop for PacketMembership<T> where T: SocketCommon {
+    fn drop(&mut self) {
+        let mreq = make_packet_mreq(self.hwaddr, self.if_index);
+        log_if_err(
+            self.sock.setsockopt(&SockOpts::DropPacketMembership::new(&mreq))
+        );
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn make_packet_mreq(hwaddr: HwAddr, if_index: c_int) -> ::raw::packet_mreq {
+    let mut mreq: ::raw::packet_mreq = unsafe { zeroed() };
+    mreq.mr_ifindex = if_index;
+    mreq.mr_type = ::raw::PACKET_MR_MULTICAST;
+    mreq.mr_alen = hwaddr.len() as c_ushort;
+    mreq.mr_address[.. hwaddr.len()].copy_from_slice(hwaddr.as_bytes());
+    mreq
+}
 
 pub trait SetSockOpt<'a> where Self: 'a {
     type Val: ?Sized;
@@ -368,10 +1238,15 @@ pub trait SetSockOpt<'a> where Self: 'a {
     fn set<T: SocketCommon>(&self, fd: &T) -> Result<()>;
 }
 
+pub trait GetSockOpt {
+    type Val;
+    fn get<T: SocketCommon>(fd: &T) -> Result<Self::Val>;
+}
+
 #[allow(non_snake_case)]
 pub mod SockOpts {
     use super::*;
-    use ::raw::sock_fprog;
+    use ::raw::{packet_mreq, sock_fprog, sock_txtime};
     use ::nlibc::c_void;
 
     pub trait ToSetSockOptArg<'a> where Self: 'a {
@@ -428,6 +1303,23 @@ pub mod SockOpts {
         }
     }
 
+    // the program fd, the socket takes its own reference
+    impl<'a> ToSetSockOptArg<'a> for EbpfProg {
+        type Owner = Box<c_int>;
+
+        #[allow(clippy::cast_possible_truncation)]
+        unsafe fn to_set_sock_opt_arg(
+            &'a self
+        ) -> Result<(Self::Owner, *const c_void, socklen_t)> {
+            let ptr = Box::into_raw(Box::new(self.as_raw_fd()));
+            Ok((
+                Box::from_raw(ptr),
+                ptr as *const c_void,
+                size_of::<c_int>() as socklen_t
+            ))
+        }
+    }
+
     impl<'a> ToSetSockOptArg<'a> for V6PmtuType {
         type Owner = Box<c_int>;
 
@@ -444,82 +1336,435 @@ pub mod SockOpts {
         }
     }
 
-    macro_rules! gen_sock_opt {
-        ($name:ident, $opt:expr, $typ:ty) => (
-            pub struct $name<'a> {
-                val: &'a $typ
-            }
+    #[allow(clippy::use_self)]
+    impl<'a> ToSetSockOptArg<'a> for u32 {
+        type Owner = Box<u32>;
 
-            impl<'a> SetSockOpt<'a> for $name<'a> {
-                type Val = $typ;
+        #[allow(clippy::cast_possible_truncation)]
+        unsafe fn to_set_sock_opt_arg(
+            &'a self
+        ) -> Result<(Self::Owner, *const c_void, socklen_t)> {
+            let ptr = Box::into_raw(Box::new(*self));
+            Ok((
+                Box::from_raw(ptr),
+                ptr as *const c_void,
+                size_of::<u32>() as socklen_t
+            ))
+        }
+    }
 
-                fn new(val: &'a $typ) -> $name<'a> {
-                    $name {
-                        val: val
-                    }
-                }
+    impl<'a> ToSetSockOptArg<'a> for TrafficClass {
+        type Owner = Box<c_int>;
 
-                fn set<T: SocketCommon>(&self, fd: &T)
-                        -> Result<()> { unsafe {
-                    let (_, ptr, len) = self.val.to_set_sock_opt_arg()?;
-                    n1try!(::nlibc::setsockopt(
-                        fd.as_raw_fd(),
-                        $opt.get_sock_opt_level().repr(),
-                        $opt.repr(),
-                        ptr,
-                        len
-                    ));
-                    Ok(())
-                }}
-            }
-        )
+        #[allow(clippy::cast_possible_truncation)]
+        unsafe fn to_set_sock_opt_arg(
+            &'a self
+        ) -> Result<(Self::Owner, *const c_void, socklen_t)> {
+            let ptr = Box::into_raw(Box::new(c_int::from(self.0)));
+            Ok((
+                Box::from_raw(ptr),
+                ptr as *const c_void,
+                size_of::<c_int>() as socklen_t
+            ))
+        }
     }
 
-    macro_rules! gen_sock_opt_any_sized {
-        ($name:ident, $opt:expr, $typ:ty) => (
-            impl<'a> ToSetSockOptArg<'a> for $typ {
-                type Owner = &'a $typ;
+    impl<'a> ToSetSockOptArg<'a> for TxTimeConfig {
+        type Owner = Box<sock_txtime>;
 
-                #[allow(clippy::cast_possible_truncation)]
-                unsafe fn to_set_sock_opt_arg(&'a self)
-                        -> Result<(&'a $typ, *const c_void, socklen_t)> {
-                    Ok((
-                        self,
-                        self as *const $typ as *const c_void,
-                        size_of::<$typ>() as socklen_t
-                    ))
-                }
-            }
+        #[allow(clippy::cast_possible_truncation)]
+        unsafe fn to_set_sock_opt_arg(
+            &'a self
+        ) -> Result<(Self::Owner, *const c_void, socklen_t)> {
+            let ptr = Box::into_raw(Box::new(sock_txtime {
+                clockid: self.clock.repr(),
+                flags: self.flags.bits()
+            }));
+            Ok((
+                Box::from_raw(ptr),
+                ptr as *const c_void,
+                size_of::<sock_txtime>() as socklen_t
+            ))
+        }
+    }
 
-            gen_sock_opt!($name, $opt, $typ);
-        )
+    impl<'a> ToSetSockOptArg<'a> for TimestampingFlags {
+        type Owner = Box<c_int>;
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        unsafe fn to_set_sock_opt_arg(
+            &'a self
+        ) -> Result<(Self::Owner, *const c_void, socklen_t)> {
+            let ptr = Box::into_raw(Box::new(self.bits() as c_int));
+            Ok((
+                Box::from_raw(ptr),
+                ptr as *const c_void,
+                size_of::<c_int>() as socklen_t
+            ))
+        }
     }
 
-    gen_sock_opt!(IpHdrIncl, SockOptIPv6::IpHdrIncl, bool);
-    gen_sock_opt_any_sized!(IcmpV6Filter, SockOptICMPv6::IcmpV6Filter,
-        icmp6_filter);
-    gen_sock_opt!(BindToDevice, SockOptSocket::BindToDevice, str);
-    gen_sock_opt!(DontRoute, SockOptSocket::DontRoute, bool);
-    gen_sock_opt!(V6Only, SockOptIPv6::V6Only, bool);
-    gen_sock_opt_any_sized!(AttachFilter, SockOptSocket::AttachFilter,
-        sock_fprog);
-    gen_sock_opt!(LockFilter, SockOptSocket::LockFilter, bool);
-    gen_sock_opt!(UnicastHops, SockOptIPv6::UnicastHops, c_int);
-    gen_sock_opt!(V6MtuDiscover, SockOptIPv6::V6MtuDiscover, V6PmtuType);
-}
+    pub trait FromGetSockOptArg {
+        type Owned;
+        // the size of the buffer handed to getsockopt()
+        fn get_sock_opt_arg_size() -> usize;
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self::Owned>;
+    }
 
-#[cfg(feature = "seccomp")]
-#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-fn allow_syscall<T>(ctx: &mut ::seccomp::Context, fd: &T, syscall: c_long)
-        -> Result<()> where T: AsRawFd {
-    use ::seccomp::*;
-    ctx.add_rule(
-        Rule::new(
-            syscall as usize,
-            Compare::arg(0)
-                .using(Op::Eq)
-                .with(fd.as_raw_fd() as u64)
-                .build()
+    unsafe fn read_c_int(buf: &[u8]) -> Result<c_int> {
+        if buf.len() != size_of::<c_int>() {
+            return Err(::errors::Error::WrongSize.into());
+        }
+        Ok(::std::ptr::read_unaligned(buf.as_ptr() as *const c_int))
+    }
+
+    impl FromGetSockOptArg for bool {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<c_int>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            Ok(read_c_int(buf)? != 0)
+        }
+    }
+
+    impl FromGetSockOptArg for str {
+        type Owned = String;
+
+        fn get_sock_opt_arg_size() -> usize {
+            IFNAMSIZ
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<String> {
+            let len = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+            Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+        }
+    }
+
+    #[allow(clippy::use_self)]
+    impl FromGetSockOptArg for c_int {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<c_int>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            read_c_int(buf)
+        }
+    }
+
+    impl FromGetSockOptArg for V6PmtuType {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<c_int>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            let val = read_c_int(buf)?;
+            Ok(Self::from_repr(val).ok_or(
+                ::errors::Error::UnexpectedSockOptValue { value: val }
+            )?)
+        }
+    }
+
+    #[allow(clippy::use_self)]
+    impl FromGetSockOptArg for u32 {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<u32>()
+        }
+
+        #[allow(clippy::cast_sign_loss)]
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            Ok(read_c_int(buf)? as u32)
+        }
+    }
+
+    impl FromGetSockOptArg for TrafficClass {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<c_int>()
+        }
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            Ok(TrafficClass(read_c_int(buf)? as u8))
+        }
+    }
+
+    impl FromGetSockOptArg for TxTimeConfig {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<sock_txtime>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            if buf.len() != size_of::<sock_txtime>() {
+                return Err(::errors::Error::WrongSize.into());
+            }
+            let raw = ::std::ptr::read_unaligned(
+                buf.as_ptr() as *const sock_txtime
+            );
+            Ok(TxTimeConfig {
+                clock: TxTimeClock::from_repr(raw.clockid).ok_or(
+                    ::errors::Error::UnexpectedSockOptValue {
+                        value: raw.clockid
+                    }
+                )?,
+                flags: TxTimeFlags::from_bits_truncate(raw.flags)
+            })
+        }
+    }
+
+    impl FromGetSockOptArg for TimestampingFlags {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<c_int>()
+        }
+
+        #[allow(clippy::cast_sign_loss)]
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            Ok(Self::from_bits_truncate(read_c_int(buf)? as u32))
+        }
+    }
+
+    impl FromGetSockOptArg for icmp_filter {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<Self>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            if buf.len() != size_of::<Self>() {
+                return Err(::errors::Error::WrongSize.into());
+            }
+            Ok(::std::ptr::read_unaligned(buf.as_ptr() as *const Self))
+        }
+    }
+
+    impl FromGetSockOptArg for icmp6_filter {
+        type Owned = Self;
+
+        fn get_sock_opt_arg_size() -> usize {
+            size_of::<Self>()
+        }
+
+        unsafe fn from_get_sock_opt_arg(buf: &[u8]) -> Result<Self> {
+            if buf.len() != size_of::<Self>() {
+                return Err(::errors::Error::WrongSize.into());
+            }
+            Ok(::std::ptr::read_unaligned(buf.as_ptr() as *const Self))
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    unsafe fn get_sock_opt<T, V>(fd: &T, level: c_int, opt: c_int)
+            -> Result<V::Owned>
+            where T: SocketCommon, V: FromGetSockOptArg + ?Sized {
+        let mut buf = vec![0_u8; V::get_sock_opt_arg_size()];
+        let mut len = buf.len() as socklen_t;
+        n1try!(::nlibc::getsockopt(
+            fd.as_raw_fd(),
+            level,
+            opt,
+            ref_to_mut_cvoid(&mut buf[..]),
+            &mut len
+        ));
+        V::from_get_sock_opt_arg(&buf[..len as usize])
+    }
+
+    macro_rules! gen_sock_opt {
+        ($name:ident, $opt:expr, $typ:ty) => (
+            pub struct $name<'a> {
+                val: &'a $typ
+            }
+
+            impl<'a> SetSockOpt<'a> for $name<'a> {
+                type Val = $typ;
+
+                fn new(val: &'a $typ) -> $name<'a> {
+                    $name {
+                        val: val
+                    }
+                }
+
+                fn set<T: SocketCommon>(&self, fd: &T)
+                        -> Result<()> { unsafe {
+                    let (_, ptr, len) = self.val.to_set_sock_opt_arg()?;
+                    n1try!(::nlibc::setsockopt(
+                        fd.as_raw_fd(),
+                        $opt.get_sock_opt_level().repr(),
+                        $opt.repr(),
+                        ptr,
+                        len
+                    ));
+                    Ok(())
+                }}
+            }
+        )
+    }
+
+    macro_rules! gen_sock_opt_any_sized {
+        ($name:ident, $opt:expr, $typ:ty) => (
+            impl<'a> ToSetSockOptArg<'a> for $typ {
+                type Owner = &'a $typ;
+
+                #[allow(clippy::cast_possible_truncation)]
+                unsafe fn to_set_sock_opt_arg(&'a self)
+                        -> Result<(&'a $typ, *const c_void, socklen_t)> {
+                    Ok((
+                        self,
+                        self as *const $typ as *const c_void,
+                        size_of::<$typ>() as socklen_t
+                    ))
+                }
+            }
+
+            gen_sock_opt!($name, $opt, $typ);
+        )
+    }
+
+    // an option that can be read back
+    macro_rules! gen_sock_opt_rw {
+        ($name:ident, $opt:expr, $typ:ty) => (
+            gen_sock_opt!($name, $opt, $typ);
+
+            impl<'a> GetSockOpt for $name<'a> {
+                type Val = <$typ as FromGetSockOptArg>::Owned;
+
+                fn get<T: SocketCommon>(fd: &T)
+                        -> Result<Self::Val> { unsafe {
+                    get_sock_opt::<T, $typ>(
+                        fd,
+                        $opt.get_sock_opt_level().repr(),
+                        $opt.repr()
+                    )
+                }}
+            }
+        )
+    }
+
+    macro_rules! gen_sock_opt_ro {
+        ($name:ident, $opt:expr, $typ:ty) => (
+            pub struct $name;
+
+            impl GetSockOpt for $name {
+                type Val = <$typ as FromGetSockOptArg>::Owned;
+
+                fn get<T: SocketCommon>(fd: &T)
+                        -> Result<Self::Val> { unsafe {
+                    get_sock_opt::<T, $typ>(
+                        fd,
+                        $opt.get_sock_opt_level().repr(),
+                        $opt.repr()
+                    )
+                }}
+            }
+        )
+    }
+
+    gen_sock_opt_rw!(IpHdrIncl, SockOptIPv6::IpHdrIncl, bool);
+    gen_sock_opt_any_sized!(IcmpV6Filter, SockOptICMPv6::IcmpV6Filter,
+        icmp6_filter);
+    impl<'a> GetSockOpt for IcmpV6Filter<'a> {
+        type Val = icmp6_filter;
+
+        fn get<T: SocketCommon>(fd: &T) -> Result<Self::Val> { unsafe {
+            get_sock_opt::<T, icmp6_filter>(
+                fd,
+                SockOptICMPv6::IcmpV6Filter.get_sock_opt_level().repr(),
+                SockOptICMPv6::IcmpV6Filter.repr()
+            )
+        }}
+    }
+    gen_sock_opt_rw!(BindToDevice, SockOptSocket::BindToDevice, str);
+    gen_sock_opt_rw!(DontRoute, SockOptSocket::DontRoute, bool);
+    gen_sock_opt_rw!(V6Only, SockOptIPv6::V6Only, bool);
+    gen_sock_opt_any_sized!(AttachFilter, SockOptSocket::AttachFilter,
+        sock_fprog);
+    gen_sock_opt_rw!(LockFilter, SockOptSocket::LockFilter, bool);
+    // the value is ignored, detaches the classic and the eBPF filters
+    gen_sock_opt!(DetachFilter, SockOptSocket::DetachFilter, c_int);
+    gen_sock_opt!(AttachBpf, SockOptSocket::AttachBpf, EbpfProg);
+    // selects the socket of a SO_REUSEPORT group by the index returned
+    gen_sock_opt!(AttachReuseportEbpf, SockOptSocket::AttachReuseportEbpf,
+        EbpfProg);
+    gen_sock_opt_rw!(UnicastHops, SockOptIPv6::UnicastHops, c_int);
+    gen_sock_opt_rw!(V6MtuDiscover, SockOptIPv6::V6MtuDiscover, V6PmtuType);
+    gen_sock_opt_rw!(RecvPktInfo, SockOptIPv6::RecvPktInfo, bool);
+    gen_sock_opt_rw!(RecvHopLimit, SockOptIPv6::RecvHopLimit, bool);
+    gen_sock_opt_rw!(RecvTClass, SockOptIPv6::RecvTClass, bool);
+    gen_sock_opt_rw!(RecvErr, SockOptIPv6::RecvErr, bool);
+    gen_sock_opt_rw!(RecvPathMtu, SockOptIPv6::RecvPathMtu, bool);
+    gen_sock_opt_rw!(TimestampNs, SockOptSocket::TimestampNs, bool);
+    gen_sock_opt_rw!(Timestamping, SockOptSocket::Timestamping,
+        TimestampingFlags);
+    // 0 to 6, the higher ones need CAP_NET_ADMIN
+    gen_sock_opt_rw!(Priority, SockOptSocket::Priority, c_int);
+    // the fwmark for the policy routing and netfilter, needs CAP_NET_ADMIN
+    gen_sock_opt_rw!(Mark, SockOptSocket::Mark, u32);
+    gen_sock_opt_rw!(TClass, SockOptIPv6::TClass, TrafficClass);
+    // the kernel doubles the sizes set, for its bookkeeping
+    gen_sock_opt_rw!(SndBuf, SockOptSocket::SndBuf, c_int);
+    gen_sock_opt_rw!(RcvBuf, SockOptSocket::RcvBuf, c_int);
+    // over the net.core.[rw]mem_max limits, needs CAP_NET_ADMIN;
+    // read back with SndBuf and RcvBuf
+    gen_sock_opt!(SndBufForce, SockOptSocket::SndBufForce, c_int);
+    gen_sock_opt!(RcvBufForce, SockOptSocket::RcvBufForce, c_int);
+    // the interface index, 0 for the routing table to choose
+    gen_sock_opt_rw!(MulticastIf, SockOptIPv6::MulticastIf, c_int);
+    gen_sock_opt_rw!(MulticastHops, SockOptIPv6::MulticastHops, c_int);
+    gen_sock_opt_rw!(MulticastLoop, SockOptIPv6::MulticastLoop, bool);
+    gen_sock_opt_rw!(TxTime, SockOptSocket::TxTime, TxTimeConfig);
+    gen_sock_opt_any_sized!(AddMembership, SockOptIPv6::AddMembership,
+        ipv6_mreq);
+    gen_sock_opt!(DropMembership, SockOptIPv6::DropMembership, ipv6_mreq);
+    gen_sock_opt_any_sized!(AddPacketMembership,
+        SockOptPacket::AddMembership, packet_mreq);
+    gen_sock_opt!(DropPacketMembership, SockOptPacket::DropMembership,
+        packet_mreq);
+
+    gen_sock_opt_rw!(V4HdrIncl, SockOptIPv4::HdrIncl, bool);
+    gen_sock_opt_rw!(V4Ttl, SockOptIPv4::Ttl, c_int);
+    gen_sock_opt_any_sized!(IcmpFilter, SockOptRaw::IcmpFilter, icmp_filter);
+    impl<'a> GetSockOpt for IcmpFilter<'a> {
+        type Val = icmp_filter;
+
+        fn get<T: SocketCommon>(fd: &T) -> Result<Self::Val> { unsafe {
+            get_sock_opt::<T, icmp_filter>(
+                fd,
+                SockOptRaw::IcmpFilter.get_sock_opt_level().repr(),
+                SockOptRaw::IcmpFilter.repr()
+            )
+        }}
+    }
+
+    gen_sock_opt_ro!(SockError, SockOptSocket::Error, c_int);
+    // the path MTU, on a connected socket
+    gen_sock_opt_ro!(Mtu, SockOptIPv6::Mtu, c_int);
+}
+
+#[cfg(feature = "seccomp")]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn allow_syscall<T>(ctx: &mut ::seccomp::Context, fd: &T, syscall: c_long)
+        -> Result<()> where T: AsRawFd {
+    use ::seccomp::*;
+    ctx.add_rule(
+        Rule::new(
+            syscall as usize,
+            Compare::arg(0)
+                .using(Op::Eq)
+                .with(fd.as_raw_fd() as u64)
+                .build()
                 .unwrap(),
             Action::Allow
         )
@@ -532,97 +1777,83 @@ pub mod futures {
     use super::*;
 
     use ::std::io;
-    use ::std::ops::Deref;
     use ::std::sync::Arc;
 
     use ::bytes::*;
     use ::mio;
     use ::mio::*;
     use ::mio::event::Evented;
-    use ::mio::unix::EventedFd;
-    use ::spin::{Mutex as SpinMutex, MutexGuard as SpinMutexGuard};
+    use ::mio::unix::{EventedFd, UnixReady};
     use ::tokio::prelude::*;
     use ::tokio::prelude::Poll;
     use ::tokio::reactor::*;
 
-    struct PollEventedLocker<T> where T: Evented {
-        poll_evented: PollEvented2<T>,
-        read_lock: SpinMutex<()>,
-        write_lock: SpinMutex<()>
-    }
+    gen_evented_eventedfd!([F: Family, P: Protocol] RawSocket<F, P>);
 
-    struct PollEventedLockerGuard<'a, T> where T: 'a + Evented {
-        poll_evented: &'a PollEvented2<T>,
-        _guard: SpinMutexGuard<'a, ()>
+    // the outcome of a batch send future: sendmmsg() fails only for the
+    // first datagram of those passed, which is then skipped and the rest
+    // is sent on, so that one bad destination does not stop the batch
+    #[derive(Debug, Default)]
+    pub struct BatchSendReport {
+        pub sent: usize,
+        // the indices in the batch and the errors
+        pub failed: Vec<(usize, Error)>
     }
 
-    impl<T> PollEventedLocker<T> where T: Evented {
-        fn new(inner: PollEvented2<T>) -> Self {
-            Self {
-                poll_evented: inner,
-                read_lock: SpinMutex::new(()),
-                write_lock: SpinMutex::new(())
-            }
-        }
-
-        fn lock_read(&self) -> PollEventedLockerGuard<T> {
-            PollEventedLockerGuard {
-                poll_evented: &self.poll_evented,
-                _guard: self.read_lock.lock()
-            }
+    impl BatchSendReport {
+        fn next(&self) -> usize {
+            self.sent + self.failed.len()
         }
 
-        fn lock_write(&self) -> PollEventedLockerGuard<T> {
-            PollEventedLockerGuard {
-                poll_evented: &self.poll_evented,
-                _guard: self.write_lock.lock()
+        // send is given the index of the first datagram not yet sent
+        fn poll_send<F>(&mut self, len: usize, mut send: F)
+                -> Poll<(), Error> where
+                F: FnMut(usize) -> ::std::result::Result<usize, Error> {
+            while self.next() < len {
+                match send(self.next()) {
+                    Ok(sent) => self.sent += sent,
+                    Err(e) => match (&e).into() {
+                        Again => return Ok(Async::NotReady),
+                        Interrupted => (),
+                        _ => {
+                            let i = self.next();
+                            self.failed.push((i, e));
+                        }
+                    }
+                }
             }
+            Ok(Async::Ready(()))
         }
     }
 
-    impl<'a, T> Deref for PollEventedLockerGuard<'a, T>
-    where T: Evented {
-        type Target = PollEvented2<T>;
-        fn deref(&self) -> &Self::Target {
-            self.poll_evented
-        }
-    }
-
-    gen_evented_eventedfd!(IPv6RawSocket);
-
-    #[derive(Clone)]
-    pub struct IPv6RawSocketAdapter(IPv6RawSocketRef);
-    type IPv6RawSocketRef = Arc<PollEventedLocker<IPv6RawSocket>>;
+    gen_evented_eventedfd!(IPv6PacketSocket);
 
-    unsafe impl Send for IPv6RawSocketAdapter {}
-    unsafe impl Sync for IPv6RawSocketAdapter {}
+    pub struct IPv6PacketSocketAdapter(IPv6PacketSocketRef);
+    type IPv6PacketSocketRef = Arc<PollEvented2<IPv6PacketSocket>>;
 
-    impl IPv6RawSocketAdapter {
-        pub fn new(handle: &Handle, inner: IPv6RawSocket) -> Result<Self> {
+    impl IPv6PacketSocketAdapter {
+        pub fn new(handle: &Handle, inner: IPv6PacketSocket)
+                -> Result<Self> {
             set_fd_nonblock(&inner, Nonblock::Yes)?;
             Ok(
-                IPv6RawSocketAdapter(
-                    Arc::new(PollEventedLocker::new(
+                IPv6PacketSocketAdapter(
+                    Arc::new(
                         PollEvented2::new_with_handle(inner, handle)?
-                    ))
+                    )
                 )
             )
         }
 
-        pub fn bind(&mut self, addr: SocketAddrV6) -> Result<()> {
-            let fd = self.as_raw_fd();
-            IPv6RawSocketImpl(fd).bind(addr)
-        }
-
-        pub fn recvfrom_direct<'a>(
+        pub fn recvpacket_direct(
             &mut self,
-            buf: &'a mut [u8],
+            maxsize: size_t,
             flags: RecvFlags
         ) -> ::std::result::Result<
-            (&'a mut [u8], SocketAddrV6),
+            (Ipv6, Option<HwAddr>),
             ::errors::Error
         > {
-            let poll_evented = self.0.lock_read();
+            let poll_evented = &*self.0;
+
             let ready = Ready::readable();
 
             if let Async::NotReady = poll_evented.poll_read_ready(ready)
@@ -630,8 +1861,10 @@ pub mod futures {
                 return Err(make_again());
             }
 
-            let fd = poll_evented.get_ref().as_raw_fd();
-            match IPv6RawSocketImpl(fd).recvfrom(buf, flags) {
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
+
+            match sock.recvpacket(maxsize, flags) {
                 Err(e) => {
                     let err = e.downcast::<Error>().unwrap();
                     if let Again = (&err).into() {
@@ -646,21 +1879,23 @@ pub mod futures {
             }
         }
 
-        pub fn sendto_direct(
+        pub fn sendpacket_direct(
             &mut self,
-            buf: &[u8],
-            addr: SocketAddrV6,
+            packet: &Ipv6,
+            dest: Option<HwAddr>,
             flags: SendFlags
         ) -> ::std::result::Result<size_t, ::errors::Error> {
-            let poll_evented = self.0.lock_write();
+            let poll_evented = &*self.0;
 
             if let Async::NotReady = poll_evented.poll_write_ready()
                     .map_err(Error::TokioError)? {
                 return Err(make_again());
             }
 
-            let fd = poll_evented.get_ref().as_raw_fd();
-            match IPv6RawSocketImpl(fd).sendto(buf, addr, flags) {
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
+
+            match sock.sendpacket(packet, dest, flags) {
                 Err(e) => {
                     let err = e.downcast::<Error>().unwrap();
                     if let Again = (&err).into() {
@@ -675,165 +1910,138 @@ pub mod futures {
             }
         }
 
-        pub fn recvfrom(
-            &mut self,
-            buf: BytesMut,
-            flags: RecvFlags
-        ) -> IPv6RawSocketRecvfromFuture {
-            IPv6RawSocketRecvfromFuture::new(self.0.clone(), buf, flags)
+        pub fn recvpacket(&mut self, maxsize: size_t, flags: RecvFlags)
+                -> IPv6PacketSocketRecvpacketFuture {
+            IPv6PacketSocketRecvpacketFuture::new(
+                self.0.clone(),
+                maxsize,
+                flags
+            )
         }
 
-        pub fn sendto(
+        pub fn sendpacket(
             &mut self,
-            buf: Bytes,
-            addr: SocketAddrV6,
+            packet: Ipv6,
+            dest: Option<HwAddr>,
             flags: SendFlags
-        ) -> IPv6RawSocketSendtoFuture {
-            IPv6RawSocketSendtoFuture::new(self.0.clone(), buf, addr, flags)
+        ) -> IPv6PacketSocketSendpacketFuture {
+            IPv6PacketSocketSendpacketFuture::new(
+                self.0.clone(),
+                packet,
+                dest,
+                flags
+            )
         }
 
-    }
+        pub fn recvpacket_batch_direct(
+            &mut self,
+            maxsize: size_t,
+            max_packets: usize,
+            flags: RecvFlags
+        ) -> ::std::result::Result<
+            Vec<(Ipv6, Option<HwAddr>)>,
+            ::errors::Error
+        > {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
 
-    impl AsRawFd for IPv6RawSocketAdapter {
-        fn as_raw_fd(&self) -> RawFd {
-            self.0.poll_evented.get_ref().as_raw_fd()
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
+
+            sock.recvpacket_batch(maxsize, max_packets, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
         }
-    }
 
-    pub struct IPv6RawSocketRecvfromFuture(
-        Option<IPv6RawSocketRecvfromFutureState>
-    );
+        pub fn sendpacket_batch_direct(
+            &mut self,
+            packets: &[(Ipv6, Option<HwAddr>)],
+            flags: SendFlags
+        ) -> ::std::result::Result<usize, ::errors::Error> {
+            let poll_evented = &*self.0;
 
-    struct IPv6RawSocketRecvfromFutureState {
-        sock: IPv6RawSocketRef,
-        buf: BytesMut,
-        flags: RecvFlags
-    }
+            if let Async::NotReady = poll_evented.poll_write_ready()
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
 
-    unsafe impl Send for IPv6RawSocketRecvfromFuture {}
-    unsafe impl Sync for IPv6RawSocketRecvfromFuture {}
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
 
-    impl IPv6RawSocketRecvfromFuture {
-        fn new(
-            sock: IPv6RawSocketRef,
-            buf: BytesMut,
+            sock.sendpacket_batch(packets, flags)
+                .map_err(|e| handle_write_error(&*poll_evented, e))
+        }
+
+        pub fn recvpacket_batch(
+            &mut self,
+            maxsize: size_t,
+            max_packets: usize,
             flags: RecvFlags
-        ) -> Self {
-            IPv6RawSocketRecvfromFuture(
-                Some(IPv6RawSocketRecvfromFutureState {
-                    sock,
-                    buf,
-                    flags
-                })
+        ) -> IPv6PacketSocketRecvpacketBatchFuture {
+            IPv6PacketSocketRecvpacketBatchFuture::new(
+                self.0.clone(),
+                maxsize,
+                max_packets,
+                flags
             )
         }
-    }
-
-    impl Future for IPv6RawSocketRecvfromFuture {
-        type Item = (Bytes, SocketAddrV6);
-        type Error = Error;
 
-        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            let (len, addr) = {
-                let state = self.0.as_mut().expect("pending recvfrom future");
-                let (slice, addr) = try_async_val!(
-                    IPv6RawSocketAdapter(state.sock.clone())
-                        .recvfrom_direct(&mut state.buf, state.flags)
-                );
-                (slice.len(), addr)
-            };
+        pub fn recvpacket_pool_direct(
+            &mut self,
+            pool: &mut BufferPool<Option<HwAddr>>,
+            flags: RecvFlags
+        ) -> ::std::result::Result<usize, ::errors::Error> {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
 
-            let mut state = self.0.take().unwrap();
-            state.buf.truncate(len);
-            let data = state.buf.freeze();
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
 
-            Ok(Async::Ready((
-                data,
-                addr
-            )))
+            let fd = poll_evented.get_ref().as_raw_fd();
+            recv_into_pool(fd, pool, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
         }
-    }
-
-    pub struct IPv6RawSocketSendtoFuture(
-        Option<IPv6RawSocketSendtoFutureState>
-    );
-
-    struct IPv6RawSocketSendtoFutureState {
-        sock: IPv6RawSocketRef,
-        buf: Bytes,
-        addr: SocketAddrV6,
-        flags: SendFlags
-    }
 
-    unsafe impl Send for IPv6RawSocketSendtoFuture {}
-    unsafe impl Sync for IPv6RawSocketSendtoFuture {}
-
-    impl IPv6RawSocketSendtoFuture {
-        fn new(
-            sock: IPv6RawSocketRef,
-            buf: Bytes,
-            addr: SocketAddrV6,
-            flags: SendFlags
-        ) -> Self {
-            IPv6RawSocketSendtoFuture(
-                Some(IPv6RawSocketSendtoFutureState {
-                    sock,
-                    buf,
-                    addr,
-                    flags
-                })
+        pub fn recvpacket_pool(
+            &mut self,
+            pool: BufferPool<Option<HwAddr>>,
+            flags: RecvFlags
+        ) -> IPv6PacketSocketRecvpacketPoolFuture {
+            IPv6PacketSocketRecvpacketPoolFuture::new(
+                self.0.clone(),
+                pool,
+                flags
             )
         }
-    }
-
-    impl Future for IPv6RawSocketSendtoFuture {
-        type Item = size_t;
-        type Error = Error;
-
-        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-            let len = {
-                let state = self.0.as_mut().expect("pending sendto future");
-                try_async!(IPv6RawSocketAdapter(state.sock.clone())
-                    .sendto_direct(&state.buf, state.addr,
-                        state.flags))
-            };
-            self.0.take();
-            len
-        }
-    }
-
-    gen_evented_eventedfd!(IPv6PacketSocket);
-
-    #[derive(Clone)]
-    pub struct IPv6PacketSocketAdapter(IPv6PacketSocketRef);
-    type IPv6PacketSocketRef = Arc<PollEventedLocker<IPv6PacketSocket>>;
 
-    unsafe impl Send for IPv6PacketSocketAdapter {}
-    unsafe impl Sync for IPv6PacketSocketAdapter {}
-
-    impl IPv6PacketSocketAdapter {
-        pub fn new(handle: &Handle, inner: IPv6PacketSocket)
-                -> Result<Self> {
-            set_fd_nonblock(&inner, Nonblock::Yes)?;
-            Ok(
-                IPv6PacketSocketAdapter(
-                    Arc::new(PollEventedLocker::new(
-                        PollEvented2::new_with_handle(inner, handle)?
-                    ))
-                )
+        // the future resolves once all of the packets are sent
+        pub fn sendpacket_batch(
+            &mut self,
+            packets: Vec<(Ipv6, Option<HwAddr>)>,
+            flags: SendFlags
+        ) -> IPv6PacketSocketSendpacketBatchFuture {
+            IPv6PacketSocketSendpacketBatchFuture::new(
+                self.0.clone(),
+                packets,
+                flags
             )
         }
 
-        pub fn recvpacket_direct(
+        pub fn recvpacket_timestamped_direct(
             &mut self,
             maxsize: size_t,
             flags: RecvFlags
         ) -> ::std::result::Result<
-            (Ipv6, MacAddr),
+            (Ipv6, Option<HwAddr>, Option<Timestamps>),
             ::errors::Error
         > {
-            let poll_evented = self.0.lock_read();
-
+            let poll_evented = &*self.0;
             let ready = Ready::readable();
 
             if let Async::NotReady = poll_evented.poll_read_ready(ready)
@@ -842,89 +2050,63 @@ pub mod futures {
             }
 
             let common_sock = &poll_evented.get_ref().0;
-            let mut sock = IPv6PacketSocketImpl { .. *common_sock };
-
-            match sock.recvpacket(maxsize, flags) {
-                Err(e) => {
-                    let err = e.downcast::<Error>().unwrap();
-                    if let Again = (&err).into() {
-                        poll_evented.clear_read_ready(ready)
-                            .map_err(Error::TokioError)?;
-                        return Err(err);
-                    }
-                    let new_e: ::failure::Error = err.into();
-                    Err(Error::SocketError(new_e.compat()))
-                },
-                Ok(x) => Ok(x)
-            }
+            let mut sock = PacketSocketImpl { .. *common_sock };
+            sock.recvpacket_timestamped(maxsize, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
         }
 
-        pub fn sendpacket_direct(
+        pub fn recvpacket_timestamped(
             &mut self,
-            packet: &Ipv6,
-            dest: Option<MacAddr>,
-            flags: SendFlags
-        ) -> ::std::result::Result<size_t, ::errors::Error> {
-            let poll_evented = self.0.lock_write();
+            maxsize: size_t,
+            flags: RecvFlags
+        ) -> IPv6PacketSocketRecvpacketTimestampedFuture {
+            IPv6PacketSocketRecvpacketTimestampedFuture::new(
+                self.0.clone(),
+                maxsize,
+                flags
+            )
+        }
 
-            if let Async::NotReady = poll_evented.poll_write_ready()
+        // see RawSocketAdapter::recv_tx_timestamp_direct()
+        pub fn recv_tx_timestamp_direct(&mut self)
+                -> ::std::result::Result<TxTimestamp, ::errors::Error> {
+            let poll_evented = &*self.0;
+            let ready = Ready::from(UnixReady::error());
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
                     .map_err(Error::TokioError)? {
                 return Err(make_again());
             }
 
-            let common_sock = &poll_evented.get_ref().0;
-            let mut sock = IPv6PacketSocketImpl { .. *common_sock };
-
-            match sock.sendpacket(packet, dest, flags) {
-                Err(e) => {
-                    let err = e.downcast::<Error>().unwrap();
-                    if let Again = (&err).into() {
-                        poll_evented.clear_write_ready()
-                            .map_err(Error::TokioError)?;
-                        return Err(err);
-                    }
-                    let new_e: ::failure::Error = err.into();
-                    Err(Error::SocketError(new_e.compat()))
+            let mut sock = (poll_evented.get_ref().0).sock;
+            match sock.recv_tx_timestamp() {
+                Ok(Some(x)) => Ok(x),
+                Ok(None) => {
+                    poll_evented.clear_read_ready(ready)
+                        .map_err(Error::TokioError)?;
+                    Err(make_again())
                 },
-                Ok(x) => Ok(x)
+                Err(e) => Err(handle_read_error(&*poll_evented, ready, e))
             }
         }
 
-        pub fn recvpacket(&mut self, maxsize: size_t, flags: RecvFlags)
-                -> IPv6PacketSocketRecvpacketFuture {
-            IPv6PacketSocketRecvpacketFuture::new(
-                self.0.clone(),
-                maxsize,
-                flags
-            )
-        }
-
-        pub fn sendpacket(
-            &mut self,
-            packet: Ipv6,
-            dest: Option<MacAddr>,
-            flags: SendFlags
-        ) -> IPv6PacketSocketSendpacketFuture {
-            IPv6PacketSocketSendpacketFuture::new(
-                self.0.clone(),
-                packet,
-                dest,
-                flags
-            )
+        pub fn recv_tx_timestamp(&mut self)
+                -> IPv6PacketSocketRecvTxTimestampFuture {
+            IPv6PacketSocketRecvTxTimestampFuture(Some(self.0.clone()))
         }
 
-        pub fn get_interface_mac(&self) -> MacAddr {
-            self.0.poll_evented.get_ref().get_interface_mac()
+        pub fn get_interface_hwaddr(&self) -> Option<HwAddr> {
+            self.0.get_ref().get_interface_hwaddr()
         }
 
         pub fn get_interface_index(&self) -> c_int {
-            self.0.poll_evented.get_ref().get_interface_index()
+            self.0.get_ref().get_interface_index()
         }
     }
 
     impl AsRawFd for IPv6PacketSocketAdapter {
         fn as_raw_fd(&self) -> RawFd {
-            self.0.poll_evented.get_ref().as_raw_fd()
+            self.0.get_ref().as_raw_fd()
         }
     }
 
@@ -938,9 +2120,6 @@ pub mod futures {
         flags: RecvFlags
     }
 
-    unsafe impl Send for IPv6PacketSocketRecvpacketFuture {}
-    unsafe impl Sync for IPv6PacketSocketRecvpacketFuture {}
-
     impl IPv6PacketSocketRecvpacketFuture {
         fn new(
             sock: IPv6PacketSocketRef,
@@ -958,7 +2137,7 @@ pub mod futures {
     }
 
     impl Future for IPv6PacketSocketRecvpacketFuture {
-        type Item = (Ipv6, MacAddr);
+        type Item = (Ipv6, Option<HwAddr>);
         type Error = Error;
 
         fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -980,18 +2159,15 @@ pub mod futures {
     struct IPv6PacketSocketSendpacketFutureState {
         sock: IPv6PacketSocketRef,
         packet: Ipv6,
-        destination: Option<MacAddr>,
+        destination: Option<HwAddr>,
         flags: SendFlags
     }
 
-    unsafe impl Send for IPv6PacketSocketSendpacketFuture {}
-    unsafe impl Sync for IPv6PacketSocketSendpacketFuture {}
-
     impl IPv6PacketSocketSendpacketFuture {
         fn new(
             sock: IPv6PacketSocketRef,
             packet: Ipv6,
-            destination: Option<MacAddr>,
+            destination: Option<HwAddr>,
             flags: SendFlags
         ) -> Self {
             IPv6PacketSocketSendpacketFuture(
@@ -1026,23 +2202,1653 @@ pub mod futures {
         }
     }
 
-    impl SocketCommon for IPv6RawSocketAdapter {}
-    impl SocketCommon for IPv6PacketSocketAdapter {}
+    pub struct IPv6PacketSocketRecvpacketBatchFuture(
+        Option<IPv6PacketSocketRecvpacketBatchFutureState>
+    );
 
-    fn make_again() -> Error {
-        Error::Again(io::Error::new(
-            io::ErrorKind::WouldBlock,
-            "request would block"
-        ))
+    struct IPv6PacketSocketRecvpacketBatchFutureState {
+        sock: IPv6PacketSocketRef,
+        maxsize: size_t,
+        max_packets: usize,
+        flags: RecvFlags
     }
-}
 
-#[allow(clippy::transmute_ptr_to_ptr)]
-unsafe fn as_sockaddr<T>(x: &T) -> &sockaddr {
-    transmute::<&T, &sockaddr>(x)
-}
+    impl IPv6PacketSocketRecvpacketBatchFuture {
+        fn new(
+            sock: IPv6PacketSocketRef,
+            maxsize: size_t,
+            max_packets: usize,
+            flags: RecvFlags
+        ) -> Self {
+            IPv6PacketSocketRecvpacketBatchFuture(
+                Some(IPv6PacketSocketRecvpacketBatchFutureState {
+                    sock,
+                    maxsize,
+                    max_packets,
+                    flags
+                })
+            )
+        }
+    }
 
-#[allow(clippy::transmute_ptr_to_ptr)]
-unsafe fn as_sockaddr_mut<T>(x: &mut T) -> &mut sockaddr {
-    transmute::<&mut T, &mut sockaddr>(x)
+    impl Future for IPv6PacketSocketRecvpacketBatchFuture {
+        type Item = Vec<(Ipv6, Option<HwAddr>)>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let ret = {
+                let state = self.0.as_mut()
+                    .expect("pending recvpacket batch future");
+                try_async!(IPv6PacketSocketAdapter(state.sock.clone())
+                    .recvpacket_batch_direct(
+                        state.maxsize,
+                        state.max_packets,
+                        state.flags
+                    )
+                )
+            };
+            self.0.take();
+            ret
+        }
+    }
+
+    pub struct IPv6PacketSocketRecvpacketPoolFuture(
+        Option<IPv6PacketSocketRecvpacketPoolFutureState>
+    );
+
+    struct IPv6PacketSocketRecvpacketPoolFutureState {
+        sock: IPv6PacketSocketRef,
+        pool: BufferPool<Option<HwAddr>>,
+        flags: RecvFlags
+    }
+
+    impl IPv6PacketSocketRecvpacketPoolFuture {
+        fn new(
+            sock: IPv6PacketSocketRef,
+            pool: BufferPool<Option<HwAddr>>,
+            flags: RecvFlags
+        ) -> Self {
+            IPv6PacketSocketRecvpacketPoolFuture(
+                Some(IPv6PacketSocketRecvpacketPoolFutureState {
+                    sock,
+                    pool,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl Future for IPv6PacketSocketRecvpacketPoolFuture {
+        type Item = BufferPool<Option<HwAddr>>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            {
+                let state = self.0.as_mut()
+                    .expect("pending recvpacket pool future");
+                try_async_val!(
+                    IPv6PacketSocketAdapter(state.sock.clone())
+                        .recvpacket_pool_direct(&mut state.pool, state.flags)
+                );
+            }
+            Ok(Async::Ready(self.0.take().unwrap().pool))
+        }
+    }
+
+    pub struct IPv6PacketSocketRecvpacketTimestampedFuture(
+        Option<IPv6PacketSocketRecvpacketFutureState>
+    );
+
+    impl IPv6PacketSocketRecvpacketTimestampedFuture {
+        fn new(
+            sock: IPv6PacketSocketRef,
+            maxsize: size_t,
+            flags: RecvFlags
+        ) -> Self {
+            IPv6PacketSocketRecvpacketTimestampedFuture(
+                Some(IPv6PacketSocketRecvpacketFutureState {
+                    sock,
+                    maxsize,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl Future for IPv6PacketSocketRecvpacketTimestampedFuture {
+        type Item = (Ipv6, Option<HwAddr>, Option<Timestamps>);
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let ret = {
+                let state = self.0.as_mut()
+                    .expect("pending recvpacket future");
+                try_async!(IPv6PacketSocketAdapter(state.sock.clone())
+                    .recvpacket_timestamped_direct(state.maxsize, state.flags))
+            };
+            self.0.take();
+            ret
+        }
+    }
+
+    pub struct IPv6PacketSocketRecvTxTimestampFuture(
+        Option<IPv6PacketSocketRef>
+    );
+
+    impl Future for IPv6PacketSocketRecvTxTimestampFuture {
+        type Item = TxTimestamp;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let ret = {
+                let sock = self.0.as_ref()
+                    .expect("pending tx timestamp future");
+                try_async!(
+                    IPv6PacketSocketAdapter(sock.clone())
+                        .recv_tx_timestamp_direct()
+                )
+            };
+            self.0.take();
+            ret
+        }
+    }
+
+    pub struct IPv6PacketSocketSendpacketBatchFuture(
+        Option<IPv6PacketSocketSendpacketBatchFutureState>
+    );
+
+    struct IPv6PacketSocketSendpacketBatchFutureState {
+        sock: IPv6PacketSocketRef,
+        packets: Vec<(Ipv6, Option<HwAddr>)>,
+        report: BatchSendReport,
+        flags: SendFlags
+    }
+
+    impl IPv6PacketSocketSendpacketBatchFuture {
+        fn new(
+            sock: IPv6PacketSocketRef,
+            packets: Vec<(Ipv6, Option<HwAddr>)>,
+            flags: SendFlags
+        ) -> Self {
+            IPv6PacketSocketSendpacketBatchFuture(
+                Some(IPv6PacketSocketSendpacketBatchFutureState {
+                    sock,
+                    packets,
+                    report: BatchSendReport::default(),
+                    flags
+                })
+            )
+        }
+    }
+
+    impl Future for IPv6PacketSocketSendpacketBatchFuture {
+        type Item = BatchSendReport;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            {
+                let state = self.0.as_mut()
+                    .expect("pending sendpacket batch future");
+                let (sock, packets, flags) =
+                    (&state.sock, &state.packets, state.flags);
+                let ready = state.report.poll_send(packets.len(), |i| {
+                    IPv6PacketSocketAdapter(sock.clone())
+                        .sendpacket_batch_direct(&packets[i..], flags)
+                })?;
+                if let Async::NotReady = ready {
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            let state = self.0.take().unwrap();
+            Ok(Async::Ready(state.report))
+        }
+    }
+
+    // The single datagram system calls of an adapter, made once the reactor
+    // reports the socket ready; the recvfrom and sendto futures retry them.
+    pub trait DatagramAdapter {
+        type Addr: Copy;
+
+        fn recvfrom_direct<'a>(
+            &mut self,
+            buf: &'a mut [u8],
+            flags: RecvFlags
+        ) -> ::std::result::Result<
+            (&'a mut [u8], Self::Addr),
+            ::errors::Error
+        >;
+
+        fn sendto_direct(
+            &mut self,
+            buf: &[u8],
+            addr: Self::Addr,
+            flags: SendFlags
+        ) -> ::std::result::Result<size_t, ::errors::Error>;
+    }
+
+    pub struct RawSocketAdapter<F, P>(RawSocketRef<F, P>)
+        where F: Family, P: Protocol;
+    type RawSocketRef<F, P> = Arc<PollEvented2<RawSocket<F, P>>>;
+
+    pub type IPv6RawSocketAdapter = RawSocketAdapter<Inet6, RawProto>;
+    pub type IPv4RawSocketAdapter = RawSocketAdapter<Inet, RawProto>;
+    pub type IPv6PingSocketAdapter = RawSocketAdapter<Inet6, PingProto>;
+
+    impl<F, P> RawSocketAdapter<F, P> where F: Family, P: Protocol {
+        pub fn new(handle: &Handle, inner: RawSocket<F, P>) -> Result<Self> {
+            set_fd_nonblock(&inner, Nonblock::Yes)?;
+            Ok(
+                RawSocketAdapter(
+                    Arc::new(
+                        PollEvented2::new_with_handle(inner, handle)?
+                    )
+                )
+            )
+        }
+
+        pub fn bind(&mut self, addr: F::Addr) -> Result<()> {
+            let fd = self.as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).bind(addr)
+        }
+
+        pub fn recvfrom(
+            &mut self,
+            buf: BytesMut,
+            flags: RecvFlags
+        ) -> RecvfromFuture<Self> {
+            RecvfromFuture::new(RawSocketAdapter(self.0.clone()), buf, flags)
+        }
+
+        pub fn sendto(
+            &mut self,
+            buf: Bytes,
+            addr: F::Addr,
+            flags: SendFlags
+        ) -> SendtoFuture<Self> {
+            SendtoFuture::new(
+                RawSocketAdapter(self.0.clone()),
+                buf,
+                addr,
+                flags
+            )
+        }
+
+        pub fn recvmsg_direct<'a>(
+            &mut self,
+            buf: &'a mut [u8],
+            flags: RecvFlags
+        ) -> ::std::result::Result<
+            (&'a mut [u8], F::Addr, Vec<Cmsg>),
+            ::errors::Error
+        > {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).recvmsg(buf, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
+        }
+
+        pub fn sendmsg_direct(
+            &mut self,
+            buf: &[u8],
+            addr: F::Addr,
+            cmsgs: &[Cmsg],
+            flags: SendFlags
+        ) -> ::std::result::Result<size_t, ::errors::Error> {
+            let poll_evented = &*self.0;
+
+            if let Async::NotReady = poll_evented.poll_write_ready()
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).sendmsg(buf, addr, cmsgs, flags)
+                .map_err(|e| handle_write_error(&*poll_evented, e))
+        }
+
+        pub fn recvmsg(
+            &mut self,
+            buf: BytesMut,
+            flags: RecvFlags
+        ) -> RecvmsgFuture<F, P> {
+            RecvmsgFuture::new(self.0.clone(), buf, flags)
+        }
+
+        pub fn sendmsg(
+            &mut self,
+            buf: Bytes,
+            addr: F::Addr,
+            cmsgs: Vec<Cmsg>,
+            flags: SendFlags
+        ) -> SendmsgFuture<F, P> {
+            SendmsgFuture::new(self.0.clone(), buf, addr, cmsgs, flags)
+        }
+
+        pub fn recvfrom_batch_direct(
+            &mut self,
+            buf: &mut [u8],
+            slot_size: usize,
+            flags: RecvFlags
+        ) -> ::std::result::Result<Vec<(usize, F::Addr)>, ::errors::Error> {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd)
+                .recvfrom_batch(buf, slot_size, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
+        }
+
+        pub fn sendto_batch_direct<T>(
+            &mut self,
+            msgs: &[(T, F::Addr)],
+            flags: SendFlags
+        ) -> ::std::result::Result<usize, ::errors::Error>
+                where T: AsRef<[u8]> {
+            let poll_evented = &*self.0;
+
+            if let Async::NotReady = poll_evented.poll_write_ready()
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).sendto_batch(msgs, flags)
+                .map_err(|e| handle_write_error(&*poll_evented, e))
+        }
+
+        pub fn recvfrom_batch(
+            &mut self,
+            maxsize: usize,
+            max_packets: usize,
+            flags: RecvFlags
+        ) -> RecvfromBatchFuture<F, P> {
+            RecvfromBatchFuture::new(
+                self.0.clone(),
+                maxsize,
+                max_packets,
+                flags
+            )
+        }
+
+        pub fn recvfrom_pool_direct(
+            &mut self,
+            pool: &mut BufferPool<F::Addr>,
+            flags: RecvFlags
+        ) -> ::std::result::Result<usize, ::errors::Error>
+                where F::Addr: PoolAddr {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            recv_into_pool(fd, pool, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
+        }
+
+        // the future gives the pool back filled,
+        // for the next receive to reuse it
+        pub fn recvfrom_pool(
+            &mut self,
+            pool: BufferPool<F::Addr>,
+            flags: RecvFlags
+        ) -> RecvfromPoolFuture<F, P> where F::Addr: PoolAddr {
+            RecvfromPoolFuture::new(self.0.clone(), pool, flags)
+        }
+
+        // the future resolves once all of the datagrams are sent or skipped
+        pub fn sendto_batch(
+            &mut self,
+            msgs: Vec<(Bytes, F::Addr)>,
+            flags: SendFlags
+        ) -> SendtoBatchFuture<F, P> {
+            SendtoBatchFuture::new(self.0.clone(), msgs, flags)
+        }
+
+        // Again until a transmit timestamp is queued; the error queue
+        // shares the read readiness, so poll it from the receiving task
+        pub fn recv_tx_timestamp_direct(&mut self)
+                -> ::std::result::Result<TxTimestamp, ::errors::Error> {
+            let poll_evented = &*self.0;
+            let ready = Ready::from(UnixReady::error());
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            match RawSocketImpl::<F, P>::from_fd(fd).recv_tx_timestamp() {
+                Ok(Some(x)) => Ok(x),
+                Ok(None) => {
+                    poll_evented.clear_read_ready(ready)
+                        .map_err(Error::TokioError)?;
+                    Err(make_again())
+                },
+                Err(e) => Err(handle_read_error(&*poll_evented, ready, e))
+            }
+        }
+
+        pub fn recv_tx_timestamp(&mut self) -> RecvTxTimestampFuture<F, P> {
+            RecvTxTimestampFuture(Some(self.0.clone()))
+        }
+    }
+
+    impl RawSocketAdapter<Inet6, PingProto> {
+        pub fn identifier(&self) -> Result<u16> {
+            get_identifier(self.as_raw_fd())
+        }
+    }
+
+    impl<F, P> DatagramAdapter for RawSocketAdapter<F, P> where
+            F: Family,
+            P: Protocol {
+        type Addr = F::Addr;
+
+        fn recvfrom_direct<'a>(
+            &mut self,
+            buf: &'a mut [u8],
+            flags: RecvFlags
+        ) -> ::std::result::Result<
+            (&'a mut [u8], F::Addr),
+            ::errors::Error
+        > {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).recvfrom(buf, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
+        }
+
+        fn sendto_direct(
+            &mut self,
+            buf: &[u8],
+            addr: F::Addr,
+            flags: SendFlags
+        ) -> ::std::result::Result<size_t, ::errors::Error> {
+            let poll_evented = &*self.0;
+
+            if let Async::NotReady = poll_evented.poll_write_ready()
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let fd = poll_evented.get_ref().as_raw_fd();
+            RawSocketImpl::<F, P>::from_fd(fd).sendto(buf, addr, flags)
+                .map_err(|e| handle_write_error(&*poll_evented, e))
+        }
+    }
+
+    impl<F, P> AsRawFd for RawSocketAdapter<F, P> where
+            F: Family,
+            P: Protocol {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.get_ref().as_raw_fd()
+        }
+    }
+
+    // the futures own an adapter sharing the registration
+    // with the one they are made by
+    pub struct RecvfromFuture<A>(Option<RecvfromFutureState<A>>)
+        where A: DatagramAdapter;
+
+    struct RecvfromFutureState<A> where A: DatagramAdapter {
+        sock: A,
+        buf: BytesMut,
+        flags: RecvFlags
+    }
+
+    pub type IPv6RawSocketRecvfromFuture
+        = RecvfromFuture<IPv6RawSocketAdapter>;
+    pub type IPv4RawSocketRecvfromFuture
+        = RecvfromFuture<IPv4RawSocketAdapter>;
+    pub type IPv6PingSocketRecvfromFuture
+        = RecvfromFuture<IPv6PingSocketAdapter>;
+    pub type PacketSocketRecvfromFuture = RecvfromFuture<PacketSocketAdapter>;
+
+    impl<A> RecvfromFuture<A> where A: DatagramAdapter {
+        fn new(sock: A, buf: BytesMut, flags: RecvFlags) -> Self {
+            RecvfromFuture(
+                Some(RecvfromFutureState {
+                    sock,
+                    buf,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<A> Future for RecvfromFuture<A> where A: DatagramAdapter {
+        type Item = (Bytes, A::Addr);
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let (len, addr) = {
+                let state = self.0.as_mut().expect("pending recvfrom future");
+                let (slice, addr) = try_async_val!(
+                    state.sock.recvfrom_direct(&mut state.buf, state.flags)
+                );
+                (slice.len(), addr)
+            };
+
+            let mut state = self.0.take().unwrap();
+            state.buf.truncate(len);
+            Ok(Async::Ready((state.buf.freeze(), addr)))
+        }
+    }
+
+    pub struct SendtoFuture<A>(Option<SendtoFutureState<A>>)
+        where A: DatagramAdapter;
+
+    struct SendtoFutureState<A> where A: DatagramAdapter {
+        sock: A,
+        buf: Bytes,
+        addr: A::Addr,
+        flags: SendFlags
+    }
+
+    pub type IPv6RawSocketSendtoFuture = SendtoFuture<IPv6RawSocketAdapter>;
+    pub type IPv4RawSocketSendtoFuture = SendtoFuture<IPv4RawSocketAdapter>;
+    pub type IPv6PingSocketSendtoFuture = SendtoFuture<IPv6PingSocketAdapter>;
+    pub type PacketSocketSendtoFuture = SendtoFuture<PacketSocketAdapter>;
+
+    impl<A> SendtoFuture<A> where A: DatagramAdapter {
+        fn new(sock: A, buf: Bytes, addr: A::Addr, flags: SendFlags) -> Self {
+            SendtoFuture(
+                Some(SendtoFutureState {
+                    sock,
+                    buf,
+                    addr,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<A> Future for SendtoFuture<A> where A: DatagramAdapter {
+        type Item = size_t;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let len = {
+                let state = self.0.as_mut().expect("pending sendto future");
+                try_async!(state.sock
+                    .sendto_direct(&state.buf, state.addr, state.flags))
+            };
+            self.0.take();
+            len
+        }
+    }
+
+    pub struct RecvmsgFuture<F, P>(Option<RecvmsgFutureState<F, P>>)
+        where F: Family, P: Protocol;
+
+    struct RecvmsgFutureState<F, P> where F: Family, P: Protocol {
+        sock: RawSocketRef<F, P>,
+        buf: BytesMut,
+        flags: RecvFlags
+    }
+
+    pub type IPv6RawSocketRecvmsgFuture = RecvmsgFuture<Inet6, RawProto>;
+
+    impl<F, P> RecvmsgFuture<F, P> where F: Family, P: Protocol {
+        fn new(sock: RawSocketRef<F, P>, buf: BytesMut, flags: RecvFlags)
+                -> Self {
+            RecvmsgFuture(
+                Some(RecvmsgFutureState {
+                    sock,
+                    buf,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<F, P> Future for RecvmsgFuture<F, P> where F: Family, P: Protocol {
+        type Item = (Bytes, F::Addr, Vec<Cmsg>);
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let (len, addr, cmsgs) = {
+                let state = self.0.as_mut().expect("pending recvmsg future");
+                let (slice, addr, cmsgs) = try_async_val!(
+                    RawSocketAdapter(state.sock.clone())
+                        .recvmsg_direct(&mut state.buf, state.flags)
+                );
+                (slice.len(), addr, cmsgs)
+            };
+
+            let mut state = self.0.take().unwrap();
+            state.buf.truncate(len);
+            Ok(Async::Ready((state.buf.freeze(), addr, cmsgs)))
+        }
+    }
+
+    pub struct SendmsgFuture<F, P>(Option<SendmsgFutureState<F, P>>)
+        where F: Family, P: Protocol;
+
+    struct SendmsgFutureState<F, P> where F: Family, P: Protocol {
+        sock: RawSocketRef<F, P>,
+        buf: Bytes,
+        addr: F::Addr,
+        cmsgs: Vec<Cmsg>,
+        flags: SendFlags
+    }
+
+    pub type IPv6RawSocketSendmsgFuture = SendmsgFuture<Inet6, RawProto>;
+
+    impl<F, P> SendmsgFuture<F, P> where F: Family, P: Protocol {
+        fn new(
+            sock: RawSocketRef<F, P>,
+            buf: Bytes,
+            addr: F::Addr,
+            cmsgs: Vec<Cmsg>,
+            flags: SendFlags
+        ) -> Self {
+            SendmsgFuture(
+                Some(SendmsgFutureState {
+                    sock,
+                    buf,
+                    addr,
+                    cmsgs,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<F, P> Future for SendmsgFuture<F, P> where F: Family, P: Protocol {
+        type Item = size_t;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let len = {
+                let state = self.0.as_mut().expect("pending sendmsg future");
+                try_async!(RawSocketAdapter(state.sock.clone())
+                    .sendmsg_direct(&state.buf, state.addr, &state.cmsgs,
+                        state.flags))
+            };
+            self.0.take();
+            len
+        }
+    }
+
+    pub struct RecvfromBatchFuture<F, P>(
+        Option<RecvfromBatchFutureState<F, P>>
+    ) where F: Family, P: Protocol;
+
+    struct RecvfromBatchFutureState<F, P> where F: Family, P: Protocol {
+        sock: RawSocketRef<F, P>,
+        buf: BytesMut,
+        slot_size: usize,
+        flags: RecvFlags
+    }
+
+    pub type IPv6RawSocketRecvfromBatchFuture
+        = RecvfromBatchFuture<Inet6, RawProto>;
+
+    impl<F, P> RecvfromBatchFuture<F, P> where F: Family, P: Protocol {
+        fn new(
+            sock: RawSocketRef<F, P>,
+            slot_size: usize,
+            max_packets: usize,
+            flags: RecvFlags
+        ) -> Self {
+            RecvfromBatchFuture(
+                Some(RecvfromBatchFutureState {
+                    sock,
+                    buf: BytesMut::from(vec![0; slot_size * max_packets]),
+                    slot_size,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<F, P> Future for RecvfromBatchFuture<F, P> where
+            F: Family,
+            P: Protocol {
+        type Item = Vec<(Bytes, F::Addr)>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let received = {
+                let state = self.0.as_mut()
+                    .expect("pending recvfrom batch future");
+                try_async_val!(
+                    RawSocketAdapter(state.sock.clone())
+                        .recvfrom_batch_direct(
+                            &mut state.buf,
+                            state.slot_size,
+                            state.flags
+                        )
+                )
+            };
+
+            let mut state = self.0.take().unwrap();
+            let mut ret = Vec::with_capacity(received.len());
+            for (len, addr) in received {
+                let mut slot = state.buf.split_to(state.slot_size);
+                slot.truncate(len);
+                ret.push((slot.freeze(), addr));
+            }
+
+            Ok(Async::Ready(ret))
+        }
+    }
+
+    pub struct RecvfromPoolFuture<F, P>(Option<RecvfromPoolFutureState<F, P>>)
+        where F: Family, P: Protocol, F::Addr: PoolAddr;
+
+    struct RecvfromPoolFutureState<F, P> where
+            F: Family,
+            P: Protocol,
+            F::Addr: PoolAddr {
+        sock: RawSocketRef<F, P>,
+        pool: BufferPool<F::Addr>,
+        flags: RecvFlags
+    }
+
+    pub type IPv6RawSocketRecvfromPoolFuture
+        = RecvfromPoolFuture<Inet6, RawProto>;
+
+    impl<F, P> RecvfromPoolFuture<F, P> where
+            F: Family,
+            P: Protocol,
+            F::Addr: PoolAddr {
+        fn new(
+            sock: RawSocketRef<F, P>,
+            pool: BufferPool<F::Addr>,
+            flags: RecvFlags
+        ) -> Self {
+            RecvfromPoolFuture(
+                Some(RecvfromPoolFutureState {
+                    sock,
+                    pool,
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<F, P> Future for RecvfromPoolFuture<F, P> where
+            F: Family,
+            P: Protocol,
+            F::Addr: PoolAddr {
+        type Item = BufferPool<F::Addr>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            {
+                let state = self.0.as_mut()
+                    .expect("pending recvfrom pool future");
+                try_async_val!(
+                    RawSocketAdapter(state.sock.clone())
+                        .recvfrom_pool_direct(&mut state.pool, state.flags)
+                );
+            }
+            Ok(Async::Ready(self.0.take().unwrap().pool))
+        }
+    }
+
+    pub struct RecvTxTimestampFuture<F, P>(Option<RawSocketRef<F, P>>)
+        where F: Family, P: Protocol;
+
+    pub type IPv6RawSocketRecvTxTimestampFuture
+        = RecvTxTimestampFuture<Inet6, RawProto>;
+
+    impl<F, P> Future for RecvTxTimestampFuture<F, P> where
+            F: Family,
+            P: Protocol {
+        type Item = TxTimestamp;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let ret = {
+                let sock = self.0.as_ref()
+                    .expect("pending tx timestamp future");
+                try_async!(
+                    RawSocketAdapter(sock.clone()).recv_tx_timestamp_direct()
+                )
+            };
+            self.0.take();
+            ret
+        }
+    }
+
+    pub struct SendtoBatchFuture<F, P>(Option<SendtoBatchFutureState<F, P>>)
+        where F: Family, P: Protocol;
+
+    struct SendtoBatchFutureState<F, P> where F: Family, P: Protocol {
+        sock: RawSocketRef<F, P>,
+        msgs: Vec<(Bytes, F::Addr)>,
+        report: BatchSendReport,
+        flags: SendFlags
+    }
+
+    pub type IPv6RawSocketSendtoBatchFuture
+        = SendtoBatchFuture<Inet6, RawProto>;
+
+    impl<F, P> SendtoBatchFuture<F, P> where F: Family, P: Protocol {
+        fn new(
+            sock: RawSocketRef<F, P>,
+            msgs: Vec<(Bytes, F::Addr)>,
+            flags: SendFlags
+        ) -> Self {
+            SendtoBatchFuture(
+                Some(SendtoBatchFutureState {
+                    sock,
+                    msgs,
+                    report: BatchSendReport::default(),
+                    flags
+                })
+            )
+        }
+    }
+
+    impl<F, P> Future for SendtoBatchFuture<F, P> where
+            F: Family,
+            P: Protocol {
+        type Item = BatchSendReport;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            {
+                let state = self.0.as_mut()
+                    .expect("pending sendto batch future");
+                let (sock, msgs, flags) =
+                    (&state.sock, &state.msgs, state.flags);
+                let ready = state.report.poll_send(msgs.len(), |i| {
+                    RawSocketAdapter(sock.clone())
+                        .sendto_batch_direct(&msgs[i..], flags)
+                })?;
+                if let Async::NotReady = ready {
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            let state = self.0.take().unwrap();
+            Ok(Async::Ready(state.report))
+        }
+    }
+
+    gen_evented_eventedfd!(PacketSocket);
+
+    pub struct PacketSocketAdapter(PacketSocketRef);
+    type PacketSocketRef = Arc<PollEvented2<PacketSocket>>;
+
+    impl PacketSocketAdapter {
+        pub fn new(handle: &Handle, inner: PacketSocket) -> Result<Self> {
+            set_fd_nonblock(&inner, Nonblock::Yes)?;
+            Ok(
+                PacketSocketAdapter(
+                    Arc::new(
+                        PollEvented2::new_with_handle(inner, handle)?
+                    )
+                )
+            )
+        }
+
+        pub fn recvfrom(&mut self, buf: BytesMut, flags: RecvFlags)
+                -> PacketSocketRecvfromFuture {
+            RecvfromFuture::new(
+                PacketSocketAdapter(self.0.clone()),
+                buf,
+                flags
+            )
+        }
+
+        pub fn sendto(
+            &mut self,
+            buf: Bytes,
+            dest: Option<HwAddr>,
+            flags: SendFlags
+        ) -> PacketSocketSendtoFuture {
+            SendtoFuture::new(
+                PacketSocketAdapter(self.0.clone()),
+                buf,
+                dest,
+                flags
+            )
+        }
+
+        pub fn get_interface_hwaddr(&self) -> Option<HwAddr> {
+            self.0.get_ref().get_interface_hwaddr()
+        }
+
+        pub fn get_interface_index(&self) -> c_int {
+            self.0.get_ref().get_interface_index()
+        }
+    }
+
+    impl DatagramAdapter for PacketSocketAdapter {
+        type Addr = Option<HwAddr>;
+
+        fn recvfrom_direct<'a>(
+            &mut self,
+            buf: &'a mut [u8],
+            flags: RecvFlags
+        ) -> ::std::result::Result<
+            (&'a mut [u8], Option<HwAddr>),
+            ::errors::Error
+        > {
+            let poll_evented = &*self.0;
+            let ready = Ready::readable();
+
+            if let Async::NotReady = poll_evented.poll_read_ready(ready)
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
+
+            sock.recvfrom(buf, flags)
+                .map_err(|e| handle_read_error(&*poll_evented, ready, e))
+        }
+
+        fn sendto_direct(
+            &mut self,
+            buf: &[u8],
+            dest: Option<HwAddr>,
+            flags: SendFlags
+        ) -> ::std::result::Result<size_t, ::errors::Error> {
+            let poll_evented = &*self.0;
+
+            if let Async::NotReady = poll_evented.poll_write_ready()
+                    .map_err(Error::TokioError)? {
+                return Err(make_again());
+            }
+
+            let common_sock = &poll_evented.get_ref().0;
+            let mut sock = PacketSocketImpl { .. *common_sock };
+
+            sock.sendto(buf, dest, flags)
+                .map_err(|e| handle_write_error(&*poll_evented, e))
+        }
+    }
+
+    impl AsRawFd for PacketSocketAdapter {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.get_ref().as_raw_fd()
+        }
+    }
+
+    // A split adapter gives its receiving half to the reader task and
+    // its sending half to the writer task.  The halves are not Clone:
+    // the reactor wakes up only the last task to poll a registration,
+    // so another writer gets a descriptor of its own from try_clone().
+    macro_rules! gen_adapter_halves {
+        ($adapter:ident, $recv_half:ident, $send_half:ident) => (
+            gen_adapter_halves!([] $adapter, $recv_half, $send_half);
+        );
+        // for a generic adapter, with the parameters in the brackets
+        ([$($gen:ident: $bound:ident),*]
+                $adapter:ident, $recv_half:ident, $send_half:ident) => (
+            pub struct $recv_half<$($gen),*>($adapter<$($gen),*>)
+                where $($gen: $bound),*;
+            pub struct $send_half<$($gen),*>($adapter<$($gen),*>)
+                where $($gen: $bound),*;
+
+            impl<$($gen),*> $adapter<$($gen),*> where $($gen: $bound),* {
+                pub fn split(self)
+                        -> ($recv_half<$($gen),*>, $send_half<$($gen),*>) {
+                    let other = $adapter(self.0.clone());
+                    ($recv_half(self), $send_half(other))
+                }
+            }
+
+            impl<$($gen),*> $recv_half<$($gen),*> where $($gen: $bound),* {
+                // for the methods doing no I/O
+                pub fn get_ref(&self) -> &$adapter<$($gen),*> {
+                    &self.0
+                }
+            }
+
+            impl<$($gen),*> $send_half<$($gen),*> where $($gen: $bound),* {
+                pub fn get_ref(&self) -> &$adapter<$($gen),*> {
+                    &self.0
+                }
+
+                pub fn try_clone(&self, handle: &Handle) -> Result<Self> {
+                    let sock = (self.0).0.get_ref().try_clone()?;
+                    Ok($send_half($adapter::new(handle, sock)?))
+                }
+            }
+
+            impl<$($gen),*> AsRawFd for $recv_half<$($gen),*>
+                    where $($gen: $bound),* {
+                fn as_raw_fd(&self) -> RawFd {
+                    self.0.as_raw_fd()
+                }
+            }
+
+            impl<$($gen),*> AsRawFd for $send_half<$($gen),*>
+                    where $($gen: $bound),* {
+                fn as_raw_fd(&self) -> RawFd {
+                    self.0.as_raw_fd()
+                }
+            }
+
+            impl<$($gen),*> SocketCommon for $recv_half<$($gen),*>
+                where $($gen: $bound),* {}
+            impl<$($gen),*> SocketCommon for $send_half<$($gen),*>
+                where $($gen: $bound),* {}
+        )
+    }
+
+    macro_rules! gen_half_methods {
+        ($(
+            fn $name:ident[$($gen:tt)*]($($arg:ident: $arg_ty:ty),*)
+                -> $ret:ty;
+        )*) => ($(
+            pub fn $name<$($gen)*>(&mut self, $($arg: $arg_ty),*) -> $ret {
+                self.0.$name($($arg),*)
+            }
+        )*)
+    }
+
+    gen_adapter_halves!(
+        IPv6PacketSocketAdapter,
+        IPv6PacketSocketRecvHalf,
+        IPv6PacketSocketSendHalf
+    );
+
+    impl IPv6PacketSocketRecvHalf {
+        gen_half_methods! {
+            fn recvpacket_direct[](maxsize: size_t, flags: RecvFlags)
+                -> ::std::result::Result<
+                    (Ipv6, Option<HwAddr>),
+                    ::errors::Error
+                >;
+            fn recvpacket[](maxsize: size_t, flags: RecvFlags)
+                -> IPv6PacketSocketRecvpacketFuture;
+            fn recvpacket_batch_direct[](
+                maxsize: size_t,
+                max_packets: usize,
+                flags: RecvFlags
+            ) -> ::std::result::Result<
+                    Vec<(Ipv6, Option<HwAddr>)>,
+                    ::errors::Error
+                >;
+            fn recvpacket_batch[](
+                maxsize: size_t,
+                max_packets: usize,
+                flags: RecvFlags
+            ) -> IPv6PacketSocketRecvpacketBatchFuture;
+            fn recvpacket_pool_direct[](
+                pool: &mut BufferPool<Option<HwAddr>>,
+                flags: RecvFlags
+            ) -> ::std::result::Result<usize, ::errors::Error>;
+            fn recvpacket_pool[](
+                pool: BufferPool<Option<HwAddr>>,
+                flags: RecvFlags
+            ) -> IPv6PacketSocketRecvpacketPoolFuture;
+            fn recvpacket_timestamped_direct[](
+                maxsize: size_t,
+                flags: RecvFlags
+            ) -> ::std::result::Result<
+                    (Ipv6, Option<HwAddr>, Option<Timestamps>),
+                    ::errors::Error
+                >;
+            fn recvpacket_timestamped[](maxsize: size_t, flags: RecvFlags)
+                -> IPv6PacketSocketRecvpacketTimestampedFuture;
+            fn recv_tx_timestamp_direct[]()
+                -> ::std::result::Result<TxTimestamp, ::errors::Error>;
+            fn recv_tx_timestamp[]() -> IPv6PacketSocketRecvTxTimestampFuture;
+        }
+    }
+
+    impl IPv6PacketSocketSendHalf {
+        gen_half_methods! {
+            fn sendpacket_direct[](
+                packet: &Ipv6,
+                dest: Option<HwAddr>,
+                flags: SendFlags
+            ) -> ::std::result::Result<size_t, ::errors::Error>;
+            fn sendpacket[](
+                packet: Ipv6,
+                dest: Option<HwAddr>,
+                flags: SendFlags
+            ) -> IPv6PacketSocketSendpacketFuture;
+            fn sendpacket_batch_direct[](
+                packets: &[(Ipv6, Option<HwAddr>)],
+                flags: SendFlags
+            ) -> ::std::result::Result<usize, ::errors::Error>;
+            fn sendpacket_batch[](
+                packets: Vec<(Ipv6, Option<HwAddr>)>,
+                flags: SendFlags
+            ) -> IPv6PacketSocketSendpacketBatchFuture;
+        }
+    }
+
+    gen_adapter_halves!(
+        [F: Family, P: Protocol]
+        RawSocketAdapter,
+        RawSocketRecvHalf,
+        RawSocketSendHalf
+    );
+
+    pub type IPv6RawSocketRecvHalf = RawSocketRecvHalf<Inet6, RawProto>;
+    pub type IPv6RawSocketSendHalf = RawSocketSendHalf<Inet6, RawProto>;
+    pub type IPv4RawSocketRecvHalf = RawSocketRecvHalf<Inet, RawProto>;
+    pub type IPv4RawSocketSendHalf = RawSocketSendHalf<Inet, RawProto>;
+    pub type IPv6PingSocketRecvHalf = RawSocketRecvHalf<Inet6, PingProto>;
+    pub type IPv6PingSocketSendHalf = RawSocketSendHalf<Inet6, PingProto>;
+
+    impl<F, P> RawSocketRecvHalf<F, P> where F: Family, P: Protocol {
+        gen_half_methods! {
+            fn recvfrom_direct['a](buf: &'a mut [u8], flags: RecvFlags)
+                -> ::std::result::Result<
+                    (&'a mut [u8], F::Addr),
+                    ::errors::Error
+                >;
+            fn recvfrom[](buf: BytesMut, flags: RecvFlags)
+                -> RecvfromFuture<RawSocketAdapter<F, P>>;
+            fn recvmsg_direct['a](buf: &'a mut [u8], flags: RecvFlags)
+                -> ::std::result::Result<
+                    (&'a mut [u8], F::Addr, Vec<Cmsg>),
+                    ::errors::Error
+                >;
+            fn recvmsg[](buf: BytesMut, flags: RecvFlags)
+                -> RecvmsgFuture<F, P>;
+            fn recvfrom_batch_direct[](
+                buf: &mut [u8],
+                slot_size: usize,
+                flags: RecvFlags
+            ) -> ::std::result::Result<
+                    Vec<(usize, F::Addr)>,
+                    ::errors::Error
+                >;
+            fn recvfrom_batch[](
+                maxsize: usize,
+                max_packets: usize,
+                flags: RecvFlags
+            ) -> RecvfromBatchFuture<F, P>;
+            fn recv_tx_timestamp_direct[]()
+                -> ::std::result::Result<TxTimestamp, ::errors::Error>;
+            fn recv_tx_timestamp[]() -> RecvTxTimestampFuture<F, P>;
+        }
+    }
+
+    impl<F, P> RawSocketRecvHalf<F, P> where
+            F: Family,
+            P: Protocol,
+            F::Addr: PoolAddr {
+        gen_half_methods! {
+            fn recvfrom_pool_direct[](
+                pool: &mut BufferPool<F::Addr>,
+                flags: RecvFlags
+            ) -> ::std::result::Result<usize, ::errors::Error>;
+            fn recvfrom_pool[](pool: BufferPool<F::Addr>, flags: RecvFlags)
+                -> RecvfromPoolFuture<F, P>;
+        }
+    }
+
+    impl<F, P> RawSocketSendHalf<F, P> where F: Family, P: Protocol {
+        gen_half_methods! {
+            fn sendto_direct[](
+                buf: &[u8],
+                addr: F::Addr,
+                flags: SendFlags
+            ) -> ::std::result::Result<size_t, ::errors::Error>;
+            fn sendto[](buf: Bytes, addr: F::Addr, flags: SendFlags)
+                -> SendtoFuture<RawSocketAdapter<F, P>>;
+            fn sendmsg_direct[](
+                buf: &[u8],
+                addr: F::Addr,
+                cmsgs: &[Cmsg],
+                flags: SendFlags
+            ) -> ::std::result::Result<size_t, ::errors::Error>;
+            fn sendmsg[](
+                buf: Bytes,
+                addr: F::Addr,
+                cmsgs: Vec<Cmsg>,
+                flags: SendFlags
+            ) -> SendmsgFuture<F, P>;
+            fn sendto_batch_direct[T: AsRef<[u8]>](
+                msgs: &[(T, F::Addr)],
+                flags: SendFlags
+            ) -> ::std::result::Result<usize, ::errors::Error>;
+            fn sendto_batch[](
+                msgs: Vec<(Bytes, F::Addr)>,
+                flags: SendFlags
+            ) -> SendtoBatchFuture<F, P>;
+        }
+    }
+
+    gen_adapter_halves!(
+        PacketSocketAdapter,
+        PacketSocketRecvHalf,
+        PacketSocketSendHalf
+    );
+
+    impl PacketSocketRecvHalf {
+        gen_half_methods! {
+            fn recvfrom_direct['a](buf: &'a mut [u8], flags: RecvFlags)
+                -> ::std::result::Result<
+                    (&'a mut [u8], Option<HwAddr>),
+                    ::errors::Error
+                >;
+            fn recvfrom[](buf: BytesMut, flags: RecvFlags)
+                -> PacketSocketRecvfromFuture;
+        }
+    }
+
+    impl PacketSocketSendHalf {
+        gen_half_methods! {
+            fn sendto_direct[](
+                buf: &[u8],
+                dest: Option<HwAddr>,
+                flags: SendFlags
+            ) -> ::std::result::Result<size_t, ::errors::Error>;
+            fn sendto[](buf: Bytes, dest: Option<HwAddr>, flags: SendFlags)
+                -> PacketSocketSendtoFuture;
+        }
+    }
+
+    impl SocketCommon for IPv6PacketSocketAdapter {}
+    impl<F, P> SocketCommon for RawSocketAdapter<F, P>
+        where F: Family, P: Protocol {}
+    impl SocketCommon for PacketSocketAdapter {}
+
+    fn make_again() -> Error {
+        Error::Again(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "request would block"
+        ))
+    }
+
+    fn handle_read_error<T>(
+        poll_evented: &PollEvented2<T>,
+        ready: Ready,
+        e: ::failure::Error
+    ) -> Error where T: Evented {
+        let err = e.downcast::<Error>().unwrap();
+        if let Again = (&err).into() {
+            if let Err(e) = poll_evented.clear_read_ready(ready) {
+                return Error::TokioError(e);
+            }
+            return err;
+        }
+        let new_e: ::failure::Error = err.into();
+        Error::SocketError(new_e.compat())
+    }
+
+    fn handle_write_error<T>(
+        poll_evented: &PollEvented2<T>,
+        e: ::failure::Error
+    ) -> Error where T: Evented {
+        let err = e.downcast::<Error>().unwrap();
+        if let Again = (&err).into() {
+            if let Err(e) = poll_evented.clear_write_ready() {
+                return Error::TokioError(e);
+            }
+            return err;
+        }
+        let new_e: ::failure::Error = err.into();
+        Error::SocketError(new_e.compat())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        use ::std::collections::BTreeSet;
+        use ::std::process;
+        use ::std::time::Duration;
+
+        use ::tokio::prelude::future::{Loop, loop_fn};
+        use ::tokio::prelude::stream::iter_ok;
+        use ::tokio::runtime::Runtime;
+        use ::tokio::timer::Timeout;
+
+        const REQUESTS_PER_WRITER: u16 = 16;
+
+        #[allow(clippy::cast_possible_truncation)]
+        fn echo_request(id: u16, seq: u16) -> Bytes {
+            Bytes::from(&[
+                IcmpV6Type::EchoRequest.repr(), 0, 0, 0,
+                (id >> 8) as u8, id as u8, (seq >> 8) as u8, seq as u8
+            ][..])
+        }
+
+        fn send_requests(
+            mut sock: IPv6RawSocketSendHalf,
+            id: u16,
+            first_seq: u16
+        ) -> impl Future<Item = (), Error = ()> + Send {
+            let localhost = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+            iter_ok(first_seq..first_seq + REQUESTS_PER_WRITER)
+                .for_each(move |seq| {
+                    sock.sendto(
+                        echo_request(id, seq),
+                        localhost,
+                        SendFlags::empty()
+                    ).map(|_| ())
+                })
+                .map_err(|e| panic!("sendto failed: {}", e))
+        }
+
+        // the sequence numbers of the echo replies to id,
+        // the requests loop back to the socket too
+        fn recv_replies(sock: IPv6RawSocketRecvHalf, id: u16, count: usize)
+                -> impl Future<Item = BTreeSet<u16>, Error = Error> + Send {
+            loop_fn((sock, BTreeSet::new()), move |(mut sock, mut seen)| {
+                sock.recvfrom(BytesMut::from(vec![0; 1500]), RecvFlags::empty())
+                    .map(move |(buf, _)| {
+                        let is_reply = buf.len() >= 8
+                            && buf[0] == IcmpV6Type::EchoReply.repr()
+                            && u16::from(buf[4]) << 8 | u16::from(buf[5]) == id;
+                        if is_reply {
+                            seen.insert(u16::from(buf[6]) << 8
+                                | u16::from(buf[7]));
+                        }
+                        if seen.len() == count {
+                            Loop::Break(seen)
+                        } else {
+                            Loop::Continue((sock, seen))
+                        }
+                    })
+            })
+        }
+
+        fn make_socket(handle: &Handle) -> IPv6RawSocketAdapter {
+            let sock = IPv6RawSocket::new(
+                IpProto::IcmpV6.repr(),
+                SockFlag::empty()
+            ).unwrap();
+            IPv6RawSocketAdapter::new(handle, sock).unwrap()
+        }
+
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        #[test]
+        fn adapters_are_send_and_sync() {
+            assert_send_sync::<IPv6RawSocketAdapter>();
+            assert_send_sync::<IPv6RawSocketRecvHalf>();
+            assert_send_sync::<IPv6RawSocketSendHalf>();
+            assert_send_sync::<IPv6RawSocketRecvfromBatchFuture>();
+            assert_send_sync::<IPv6RawSocketSendmsgFuture>();
+            assert_send_sync::<IPv6PacketSocketRecvHalf>();
+            assert_send_sync::<IPv6PacketSocketSendpacketBatchFuture>();
+            assert_send_sync::<IPv4RawSocketSendHalf>();
+            assert_send_sync::<IPv6PingSocketRecvfromFuture>();
+            assert_send_sync::<PacketSocketSendHalf>();
+        }
+
+        #[test]
+        #[ignore = "needs CAP_NET_RAW"]
+        fn split_halves_share_socket() {
+            let rt = Runtime::new().unwrap();
+            let sock = make_socket(rt.reactor());
+            let fd = sock.as_raw_fd();
+
+            let (recv, send) = sock.split();
+            assert_eq!(recv.as_raw_fd(), fd);
+            assert_eq!(send.as_raw_fd(), fd);
+            assert_eq!(recv.get_ref().as_raw_fd(), fd);
+
+            let other = send.try_clone(rt.reactor()).unwrap();
+            assert_ne!(other.as_raw_fd(), fd);
+            drop(send);
+            assert!(other.getsockopt::<SockOpts::RcvBuf>().unwrap() > 0);
+        }
+
+        // a reader and two writers on the thread pool, one of the writers
+        // on a descriptor of its own
+        #[test]
+        #[ignore = "needs CAP_NET_RAW"]
+        #[allow(clippy::cast_possible_truncation)]
+        fn concurrent_reader_and_writers() {
+            let mut rt = Runtime::new().unwrap();
+            let sock = make_socket(rt.reactor());
+            let id = process::id() as u16;
+
+            let (recv, send) = sock.split();
+            let other = send.try_clone(rt.reactor()).unwrap();
+
+            let replies = Timeout::new(
+                recv_replies(recv, id, 2 * REQUESTS_PER_WRITER as usize),
+                Duration::from_secs(5)
+            );
+            rt.spawn(send_requests(send, id, 0));
+            rt.spawn(send_requests(other, id, REQUESTS_PER_WRITER));
+
+            let seen = rt.block_on(replies).unwrap();
+            assert_eq!(
+                seen,
+                (0..2 * REQUESTS_PER_WRITER).collect::<BTreeSet<_>>()
+            );
+        }
+
+        // datagram 2 fails, 3 and 4 are still sent after a short count
+        #[test]
+        fn batch_send_skips_failures() {
+            let mut report = BatchSendReport::default();
+            let mut calls = vec![];
+            let ready = report.poll_send(5, |i| {
+                calls.push(i);
+                match i {
+                    0 => Ok(2),
+                    2 => Err(Error::IoError(
+                        io::Error::from_raw_os_error(::nlibc::ENETUNREACH)
+                    )),
+                    _ => Ok(1)
+                }
+            }).unwrap();
+
+            assert!(ready.is_ready());
+            assert_eq!(calls, vec![0, 2, 3, 4]);
+            assert_eq!(report.sent, 4);
+            assert_eq!(report.failed.len(), 1);
+            assert_eq!(report.failed[0].0, 2);
+        }
+
+        #[test]
+        fn batch_send_waits_on_again() {
+            let mut report = BatchSendReport::default();
+            let ready = report.poll_send(3, |i| match i {
+                0 => Ok(1),
+                _ => Err(Error::Again(
+                    io::Error::from_raw_os_error(::nlibc::EAGAIN)
+                ))
+            }).unwrap();
+
+            assert!(ready.is_not_ready());
+            assert_eq!(report.sent, 1);
+            assert!(report.failed.is_empty());
+        }
+    }
+}
+
+#[allow(clippy::transmute_ptr_to_ptr)]
+unsafe fn as_sockaddr<T>(x: &T) -> &sockaddr {
+    transmute::<&T, &sockaddr>(x)
+}
+
+#[allow(clippy::transmute_ptr_to_ptr)]
+unsafe fn as_sockaddr_mut<T>(x: &mut T) -> &mut sockaddr {
+    transmute::<&mut T, &mut sockaddr>(x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::time::SystemTime;
+
+    use ::test_util::*;
+
+    #[test]
+    fn sock_opt_roundtrip() {
+        let mut sock = make_test_socket();
+
+        sock.setsockopt(&SockOpts::UnicastHops::new(&42)).unwrap();
+        assert_eq!(sock.getsockopt::<SockOpts::UnicastHops>().unwrap(), 42);
+
+        sock.setsockopt(&SockOpts::V6MtuDiscover::new(&V6PmtuType::Do))
+            .unwrap();
+        assert_eq!(
+            sock.getsockopt::<SockOpts::V6MtuDiscover>().unwrap(),
+            V6PmtuType::Do
+        );
+
+        sock.setsockopt(&SockOpts::DontRoute::new(&true)).unwrap();
+        assert!(sock.getsockopt::<SockOpts::DontRoute>().unwrap());
+        sock.setsockopt(&SockOpts::DontRoute::new(&false)).unwrap();
+        assert!(!sock.getsockopt::<SockOpts::DontRoute>().unwrap());
+    }
+
+    #[test]
+    fn traffic_sock_opts() {
+        let mut sock = make_test_socket();
+
+        sock.setsockopt(&SockOpts::Priority::new(&3)).unwrap();
+        assert_eq!(sock.getsockopt::<SockOpts::Priority>().unwrap(), 3);
+
+        let tclass = TrafficClass::from_dscp(46);
+        sock.setsockopt(&SockOpts::TClass::new(&tclass)).unwrap();
+        let tclass = sock.getsockopt::<SockOpts::TClass>().unwrap();
+        assert_eq!((tclass.dscp(), tclass.ecn()), (46, 0));
+
+        sock.setsockopt(&SockOpts::SndBuf::new(&65536)).unwrap();
+        assert!(sock.getsockopt::<SockOpts::SndBuf>().unwrap() >= 65536);
+
+        sock.setsockopt(&SockOpts::MulticastHops::new(&5)).unwrap();
+        assert_eq!(sock.getsockopt::<SockOpts::MulticastHops>().unwrap(), 5);
+        sock.setsockopt(&SockOpts::MulticastLoop::new(&false)).unwrap();
+        assert!(!sock.getsockopt::<SockOpts::MulticastLoop>().unwrap());
+
+        // the other clocks need CAP_NET_ADMIN
+        let txtime = TxTimeConfig {
+            clock: TxTimeClock::Monotonic,
+            flags: TxTimeFlags::DeadlineMode
+        };
+        sock.setsockopt(&SockOpts::TxTime::new(&txtime)).unwrap();
+        assert_eq!(sock.getsockopt::<SockOpts::TxTime>().unwrap(), txtime);
+    }
+
+    #[test]
+    fn read_only_sock_opts() {
+        let sock = make_test_socket();
+        assert!(sock.take_error().unwrap().is_none());
+        assert!(sock.getsockopt::<SockOpts::RcvBuf>().unwrap() > 0);
+        assert_eq!(sock.getsockopt::<SockOpts::BindToDevice>().unwrap(), "");
+
+        // no path, no path MTU
+        assert!(sock.getsockopt::<SockOpts::Mtu>().is_err());
+        sock.0.connect("[::1]:9").unwrap();
+        assert!(sock.getsockopt::<SockOpts::Mtu>().unwrap() >= 1280);
+    }
+    // skipped when outside of net.ipv4.ping_group_range
+    #[test]
+    fn ping_socket_echo() {
+        let mut sock = match IPv6PingSocket::new(SockFlag::empty()) {
+            Ok(x) => x,
+            Err(_) => return
+        };
+        let localhost = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        assert_eq!(sock.identifier().unwrap(), 0);
+
+        // the identifier 0 is replaced by the kernel
+        let request = [
+            IcmpV6Type::EchoRequest.repr(), 0, 0, 0,
+            0, 0, 0, 1,
+            b'h', b'i'
+        ];
+        sock.sendto(&request, localhost, SendFlags::empty()).unwrap();
+        let id = sock.identifier().unwrap();
+        assert_ne!(id, 0);
+
+        let mut buf = [0; 64];
+        let (reply, src) = sock.recvfrom(&mut buf, RecvFlags::empty())
+            .unwrap();
+        assert_eq!(*src.ip(), Ipv6Addr::LOCALHOST);
+        assert_eq!(reply[0], IcmpV6Type::EchoReply.repr());
+        assert_eq!(u16::from(reply[4]) << 8 | u16::from(reply[5]), id);
+        assert_eq!(&reply[6..], &request[6..]);
+    }
+
+    #[test]
+    fn software_tx_timestamps() {
+        let mut sock = make_test_socket();
+        let flags = TimestampingFlags::TxSoftware
+            | TimestampingFlags::Software
+            | TimestampingFlags::OptId
+            | TimestampingFlags::OptTsonly;
+        sock.setsockopt(&SockOpts::Timestamping::new(&flags)).unwrap();
+        assert_eq!(
+            sock.getsockopt::<SockOpts::Timestamping>().unwrap(),
+            flags
+        );
+
+        let mut errqueue = RawSocketImpl::<Inet6>::from_fd(sock.as_raw_fd());
+        assert_eq!(errqueue.recv_tx_timestamp().unwrap(), None);
+
+        // the loopback timestamps on transmit, synchronously
+        let before = SystemTime::now();
+        for _ in 0..2 {
+            sock.0.send_to(b"hi", "[::1]:9").unwrap();
+        }
+        for key in 0..2 {
+            let ts = errqueue.recv_tx_timestamp().unwrap().unwrap();
+            assert_eq!(ts.kind, TxTimestampKind::Sent);
+            assert_eq!(ts.key, key);
+            assert!(ts.stamps.software.unwrap() >= before);
+            assert_eq!(ts.stamps.hardware, None);
+        }
+    }
+
+    #[test]
+    fn family_addr_roundtrip() {
+        let addr6 = SocketAddrV6::new("fe80::1".parse().unwrap(), 7, 0, 3);
+        assert_eq!(Inet6::from_raw(&Inet6::to_raw(&addr6)).unwrap(), addr6);
+
+        let addr4 = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 7);
+        assert_eq!(Inet::from_raw(&Inet::to_raw(&addr4)).unwrap(), addr4);
+
+        let link = LinkAddr {
+            if_index: 2,
+            proto: 0x86dd,
+            hwaddr: Some(HwAddr::from_bytes(&[2, 0, 0, 0, 0, 1]).unwrap())
+        };
+        let raw = LinkLayer::to_raw(&link);
+        assert_eq!(raw.sll_protocol, 0x86dd_u16.to_be());
+        assert_eq!(LinkLayer::from_raw(&raw).unwrap(), link);
+    }
+
+    #[test]
+    fn raw_sockets_clear_the_port() {
+        let addr = SocketAddrV6::new("fe80::1".parse().unwrap(), 58, 0, 3);
+
+        let raw = RawSocketImpl::<Inet6, RawProto>::to_raw(&addr);
+        assert_eq!(raw.sin6_port, 0);
+        assert_eq!(raw.sin6_scope_id, 3);
+
+        let ping = RawSocketImpl::<Inet6, PingProto>::to_raw(&addr);
+        assert_eq!(ping.sin6_port, 58_u16.to_be());
+    }
 }
@@ -0,0 +1,481 @@
+// std::future adapters on the readiness of tokio 1.x AsyncFd, to be used
+// with async/await; the futures 0.1 adapters in futures stay as they are.
+// The adapters are to be created inside a tokio 1.x runtime with io enabled.
+
+use ::std::future::Future;
+use ::std::io;
+use ::std::os::unix::prelude::*;
+use ::std::pin::Pin;
+use ::std::task::{Context, Poll};
+
+use ::futures_core::Stream;
+use ::futures_sink::Sink;
+use ::nlibc::size_t;
+use ::tokio1::io::unix::AsyncFd;
+
+use ::*;
+use ::errors::{Error, Result};
+
+// the sockets exchanging datagrams with an address of Addr
+pub trait DatagramSocket: AsRawFd {
+    type Addr: Copy;
+
+    fn recv_from_len(&mut self, buf: &mut [u8], flags: RecvFlags)
+        -> Result<(usize, Self::Addr)>;
+
+    fn send_to(&mut self, buf: &[u8], addr: Self::Addr, flags: SendFlags)
+        -> Result<size_t>;
+}
+
+macro_rules! gen_datagram_socket {
+    ($typ:ty, $addr:ty) => (
+        impl DatagramSocket for $typ {
+            type Addr = $addr;
+
+            fn recv_from_len(&mut self, buf: &mut [u8], flags: RecvFlags)
+                    -> Result<(usize, Self::Addr)> {
+                let (slice, addr) = self.recvfrom(buf, flags)?;
+                Ok((slice.len(), addr))
+            }
+
+            fn send_to(
+                &mut self,
+                buf: &[u8],
+                addr: Self::Addr,
+                flags: SendFlags
+            ) -> Result<size_t> {
+                self.sendto(buf, addr, flags)
+            }
+        }
+    )
+}
+
+gen_datagram_socket!(PacketSocket, Option<HwAddr>);
+
+impl<F, P> DatagramSocket for RawSocket<F, P> where F: Family, P: Protocol {
+    type Addr = F::Addr;
+
+    fn recv_from_len(&mut self, buf: &mut [u8], flags: RecvFlags)
+            -> Result<(usize, Self::Addr)> {
+        let (slice, addr) = self.recvfrom(buf, flags)?;
+        Ok((slice.len(), addr))
+    }
+
+    fn send_to(&mut self, buf: &[u8], addr: Self::Addr, flags: SendFlags)
+            -> Result<size_t> {
+        self.sendto(buf, addr, flags)
+    }
+}
+
+pub struct AsyncSocket<T> where T: AsRawFd {
+    inner: AsyncFd<T>
+}
+
+impl<T> AsyncSocket<T> where T: AsRawFd {
+    pub fn new(inner: T) -> Result<Self> {
+        set_fd_nonblock(&inner, Nonblock::Yes)?;
+        Ok(AsyncSocket {
+            inner: AsyncFd::new(inner).map_err(Error::IoError)?
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+
+    fn poll_read_with<F, R>(&mut self, cx: &mut Context, mut f: F)
+            -> Poll<Result<R>> where F: FnMut(&mut T) -> Result<R> {
+        loop {
+            let mut guard = match self.inner.poll_read_ready_mut(cx) {
+                Poll::Ready(Ok(x)) => x,
+                Poll::Ready(Err(e)) =>
+                    return Poll::Ready(Err(Error::TokioError(e).into())),
+                Poll::Pending => return Poll::Pending
+            };
+
+            // the readiness is cleared on EAGAIN, and polled again
+            if let Ok(ret) = guard.try_io(|x| again_to_would_block(
+                || f(x.get_mut())
+            )) {
+                return Poll::Ready(flatten_io(ret));
+            }
+        }
+    }
+
+    fn poll_write_with<F, R>(&mut self, cx: &mut Context, mut f: F)
+            -> Poll<Result<R>> where F: FnMut(&mut T) -> Result<R> {
+        loop {
+            let mut guard = match self.inner.poll_write_ready_mut(cx) {
+                Poll::Ready(Ok(x)) => x,
+                Poll::Ready(Err(e)) =>
+                    return Poll::Ready(Err(Error::TokioError(e).into())),
+                Poll::Pending => return Poll::Pending
+            };
+
+            if let Ok(ret) = guard.try_io(|x| again_to_would_block(
+                || f(x.get_mut())
+            )) {
+                return Poll::Ready(flatten_io(ret));
+            }
+        }
+    }
+}
+
+impl<T> AsyncSocket<T> where T: DatagramSocket {
+    pub fn poll_recv_from(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+        flags: RecvFlags
+    ) -> Poll<Result<(usize, T::Addr)>> {
+        self.poll_read_with(cx, |x| x.recv_from_len(buf, flags))
+    }
+
+    pub fn poll_send_to(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        addr: T::Addr,
+        flags: SendFlags
+    ) -> Poll<Result<size_t>> {
+        self.poll_write_with(cx, |x| x.send_to(buf, addr, flags))
+    }
+
+    // resolves to the length of the datagram and its source
+    pub fn recv_from<'a>(&'a mut self, buf: &'a mut [u8], flags: RecvFlags)
+            -> RecvFrom<'a, T> {
+        RecvFrom {
+            sock: self,
+            buf,
+            flags
+        }
+    }
+
+    pub fn send_to<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+        addr: T::Addr,
+        flags: SendFlags
+    ) -> SendTo<'a, T> {
+        SendTo {
+            sock: self,
+            buf,
+            addr,
+            flags
+        }
+    }
+}
+
+impl AsyncSocket<IPv6PacketSocket> {
+    pub fn poll_recv_packet(
+        &mut self,
+        cx: &mut Context,
+        maxsize: size_t,
+        flags: RecvFlags
+    ) -> Poll<Result<(Ipv6, Option<HwAddr>)>> {
+        self.poll_read_with(cx, |x| x.recvpacket(maxsize, flags))
+    }
+
+    pub fn poll_send_packet(
+        &mut self,
+        cx: &mut Context,
+        packet: &Ipv6,
+        dest: Option<HwAddr>,
+        flags: SendFlags
+    ) -> Poll<Result<size_t>> {
+        self.poll_write_with(cx, |x| x.sendpacket(packet, dest, flags))
+    }
+
+    pub fn recv_packet(&mut self, maxsize: size_t, flags: RecvFlags)
+            -> RecvPacket {
+        RecvPacket {
+            sock: self,
+            maxsize,
+            flags
+        }
+    }
+
+    pub fn send_packet<'a>(
+        &'a mut self,
+        packet: &'a Ipv6,
+        dest: Option<HwAddr>,
+        flags: SendFlags
+    ) -> SendPacket<'a> {
+        SendPacket {
+            sock: self,
+            packet,
+            dest,
+            flags
+        }
+    }
+}
+
+impl<T> AsRawFd for AsyncSocket<T> where T: AsRawFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl<T> SocketCommon for AsyncSocket<T> where T: AsRawFd {}
+
+fn flatten_io<R>(x: io::Result<Result<R>>) -> Result<R> {
+    match x {
+        Ok(ret) => ret,
+        Err(e) => Err(Error::IoError(e).into())
+    }
+}
+
+// tells tokio to wait for the readiness again, other errors are passed
+fn again_to_would_block<F, R>(mut f: F) -> io::Result<Result<R>>
+        where F: FnMut() -> Result<R> {
+    loop {
+        let ret = f();
+        match ret.as_ref().err().and_then(|e| e.downcast_ref::<Error>()) {
+            Some(&Error::Interrupted(_)) => continue,
+            Some(&Error::Again(_)) =>
+                return Err(io::ErrorKind::WouldBlock.into()),
+            _ => return Ok(ret)
+        }
+    }
+}
+
+pub struct RecvFrom<'a, T> where T: 'a + DatagramSocket {
+    sock: &'a mut AsyncSocket<T>,
+    buf: &'a mut [u8],
+    flags: RecvFlags
+}
+
+impl<'a, T> Future for RecvFrom<'a, T> where T: DatagramSocket {
+    type Output = Result<(usize, T::Addr)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.sock.poll_recv_from(cx, this.buf, this.flags)
+    }
+}
+
+pub struct SendTo<'a, T> where T: 'a + DatagramSocket {
+    sock: &'a mut AsyncSocket<T>,
+    buf: &'a [u8],
+    addr: T::Addr,
+    flags: SendFlags
+}
+
+impl<'a, T> Future for SendTo<'a, T>
+        where T: DatagramSocket, T::Addr: Unpin {
+    type Output = Result<size_t>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.sock.poll_send_to(cx, this.buf, this.addr, this.flags)
+    }
+}
+
+pub struct RecvPacket<'a> {
+    sock: &'a mut AsyncSocket<IPv6PacketSocket>,
+    maxsize: size_t,
+    flags: RecvFlags
+}
+
+impl<'a> Future for RecvPacket<'a> {
+    type Output = Result<(Ipv6, Option<HwAddr>)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.sock.poll_recv_packet(cx, this.maxsize, this.flags)
+    }
+}
+
+pub struct SendPacket<'a> {
+    sock: &'a mut AsyncSocket<IPv6PacketSocket>,
+    packet: &'a Ipv6,
+    dest: Option<HwAddr>,
+    flags: SendFlags
+}
+
+impl<'a> Future for SendPacket<'a> {
+    type Output = Result<size_t>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.sock.poll_send_packet(cx, this.packet, this.dest, this.flags)
+    }
+}
+
+// A Stream of the received datagrams and a Sink of the ones to send.
+// Datagrams longer than max_size are truncated.
+pub struct Datagrams<T> where T: DatagramSocket {
+    sock: AsyncSocket<T>,
+    recv_buf: Vec<u8>,
+    recv_flags: RecvFlags,
+    send_flags: SendFlags,
+    pending: Option<(Vec<u8>, T::Addr)>
+}
+
+impl<T> Datagrams<T> where T: DatagramSocket {
+    pub fn new(sock: AsyncSocket<T>, max_size: usize) -> Self {
+        Datagrams {
+            sock,
+            recv_buf: vec![0; max_size],
+            recv_flags: RecvFlags::empty(),
+            send_flags: SendFlags::empty(),
+            pending: None
+        }
+    }
+
+    pub fn set_recv_flags(&mut self, flags: RecvFlags) {
+        self.recv_flags = flags;
+    }
+
+    pub fn set_send_flags(&mut self, flags: SendFlags) {
+        self.send_flags = flags;
+    }
+
+    pub fn get_ref(&self) -> &AsyncSocket<T> {
+        &self.sock
+    }
+
+    pub fn get_mut(&mut self) -> &mut AsyncSocket<T> {
+        &mut self.sock
+    }
+
+    pub fn into_inner(self) -> AsyncSocket<T> {
+        self.sock
+    }
+
+    fn poll_send_pending(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        if let Some((ref buf, addr)) = self.pending {
+            match self.sock.poll_send_to(cx, buf, addr, self.send_flags) {
+                Poll::Ready(Ok(_)) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+        self.pending = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+// never ends, the errors are passed as items
+impl<T> Stream for Datagrams<T> where T: DatagramSocket + Unpin,
+        T::Addr: Unpin {
+    type Item = Result<(Vec<u8>, T::Addr)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context)
+            -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let flags = this.recv_flags;
+        match this.sock.poll_recv_from(cx, &mut this.recv_buf, flags) {
+            Poll::Ready(Ok((len, addr))) =>
+                Poll::Ready(Some(Ok((this.recv_buf[.. len].to_vec(), addr)))),
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+// buffers one datagram
+impl<T> Sink<(Vec<u8>, T::Addr)> for Datagrams<T>
+        where T: DatagramSocket + Unpin, T::Addr: Unpin {
+    type Error = ::failure::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context)
+            -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: (Vec<u8>, T::Addr))
+            -> Result<()> {
+        let this = self.get_mut();
+        assert!(this.pending.is_none(), "start_send without poll_ready");
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context)
+            -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context)
+            -> Poll<Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use ::std::future::poll_fn;
+    use ::std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+    use ::tokio1::runtime::Builder;
+
+    use ::test_util::*;
+
+    fn make_datagrams(max_size: usize)
+            -> (Datagrams<TestSocket>, SocketAddr) {
+        let sock = make_test_socket();
+        let addr = sock.0.local_addr().unwrap();
+        (Datagrams::new(AsyncSocket::new(sock).unwrap(), max_size), addr)
+    }
+
+    #[test]
+    fn datagrams_round_trip() {
+        let rt = Builder::new_current_thread().enable_io().build().unwrap();
+        let ((mut tx, tx_addr), (mut rx, rx_addr)) = {
+            let _guard = rt.enter();
+            (make_datagrams(4), make_datagrams(4))
+        };
+
+        for msg in &[&b"ping"[..], &b"pingpong"[..]] {
+            rt.block_on(poll_fn(|cx| Pin::new(&mut tx).poll_ready(cx)))
+                .unwrap();
+            Pin::new(&mut tx).start_send((msg.to_vec(), rx_addr)).unwrap();
+            rt.block_on(poll_fn(|cx| Pin::new(&mut tx).poll_flush(cx)))
+                .unwrap();
+        }
+
+        // the second one is truncated to max_size
+        for _ in 0 .. 2 {
+            let (data, src) = rt.block_on(
+                poll_fn(|cx| Pin::new(&mut rx).poll_next(cx))
+            ).unwrap().unwrap();
+            assert_eq!(data, b"ping");
+            assert_eq!(src, tx_addr);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs the group within net.ipv4.ping_group_range"]
+    fn ping_socket_echo() {
+        let rt = Builder::new_current_thread().enable_io().build().unwrap();
+        let sock = IPv6PingSocket::new(SockFlag::empty()).unwrap();
+        let mut sock = {
+            let _guard = rt.enter();
+            AsyncSocket::new(sock).unwrap()
+        };
+
+        let localhost = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+        let request = [IcmpV6Type::EchoRequest.repr(), 0, 0, 0, 0, 0, 0, 1];
+        rt.block_on(sock.send_to(&request, localhost, SendFlags::empty()))
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let (len, src) = rt.block_on(
+            sock.recv_from(&mut buf, RecvFlags::empty())
+        ).unwrap();
+        assert_eq!(len, request.len());
+        assert_eq!(*src.ip(), Ipv6Addr::LOCALHOST);
+        assert_eq!(buf[0], IcmpV6Type::EchoReply.repr());
+    }
+}
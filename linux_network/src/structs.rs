@@ -1,4 +1,5 @@
 use ::std::fmt::*;
+use ::std::net::Ipv6Addr;
 
 use ::nlibc::*;
 
@@ -56,6 +57,57 @@ pub mod raw {
         pub jf: u8,
         pub k: u32
     }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct in6_pktinfo {
+        pub ipi6_addr: in6_addr,
+        pub ipi6_ifindex: c_uint
+    }
+
+    // followed by the sockaddr of the offender, see SO_EE_OFFENDER
+    #[derive(Clone, Copy, Debug)]
+    #[repr(C)]
+    pub struct sock_extended_err {
+        pub ee_errno: u32,
+        pub ee_origin: u8,
+        pub ee_type: u8,
+        pub ee_code: u8,
+        pub ee_pad: u8,
+        pub ee_info: u32,
+        pub ee_data: u32
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    #[repr(C)]
+    pub struct hwtstamp_config {
+        pub flags: c_int,
+        pub tx_type: c_int,
+        pub rx_filter: c_int
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    #[repr(C)]
+    pub struct sock_txtime {
+        pub clockid: clockid_t,
+        pub flags: u32
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct ip6_mtuinfo {
+        pub ip6m_addr: sockaddr_in6,
+        pub ip6m_mtu: u32
+    }
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct packet_mreq {
+        pub mr_ifindex: c_int,
+        pub mr_type: c_ushort,
+        pub mr_alen: c_ushort,
+        pub mr_address: [c_uchar; 8]
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -90,6 +142,64 @@ impl Default for icmp6_filter {
     }
 }
 
+// unlike icmp6_filter, a set bit blocks the type,
+// and only the types below 32 can be filtered
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct icmp_filter {
+    data: uint32_t
+}
+
+impl icmp_filter {
+    pub fn new() -> Self {
+        Self { data: 0xffff_ffff }
+    }
+
+    pub fn new_pass() -> Self {
+        Self { data: 0 }
+    }
+
+    pub fn pass(&mut self, icmp_type: IcmpType) {
+        self.data &= !(1 << icmp_type.repr());
+    }
+
+    pub fn block(&mut self, icmp_type: IcmpType) {
+        self.data |= 1 << icmp_type.repr();
+    }
+}
+
+impl Default for icmp_filter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the IPv6 traffic class: the DSCP in the upper 6 bits,
+// the ECN codepoint in the lower 2
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct TrafficClass(pub u8);
+
+impl TrafficClass {
+    pub fn from_dscp(dscp: u8) -> Self {
+        TrafficClass((dscp & 0x3f) << 2)
+    }
+
+    pub fn dscp(self) -> u8 {
+        self.0 >> 2
+    }
+
+    pub fn ecn(self) -> u8 {
+        self.0 & 0x3
+    }
+}
+
+// SO_TXTIME, the launch times are given with Cmsg::TxTime
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TxTimeConfig {
+    pub clock: TxTimeClock,
+    pub flags: TxTimeFlags
+}
+
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub struct MacAddr([u8; 6]);
 
@@ -114,6 +224,12 @@ impl MacAddr {
         arr.copy_from_slice(s);
         Ok(MacAddr(arr))
     }
+
+    // the Ethernet destination of an IPv6 multicast group, see RFC 2464
+    pub fn from_ipv6_multicast(group: &Ipv6Addr) -> Self {
+        let o = group.octets();
+        MacAddr([0x33, 0x33, o[12], o[13], o[14], o[15]])
+    }
 }
 
 impl Debug for MacAddr {
@@ -124,15 +240,73 @@ impl Debug for MacAddr {
 
 impl Display for MacAddr {
     fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
-        let octets = self.0.iter()
-            .map(|x| format!("{:x}", x))
-            .map(|s| if s.len() == 1 {"0".to_string() + &s} else {s})
-            .map(|s| ":".to_string() + &s)
-            .collect::<Vec<String>>();
-        let s = octets.iter()
-            .flat_map(|x| x.chars())
-            .skip(1)
-            .collect::<String>();
-        write!(f, "{}", s)
+        fmt_hw_addr(&self.0, f)
+    }
+}
+
+// the size of sockaddr_ll's sll_addr
+pub const MAX_HW_ADDR_LEN: usize = 8;
+
+// a link-layer address of any length the packet sockets can carry
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct HwAddr {
+    len: u8,
+    addr: [u8; MAX_HW_ADDR_LEN]
+}
+
+impl HwAddr {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.addr[.. self.len as usize]
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_bytes<T>(x: T) -> Result<Self> where T: AsRef<[u8]> {
+        let s = x.as_ref();
+        if s.is_empty() || s.len() > MAX_HW_ADDR_LEN {
+            return Err(Error::WrongSize.into());
+        }
+
+        let mut addr = [0; MAX_HW_ADDR_LEN];
+        addr[.. s.len()].copy_from_slice(s);
+        Ok(HwAddr {
+            len: s.len() as u8,
+            addr
+        })
+    }
+}
+
+impl From<MacAddr> for HwAddr {
+    fn from(mac: MacAddr) -> Self {
+        Self::from_bytes(mac.as_bytes()).expect("a valid mac address")
+    }
+}
+
+impl Debug for HwAddr {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        (self as &dyn Display).fmt(f)
+    }
+}
+
+impl Display for HwAddr {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        fmt_hw_addr(self.as_bytes(), f)
     }
 }
+
+fn fmt_hw_addr(addr: &[u8], f: &mut Formatter) -> ::std::fmt::Result {
+    let octets = addr.iter()
+        .map(|x| format!("{:x}", x))
+        .map(|s| if s.len() == 1 {"0".to_string() + &s} else {s})
+        .map(|s| ":".to_string() + &s)
+        .collect::<Vec<String>>();
+    let s = octets.iter()
+        .flat_map(|x| x.chars())
+        .skip(1)
+        .collect::<String>();
+    write!(f, "{}", s)
+}
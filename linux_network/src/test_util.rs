@@ -0,0 +1,58 @@
+// Fixtures for the tests of this crate and of the crates using it,
+// enabled by the test-util feature.
+
+use ::std::net::UdpSocket;
+use ::std::os::unix::prelude::*;
+
+use ::*;
+
+// needs no privileges, unlike the raw and packet sockets
+pub struct TestSocket(pub UdpSocket);
+
+impl AsRawFd for TestSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl SocketCommon for TestSocket {}
+
+// bound to an ephemeral port of the IPv6 loopback address
+pub fn make_test_socket() -> TestSocket {
+    TestSocket(UdpSocket::bind("[::1]:0").unwrap())
+}
+
+#[cfg(feature = "std-future")]
+mod datagrams {
+    use ::std::io;
+    use ::std::net::SocketAddr;
+
+    use ::nlibc::size_t;
+
+    use ::*;
+    use ::errors::{Error, Result};
+    use ::std_future::DatagramSocket;
+    use super::TestSocket;
+
+    fn from_io(e: io::Error) -> ::failure::Error {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            Error::Again(e).into()
+        } else {
+            Error::IoError(e).into()
+        }
+    }
+
+    impl DatagramSocket for TestSocket {
+        type Addr = SocketAddr;
+
+        fn recv_from_len(&mut self, buf: &mut [u8], _: RecvFlags)
+                -> Result<(usize, Self::Addr)> {
+            self.0.recv_from(buf).map_err(from_io)
+        }
+
+        fn send_to(&mut self, buf: &[u8], addr: Self::Addr, _: SendFlags)
+                -> Result<size_t> {
+            self.0.send_to(buf, addr).map_err(from_io)
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// SO_TIMESTAMPING, the kernel and NIC timestamps of the packets sent and
+// received, see the kernel Documentation/networking/timestamping.txt.
+// The receive timestamps come with the packets as Cmsg::Timestamping,
+// the transmit ones are queued on the socket error queue.
+
+use ::std::time::SystemTime;
+
+use ::*;
+use ::raw::{SCM_TSTAMP_ACK, SCM_TSTAMP_SCHED, SCM_TSTAMP_SND};
+
+// the software timestamps are of CLOCK_REALTIME, the hardware ones
+// of the NIC clock, which is not necessarily synchronized to it
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Timestamps {
+    pub software: Option<SystemTime>,
+    pub hardware: Option<SystemTime>
+}
+
+impl Timestamps {
+    pub fn from_cmsgs(cmsgs: &[Cmsg]) -> Option<Self> {
+        cmsgs.iter().filter_map(|x| match *x {
+            Cmsg::Timestamping(stamps) => Some(stamps),
+            _ => None
+        }).next()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TxTimestampKind {
+    // entering the queueing discipline, TimestampingFlags::TxSched
+    Sched,
+    // handed to the NIC, or sent by it with the hardware timestamps
+    Sent,
+    // acknowledged, TCP only
+    Ack,
+    Other(u32)
+}
+
+impl From<u32> for TxTimestampKind {
+    fn from(x: u32) -> Self {
+        match x {
+            SCM_TSTAMP_SCHED => TxTimestampKind::Sched,
+            SCM_TSTAMP_SND => TxTimestampKind::Sent,
+            SCM_TSTAMP_ACK => TxTimestampKind::Ack,
+            x => TxTimestampKind::Other(x)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TxTimestamp {
+    pub kind: TxTimestampKind,
+    // with TimestampingFlags::OptId, the number of the packet
+    // counted from 0 since the option was set
+    pub key: u32,
+    pub stamps: Timestamps
+}
+
+impl TxTimestamp {
+    // from the ancillary data of a recvmsg() with RecvFlags::ErrQueue,
+    // None if it is not a timestamp
+    pub fn from_cmsgs(cmsgs: &[Cmsg]) -> Option<Self> {
+        let err = cmsgs.iter().filter_map(|x| match *x {
+            Cmsg::ExtendedErr(ref err)
+                if err.origin == ErrOrigin::Timestamping => Some(err),
+            _ => None
+        }).next()?;
+
+        Some(TxTimestamp {
+            kind: err.info.into(),
+            key: err.data,
+            stamps: Timestamps::from_cmsgs(cmsgs)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::time::{Duration, UNIX_EPOCH};
+    use ::nlibc::ENOMSG;
+
+    #[test]
+    fn tx_timestamp_from_cmsgs() {
+        let stamps = Timestamps {
+            software: Some(UNIX_EPOCH + Duration::new(1, 2)),
+            hardware: None
+        };
+        let mut err = ExtendedErr {
+            errno: ENOMSG,
+            origin: ErrOrigin::Timestamping,
+            icmp_type: 0,
+            icmp_code: 0,
+            info: SCM_TSTAMP_SND,
+            data: 7,
+            offender: None
+        };
+        let cmsgs = vec![
+            Cmsg::Timestamping(stamps),
+            Cmsg::ExtendedErr(err)
+        ];
+        assert_eq!(TxTimestamp::from_cmsgs(&cmsgs), Some(TxTimestamp {
+            kind: TxTimestampKind::Sent,
+            key: 7,
+            stamps
+        }));
+        assert_eq!(TxTimestamp::from_cmsgs(&cmsgs[..1]), None);
+
+        err.origin = ErrOrigin::Icmp6;
+        let cmsgs = vec![Cmsg::Timestamping(stamps), Cmsg::ExtendedErr(err)];
+        assert_eq!(TxTimestamp::from_cmsgs(&cmsgs), None);
+    }
+}
@@ -68,6 +68,21 @@ pub fn addr_from_segments(ad: &[u8; 16]) -> Ipv6Addr {
    )
 }
 
+// RFC 6724 section 3.1, the loopback address is link-local
+#[allow(clippy::cast_possible_truncation)]
+fn rfc6724_scope(addr: &Ipv6Addr) -> u8 {
+    let s = addr.segments();
+    if addr.is_multicast() {
+        (s[0] & 0x000f) as u8
+    } else if addr.is_loopback() || s[0] & 0xffc0 == 0xfe80 {
+        2
+    } else if s[0] & 0xffc0 == 0xfec0 {
+        5
+    } else {
+        14
+    }
+}
+
 pub fn log_if_err<T>(x: ::std::result::Result<T, ::failure::Error>) {
     if let Err(e) = x {
         let mut out = String::new();
@@ -89,7 +104,11 @@ pub fn log_if_err<T>(x: ::std::result::Result<T, ::failure::Error>) {
 #[macro_export]
 macro_rules! gen_evented_eventedfd {
     ($name:ident) => (
-        impl Evented for $name {
+        gen_evented_eventedfd!([] $name);
+    );
+    // for a generic type, with the generics in the brackets
+    ([$($gen:tt)*] $name:ty) => (
+        impl<$($gen)*> Evented for $name {
             fn register(
                 &self,
                 poll: &mio::Poll,
@@ -2,9 +2,12 @@ use std::ffi::*;
 
 use clap::*;
 
+use ping6_datacommon::parse_mark;
+
 pub struct Config {
     pub bind_address: Option<String>,
     pub bind_interface: Option<String>,
+    pub mark: Option<u32>,
     pub mode: ModeConfig
 }
 
@@ -32,6 +35,8 @@ pub fn get_config() -> Config {
         bind_address: matches.value_of("bind").map(str::to_string),
         bind_interface: matches.value_of("bind-to-interface")
             .map(str::to_string),
+        mark: matches.value_of("mark")
+            .map(|x| parse_mark(x).expect("mark must be a number")),
         mode: if matches.is_present("stream") {
                 ModeConfig::Stream(StreamConfig {
                     message: matches.value_of_os("message")
@@ -71,6 +76,12 @@ pub fn get_args<'a>() -> ArgMatches<'a> {
             .takes_value(true)
             .value_name("INTERFACE")
             .help("Binds to an interface")
+        ).arg(Arg::with_name("mark")
+            .long("mark")
+            .takes_value(true)
+            .value_name("MARK")
+            .help("Sets the firewall mark of the packets sent, \
+                for the policy routing.  Needs CAP_NET_ADMIN")
         ).arg(Arg::with_name("raw")
             .long("raw")
             .short("r")
@@ -14,7 +14,7 @@ use ::errors::Result;
 use ::util::*;
 
 pub fn datagram_mode(
-    (config, bound_addr, mut sock): InitState
+    (config, bound_addr, mut sock, _, _): InitState
 ) -> Result<()> {
     let datagram_conf = match config.mode {
         ModeConfig::Datagram(ref conf) => conf,
@@ -34,8 +34,8 @@ pub fn datagram_mode(
             break;
         }
 
-        let (buf, sockaddr) =
-            match sock.recvfrom(&mut raw_buf, RecvFlags::empty()) {
+        let (buf, sockaddr, cmsgs) =
+            match sock.recvmsg(&mut raw_buf, RecvFlags::empty()) {
                 x@Ok(_) => x,
                 Err(e) => {
                     let err_opt =
@@ -50,28 +50,40 @@ pub fn datagram_mode(
                 }
             }?;
         let src = *sockaddr.ip();
+        let dst = get_destination(&cmsgs).or(bound_addr.map(|x| *x.ip()));
         let packet = Icmpv6Packet::new(&buf).unwrap();
         let payload = packet.payload();
 
         debug!("received packet, payload size = {} from {}",
             payload.len(), src);
 
-        if !validate_icmpv6(&packet, src, bound_addr) {
+        if !validate_icmpv6(&packet, src, dst) {
             info!("invalid icmpv6 packet, dropping");
             continue;
         }
 
         if datagram_conf.binary {
-            binary_print(stdout_locked.as_mut().unwrap(), payload, src,
+            binary_print(stdout_locked.as_mut().unwrap(), payload, src, dst,
                 datagram_conf.raw.into())?;
         } else {
-            regular_print(payload, src, datagram_conf.raw.into())?;
+            regular_print(payload, src, dst, datagram_conf.raw.into())?;
         }
     }
 
     Ok(())
 }
 
+fn get_destination(cmsgs: &[Cmsg]) -> Option<Ipv6Addr> {
+    cmsgs.iter().filter_map(|x| match *x {
+        Cmsg::PktInfo(ref info) => Some(info.addr),
+        _ => None
+    }).next()
+}
+
+fn format_destination(dst: Option<Ipv6Addr>) -> String {
+    dst.map_or_else(String::new, |x| format!(" to {}", x))
+}
+
 #[allow(clippy::cast_possible_truncation)]
 fn validate_payload<T>(payload_arg: T) -> bool where T: AsRef<[u8]> {
     let payload = payload_arg.as_ref();
@@ -98,6 +110,7 @@ fn binary_print(
     out: &mut io::StdoutLock,
     payload: &[u8],
     src: Ipv6Addr,
+    dst: Option<Ipv6Addr>,
     raw: Raw
 ) -> Result<()> {
     let payload_for_print;
@@ -121,7 +134,8 @@ fn binary_print(
 
     if let Some(payload_for_print) = payload_for_print {
         let str_payload = String::from_utf8_lossy(payload_for_print);
-        info!("received message from {}: {}", src, str_payload);
+        info!("received message from {}{}: {}",
+            src, format_destination(dst), str_payload);
         io::stdout().flush()?;
     }
 
@@ -130,7 +144,12 @@ fn binary_print(
 
 gen_boolean_enum!(Raw);
 
-fn regular_print(payload: &[u8], src: Ipv6Addr, raw: Raw) -> Result<()> {
+fn regular_print(
+    payload: &[u8],
+    src: Ipv6Addr,
+    dst: Option<Ipv6Addr>,
+    raw: Raw
+) -> Result<()> {
     let payload_for_print = match raw {
         Raw::Yes => Some(payload),
         Raw::No => {
@@ -143,7 +162,8 @@ fn regular_print(payload: &[u8], src: Ipv6Addr, raw: Raw) -> Result<()> {
     };
     if let Some(payload_for_print) = payload_for_print {
         let str_payload = String::from_utf8_lossy(payload_for_print);
-        println!("received message from {}: {}", src, str_payload);
+        println!("received message from {}{}: {}",
+            src, format_destination(dst), str_payload);
     }
     Ok(())
 }
@@ -15,7 +15,6 @@ extern crate mio;
 extern crate owning_ref;
 extern crate pnet_packet;
 extern crate seccomp;
-extern crate send_box;
 #[macro_use] extern crate state_machine_future;
 extern crate tokio;
 extern crate tokio_timer;
@@ -29,6 +28,8 @@ mod errors;
 mod stream;
 mod util;
 
+use std::os::unix::prelude::*;
+
 use linux_network::*;
 use ping6_datacommon::*;
 
@@ -78,6 +79,12 @@ fn init() -> Result<InitState> {
         info!("bound to {} interface", ifname);
     }
 
+    if let Some(mark) = config.mark {
+        gain_net_admin()?;
+        sock.setsockopt(&SockOpts::Mark::new(&mark))?;
+        info!("set firewall mark {:#x}", mark);
+    }
+
     drop_caps()?;
     set_no_new_privs()?;
     debug!("PR_SET_NO_NEW_PRIVS set");
@@ -87,7 +94,7 @@ fn init() -> Result<InitState> {
         sock.bind(bound_sockaddr)?;
         info!("bound to {} address", addr);
 
-        Some(*bound_sockaddr.ip())
+        Some(bound_sockaddr)
     } else {
         None
     };
@@ -98,31 +105,57 @@ fn init() -> Result<InitState> {
     debug!("set icmpv6 type filter");
 
     sock.setsockopt(&SockOpts::V6MtuDiscover::new(&V6PmtuType::Do))?;
+    // the address pinged, when not bound to one
+    sock.setsockopt(&SockOpts::RecvPktInfo::new(&true))?;
 
     setup_signal_handler()?;
 
+    let use_stream_mode =
+        ModeConfigKind::from(&config.mode) == ModeConfigKind::Stream;
+    // the ACK sending task writes through a descriptor of its own,
+    // the filter only allows the descriptors existing when it is loaded;
+    // the reactor only reads and writes those it opens while the runtime
+    // is built
+    let (ack_sock, rt) = if use_stream_mode {
+        (Some(sock.try_clone()?), Some(make_stream_runtime()?))
+    } else {
+        (None, None)
+    };
+    enter_sandbox();
+
     setup_seccomp(
         &sock,
+        ack_sock.as_ref(),
         StdoutUse::Yes,
-        (ModeConfigKind::from(&config.mode) == ModeConfigKind::Stream).into()
+        rt.as_ref().map(|x| x.1.as_slice())
     )?;
 
-    Ok((config, bound_addr, sock))
+    Ok((config, bound_addr, sock, ack_sock, rt.map(|x| x.0)))
 }
 
+// runtime_fds are those of the stream mode runtime
 fn setup_seccomp<T>(
     sock: &T,
+    ack_sock: Option<&T>,
     use_stdout: StdoutUse,
-    use_stream_mode: UseStreamMode
-) -> Result<()> where T: SocketCommon {
-    // tokio libs syscall use is not documented
-    if use_stream_mode.into() {
-        return Ok(())
-    }
-
+    runtime_fds: Option<&[RawFd]>
+) -> Result<()> where T: SyscallSet {
     let mut ctx = allow_defaults()?;
     allow_console_out(&mut ctx, use_stdout)?;
-    sock.allow_receiving(&mut ctx)?;
+
+    let mut policy = SeccompPolicy::new();
+    policy.allow_socket(sock);
+    if let Some(x) = ack_sock {
+        policy.allow_socket(x);
+    }
+    if let Some(fds) = runtime_fds {
+        // stdout is made nonblocking and registered in the reactor
+        policy.allow_runtime()
+            .allow_runtime_fds(fds)
+            .allow_fd_syscall(::libc::STDOUT_FILENO, ::libc::SYS_fcntl);
+    }
+    policy.add_to(&mut ctx)?;
+
     ctx.load()?;
     Ok(())
 }
@@ -16,7 +16,7 @@ pub struct AckSender {
     src: Ipv6Addr,
     dst: SocketAddrV6,
     send_buf: BytesMut,
-    sock: IPv6RawSocketAdapter,
+    sock: IPv6RawSocketSendHalf,
     send_fut: Option<IPv6RawSocketSendtoFuture>,
     ranges_to_send: VecDeque<IRange<Wrapping<u16>>>,
     set_ws_packet: bool
@@ -28,7 +28,7 @@ impl AckSender {
         src: Ipv6Addr,
         dst: SocketAddrV6,
         mtu: u16,
-        sock: IPv6RawSocketAdapter
+        sock: IPv6RawSocketSendHalf
     ) -> Self {
         Self {
             ack_gen, src, dst,
@@ -59,7 +59,7 @@ impl Future for AckSender {
 
                     debug!("sending ACK for range {} .. {}", l, r);
                     self.send_fut = Some(make_send_fut_raw(
-                        self.sock.clone(),
+                        &mut self.sock,
                         &mut self.send_buf,
                         self.src,
                         self.dst,
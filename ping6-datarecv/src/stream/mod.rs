@@ -12,6 +12,7 @@ use ::ping6_datacommon::*;
 
 use ::config::*;
 use ::errors::{Error, Result};
+use self::packet::*;
 use self::stdout::*;
 use ::tokio::prelude::*;
 use ::util::InitState;
@@ -19,15 +20,15 @@ use ::util::InitState;
 use self::stm::*;
 
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-pub fn stream_mode((config, _, sock): InitState) -> Result<()> {
+pub fn stream_mode(
+    (config, bound_addr, sock, ack_sock, rt): InitState
+) -> Result<()> {
     let stream_conf = match config.mode {
         ModeConfig::Stream(ref x) => x,
         _ => unreachable!()
     };
 
-    let mut rt = ::tokio::runtime::Builder::new()
-        .core_threads(1)
-        .build()?;
+    let mut rt = rt.expect("the stream mode has a runtime");
 
     let mtu = match config.bind_interface {
         Some(ref s) => {
@@ -42,22 +43,43 @@ pub fn stream_mode((config, _, sock): InitState) -> Result<()> {
         None => IPV6_MIN_MTU
     };
 
-    let async_sock = futures::IPv6RawSocketAdapter::new(rt.reactor(), sock)?;
+    let (recv_sock, send_sock) =
+        futures::IPv6RawSocketAdapter::new(rt.reactor(), sock)?.split();
+    let (_, ack_sock) = futures::IPv6RawSocketAdapter::new(
+        rt.reactor(),
+        ack_sock.expect("the stream mode has a socket for the ACKs")
+    )?.split();
     let data_out = StdoutBytesWriter::new(rt.reactor())?;
 
+    // resolved before the seccomp filter, the zone needs a socket
+    let src = bound_addr.expect("the stream mode is bound to an address");
+    let (packets, recv_task) = PacketReceiver::new(
+        recv_sock,
+        mtu as usize,
+        move |x, from| {
+            let res = validate_stream_packet(x, Some((*from.ip(), *src.ip())))
+                && !parse_stream_client_packet(x).flags
+                    .contains(StreamPacketFlags::WS);
+            if res {
+                debug!("valid packet received");
+            } else {
+                debug!("invalid packet filtered out");
+            }
+            res
+        }
+    );
+    rt.spawn(recv_task);
+
     let init_state = StreamCommonState {
         config: unsafe { (&config as *const Config).as_ref().unwrap() },
-        src: make_socket_addr(
-            config.bind_address.as_ref().unwrap(),
-            Resolve::No
-        )?,
+        src,
         window_size: stream_conf.window_size,
-        sock: async_sock,
+        packets,
+        send_sock,
+        ack_sock: Some(ack_sock),
         mtu,
         data_out,
         send_buf: BytesMut::with_capacity(mtu as usize),
-        // if we assumed default mtu, then the incoming packet size is unknown
-        recv_buf: BytesMut::with_capacity(u16::max_value() as usize),
         handle: rt.executor()
     };
 
@@ -1,4 +1,3 @@
-use ::std::cell::UnsafeCell;
 use ::std::io;
 use ::std::io::prelude::*;
 use ::std::os::unix::prelude::*;
@@ -26,10 +25,10 @@ impl AsRawFd for StdoutWrapper {
 
 gen_evented_eventedfd!(StdoutWrapper);
 
+// the clones share the registration, the nonblock flag
+// is dropped along with the last one
 #[derive(Clone)]
-pub struct StdoutBytesWriter(Arc<UnsafeCell<StdoutBytesWriterImpl>>);
-unsafe impl Send for StdoutBytesWriter {}
-unsafe impl Sync for StdoutBytesWriter {}
+pub struct StdoutBytesWriter(Arc<StdoutBytesWriterImpl>);
 
 struct StdoutBytesWriterImpl {
     stdout: PollEvented2<StdoutWrapper>,
@@ -39,7 +38,7 @@ struct StdoutBytesWriterImpl {
 impl StdoutBytesWriter {
     pub fn new(handle: &Handle) -> Result<Self> {
         let old = set_fd_nonblock(&io::stdout(), Nonblock::Yes)?;
-        let ret = Arc::new(UnsafeCell::new(
+        let ret = Arc::new(
             StdoutBytesWriterImpl {
                 stdout: PollEvented2::new_with_handle(
                     StdoutWrapper(io::stdout()),
@@ -47,20 +46,18 @@ impl StdoutBytesWriter {
                 )?,
                 drop_nonblock: !old
             }
-        ));
+        );
         Ok(StdoutBytesWriter(ret))
     }
 }
 
 impl Write for StdoutBytesWriter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let theself = unsafe { self.0.get().as_ref().unwrap() };
-        theself.stdout.get_ref().0.lock().write(buf)
+        self.0.stdout.get_ref().0.lock().write(buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let theself = unsafe { self.0.get().as_ref().unwrap() };
-        theself.stdout.get_ref().0.lock().flush()
+        self.0.stdout.get_ref().0.lock().flush()
     }
 }
 
@@ -71,12 +68,11 @@ impl AsyncWrite for StdoutBytesWriter {
     }
 }
 
-impl Drop for StdoutBytesWriter {
+impl Drop for StdoutBytesWriterImpl {
     fn drop(&mut self) {
-        let theself = unsafe { self.0.get().as_ref().unwrap() };
-        if theself.drop_nonblock {
+        if self.drop_nonblock {
             set_fd_nonblock(
-                &theself.stdout.get_ref().0,
+                &self.stdout.get_ref().0,
                 Nonblock::No
             ).expect("unable to drop the nonblock flag on stdout");
         }
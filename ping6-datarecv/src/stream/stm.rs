@@ -8,7 +8,6 @@ use ::std::time::*;
 use ::bytes::*;
 use ::futures::future::*;
 use ::futures::prelude::*;
-use ::futures::stream::unfold;
 use ::futures::task::*;
 use ::owning_ref::OwningRef;
 use ::state_machine_future::RentToOwn;
@@ -17,7 +16,6 @@ use ::tokio::timer::*;
 
 use ::linux_network::*;
 use ::ping6_datacommon::*;
-use ::send_box::SendBox;
 
 use ::config::Config;
 use ::errors::{Error, Result};
@@ -26,11 +24,14 @@ use ::stream::packet::*;
 use ::stream::stdout::StdoutBytesWriter;
 use ::stream::util::make_send_fut;
 
-type FutureE<T> = dyn(::futures::Future<Item = T, Error = ::failure::Error>);
-type StreamE<T> = dyn(::futures::stream::Stream<
+type FutureE<T> = dyn Future<
     Item = T,
     Error = ::failure::Error
->);
+> + Send;
+type StreamE<T> = dyn Stream<
+    Item = T,
+    Error = ::failure::Error
+> + Send;
 
 #[derive(StateMachineFuture)]
 pub enum StreamMachine<'s> {
@@ -42,7 +43,7 @@ pub enum StreamMachine<'s> {
     #[state_machine_future(transitions(SendSynAck))]
     WaitForFirstSyn {
         common: StreamCommonState<'s>,
-        recv_first_syn: SendBox<FutureE<(Bytes, SocketAddrV6)>>
+        recv_first_syn: Box<FutureE<(Bytes, SocketAddrV6)>>
     },
 
     #[state_machine_future(transitions(WaitForAck))]
@@ -50,7 +51,7 @@ pub enum StreamMachine<'s> {
         common: StreamCommonState<'s>,
         active: ActiveStreamCommonState,
         send_syn_ack: futures::IPv6RawSocketSendtoFuture,
-        next_action: Option<SendBox<
+        next_action: Option<Box<
             StreamE<TimedResult<(Bytes, SocketAddrV6)>>
         >>
     },
@@ -59,7 +60,7 @@ pub enum StreamMachine<'s> {
     WaitForAck {
         common: StreamCommonState<'s>,
         active: ActiveStreamCommonState,
-        recv_stream: SendBox<StreamE<
+        recv_stream: Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>
     },
@@ -69,7 +70,7 @@ pub enum StreamMachine<'s> {
         common: StreamCommonState<'s>,
         active: ActiveStreamCommonState,
         task: Arc<Mutex<Option<Task>>>,
-        recv_stream: SendBox<StreamE<(Bytes, SocketAddrV6)>>,
+        recv_stream: Box<StreamE<(Bytes, SocketAddrV6)>>,
         write_future: Option<WriteBorrow<StdoutBytesWriter>>,
         timeout: Delay,
         ack_sender_handle: AckGenHandle,
@@ -82,7 +83,7 @@ pub enum StreamMachine<'s> {
         active: ActiveStreamCommonState,
         fin_seqno: u16,
         send_fut: futures::IPv6RawSocketSendtoFuture,
-        next_action: Option<SendBox<
+        next_action: Option<Box<
             StreamE<TimedResult<(Bytes, SocketAddrV6)>>
         >>
     },
@@ -92,7 +93,7 @@ pub enum StreamMachine<'s> {
         common: StreamCommonState<'s>,
         active: ActiveStreamCommonState,
         fin_seqno: u16,
-        recv_stream: SendBox<StreamE<
+        recv_stream: Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>
     },
@@ -159,7 +160,7 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
     fn poll_init_state<'a>(
         state: &'a mut RentToOwn<'a, InitState<'s>>
     ) -> Poll<AfterInitState<'s>, ::failure::Error> {
-        let recv_first_syn = make_recv_first_syn(&mut state.common);
+        let recv_first_syn = make_recv_first_syn(&state.common);
         transition!(WaitForFirstSyn {
             common: state.take().common,
             recv_first_syn
@@ -217,14 +218,14 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
         let size = try_ready!(state.send_syn_ack.poll());
         debug_assert_eq!(size, STREAM_SERVER_FULL_HEADER_SIZE);
 
-        let SendSynAck { mut common, active, next_action, .. }
+        let SendSynAck { common, active, next_action, .. }
             = state.take();
 
         let timed_packets = next_action.unwrap_or_else(|| {
             let seqno = active.next_seqno;
             let seqno_tracker_ref = active.seqno_tracker.clone();
             let order_ref = active.order.clone();
-            let packets = make_recv_packets_stream(&mut common)
+            let packets = common.packets.subscribe()
                 .and_then(move |(data, dst)| {
                     let pass = {
                         let packet = parse_stream_client_packet(&data);
@@ -256,11 +257,9 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
                 packets,
                 Duration::from_millis(PACKET_LOSS_TIMEOUT)
             );
-            unsafe {
-                SendBox::new(Box::new(
-                    timed.take(RETRANSMISSIONS_NUMBER)
-                ))
-            }
+            let timed_box: Box<StreamE<_>>
+                = Box::new(timed.take(RETRANSMISSIONS_NUMBER));
+            timed_box
         });
 
         transition!(WaitForAck {
@@ -301,8 +300,8 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
             Ok(Async::Ready(None)) => {
                 info!("connection attempt timed out, \
                     waiting for new connection");
-                let mut st = state.take();
-                let recv_first_syn = make_recv_first_syn(&mut st.common);
+                let st = state.take();
+                let recv_first_syn = make_recv_first_syn(&st.common);
                 transition!(WaitForFirstSyn {
                     common: st.common,
                     recv_first_syn
@@ -328,7 +327,7 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
             *common.src.ip(),
             active.dst,
             common.mtu,
-            common.sock.clone()
+            common.ack_sock.take().expect("a single ACK sending task")
         );
 
         // spawn the ack packet sending task
@@ -347,7 +346,7 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
 
         let seqno_tracker_ref = active.seqno_tracker.clone();
         let window_size = common.window_size;
-        let recv_stream = Box::new(make_recv_packets_stream(&mut common).filter(
+        let recv_stream = Box::new(common.packets.subscribe().filter(
             move |&(ref data,_)| {
                 let packet = parse_stream_client_packet(&data);
 
@@ -370,7 +369,7 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
                 common,
                 active,
                 task,
-                recv_stream: unsafe { SendBox::new(Box::new(recv_stream)) },
+                recv_stream,
                 write_future: None,
                 timeout,
                 ack_sender_handle,
@@ -443,12 +442,12 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
         let size = try_ready!(state.send_fut.poll());
         debug_assert_eq!(size, STREAM_SERVER_FULL_HEADER_SIZE);
 
-        let SendFinAck { mut common, active, fin_seqno, next_action, .. }
+        let SendFinAck { common, active, fin_seqno, next_action, .. }
             = state.take();
 
         let timed_packets = next_action.unwrap_or_else(|| {
             let seqno = fin_seqno;
-            let packets = make_recv_packets_stream(&mut common)
+            let packets = common.packets.subscribe()
                 .and_then(move |(data, dst)| {
                     let pass = {
                         let packet = parse_stream_client_packet(&data);
@@ -467,11 +466,9 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
                 packets,
                 Duration::from_millis(PACKET_LOSS_TIMEOUT)
             );
-            unsafe {
-                SendBox::new(Box::new(
-                    timed.take(RETRANSMISSIONS_NUMBER)
-                ))
-            }
+            let timed_box: Box<StreamE<_>>
+                = Box::new(timed.take(RETRANSMISSIONS_NUMBER));
+            timed_box
         });
 
         transition!(WaitForLastAck {
@@ -542,47 +539,9 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
     }
 }
 
-fn make_recv_packets_stream<'a>(
-    common: &mut StreamCommonState<'a>
-) -> impl Stream<Item = (Bytes, SocketAddrV6), Error = ::failure::Error> {
-    let csrc = common.src;
-    let mtu = common.mtu as usize;
-
-    unfold((
-            common.sock.clone(),
-            common.recv_buf.split_to(mtu),
-            mtu
-        ),
-        move |(mut sock, mut recv_buf, mtu)| {
-            let len = recv_buf.len();
-            if len < mtu {
-                recv_buf.reserve(mtu - len);
-                unsafe { recv_buf.advance_mut(mtu - len); }
-            }
-            Some(sock.recvfrom(recv_buf.take(), RecvFlags::empty())
-                .map_err(|e| e.into())
-                .map(move |x| (x, (sock, recv_buf, mtu)))
-            )
-        }
-    ).filter(move |&(ref x, src)| {
-        let res = validate_stream_packet(
-            &x,
-            Some((*src.ip(), *csrc.ip()))
-        );
-        let flags = parse_stream_client_packet(&x).flags;
-        if res && !flags.contains(StreamPacketFlags::WS) {
-            debug!("valid packet received");
-            true
-        } else {
-            debug!("invalid packet filtered out");
-            false
-        }
-    })
-}
-
-fn make_recv_first_syn(common: &mut StreamCommonState)
-        -> SendBox<FutureE<(Bytes, SocketAddrV6)>> {
-    let recv_future = make_recv_packets_stream(common)
+fn make_recv_first_syn(common: &StreamCommonState)
+        -> Box<FutureE<(Bytes, SocketAddrV6)>> {
+    let recv_future = common.packets.subscribe()
         .filter(|&(ref data, _)| {
             let packet = parse_stream_client_packet(&data);
 
@@ -594,7 +553,7 @@ fn make_recv_first_syn(common: &mut StreamCommonState)
         }).into_future()
         .map(|(x,_)| x.unwrap())
         .map_err(|(e,_)| e);
-    unsafe { SendBox::new(Box::new(recv_future)) }
+    Box::new(recv_future)
 }
 
 fn make_syn_ack_future<'a>(
@@ -737,11 +696,14 @@ pub struct StreamCommonState<'a> {
     pub config: &'a Config,
     pub src: SocketAddrV6,
     pub window_size: u32,
-    pub sock: futures::IPv6RawSocketAdapter,
+    // the receive streams of the successive states subscribe to it in turn
+    pub packets: PacketReceiver,
+    pub send_sock: futures::IPv6RawSocketSendHalf,
+    // moved into the ACK sending task once the connection is established
+    pub ack_sock: Option<futures::IPv6RawSocketSendHalf>,
     pub mtu: u16,
     pub data_out: StdoutBytesWriter,
     pub send_buf: BytesMut,
-    pub recv_buf: BytesMut,
     pub handle: ::tokio::runtime::TaskExecutor
 }
 
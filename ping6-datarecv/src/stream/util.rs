@@ -10,7 +10,7 @@ use ::stream::packet::make_stream_server_icmpv6_packet;
 
 #[allow(clippy::too_many_arguments)]
 pub fn make_send_fut_raw(
-    mut sock: futures::IPv6RawSocketAdapter,
+    sock: &mut futures::IPv6RawSocketSendHalf,
     mut send_buf: &mut BytesMut,
     src: Ipv6Addr,
     dst: SocketAddrV6,
@@ -45,7 +45,7 @@ pub fn make_send_fut<'a>(
     payload: &[u8]
 ) -> futures::IPv6RawSocketSendtoFuture {
     make_send_fut_raw(
-        common.sock.clone(),
+        &mut common.send_sock,
         &mut common.send_buf,
         *common.src.ip(),
         dst,
@@ -1,6 +1,6 @@
 use ::std::io;
 use ::std::io::prelude::*;
-use ::std::net::Ipv6Addr;
+use ::std::net::{Ipv6Addr, SocketAddrV6};
 
 use ::pnet_packet::icmpv6;
 use ::pnet_packet::icmpv6::*;
@@ -8,11 +8,20 @@ use ::pnet_packet::icmpv6::ndp::Icmpv6Codes;
 use ::pnet_packet::*;
 
 use ::linux_network::IPv6RawSocket;
+use ::tokio::runtime::Runtime;
 
 use ::config::Config;
 use ::errors::Result;
 
-pub type InitState = (Config, Option<Ipv6Addr>, IPv6RawSocket);
+// the last socket is the ACK sender's descriptor in the stream mode,
+// whose runtime is built before the seccomp filter
+pub type InitState = (
+    Config,
+    Option<SocketAddrV6>,
+    IPv6RawSocket,
+    Option<IPv6RawSocket>,
+    Option<Runtime>
+);
 
 pub fn validate_icmpv6(
         packet: &Icmpv6Packet,
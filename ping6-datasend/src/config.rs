@@ -2,10 +2,13 @@ use std::ffi::*;
 
 use clap::*;
 
+use ping6_datacommon::parse_mark;
+
 pub struct Config {
-    pub source: String,
+    pub source: Option<String>,
     pub destination: String,
     pub bind_interface: Option<String>,
+    pub mark: Option<u32>,
     pub mode: ModeConfig
 }
 
@@ -37,10 +40,12 @@ pub fn get_config() -> Config {
     };
 
     Config {
-        source: matches.value_of("source").unwrap().to_string(),
+        source: matches.value_of("source").map(str::to_string),
         destination: matches.value_of("destination").unwrap().to_string(),
         bind_interface: matches.value_of("bind-to-interface")
             .map(str::to_string),
+        mark: matches.value_of("mark")
+            .map(|x| parse_mark(x).expect("mark must be a number")),
         mode: if matches.is_present("stream") {
                 ModeConfig::Stream(StreamConfig {
                     window_size: matches.value_of("window-size").unwrap()
@@ -71,21 +76,23 @@ pub fn get_args<'a>() -> ArgMatches<'a> {
             .help("Forms raw packets without payload identification")
             .conflicts_with("stream")
         ).arg(Arg::with_name("source")
-            .required(true)
+            .long("source")
+            .short("S")
+            .takes_value(true)
             .value_name("SOURCE_ADDRESS")
-            .index(1)
-            .help("Source address to use")
+            .help("Source address to use.  By default, an address of \
+                the outgoing interface is picked")
         ).arg(Arg::with_name("destination")
             .required(true)
             .value_name("DESTINATION")
-            .index(2)
+            .index(1)
             .help("Messages destination")
         ).arg(Arg::with_name("messages")
             .required(true)
             .conflicts_with("use-stdin")
             .value_name("MESSAGES")
             .multiple(true)
-            .index(3)
+            .index(2)
             .help("The messages to send, one argument for a packet")
         ).arg(Arg::with_name("bind-to-interface")
             .short("I")
@@ -93,6 +100,12 @@ pub fn get_args<'a>() -> ArgMatches<'a> {
             .takes_value(true)
             .value_name("INTERFACE")
             .help("Binds to an interface")
+        ).arg(Arg::with_name("mark")
+            .long("mark")
+            .takes_value(true)
+            .value_name("MARK")
+            .help("Sets the firewall mark of the packets sent, \
+                for the policy routing.  Needs CAP_NET_ADMIN")
         ).arg(Arg::with_name("use-stdin")
             .required(true)
             .conflicts_with("messages")
@@ -15,7 +15,7 @@ use ::errors::{Error, Result};
 use ::util::*;
 use ::stdin::StdinBytesIterator;
 
-pub fn datagram_mode((config, src, dst, mut sock): InitState) -> Result<()> {
+pub fn datagram_mode((config, src, dst, mut sock, _): InitState) -> Result<()> {
     let datagram_conf = match config.mode {
         ModeConfig::Datagram(ref conf) => conf,
         _ => unreachable!()
@@ -15,7 +15,6 @@ extern crate owning_ref;
 extern crate pnet_packet;
 extern crate rand;
 extern crate seccomp;
-extern crate send_box;
 #[macro_use] extern crate state_machine_future;
 extern crate tokio;
 extern crate tokio_timer;
@@ -31,6 +30,8 @@ mod stdin;
 mod stream;
 mod util;
 
+use std::os::unix::prelude::*;
+
 use linux_network::*;
 use ping6_datacommon::*;
 
@@ -80,6 +81,12 @@ fn init() -> Result<InitState> {
         info!("bound to {} interface", ifname);
     }
 
+    if let Some(mark) = config.mark {
+        gain_net_admin()?;
+        sock.setsockopt(&SockOpts::Mark::new(&mark))?;
+        info!("set firewall mark {:#x}", mark);
+    }
+
     drop_caps()?;
     set_no_new_privs()?;
     debug!("PR_SET_NO_NEW_PRIVS set");
@@ -91,11 +98,22 @@ fn init() -> Result<InitState> {
 
     sock.setsockopt(&SockOpts::V6MtuDiscover::new(&V6PmtuType::Do))?;
 
-    let src = make_socket_addr(&config.source, Resolve::No)?;
-
     let dst = make_socket_addr(&config.destination, Resolve::Yes)?;
     info!("resolved destination address: {}", dst);
 
+    let src = match config.source {
+        Some(ref x) => make_socket_addr(x, Resolve::No)?,
+        None => {
+            let index = match config.bind_interface {
+                Some(ref x) => Some(Interface::by_name(x)?.index),
+                None => None
+            };
+            let src = select_source_address(&dst, index)?;
+            info!("selected source address: {}", src);
+            src
+        }
+    };
+
     setup_signal_handler()?;
 
     let use_stdin = if let ModeConfig::Datagram(ref datagram_conf) = config.mode {
@@ -103,33 +121,50 @@ fn init() -> Result<InitState> {
     } else {
         false
     };
+    let use_stream_mode =
+        ModeConfigKind::from(&config.mode) == ModeConfigKind::Stream;
+    // the reactor only reads and writes the descriptors it opens
+    // while the runtime is built
+    let rt = if use_stream_mode {
+        Some(make_stream_runtime()?)
+    } else {
+        None
+    };
+    enter_sandbox();
+
     setup_seccomp(
         &sock,
         use_stdin.into(),
-        (ModeConfigKind::from(&config.mode) == ModeConfigKind::Stream).into()
+        rt.as_ref().map(|x| x.1.as_slice())
     )?;
 
-    Ok((config, src, dst, sock))
+    Ok((config, src, dst, sock, rt.map(|x| x.0)))
 }
 
 gen_boolean_enum!(StdinUse);
 
+// runtime_fds are those of the stream mode runtime
 fn setup_seccomp<T>(
     sock: &T,
     use_stdin: StdinUse,
-    use_stream_mode: UseStreamMode
-) -> Result<()> where T: SocketCommon {
-    // tokio libs syscall use is not documented
-    if use_stream_mode.into() {
-        return Ok(())
-    }
-
+    runtime_fds: Option<&[RawFd]>
+) -> Result<()> where T: SyscallSet {
     let mut ctx = allow_defaults()?;
     allow_console_out(&mut ctx, StdoutUse::No)?;
-    if use_stdin.into() {
+    if use_stdin.into() || runtime_fds.is_some() {
         allow_console_in(&mut ctx)?;
     }
-    sock.allow_sending(&mut ctx)?;
+
+    let mut policy = SeccompPolicy::new();
+    policy.allow_socket(sock);
+    if let Some(fds) = runtime_fds {
+        // stdin is made nonblocking and registered in the reactor
+        policy.allow_runtime()
+            .allow_runtime_fds(fds)
+            .allow_fd_syscall(::libc::STDIN_FILENO, ::libc::SYS_fcntl);
+    }
+    policy.add_to(&mut ctx)?;
+
     ctx.load()?;
     Ok(())
 }
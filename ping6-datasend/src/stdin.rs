@@ -1,8 +1,6 @@
-use ::std::cell::UnsafeCell;
 use ::std::io;
 use ::std::io::prelude::*;
 use ::std::os::unix::prelude::*;
-use ::std::sync::Arc;
 
 use ::mio;
 use ::mio::*;
@@ -67,12 +65,7 @@ impl AsRawFd for StdinWrapper {
 
 gen_evented_eventedfd!(StdinWrapper);
 
-#[derive(Clone)]
-pub struct StdinBytesReader(Arc<UnsafeCell<StdinBytesReaderImpl>>);
-unsafe impl Send for StdinBytesReader {}
-unsafe impl Sync for StdinBytesReader {}
-
-struct StdinBytesReaderImpl {
+pub struct StdinBytesReader {
     stdin: PollEvented2<StdinWrapper>,
     drop_nonblock: bool
 }
@@ -80,20 +73,19 @@ struct StdinBytesReaderImpl {
 impl StdinBytesReader {
     pub fn new(handle: &Handle) -> Result<Self> {
         let old = set_fd_nonblock(&io::stdin(), Nonblock::Yes)?;
-        Ok(StdinBytesReader(Arc::new(UnsafeCell::new(StdinBytesReaderImpl {
+        Ok(StdinBytesReader {
             stdin: PollEvented2::new_with_handle(
                 StdinWrapper(io::stdin()),
                 handle
             )?,
             drop_nonblock: !old
-        }))))
+        })
     }
 }
 
 impl Read for StdinBytesReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let theself = unsafe { self.0.get().as_ref().unwrap() };
-        theself.stdin.get_ref().0.lock().read(buf)
+        self.stdin.get_ref().0.lock().read(buf)
     }
 }
 
@@ -101,9 +93,8 @@ impl AsyncRead for StdinBytesReader {}
 
 impl Drop for StdinBytesReader {
     fn drop(&mut self) {
-        let theself = unsafe { self.0.get().as_ref().unwrap() };
-        if theself.drop_nonblock {
-            set_fd_nonblock(theself.stdin.get_ref(), Nonblock::No).unwrap();
+        if self.drop_nonblock {
+            set_fd_nonblock(self.stdin.get_ref(), Nonblock::No).unwrap();
         }
     }
 }
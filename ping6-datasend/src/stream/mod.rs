@@ -20,15 +20,13 @@ use ::util::InitState;
 use self::stm::*;
 
 #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-pub fn stream_mode((config, src, dst, sock): InitState) -> Result<()> {
+pub fn stream_mode((config, src, dst, sock, rt): InitState) -> Result<()> {
     let _stream_conf = match config.mode {
         ModeConfig::Stream(ref conf) => conf,
         _ => unreachable!()
     };
 
-    let mut rt = ::tokio::runtime::Builder::new()
-        .core_threads(1)
-        .build()?;
+    let mut rt = rt.expect("the stream mode has a runtime");
 
     let mtu = match config.bind_interface {
         Some(ref s) => {
@@ -43,19 +41,27 @@ pub fn stream_mode((config, src, dst, sock): InitState) -> Result<()> {
         None => IPV6_MIN_MTU
     };
 
-    let async_sock = futures::IPv6RawSocketAdapter::new(rt.reactor(), sock)?;
+    let (recv_sock, send_sock) =
+        futures::IPv6RawSocketAdapter::new(rt.reactor(), sock)?.split();
     let data = StdinBytesReader::new(rt.reactor())?;
 
+    let (packets, recv_task) = PacketReceiver::new(
+        recv_sock,
+        mtu as usize,
+        move |x, from| from == dst
+            && validate_stream_packet(x, Some((*from.ip(), *src.ip())))
+    );
+    rt.spawn(recv_task);
+
     let init_state = StreamCommonState {
         config: unsafe { (&config as *const Config).as_ref().unwrap() },
         src,
         dst,
-        sock: async_sock,
+        packets,
+        send_sock,
         mtu,
         data_source: data,
         send_buf: BytesMut::with_capacity(mtu as usize),
-        // if we assumed default mtu, then the incoming packet size is unknown
-        recv_buf: BytesMut::with_capacity(u16::max_value() as usize),
         next_seqno: Wrapping(thread_rng().gen())
     };
 
@@ -10,14 +10,12 @@ use ::std::time::*;
 use ::bytes::*;
 use ::futures::prelude::*;
 use ::futures::Stream;
-use ::futures::stream::*;
 use ::state_machine_future::RentToOwn;
 use ::tokio::prelude::*;
 use ::tokio::timer::Delay;
 
 use ::linux_network::*;
 use ::ping6_datacommon::*;
-use ::send_box::SendBox;
 
 use ::config::*;
 use ::errors::{Error, Result};
@@ -25,10 +23,10 @@ use ::stdin::StdinBytesReader;
 use ::stream::buffers::*;
 use ::stream::packet::*;
 
-type StreamE<T> = dyn(::futures::stream::Stream<
+type StreamE<T> = dyn Stream<
     Item = T,
     Error = ::failure::Error
->);
+> + Send;
 
 // TODO: tune or make configurable
 const TMP_BUFFER_SIZE: usize = 64 * 1024;
@@ -44,7 +42,7 @@ pub enum StreamMachine<'s> {
     SendFirstSyn {
         common: StreamCommonState<'s>,
         send: futures::IPv6RawSocketSendtoFuture,
-        next_action: Option<SendBox<StreamE<
+        next_action: Option<Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>>
     },
@@ -52,7 +50,7 @@ pub enum StreamMachine<'s> {
     #[state_machine_future(transitions(SendFirstSyn, SendAck))]
     WaitForSynAck {
         common: StreamCommonState<'s>,
-        recv_stream: SendBox<StreamE<
+        recv_stream: Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>
     },
@@ -71,7 +69,7 @@ pub enum StreamMachine<'s> {
         next_data: RefCell<Option<NextData>>,
         retransmit_queue: RefCell<VecDeque<(Vec<u8>, u16)>>,
         send_fut: RefCell<Option<futures::IPv6RawSocketSendtoFuture>>,
-        recv_stream: RefCell<SendBox<
+        recv_stream: RefCell<Box<
             StreamE<(Bytes, SocketAddrV6)>
         >>,
         ack_wait: AckWaitlist,
@@ -94,7 +92,7 @@ pub enum StreamMachine<'s> {
     SendFin {
         common: StreamCommonState<'s>,
         send_fut: futures::IPv6RawSocketSendtoFuture,
-        next_action: Option<SendBox<StreamE<
+        next_action: Option<Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>>
     },
@@ -102,7 +100,7 @@ pub enum StreamMachine<'s> {
     #[state_machine_future(transitions(SendFin, SendLastAck))]
     WaitForFinAck {
         common: StreamCommonState<'s>,
-        recv_stream: SendBox<StreamE<
+        recv_stream: Box<StreamE<
             TimedResult<(Bytes, SocketAddrV6)>
         >>
     },
@@ -171,11 +169,11 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
         debug_assert_eq!(size, STREAM_CLIENT_FULL_HEADER_SIZE);
 
         let state = state.take();
-        let mut common = state.common;
+        let common = state.common;
 
         let timed_packets = state.next_action.unwrap_or_else(|| {
             let seqno = common.next_seqno;
-            let packets = make_recv_packets_stream(&mut common)
+            let packets = common.packets.subscribe()
                 .filter(move |&(ref data, _)| {
                     let packet = parse_stream_server_packet(&data);
 
@@ -189,11 +187,9 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
                 packets,
                 Duration::from_millis(PACKET_LOSS_TIMEOUT)
             );
-            unsafe {
-                SendBox::new(Box::new(
-                    timed.take(RETRANSMISSIONS_NUMBER)
-                ))
-            }
+            let timed_box: Box<StreamE<_>>
+                = Box::new(timed.take(RETRANSMISSIONS_NUMBER));
+            timed_box
         });
 
         transition!(WaitForSynAck {
@@ -265,10 +261,8 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
         let sc = get_stream_config(&state.common.config);
 
         let recv_stream_box: Box<StreamE<(Bytes, SocketAddrV6)>>
-            = Box::new(make_recv_ack_or_fin(&mut state.common));
-        let recv_stream = RefCell::new(unsafe {
-            SendBox::new(recv_stream_box)
-        });
+            = Box::new(make_recv_ack_or_fin(&state.common));
+        let recv_stream = RefCell::new(recv_stream_box);
         let mtu = state.common.mtu;
         transition!(SendData {
             common: state.take().common,
@@ -347,12 +341,12 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
         debug_assert_eq!(size, STREAM_CLIENT_FULL_HEADER_SIZE);
 
         let state = state.take();
-        let mut common = state.common;
+        let common = state.common;
 
         let cdst = common.dst;
         let timed_packets = state.next_action.unwrap_or_else(|| {
             let seqno = common.next_seqno;
-            let packets = make_recv_packets_stream(&mut common)
+            let packets = common.packets.subscribe()
                 .filter(move |&(ref data, dst)| {
                     let packet = parse_stream_server_packet(&data);
 
@@ -368,11 +362,9 @@ impl<'s> PollStreamMachine<'s> for StreamMachine<'s> {
                 packets,
                 Duration::from_millis(PACKET_LOSS_TIMEOUT)
             );
-            unsafe {
-                SendBox::new(Box::new(
-                    timed.take(RETRANSMISSIONS_NUMBER)
-                ))
-            }
+            let timed_box: Box<StreamE<_>>
+                = Box::new(timed.take(RETRANSMISSIONS_NUMBER));
+            timed_box
         });
 
         transition!(WaitForFinAck {
@@ -454,7 +446,7 @@ fn make_send_fut<'a>(
     );
 
     debug!("send packet with seqno {}", seqno);
-    common.sock.sendto(
+    common.send_sock.sendto(
         packet,
         dst,
         SendFlags::empty()
@@ -466,40 +458,6 @@ fn make_first_syn_future<'a>(common: &mut StreamCommonState<'a>)
     make_send_fut(common, StreamPacketFlags::Syn, &[], None)
 }
 
-fn make_recv_packets_stream<'a>(common: &mut StreamCommonState<'a>)
-        -> impl Stream<
-            Item = (Bytes, SocketAddrV6),
-            Error = ::failure::Error
-        > {
-    let csrc = common.src;
-    let cdst = common.dst;
-    let mtu = common.mtu as usize;
-
-    unfold((
-            common.sock.clone(),
-            common.recv_buf.split_to(mtu),
-            mtu
-        ),
-        move |(mut sock, mut recv_buf, mtu)| {
-            let len = recv_buf.len();
-            if len < mtu {
-                recv_buf.reserve(mtu - len);
-                unsafe { recv_buf.advance_mut(mtu - len); }
-            }
-            Some(sock.recvfrom(recv_buf.clone(), RecvFlags::empty())
-                .map_err(|e| e.into())
-                .map(move |x| (x, (sock, recv_buf, mtu)))
-            )
-        }
-    ).filter(move |&(ref x, src)| {
-        src == cdst
-            && validate_stream_packet(
-                &x,
-                Some((*src.ip(), *csrc.ip()))
-            )
-    })
-}
-
 fn make_packet_loss_instant() -> Instant {
     Instant::now() + Duration::from_millis(PACKET_LOSS_TIMEOUT)
 }
@@ -508,12 +466,12 @@ fn make_connection_timeout_instant() -> Instant {
     Instant::now() + Duration::from_millis(CONNECTION_LOSS_TIMEOUT)
 }
 
-fn make_recv_ack_or_fin<'a>(common: &mut StreamCommonState<'a>)
+fn make_recv_ack_or_fin<'a>(common: &StreamCommonState<'a>)
         -> impl Stream<
             Item = (Bytes, SocketAddrV6),
             Error = ::failure::Error
         > {
-    make_recv_packets_stream(common)
+    common.packets.subscribe()
         .filter(|&(ref packet_buff, _)| {
             let packet = parse_stream_server_packet(&packet_buff);
             (packet.flags.contains(StreamPacketFlags::Ack)
@@ -734,10 +692,11 @@ pub struct StreamCommonState<'a> {
     pub config: &'a Config,
     pub src: SocketAddrV6,
     pub dst: SocketAddrV6,
-    pub sock: futures::IPv6RawSocketAdapter,
+    // the receive streams of the successive states subscribe to it in turn
+    pub packets: PacketReceiver,
+    pub send_sock: futures::IPv6RawSocketSendHalf,
     pub mtu: u16,
     pub data_source: StdinBytesReader,
     pub send_buf: BytesMut,
-    pub recv_buf: BytesMut,
     pub next_seqno: Wrapping<u16>,
 }
@@ -1,7 +1,15 @@
 use std::net::SocketAddrV6;
 
 use ::linux_network::IPv6RawSocket;
+use ::tokio::runtime::Runtime;
 
 use ::config::Config;
 
-pub type InitState = (Config, SocketAddrV6, SocketAddrV6, IPv6RawSocket);
+// the runtime of the stream mode is built before the seccomp filter
+pub type InitState = (
+    Config,
+    SocketAddrV6,
+    SocketAddrV6,
+    IPv6RawSocket,
+    Option<Runtime>
+);
@@ -11,8 +11,6 @@ use ::range_tracker::*;
 // destructor must run after all slices' destructors
 #[derive(Clone)]
 pub struct TrimmingBuffer(Arc<RwLock<TrimmingBufferImpl>>);
-unsafe impl Send for TrimmingBuffer {}
-unsafe impl Sync for TrimmingBuffer {}
 
 struct TrimmingBufferImpl {
     inner: VecDeque<u8>,
@@ -25,6 +23,10 @@ struct TrimmingBufferImplBufferGetter(
     Arc<RwLock<TrimmingBufferImpl>>,
     *const TrimmingBufferImpl
 );
+// the pointer targets the value inside the RwLock of the Arc held alongside,
+// and is only dereferenced by the methods holding that lock
+unsafe impl Send for TrimmingBufferImplBufferGetter {}
+unsafe impl Sync for TrimmingBufferImplBufferGetter {}
 
 impl<'a> RangeTrackerParentHandle<'a, u8> for TrimmingBufferImplBufferGetter {
     type Borrowed = &'a VecDeque<u8>;
@@ -169,6 +171,8 @@ impl TrimmingBuffer {
 }
 
 pub struct TrimmingBufferSlice(TrimmingBufferSliceImpl);
+// the parent Arc keeps the buffer alive, add() never reallocates it
+// and the bytes are not drained before the slice is dropped
 unsafe impl Send for TrimmingBufferSlice {}
 unsafe impl Sync for TrimmingBufferSlice {}
 unsafe impl ::owning_ref::StableAddress for TrimmingBufferSlice {}
@@ -40,3 +40,6 @@ pub const PACKET_LOSS_TIMEOUT: u64 = 2000;
 pub const CONNECTION_LOSS_TIMEOUT: u64 = 30000;
 pub const RETRANSMISSIONS_NUMBER: u64 = 3;
 pub const ACK_SEND_PERIOD: u64 = PACKET_LOSS_TIMEOUT / 3;
+
+// the number of datagrams received by a single system call
+pub const RECV_BATCH_SIZE: usize = 16;
@@ -4,6 +4,7 @@
 #![allow(clippy::stutter)]
 
 #[macro_use] extern crate bitflags;
+extern crate bytes;
 extern crate byteorder;
 extern crate capabilities;
 #[macro_use] extern crate failure;
@@ -24,6 +25,7 @@ pub mod buffer;
 pub mod constants;
 pub mod errors;
 pub mod range_tracker;
+pub mod receiver;
 pub mod timeout;
 
 use std::cell::RefCell;
@@ -50,6 +52,7 @@ pub use buffer::*;
 pub use constants::*;
 pub use errors::*;
 pub use range_tracker::*;
+pub use receiver::*;
 pub use timeout::*;
 
 gen_boolean_enum!(pub UseStreamMode);
@@ -58,39 +61,61 @@ gen_boolean_enum!(pub Resolve);
 
 pub fn make_socket_addr<T>(addr_str: T, resolve: Resolve)
         -> Result<SocketAddrV6> where T: AsRef<str> {
-    let sockaddr_in = make_sockaddr_in6_v6_dgram(
-        addr_str,
-        None,
-        IPPROTO_ICMPV6,
-        0,
-        match resolve {
-            Resolve::Yes => AddrInfoFlags::empty(),
-            Resolve::No => AddrInfoFlags::NumericHost
-        }
-    )?;
+    let mut resolver = Resolver::new();
+    resolver.protocol(IPPROTO_ICMPV6);
+    if let Resolve::No = resolve {
+        resolver.numeric_only();
+    }
+    resolver.resolve_one(addr_str)
+}
 
-    Ok(SocketAddrV6::new(
-        sockaddr_in.sin6_addr.s6_addr.into(),
-        0,
-        0,
-        sockaddr_in.sin6_scope_id
-    ))
+// Built before the seccomp filter is loaded, so that its setup
+// does not need more than SeccompPolicy::allow_runtime(), and before
+// enter_sandbox(): the empty root has no /proc to list the descriptors
+// it opened in, those are returned for SeccompPolicy::allow_runtime_fds().
+// The worker threads are only started by the first spawn, so the sandbox
+// can still unshare a user namespace.
+pub fn make_stream_runtime()
+        -> Result<(::tokio::runtime::Runtime, Vec<RawFd>)> {
+    let fds = FdSnapshot::take()?;
+    let rt = ::tokio::runtime::Builder::new()
+        .core_threads(1)
+        .build()?;
+    Ok((rt, fds.opened_since()?))
 }
 
 pub fn gain_net_raw() -> Result<()> {
+    gain_capability(Capability::CAP_NET_RAW)
+}
+
+// for SockOpts::Mark, the binaries need cap_net_admin=p then
+pub fn gain_net_admin() -> Result<()> {
+    gain_capability(Capability::CAP_NET_ADMIN)
+}
+
+fn gain_capability(cap: Capability) -> Result<()> {
     let mut caps = Capabilities::from_current_proc()
         .map_err(Error::Priv)?;
-    if !caps.update(&[Capability::CAP_NET_RAW], Flag::Effective, true) {
+    if !caps.update(&[cap], Flag::Effective, true) {
         return Err(Error::Priv(io::Error::new(
             io::ErrorKind::Other,
             "cannot update capset"
         )).into());
     }
     caps.apply().map_err(Error::Priv)?;
-    debug!("gained CAP_NET_RAW");
+    debug!("gained {:?}", cap);
     Ok(())
 }
 
+// decimal, or hexadecimal with 0x as ip-rule(8) takes it
+pub fn parse_mark(mark: &str) -> Option<u32> {
+    if mark.starts_with("0x") {
+        u32::from_str_radix(&mark[2..], 16).ok()
+    } else {
+        mark.parse().ok()
+    }
+}
+
 pub fn drop_caps() -> Result<()> {
     Capabilities::new()?
         .apply()
@@ -99,6 +124,24 @@ pub fn drop_caps() -> Result<()> {
     Ok(())
 }
 
+// The raw socket keeps working in its original network namespace.
+// Unprivileged user namespaces may be disabled, so this is best effort.
+pub fn enter_sandbox() {
+    match make_sandbox().apply() {
+        Ok(()) => debug!("entered the sandbox"),
+        Err(e) => warn!("cannot set up the sandbox: {}", e)
+    }
+}
+
+fn make_sandbox() -> Sandbox {
+    let mut ret = Sandbox::new();
+    ret.unshare(
+        Namespaces::User | Namespaces::Net | Namespaces::Ipc | Namespaces::Uts
+    );
+    ret.empty_root().limit(Resource::CoreSize, 0);
+    ret
+}
+
 #[allow(clippy::cast_possible_truncation)]
 pub fn ping6_data_checksum<T>(payload: T) -> u16 where T: AsRef<[u8]> {
     use std::hash::Hasher;
@@ -355,3 +398,50 @@ impl<T> DerefMut for DerefWrapper<T> {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::fs::read_dir;
+    use ::std::panic::catch_unwind;
+    use ::std::time::*;
+
+    use ::nix::sys::wait::*;
+    use ::nix::unistd::*;
+    use ::tokio_timer::Delay;
+
+    // The stream mode setup of the ping6-data tools: the runtime, then
+    // the sandbox, then the filter.  The sandbox is entered in a child,
+    // the test harness threads would make the user namespace fail.
+    #[test]
+    #[ignore = "needs unprivileged user namespaces"]
+    fn stream_runtime_in_sandbox() {
+        match fork().unwrap() {
+            ForkResult::Child => {
+                let ret = catch_unwind(|| {
+                    let (mut rt, fds) = make_stream_runtime().unwrap();
+                    assert!(!fds.is_empty());
+
+                    make_sandbox().apply().unwrap();
+                    assert!(read_dir("/proc/self/fd").is_err());
+
+                    let mut ctx = allow_defaults().unwrap();
+                    SeccompPolicy::new()
+                        .allow_runtime()
+                        .allow_runtime_fds(&fds)
+                        .add_to(&mut ctx)
+                        .unwrap();
+                    ctx.load().unwrap();
+
+                    let delay = Instant::now() + Duration::from_millis(10);
+                    rt.block_on(Delay::new(delay)).unwrap();
+                });
+                unsafe { libc::_exit(if ret.is_ok() { 0 } else { 1 }) }
+            },
+            ForkResult::Parent { child } => assert_eq!(
+                waitpid(child, None).unwrap(),
+                WaitStatus::Exited(child, 0)
+            )
+        }
+    }
+}
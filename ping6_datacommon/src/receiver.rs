@@ -0,0 +1,143 @@
+use ::std::collections::VecDeque;
+use ::std::net::SocketAddrV6;
+
+use ::bytes::{Bytes, BytesMut};
+use ::futures::stream::{iter_ok, unfold};
+use ::futures::sync::mpsc::*;
+use ::tokio::prelude::*;
+
+use ::linux_network::*;
+use ::linux_network::futures::IPv6RawSocketRecvHalf;
+
+use ::constants::RECV_BATCH_SIZE;
+
+type Packet = (Bytes, SocketAddrV6);
+
+type Delivery = ::std::result::Result<Packet, ::failure::Error>;
+
+// The receive half of the stream mode socket is owned by a task of its own,
+// the states of the protocol machine take turns subscribing to the datagrams
+// it receives.  The task ends when the PacketReceiver is dropped.
+pub struct PacketReceiver {
+    subscriptions: UnboundedSender<UnboundedSender<Delivery>>
+}
+
+impl PacketReceiver {
+    // the datagrams not passing the filter are dropped in the pool,
+    // the task is for the caller to spawn
+    pub fn new<F>(sock: IPv6RawSocketRecvHalf, mtu: usize, filter: F)
+            -> (Self, impl Future<Item = (), Error = ()> + Send) where
+            F: FnMut(&[u8], SocketAddrV6) -> bool + Send + 'static {
+        let (tx, rx) = unbounded();
+        let task = receive_task(recv_packets(sock, mtu, filter), rx);
+        (PacketReceiver { subscriptions: tx }, task)
+    }
+
+    // the datagrams received from now on go to the new stream,
+    // the previous subscriber gets no more of them
+    pub fn subscribe(&self) -> impl Stream<
+        Item = (Bytes, SocketAddrV6),
+        Error = ::failure::Error
+    > + Send {
+        let (tx, rx) = unbounded();
+        // the task is gone only after a receive error reported
+        // to the previous subscriber, this stream just ends then
+        let _ = self.subscriptions.unbounded_send(tx);
+        rx.then(|x| match x {
+            Ok(x) => x,
+            Err(()) => unreachable!()
+        })
+    }
+}
+
+fn receive_task<S>(
+    mut packets: S,
+    mut subscriptions: UnboundedReceiver<UnboundedSender<Delivery>>
+) -> impl Future<Item = (), Error = ()> + Send where
+        S: Stream<Item = Packet, Error = ::failure::Error> + Send {
+    let mut subscriber: Option<UnboundedSender<Delivery>> = None;
+    // the datagrams received for a subscriber gone meanwhile
+    let mut pending = VecDeque::new();
+
+    future::poll_fn(move || {
+        loop {
+            loop {
+                match subscriptions.poll() {
+                    Ok(Async::Ready(Some(x))) => subscriber = Some(x),
+                    // the protocol machine is done
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => break,
+                    Err(()) => unreachable!()
+                }
+            }
+
+            // without a subscriber the datagrams wait in the socket
+            let sent = match subscriber {
+                Some(ref tx) => send_pending(tx, &mut pending),
+                None => return Ok(Async::NotReady)
+            };
+            if !sent {
+                subscriber = None;
+                continue;
+            }
+
+            match packets.poll() {
+                Ok(Async::Ready(Some(x))) => pending.push_back(x),
+                Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    if let Some(tx) = subscriber.take() {
+                        let _ = tx.unbounded_send(Err(e));
+                    }
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    })
+}
+
+// false if the subscriber has dropped its stream
+fn send_pending(
+    tx: &UnboundedSender<Delivery>,
+    pending: &mut VecDeque<Packet>
+) -> bool {
+    while let Some(x) = pending.pop_front() {
+        if let Err(e) = tx.unbounded_send(Ok(x)) {
+            if let Ok(x) = e.into_inner() {
+                pending.push_front(x);
+            }
+            return false;
+        }
+    }
+    true
+}
+
+fn recv_packets<F>(sock: IPv6RawSocketRecvHalf, mtu: usize, filter: F)
+        -> impl Stream<Item = Packet, Error = ::failure::Error> + Send where
+        F: FnMut(&[u8], SocketAddrV6) -> bool + Send + 'static {
+    let pool = BufferPool::new(mtu, RECV_BATCH_SIZE)
+        .expect("a valid buffer pool size");
+    let recv_buf = BytesMut::with_capacity(u16::max_value() as usize);
+
+    // the datagrams are validated in the pool, only the valid ones
+    // are copied out to the receive buffer
+    unfold((sock, pool, recv_buf, filter),
+        |(mut sock, pool, mut recv_buf, mut filter)| {
+            let recv = sock.recvfrom_pool(pool, RecvFlags::empty());
+            Some(recv
+                .map_err(|e| e.into())
+                .map(move |pool| {
+                    recv_buf.reserve(pool.len() * pool.slot_size());
+                    let batch = pool.iter()
+                        .filter(|&(x, src)| filter(x, src))
+                        .map(|(x, src)| {
+                            recv_buf.extend_from_slice(x);
+                            (recv_buf.split_to(x.len()).freeze(), src)
+                        })
+                        .collect::<Vec<_>>();
+                    (batch, (sock, pool, recv_buf, filter))
+                })
+            )
+        }
+    ).map(iter_ok::<_, ::failure::Error>).flatten()
+}
@@ -1,4 +1,7 @@
+use ::std::collections::HashMap;
 use ::std::ffi::OsString;
+use ::std::net::Ipv6Addr;
+use ::std::path::PathBuf;
 use ::std::str::FromStr;
 use ::std::sync::Arc;
 
@@ -19,13 +22,33 @@ fn DEFAULT_MAX_QUEUED() -> usize { 42 }
 #[allow(non_snake_case)]
 fn DEFAULT_ROUTER_FLAG() -> Router { Router::Yes }
 
+#[allow(non_snake_case)]
+fn DEFAULT_HA_PRIORITY() -> u16 { 100 }
+
+#[allow(non_snake_case)]
+fn DEFAULT_HA_PORT() -> u16 { 4242 }
+
+#[allow(non_snake_case)]
+fn DEFAULT_HEARTBEAT_INTERVAL() -> u64 { 1000 }
+
+#[allow(non_snake_case)]
+fn DEFAULT_DEAD_INTERVAL() -> u64 { 3500 }
+
+#[allow(non_snake_case)]
+fn DEFAULT_TAKEOVER_ADVERTS() -> usize { 64 }
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     #[serde(skip)] pub config_file: OsString,
     #[serde(skip)] pub daemonize: bool,
     #[serde(skip)] pub pid_file: OsString,
+    #[serde(skip)] pub systemd: bool,
     #[serde(skip)] pub verbose_logging: bool,
     pub su: Option<SuTarget>,
+    pub ha: Option<HaConfig>,
+    #[serde(rename = "prefix-group")]
+    #[serde(default)]
+    pub prefix_groups: Vec<PrefixGroupConfig>,
     #[serde(rename = "interface")] pub interfaces: Vec<InterfaceConfig>
 }
 
@@ -33,6 +56,20 @@ pub struct Config {
 pub struct InterfaceConfig {
     pub name: String,
     #[serde(default = "DEFAULT_MAX_QUEUED")] pub max_queued: usize,
+    #[serde(rename = "prefix")]
+    #[serde(default)]
+    pub prefixes: Vec<Arc<PrefixConfig>>,
+    #[serde(rename = "prefix-groups")]
+    #[serde(default)]
+    pub prefix_group_names: Vec<String>,
+    // prefixes served by the other interfaces and not by this one
+    #[serde(skip)] pub foreign_prefixes: Vec<Ipv6Network>
+}
+
+// a set of prefixes shared by several uplinks
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PrefixGroupConfig {
+    pub name: String,
     #[serde(rename = "prefix")] pub prefixes: Vec<Arc<PrefixConfig>>
 }
 
@@ -52,6 +89,87 @@ pub struct PrefixConfig {
     pub reply_unconditionally: bool
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HaConfig {
+    #[serde(default = "DEFAULT_HA_PRIORITY")] pub priority: u16,
+    #[serde(rename = "heartbeat-interval")]
+    #[serde(default = "DEFAULT_HEARTBEAT_INTERVAL")]
+    pub heartbeat_interval: u64,
+    #[serde(rename = "dead-interval")]
+    #[serde(default = "DEFAULT_DEAD_INTERVAL")]
+    pub dead_interval: u64,
+    #[serde(rename = "takeover-adverts")]
+    #[serde(default = "DEFAULT_TAKEOVER_ADVERTS")]
+    pub takeover_adverts: usize,
+    #[serde(rename = "multicast-group")]
+    pub multicast_group: Option<Ipv6Addr>,
+    #[serde(default = "DEFAULT_HA_PORT")] pub port: u16,
+    pub interface: Option<String>,
+    #[serde(rename = "unix-socket")] pub unix_socket: Option<PathBuf>,
+    #[serde(default)] pub peers: Vec<PathBuf>
+}
+
+impl Config {
+    fn resolve_prefix_groups(&mut self) -> Result<()> {
+        let mut groups = HashMap::new();
+        for i in &self.prefix_groups {
+            if groups.insert(i.name.as_str(), &i.prefixes).is_some() {
+                bail!("prefix group {} is defined more than once", i.name);
+            }
+        }
+
+        for i in &mut self.interfaces {
+            for j in &i.prefix_group_names {
+                match groups.get(j.as_str()) {
+                    Some(x) => i.prefixes.extend(x.iter().cloned()),
+                    None => bail!(
+                        "interface {} references an undefined \
+                            prefix group {}",
+                        i.name,
+                        j
+                    )
+                }
+            }
+
+            if i.prefixes.is_empty() {
+                bail!("no prefixes configured for interface {}", i.name);
+            }
+        }
+
+        let served: Vec<Vec<Ipv6Network>> = self.interfaces.iter()
+            .map(|x| x.prefixes.iter().map(|y| y.prefix).collect())
+            .collect();
+        for (i, ifc) in self.interfaces.iter_mut().enumerate() {
+            let mut foreign = Vec::new();
+            for (j, other) in served.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                for k in other {
+                    if !served[i].contains(k) && !foreign.contains(k) {
+                        foreign.push(*k);
+                    }
+                }
+            }
+            ifc.foreign_prefixes = foreign;
+        }
+
+        Ok(())
+    }
+}
+
+// a more specific prefix served by another uplink means
+// the target is reachable through it
+pub fn find_foreign_prefix<'a>(
+    foreign_prefixes: &'a [Ipv6Network],
+    target: Ipv6Addr,
+    prefix: &Ipv6Network
+) -> Option<&'a Ipv6Network> {
+    foreign_prefixes.iter()
+        .filter(|x| x.contains(target))
+        .find(|x| x.netmask() > prefix.netmask())
+}
+
 gen_boolean_enum!(pub serde Override);
 gen_boolean_enum!(pub serde Router);
 
@@ -173,6 +291,12 @@ pub fn read_config() -> Result<Config> {
             .short("d")
             .long("daemonize")
             .help("Forks and sets log to syslog instead of the console")
+        ).arg(Arg::with_name("systemd")
+            .short("s")
+            .long("systemd")
+            .conflicts_with("daemonize")
+            .help("Runs in the foreground as a systemd notify-type service, \
+                logging to the journal")
         ).arg(Arg::with_name("pid")
             .short("p")
             .long("pid-file")
@@ -183,8 +307,9 @@ pub fn read_config() -> Result<Config> {
         ).arg(Arg::with_name("verbose")
             .short("v")
             .long("verbose")
-            .help("Enables extremely verbose logging when daemonizing.  \
-                Use RUST_LOG for the console logging")
+            .help("Enables extremely verbose logging when daemonizing \
+                or running under systemd.  Use RUST_LOG for the console \
+                logging")
         ).get_matches();
 
     let config_filename = matches.value_of_os("config").unwrap();
@@ -205,7 +330,131 @@ pub fn read_config() -> Result<Config> {
     config.config_file = config_filename.into();
     config.daemonize = matches.is_present("daemonize");
     config.pid_file = matches.value_of_os("pid").unwrap().into();
+    config.systemd = matches.is_present("systemd");
     config.verbose_logging = matches.is_present("verbose");
 
+    config.resolve_prefix_groups()?;
+
     Ok(config)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SHARED: &str = r#"
+        [[prefix-group]]
+        name = "shared"
+        [[prefix-group.prefix]]
+        prefix = "2001:db8::/48"
+    "#;
+
+    fn resolve(config: &str) -> Result<Config> {
+        let mut config: Config = ::toml::from_str(config)?;
+        config.resolve_prefix_groups()?;
+        Ok(config)
+    }
+
+    fn nets(prefixes: &[&str]) -> Vec<Ipv6Network> {
+        prefixes.iter().map(|x| Ipv6Network::from_str(x).unwrap()).collect()
+    }
+
+    #[test]
+    fn foreign_prefixes() {
+        let cases = [
+            // two uplinks sharing a group are not foreign to each other
+            (
+                r#"
+                    [[interface]]
+                    name = "up0"
+                    prefix-groups = ["shared"]
+
+                    [[interface]]
+                    name = "up1"
+                    prefix-groups = ["shared"]
+                "#,
+                vec![nets(&[]), nets(&[])]
+            ),
+            // a more specific prefix served elsewhere is listed
+            (
+                r#"
+                    [[interface]]
+                    name = "up0"
+                    prefix-groups = ["shared"]
+
+                    [[interface]]
+                    name = "up1"
+                    prefix-groups = ["shared"]
+                    [[interface.prefix]]
+                    prefix = "2001:db8:0:42::/64"
+                "#,
+                vec![nets(&["2001:db8:0:42::/64"]), nets(&[])]
+            )
+        ];
+
+        for &(ref config, ref expected) in cases.iter() {
+            let config = resolve(&format!("{}{}", SHARED, config)).unwrap();
+            let foreign: Vec<_> = config.interfaces.iter()
+                .map(|x| x.foreign_prefixes.clone())
+                .collect();
+            assert_eq!(&foreign, expected);
+        }
+    }
+
+    #[test]
+    fn more_specific_foreign_prefix_skipped() {
+        let foreign = nets(&["2001:db8:0:42::/64"]);
+        let prefix = Ipv6Network::from_str("2001:db8::/48").unwrap();
+
+        let inside = "2001:db8:0:42::1".parse().unwrap();
+        assert_eq!(
+            find_foreign_prefix(&foreign, inside, &prefix),
+            Some(&foreign[0])
+        );
+        let outside = "2001:db8:0:43::1".parse().unwrap();
+        assert_eq!(find_foreign_prefix(&foreign, outside, &prefix), None);
+
+        // a less specific one is not a reason to skip the target
+        let prefix = Ipv6Network::from_str("2001:db8:0:42::/96").unwrap();
+        assert_eq!(find_foreign_prefix(&foreign, inside, &prefix), None);
+    }
+
+    #[test]
+    fn prefix_group_errors() {
+        let cases = [
+            (
+                r#"
+                    [[prefix-group]]
+                    name = "shared"
+                    [[prefix-group.prefix]]
+                    prefix = "2001:db8:1::/48"
+
+                    [[interface]]
+                    name = "up0"
+                    prefix-groups = ["shared"]
+                "#,
+                "prefix group shared is defined more than once"
+            ),
+            (
+                r#"
+                    [[interface]]
+                    name = "up0"
+                    prefix-groups = ["missing"]
+                "#,
+                "interface up0 references an undefined prefix group missing"
+            ),
+            (
+                r#"
+                    [[interface]]
+                    name = "up0"
+                "#,
+                "no prefixes configured for interface up0"
+            )
+        ];
+
+        for &(config, expected) in cases.iter() {
+            let err = resolve(&format!("{}{}", SHARED, config)).unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+}
@@ -3,7 +3,16 @@
 use ::pnet_packet::icmpv6::ndp::NeighborAdvertFlags::*;
 
 pub const NEIGHBOR_ADVERT_SIZE: usize = 24;
-pub const NEIGHBOR_ADVERT_LL_ADDR_OPTION_SIZE: usize = 8;
+pub const NDP_OPTION_HEADER_SIZE: usize = 2;
+pub const NDP_OPTION_UNIT_SIZE: usize = 8;
+// anything less means the packet came from beyond the link
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+// beyond that many groups the interface is put into the allmulti mode
+pub const MAX_SOLICITED_NODE_GROUPS: usize = 64;
+
+// solicitations received, and advertisements handled, per system call
+pub const RECV_BATCH_SIZE: usize = 32;
 
 bitflags!(
     pub struct NdpAdvertFlags: u8 {
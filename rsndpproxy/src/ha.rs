@@ -0,0 +1,447 @@
+// Active/standby coordination between rsndpproxy instances serving
+// the same segment.  Every instance periodically announces its priority
+// and whether it considers itself active; the live instance with the highest
+// (priority, id) pair becomes the active one, all others stay silent.
+
+use ::std::collections::HashMap;
+use ::std::ffi::CString;
+use ::std::io;
+use ::std::net::*;
+use ::std::os::unix::net::UnixDatagram;
+use ::std::path::PathBuf;
+use ::std::time::*;
+
+use ::bytes::{BigEndian as BE, ByteOrder};
+use ::failure::ResultExt;
+use ::tokio::prelude::*;
+use ::tokio::timer::Interval;
+
+use ::broadcast::*;
+use ::config::HaConfig;
+use ::errors::Result;
+use ::util::*;
+
+const HEARTBEAT_MAGIC: &[u8; 4] = b"RSHA";
+const HEARTBEAT_VERSION: u8 = 1;
+const HEARTBEAT_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum HaRole {
+    Active, Standby
+}
+
+bitflags!(
+    struct HeartbeatFlags: u8 {
+        const Active = 1;
+        const Resigning = 2;
+    }
+);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Heartbeat {
+    id: u64,
+    priority: u16,
+    flags: HeartbeatFlags
+}
+
+struct Peer {
+    priority: u16,
+    last_seen: Instant
+}
+
+enum Transport {
+    Multicast {
+        sock: UdpSocket,
+        group: SocketAddrV6
+    },
+    Unix {
+        sock: UnixDatagram,
+        path: PathBuf,
+        peers: Vec<PathBuf>
+    }
+}
+
+pub struct Coordinator {
+    transport: Transport,
+    id: u64,
+    priority: u16,
+    dead_interval: Duration,
+    started: Instant,
+    timer: Interval,
+    peers: HashMap<u64, Peer>,
+    role: Option<HaRole>,
+    roles: Sender<HaRole>
+}
+
+pub fn spawn_coordinator(conf: &HaConfig, max_receivers: usize)
+        -> Result<Receiver<HaRole>> {
+    let (roles_rx, roles_tx) = broadcaster(max_receivers);
+    let coordinator = Coordinator::new(conf, roles_tx)?;
+    info!(
+        "high availability mode on, instance id {:016x}, priority {}",
+        coordinator.id,
+        coordinator.priority
+    );
+    ::tokio::spawn(coordinator);
+    Ok(roles_rx)
+}
+
+impl Heartbeat {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() != HEARTBEAT_SIZE
+                || &buf[0..4] != HEARTBEAT_MAGIC
+                || buf[4] != HEARTBEAT_VERSION {
+            return None;
+        }
+
+        Some(Self {
+            flags: HeartbeatFlags::from_bits(buf[5])?,
+            priority: BE::read_u16(&buf[6..8]),
+            id: BE::read_u64(&buf[8..16])
+        })
+    }
+
+    fn write(&self, buf: &mut [u8; HEARTBEAT_SIZE]) {
+        buf[0..4].copy_from_slice(HEARTBEAT_MAGIC);
+        buf[4] = HEARTBEAT_VERSION;
+        buf[5] = self.flags.bits();
+        BE::write_u16(&mut buf[6..8], self.priority);
+        BE::write_u64(&mut buf[8..16], self.id);
+    }
+}
+
+impl Transport {
+    fn new(conf: &HaConfig) -> Result<Self> {
+        match (conf.multicast_group, &conf.unix_socket) {
+            (Some(group), &None) => {
+                if !group.is_multicast() {
+                    bail!("ha multicast-group {} is not a multicast address",
+                        group);
+                }
+                let if_name = match conf.interface {
+                    Some(ref x) => x,
+                    None => bail!("ha multicast-group requires the ha \
+                        interface option")
+                };
+                let if_index = unsafe {
+                    ::nix::libc::if_nametoindex(
+                        CString::new(if_name.as_str())?.as_ptr()
+                    )
+                };
+                if if_index == 0 {
+                    bail!("no \"{}\" network interface", if_name);
+                }
+
+                let sock = UdpSocket::bind(SocketAddrV6::new(
+                    Ipv6Addr::UNSPECIFIED,
+                    conf.port,
+                    0,
+                    0
+                )).context("cannot bind the ha heartbeat socket")?;
+                sock.join_multicast_v6(&group, if_index)
+                    .context("cannot join the ha multicast group")?;
+                sock.set_multicast_loop_v6(false)?;
+                sock.set_nonblocking(true)?;
+
+                Ok(Transport::Multicast {
+                    sock,
+                    group: SocketAddrV6::new(group, conf.port, 0, if_index)
+                })
+            },
+            (None, &Some(ref path)) => {
+                if conf.peers.is_empty() {
+                    bail!("ha unix-socket requires at least one ha peer");
+                }
+
+                // a stale socket left by a crashed instance
+                let _ = ::std::fs::remove_file(path);
+                let sock = UnixDatagram::bind(path)
+                    .context("cannot bind the ha heartbeat socket")?;
+                sock.set_nonblocking(true)?;
+
+                Ok(Transport::Unix {
+                    sock,
+                    path: path.clone(),
+                    peers: conf.peers.clone()
+                })
+            },
+            _ => bail!("exactly one of ha multicast-group and unix-socket \
+                must be set")
+        }
+    }
+
+    fn send(&self, buf: &[u8]) {
+        match *self {
+            Transport::Multicast { ref sock, group } => {
+                if let Err(e) = sock.send_to(buf, group) {
+                    debug!("cannot send a heartbeat: {}", e);
+                }
+            },
+            Transport::Unix { ref sock, ref peers, .. } => {
+                // absent peers are normal, they are what we are watching for
+                for i in peers {
+                    if let Err(e) = sock.send_to(buf, i) {
+                        debug!(
+                            "cannot send a heartbeat to {}: {}",
+                            i.to_string_lossy(),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let ret = match *self {
+            Transport::Multicast { ref sock, .. } =>
+                sock.recv_from(buf).map(|(len, _)| len),
+            Transport::Unix { ref sock, .. } =>
+                sock.recv_from(buf).map(|(len, _)| len)
+        };
+        match ret {
+            Ok(len) => Ok(Some(len)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        if let Transport::Unix { ref path, .. } = *self {
+            let _ = ::std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Coordinator {
+    fn new(conf: &HaConfig, roles: Sender<HaRole>) -> Result<Self> {
+        if conf.heartbeat_interval == 0
+                || conf.dead_interval <= conf.heartbeat_interval {
+            bail!("ha dead-interval must be longer than a nonzero \
+                heartbeat-interval");
+        }
+
+        let interval = Duration::from_millis(conf.heartbeat_interval);
+        let now = Instant::now();
+
+        Ok(Self {
+            transport: Transport::new(conf)?,
+            id: make_instance_id(),
+            priority: conf.priority,
+            dead_interval: Duration::from_millis(conf.dead_interval),
+            started: now,
+            timer: Interval::new(now, interval),
+            peers: HashMap::new(),
+            role: None,
+            roles
+        })
+    }
+
+    fn receive_heartbeats(&mut self) -> Result<()> {
+        let mut buf = [0; HEARTBEAT_SIZE + 1];
+        while let Some(len) = self.transport.recv(&mut buf)
+                .context("cannot receive a heartbeat")? {
+            let hb = match Heartbeat::parse(&buf[..len]) {
+                Some(x) => x,
+                None => {
+                    debug!("invalid heartbeat received, dropping");
+                    continue;
+                }
+            };
+
+            if hb.id == self.id {
+                continue;
+            }
+
+            if hb.flags.contains(HeartbeatFlags::Resigning) {
+                debug!("peer {:016x} resigned", hb.id);
+                self.peers.remove(&hb.id);
+                continue;
+            }
+
+            if !self.peers.contains_key(&hb.id) {
+                info!(
+                    "discovered ha peer {:016x} with priority {}",
+                    hb.id,
+                    hb.priority
+                );
+            }
+            self.peers.insert(hb.id, Peer {
+                priority: hb.priority,
+                last_seen: Instant::now()
+            });
+        }
+
+        Ok(())
+    }
+
+    fn expire_peers(&mut self) {
+        let now = Instant::now();
+        let dead_interval = self.dead_interval;
+        self.peers.retain(|id, peer| {
+            let alive = now.duration_since(peer.last_seen) < dead_interval;
+            if !alive {
+                info!("ha peer {:016x} is dead", id);
+            }
+            alive
+        });
+    }
+
+    fn elect(&self) -> HaRole {
+        // give the peers a chance to announce themselves before taking over
+        if Instant::now().duration_since(self.started) < self.dead_interval {
+            return HaRole::Standby;
+        }
+
+        elect_among((self.priority, self.id), &self.peers)
+    }
+
+    fn send_heartbeat(&self, flags: HeartbeatFlags) {
+        let mut buf = [0; HEARTBEAT_SIZE];
+        Heartbeat {
+            id: self.id,
+            priority: self.priority,
+            flags
+        }.write(&mut buf);
+        self.transport.send(&buf);
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.receive_heartbeats()?;
+        self.expire_peers();
+
+        let role = self.elect();
+        if self.role != Some(role) {
+            match role {
+                HaRole::Active => info!("this instance is now active"),
+                HaRole::Standby => info!("this instance is now standby")
+            }
+            self.role = Some(role);
+            self.roles.start_send(role)?;
+        }
+
+        let flags = match role {
+            HaRole::Active => HeartbeatFlags::Active,
+            HaRole::Standby => HeartbeatFlags::empty()
+        };
+        self.send_heartbeat(flags);
+
+        Ok(())
+    }
+}
+
+impl Future for Coordinator {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if !self.roles.are_receivers_present() {
+                debug!("no ha role broadcast readers left, \
+                    ha coordinator quitting");
+                return Ok(Async::Ready(()));
+            }
+
+            match self.timer.poll().map_err(|e| log_err(e.into()))? {
+                Async::Ready(Some(_)) => self.tick().map_err(log_err)?,
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => return Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl Drop for Coordinator {
+    fn drop(&mut self) {
+        // let the standby take over without waiting for the dead interval
+        if let Some(HaRole::Active) = self.role {
+            self.send_heartbeat(HeartbeatFlags::Resigning);
+            info!("resigned from the active role");
+        }
+    }
+}
+
+// the ids break the ties between equal priorities
+fn elect_among(me: (u16, u64), peers: &HashMap<u64, Peer>) -> HaRole {
+    if peers.iter().all(|(id, peer)| (peer.priority, *id) < me) {
+        HaRole::Active
+    } else {
+        HaRole::Standby
+    }
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn make_instance_id() -> u64 {
+    let pid = unsafe { ::nix::libc::getpid() } as u64;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("system time after the epoch");
+    (now.as_secs() << 32) ^ u64::from(now.subsec_nanos()) ^ (pid << 16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_heartbeat() -> Heartbeat {
+        Heartbeat {
+            id: 0x0123_4567_89ab_cdef,
+            priority: 200,
+            flags: HeartbeatFlags::Active
+        }
+    }
+
+    #[test]
+    fn heartbeat_round_trip() {
+        let hb = make_heartbeat();
+        let mut buf = [0; HEARTBEAT_SIZE];
+        hb.write(&mut buf);
+
+        assert_eq!(&buf[0..4], HEARTBEAT_MAGIC);
+        assert_eq!(Heartbeat::parse(&buf), Some(hb));
+    }
+
+    #[test]
+    fn invalid_heartbeats() {
+        let mut buf = [0; HEARTBEAT_SIZE];
+        make_heartbeat().write(&mut buf);
+
+        assert_eq!(Heartbeat::parse(&buf[..HEARTBEAT_SIZE - 1]), None);
+        assert_eq!(Heartbeat::parse(&[]), None);
+
+        let mut longer = buf.to_vec();
+        longer.push(0);
+        assert_eq!(Heartbeat::parse(&longer), None);
+
+        let mut bad_version = buf;
+        bad_version[4] = HEARTBEAT_VERSION + 1;
+        assert_eq!(Heartbeat::parse(&bad_version), None);
+
+        let mut unknown_flags = buf;
+        unknown_flags[5] = 0x80;
+        assert_eq!(Heartbeat::parse(&unknown_flags), None);
+    }
+
+    fn make_peers(peers: &[(u64, u16)]) -> HashMap<u64, Peer> {
+        peers.iter()
+            .map(|&(id, priority)| (id, Peer {
+                priority,
+                last_seen: Instant::now()
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn election() {
+        assert_eq!(elect_among((100, 1), &HashMap::new()), HaRole::Active);
+
+        let peers = make_peers(&[(2, 50), (3, 100)]);
+        // the same priority, the higher id wins
+        assert_eq!(elect_among((100, 1), &peers), HaRole::Standby);
+        assert_eq!(elect_among((100, 4), &peers), HaRole::Active);
+        // the priority goes first
+        assert_eq!(elect_among((101, 1), &peers), HaRole::Active);
+        assert_eq!(elect_among((60, 9), &peers), HaRole::Standby);
+    }
+}
@@ -11,7 +11,6 @@ extern crate capabilities;
 extern crate env_logger;
 #[macro_use] extern crate failure;
 extern crate futures;
-extern crate interfaces;
 extern crate ip_network;
 #[macro_use] extern crate log;
 extern crate nix;
@@ -26,20 +25,23 @@ extern crate toml;
 extern crate users;
 
 extern crate linux_network;
-extern crate send_box;
 
 mod broadcast;
 mod config;
 mod constants;
 mod errors;
+mod ha;
 mod packet;
 mod server;
+mod systemd;
 mod util;
 
 use std::fs::*;
 use std::io;
 use std::process::exit;
 use std::os::unix::prelude::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use failure::ResultExt;
 use futures::future::*;
@@ -57,7 +59,9 @@ use linux_network::Permissions;
 use broadcast::*;
 use config::*;
 use errors::{Error, Result};
+use ha::*;
 use server::*;
+use systemd::Notifier;
 use util::*;
 
 fn main() {
@@ -108,20 +112,23 @@ fn daemonize() -> Result<()> {
 }
 
 fn setup_logging(config: &Config) -> Result<()> {
+    let log_level =
+        if config.verbose_logging {
+            log::LogLevelFilter::Debug
+        } else {
+            log::LogLevelFilter::Info
+        };
+
     if config.daemonize {
-        let log_level =
-            if config.verbose_logging {
-                log::LogLevelFilter::Debug
-            } else {
-                log::LogLevelFilter::Info
-            };
         syslog::init(syslog::Facility::LOG_DAEMON,
             log_level, Some(crate_name!()))?;
+    } else if config.systemd {
+        systemd::init_journal_logging(log_level)?;
     } else {
         if config.verbose_logging {
             eprintln!("Using \"--verbose\" option has no effect without \
-                \"--daemonize.\"  Use the RUST_LOG environment variable \
-                instead.");
+                \"--daemonize\" or \"--systemd.\"  Use the RUST_LOG \
+                environment variable instead.");
             exit(1);
         }
 
@@ -142,6 +149,7 @@ fn the_main(config: Config) -> Result<()> {
             debug!("\t{}", i);
         }
         debug!("daemonize is {}", if config.daemonize {"on"} else {"off"});
+        debug!("systemd mode is {}", if config.systemd {"on"} else {"off"});
     }
 
     if get_effective_uid() != 0 {
@@ -154,17 +162,43 @@ fn the_main(config: Config) -> Result<()> {
         create_pid_file(&config.pid_file)?;
     }
 
+    // the notification socket must be reachable before
+    // the privileges are dropped
+    let notifier = if config.systemd {
+        match Notifier::from_env()? {
+            Some(x) => Some(Arc::new(x)),
+            None => {
+                warn!("NOTIFY_SOCKET is not set, systemd will not \
+                    be notified");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     drop_privileges(&config.su)?;
 
     if config.interfaces.is_empty() {
         bail!("You must configure at least one interface.");
     }
 
+    // the servers started before a setup failure are stopped,
+    // the runtime returns once they are gone
+    let setup_failed = Arc::new(AtomicBool::new(false));
+    let setup_failed_flag = setup_failed.clone();
     tokio::run(poll_fn(move || {
-        setup_server(&config).map_err(log_err)?;
+        if let Err(e) = setup_server(&config, notifier.clone()) {
+            log_err(e);
+            setup_failed_flag.store(true, Ordering::Relaxed);
+        }
         Ok(Async::Ready(()))
     }));
 
+    if setup_failed.load(Ordering::Relaxed) {
+        error!("{} could not start", crate_name!());
+        exit(1);
+    }
     info!("{} stopping", crate_name!());
     Ok(())
 }
@@ -174,16 +208,56 @@ pub enum QuitKind {
     Fast, Normal
 }
 
-fn setup_server(config: &Config) -> Result<()> {
-    let (quit_rx, quit_tx) = broadcaster(config.interfaces.len());
+fn setup_server(config: &Config, notifier: Option<Arc<Notifier>>)
+        -> Result<()> {
+    let n_quit_receivers = config.interfaces.len()
+        + if notifier.is_some() {1} else {0};
+    // the servers quit once quit_tx is dropped on an error
+    let (quit_rx, quit_tx) = broadcaster(n_quit_receivers);
+
+    let ha = match config.ha {
+        Some(ref ha_conf) => Some((
+            spawn_coordinator(ha_conf, config.interfaces.len())?,
+            ha_conf.takeover_adverts
+        )),
+        None => None
+    };
+
+    let supervisor_quit = notifier.as_ref().map(|_| Receiver::clone(&quit_rx));
+    let (stats, failed) = handle_requests(&config.interfaces, quit_rx, ha);
+
+    // a partial start is not served at all
+    if !failed.is_empty() {
+        if let Some(ref notifier) = notifier {
+            log_if_err(notifier.status(format!(
+                "failed to start on interfaces {}",
+                failed.join(", ")
+            )));
+        }
+        bail!("could not serve interfaces {}", failed.join(", "));
+    }
+
+    if let Some(ref notifier) = notifier {
+        notifier.status(format!(
+            "serving {} interfaces",
+            stats.len()
+        ))?;
+        notifier.ready()?;
+        debug!("notified systemd of readiness");
+    }
 
-    handle_signals(quit_tx);
-    handle_requests(&config.interfaces, quit_rx);
+    handle_signals(quit_tx, notifier.clone());
+    if let Some(notifier) = notifier {
+        systemd::spawn_supervisor(notifier, stats, supervisor_quit.unwrap());
+    }
 
     Ok(())
 }
 
-fn handle_signals(mut sender: Sender<QuitKind>) {
+fn handle_signals(
+    mut sender: Sender<QuitKind>,
+    notifier: Option<Arc<Notifier>>
+) {
     let mut interrupted = signal::Signal::new(signal::SIGINT).flatten_stream();
     let mut terminated = signal::Signal::new(signal::SIGTERM).flatten_stream();
 
@@ -219,6 +293,12 @@ fn handle_signals(mut sender: Sender<QuitKind>) {
                         fast_quit_sent = true;
                         active = true;
                     }
+
+                    if to_send.is_some() {
+                        if let Some(ref notifier) = notifier {
+                            log_if_err(notifier.stopping());
+                        }
+                    }
                 }
 
                 if !sender.are_receivers_present() {
@@ -233,26 +313,46 @@ fn handle_signals(mut sender: Sender<QuitKind>) {
     );
 }
 
-fn handle_requests(interfaces: &[InterfaceConfig], quit: Receiver<QuitKind>) {
+fn handle_requests(
+    interfaces: &[InterfaceConfig],
+    quit: Receiver<QuitKind>,
+    ha: Option<(Receiver<HaRole>, usize)>
+) -> (Vec<(String, Arc<PacketStats>)>, Vec<String>) {
+    let mut stats = Vec::with_capacity(interfaces.len());
+    let mut failed = Vec::new();
     let mut quit = Some(quit);
+    let (mut ha_roles, max_takeover_targets) = match ha {
+        Some((roles, n)) => (Some(roles), n),
+        None => (None, 0)
+    };
     for i in 0 .. interfaces.len() {
         let j = interfaces[i].clone();
+        let last = i == interfaces.len() - 1;
         let quit_to_move =
-            if i == interfaces.len() - 1 {
+            if last {
                 quit.take().unwrap()
             } else {
                 Receiver::clone(quit.as_ref().unwrap())
             };
-        tokio::spawn(
-            result(
-                Server::new(
-                    &j,
-                    quit_to_move
-                ).map_err(log_err)
-            ).flatten()
-        );
-        debug!("server for interface {} started", interfaces[i].name);
+        let ha_to_move =
+            if last {
+                ha_roles.take()
+            } else {
+                ha_roles.as_ref().map(Receiver::clone)
+            };
+        match Server::new(&j, quit_to_move, ha_to_move, max_takeover_targets) {
+            Ok(server) => {
+                stats.push((j.name.clone(), server.get_stats()));
+                tokio::spawn(server);
+                debug!("server for interface {} started", interfaces[i].name);
+            },
+            Err(e) => {
+                log_err(e);
+                failed.push(j.name.clone());
+            }
+        }
     }
+    (stats, failed)
 }
 
 fn create_pid_file<T>(pid_filename: T) -> Result<()>
@@ -319,8 +419,14 @@ fn drop_privileges(su: &Option<SuTarget>) -> Result<()> {
         | SecBits::NoRootLocked
         | SecBits::NoCapAmbientRaise
         | SecBits::NoCapAmbientRaiseLocked;
-    set_securebits(bits)
-        .map_err(|e| Error::SecurebitsError(e.compat()))?;
+    Sandbox::new()
+        .securebits(bits)
+        // the sockets are created later and need this network namespace
+        .unshare(Namespaces::Ipc | Namespaces::Uts)
+        .limit(Resource::CoreSize, 0)
+        .apply()
+        .context("cannot set up the sandbox")?;
+    debug!("entered new ipc and uts namespaces");
 
     drop_supplementary_groups().context("cannot drop supplementary groups")?;
     debug!("dropped supplementary groups");
@@ -370,7 +476,7 @@ fn drop_privileges(su: &Option<SuTarget>) -> Result<()> {
     caps.apply().map_err(Error::PrivDrop)?;
     debug!("dropped linux capabilities");
 
-    // TODO: chroot, namespaces
+    // no chroot: the systemd and ha unix sockets are addressed by path
 
     Ok(())
 }
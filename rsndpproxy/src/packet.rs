@@ -4,7 +4,7 @@ use ::bytes::*;
 use ::pnet_packet::{*, ipv6::*};
 use ::pnet_packet::icmpv6::{*, ndp::*};
 
-use ::linux_network::MacAddr;
+use ::linux_network::HwAddr;
 
 use ::config::*;
 use ::constants::*;
@@ -15,7 +15,7 @@ pub struct Advertisement {
     pub src: Ipv6Addr,
     pub dst: Ipv6Addr,
     pub target: Ipv6Addr,
-    pub ll_addr_opt: Option<MacAddr>
+    pub ll_addr_opt: Option<HwAddr>
 }
 
 #[derive(Debug)]
@@ -23,7 +23,7 @@ pub struct Solicitation {
     pub src: Ipv6Addr,
     pub dst: Ipv6Addr,
     pub target: Ipv6Addr,
-    pub ll_addr_opt: Option<MacAddr>
+    pub ll_addr_opt: Option<HwAddr>
 }
 
 impl Advertisement {
@@ -32,7 +32,30 @@ impl Advertisement {
         override_flag: Override,
         router_flag: Router
     ) -> Bytes {
-        let size = NEIGHBOR_ADVERT_SIZE + NEIGHBOR_ADVERT_LL_ADDR_OPTION_SIZE;
+        let mut flags = NdpAdvertFlags::Solicited;
+        if let Override::Yes = override_flag {
+            flags |= NdpAdvertFlags::Override;
+        }
+        if let Router::Yes = router_flag {
+            flags |= NdpAdvertFlags::Router;
+        }
+        self.to_packet(flags)
+    }
+
+    pub fn unsolicited_to_packet(&self, router_flag: Router) -> Bytes {
+        // unsolicited advertisements are only sent to update the neighbors'
+        // caches, so they must override the existing entries
+        let mut flags = NdpAdvertFlags::Override;
+        if let Router::Yes = router_flag {
+            flags |= NdpAdvertFlags::Router;
+        }
+        self.to_packet(flags)
+    }
+
+    fn to_packet(&self, flags: NdpAdvertFlags) -> Bytes {
+        let option_units = self.ll_addr_opt.as_ref()
+            .map_or(0, |x| ll_addr_option_units(x.len()));
+        let size = NEIGHBOR_ADVERT_SIZE + option_units * NDP_OPTION_UNIT_SIZE;
         let mut icmp_bytes = BytesMut::with_capacity(size);
 
         {
@@ -41,24 +64,26 @@ impl Advertisement {
             {
                 let mut icmp = MutableNeighborAdvertPacket::new(buff).unwrap();
 
-                let mut flags = NdpAdvertFlags::Solicited;
-                if let Override::Yes = override_flag {
-                    flags |= NdpAdvertFlags::Override;
-                }
-                if let Router::Yes = router_flag {
-                    flags |= NdpAdvertFlags::Router;
-                }
-
                 icmp.set_icmpv6_type(Icmpv6Types::NeighborAdvert);
                 icmp.set_icmpv6_code(Icmpv6Codes::NoCode);
                 icmp.set_flags(flags.bits());
                 icmp.set_target_addr(self.target);
                 match self.ll_addr_opt {
-                    Some(ref mac) => icmp.set_options(&[NdpOption {
-                        option_type: NdpOptionTypes::TargetLLAddr,
-                        length: 1,
-                        data: mac.as_bytes().to_vec()
-                    }]),
+                    Some(ref addr) => {
+                        // the option is padded to the 8-octet boundary
+                        let mut data = addr.as_bytes().to_vec();
+                        data.resize(
+                            option_units * NDP_OPTION_UNIT_SIZE
+                                - NDP_OPTION_HEADER_SIZE,
+                            0
+                        );
+                        #[allow(clippy::cast_possible_truncation)]
+                        icmp.set_options(&[NdpOption {
+                            option_type: NdpOptionTypes::TargetLLAddr,
+                            length: option_units as u8,
+                            data
+                        }]);
+                    },
                     None => icmp.set_options(&[])
                 }
                 icmp.set_payload(&[]);
@@ -79,44 +104,55 @@ impl Advertisement {
 }
 
 impl Solicitation {
-    pub fn parse(packet: &Ipv6) -> Option<Self> {
+    // hw_addr_len is None for the links without link-layer addresses
+    pub fn parse(
+        packet: &Ipv6Packet,
+        hw_addr_len: Option<usize>
+    ) -> Option<Self> {
         // validates only the points required
         // by https://tools.ietf.org/html/rfc4861#section-6.1.1
 
-        let icmp_data = &packet.payload;
-        let src = packet.source;
-        let dst = packet.destination;
+        let icmp_data = packet.payload();
+        let src = packet.get_source();
+        let dst = packet.get_destination();
 
-        let solicit = match NeighborSolicitPacket::new(icmp_data.as_ref()) {
-            Some(packet) => packet.from_packet(),
-            None => return None
-        };
+        let solicit = NeighborSolicitPacket::new(icmp_data)?;
 
         let checksum = {
-            let packet = icmpv6::Icmpv6Packet::new(icmp_data.as_ref())
+            let packet = icmpv6::Icmpv6Packet::new(icmp_data)
                 .expect("a valid icmpv6 packet");
             icmpv6::checksum(&packet, &src, &dst)
         };
 
-        if solicit.icmpv6_type != icmpv6::Icmpv6Types::NeighborSolicit
-                || solicit.icmpv6_code != Icmpv6Codes::NoCode
-                || solicit.checksum != checksum
+        let target = solicit.get_target_addr();
+        if packet.get_hop_limit() != NDP_HOP_LIMIT
+                || solicit.get_icmpv6_type()
+                    != icmpv6::Icmpv6Types::NeighborSolicit
+                || solicit.get_icmpv6_code() != Icmpv6Codes::NoCode
+                || solicit.get_checksum() != checksum
                 || icmp_data.len() < 24
-                || solicit.target_addr.is_multicast()
+                || target.is_multicast()
                 || (src.is_unspecified()
                     || !is_solicited_node_multicast(&dst)) {
             return None;
         }
 
         let mut ll_addr_opt = None;
-        for i in solicit.options {
-            if i.option_type == NdpOptionTypes::SourceLLAddr {
+        for i in solicit.get_options_iter() {
+            if i.get_option_type() == NdpOptionTypes::SourceLLAddr {
+                // meaningless on a link without link-layer addresses
+                let len = match hw_addr_len {
+                    Some(x) => x,
+                    None => continue
+                };
+                let data = i.payload();
                 if ll_addr_opt.is_some()
-                        || i.length != 1
-                        || i.data.len() != 6 {
+                        || usize::from(i.get_length())
+                            != ll_addr_option_units(len)
+                        || data.len() < len {
                     return None;
                 }
-                ll_addr_opt = Some(MacAddr::from_bytes(i.data).unwrap())
+                ll_addr_opt = Some(HwAddr::from_bytes(&data[..len]).unwrap())
             }
         }
 
@@ -127,8 +163,13 @@ impl Solicitation {
         Some(Self {
             src,
             dst,
-            target: solicit.target_addr,
+            target,
             ll_addr_opt
         })
     }
 }
+
+fn ll_addr_option_units(hw_addr_len: usize) -> usize {
+    (NDP_OPTION_HEADER_SIZE + hw_addr_len + NDP_OPTION_UNIT_SIZE - 1)
+        / NDP_OPTION_UNIT_SIZE
+}
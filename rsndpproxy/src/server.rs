@@ -1,34 +1,48 @@
+use ::std::collections::VecDeque;
 use ::std::net::*;
 use ::std::sync::{Arc, atomic::*};
 
+use ::bytes::Bytes;
 use ::failure::ResultExt;
-use ::futures::stream::unfold;
+use ::futures::stream::{iter_ok, unfold};
+use ::ip_network::Ipv6Network;
 use ::tokio::prelude::*;
 
 use ::linux_network::{*, futures, futures::*};
-use ::send_box::SendBox;
 
 use ::broadcast::*;
 use ::config::*;
+use ::constants::*;
 use ::errors::{Error, Result};
+use ::ha::HaRole;
 use ::packet::*;
 use ::util::*;
 
-type StreamE<T> = dyn(Stream<Item = T, Error = ::failure::Error>);
+type StreamE<T> = dyn(Stream<Item = T, Error = ::failure::Error> + Send);
 
 pub struct Server {
-    recv_sock: futures::IPv6PacketSocketAdapter,
+    // the receiving half of the packet socket feeds the input stream,
+    // this one is kept for the interface flags
+    packet_sock: futures::IPv6PacketSocketSendHalf,
     send_sock: futures::IPv6RawSocketAdapter,
-    input: SendBox<StreamE<(Solicitation, Arc<PrefixConfig>)>>,
+    input: Box<StreamE<(Solicitation, Arc<PrefixConfig>)>>,
     quit: Receiver<::QuitKind>,
+    ha: Option<Receiver<HaRole>>,
+    role: HaRole,
+    takeover_targets: VecDeque<(Ipv6Addr, Arc<PrefixConfig>)>,
+    max_takeover_targets: usize,
+    memberships: Vec<MulticastMembership<IPv6RawSocket>>,
     drop_allmulti: DropAllmulti,
     ifname: String,
-    queued_sends: Arc<AtomicUsize>,
+    outgoing: Vec<(Bytes, SocketAddrV6)>,
+    sending: Option<futures::IPv6RawSocketSendtoBatchFuture>,
+    // the destinations of the batch being sent, in order
+    in_flight: Vec<SocketAddrV6>,
     max_queued: usize,
     stats: Arc<PacketStats>
 }
 
-struct PacketStats {
+pub struct PacketStats {
     packets_received: AtomicUsize,
     packets_sent: AtomicUsize
 }
@@ -39,7 +53,9 @@ impl Server {
     #[allow(clippy::cast_sign_loss)]
     pub fn new(
         ifc: &InterfaceConfig,
-        quit: Receiver<::QuitKind>
+        quit: Receiver<::QuitKind>,
+        ha: Option<Receiver<HaRole>>,
+        max_takeover_targets: usize
     ) -> Result<Self> {
         for i in &ifc.prefixes {
             if !i.reply_unconditionally {
@@ -47,30 +63,51 @@ impl Server {
             }
         }
 
-        let (recv_sock, drop_allmulti) = Self::setup_recv_socket(ifc)?;
+        let mut recv_sock = Self::setup_recv_socket(ifc)?;
         let send_sock = Self::setup_send_socket(ifc)?;
+        let (memberships, drop_allmulti) =
+            Self::setup_multicast(ifc, &mut recv_sock)?;
 
         let mtu = get_interface_mtu(&recv_sock, &ifc.name)? as usize;
         let prefixes = ifc.prefixes.clone();
 
         let stats = Arc::new(PacketStats::new());
 
+        let hwaddr = recv_sock.get_interface_hwaddr();
+        match hwaddr {
+            Some(x) => debug!("interface {} link-layer address is {}",
+                ifc.name, x),
+            None => debug!("interface {} has no link-layer address",
+                ifc.name)
+        }
+
+        let (recv_sock, packet_sock) = recv_sock.split();
+        let pool = BufferPool::new(mtu, RECV_BATCH_SIZE)?;
         let input = Self::make_input_stream(
-                recv_sock.clone(),
-                mtu,
+                recv_sock,
+                pool,
+                hwaddr,
                 prefixes.clone(),
+                ifc.foreign_prefixes.clone(),
                 ifc.name.clone(),
                 stats.clone()
             );
 
         Ok(Self {
-            recv_sock,
+            packet_sock,
             send_sock,
-            input: unsafe { SendBox::new(Box::new(input)) },
+            input: Box::new(input),
             quit,
+            role: if ha.is_some() { HaRole::Standby } else { HaRole::Active },
+            ha,
+            takeover_targets: VecDeque::with_capacity(max_takeover_targets),
+            max_takeover_targets,
+            memberships,
             drop_allmulti,
             ifname: ifc.name.clone(),
-            queued_sends: Arc::new(AtomicUsize::new(0)),
+            outgoing: Vec::with_capacity(ifc.max_queued),
+            sending: None,
+            in_flight: Vec::with_capacity(ifc.max_queued),
             max_queued: ifc.max_queued,
             stats
         })
@@ -78,7 +115,7 @@ impl Server {
 
     fn setup_recv_socket(
         ifc: &InterfaceConfig
-    ) -> Result<(futures::IPv6PacketSocketAdapter, DropAllmulti)> {
+    ) -> Result<futures::IPv6PacketSocketAdapter> {
         let recv_sock_raw = IPv6PacketSocket::new(
             ::linux_network::raw::ETHERTYPE_IPV6,
             SockFlag::empty(),
@@ -97,7 +134,7 @@ impl Server {
 
         recv_sock.setsockopt(&SockOpts::DontRoute::new(&true))?;
 
-        let filter = Self::create_filter();
+        let filter = Self::create_filter()?;
         recv_sock.setsockopt(&SockOpts::AttachFilter::new(filter.get()))?;
         recv_sock.setsockopt(&SockOpts::LockFilter::new(&true))?;
 
@@ -106,10 +143,7 @@ impl Server {
             ifc.name
         );
 
-        let drop_allmulti = !recv_sock.set_allmulti(true, &ifc.name)?;
-        debug!("ensured allmulti is set on interface {}", ifc.name);
-
-        Ok((recv_sock, drop_allmulti.into()))
+        Ok(recv_sock)
     }
 
     fn setup_send_socket(
@@ -141,34 +175,88 @@ impl Server {
         );
 
         send_sock.setsockopt(&SockOpts::DontRoute::new(&true))?;
-        send_sock.setsockopt(&SockOpts::UnicastHops::new(&255))?;
+        set_ndp_hop_limits(&mut send_sock)?;
         send_sock.setsockopt(&SockOpts::V6MtuDiscover::new(&V6PmtuType::Do))?;
 
         Ok(send_sock)
     }
 
-    fn create_filter() -> Box<BpfProg> {
-        use ::linux_network::BpfCommandFlags as B;
-        use ::linux_network::raw::*;
-        use ::nix::libc::*;
+    // joins the solicited-node multicast groups of the proxied prefixes
+    // if there are few of them, otherwise sets allmulti on the interface
+    fn setup_multicast(
+        ifc: &InterfaceConfig,
+        recv_sock: &mut futures::IPv6PacketSocketAdapter
+    ) -> Result<(
+        Vec<MulticastMembership<IPv6RawSocket>>,
+        DropAllmulti
+    )> {
+        if let Some(groups) = solicited_node_groups(&ifc.prefixes) {
+            let memberships = Self::setup_membership_socket()
+                .and_then(|sock| groups.into_iter()
+                    .map(|x| MulticastMembership::join(
+                        sock.try_clone()?,
+                        x,
+                        recv_sock.get_interface_index()
+                    ))
+                    .collect::<::std::result::Result<Vec<_>, _>>()
+                );
+
+            match memberships {
+                Ok(x) => {
+                    debug!(
+                        "joined {} solicited-node multicast groups \
+                            on interface {}",
+                        x.len(),
+                        ifc.name
+                    );
+                    return Ok((x, DropAllmulti::No));
+                },
+                Err(e) => warn!(
+                    "failed to join the solicited-node multicast groups \
+                        on interface {}, setting allmulti instead: {}",
+                    ifc.name,
+                    e
+                )
+            }
+        }
+
+        let drop_allmulti = !recv_sock.set_allmulti(true, &ifc.name)?;
+        debug!("ensured allmulti is set on interface {}", ifc.name);
+
+        Ok((Vec::new(), drop_allmulti.into()))
+    }
 
-        bpf_filter!(
-            bpf_stmt!(B::LD | B::B | B::ABS, 6);
-            bpf_jump!(B::JMP | B::JEQ | B::K, IPPROTO_ICMPV6, 0, 3);
+    // every membership holds a descriptor of this socket,
+    // which receives nothing
+    fn setup_membership_socket() -> Result<IPv6RawSocket> {
+        let mut sock = IPv6RawSocket::new(
+            IpProto::IcmpV6.repr(),
+            SockFlag::empty()
+        )?;
+        sock.setsockopt(&SockOpts::IcmpV6Filter::new(&icmp6_filter::new()))?;
+        Ok(sock)
+    }
 
-            bpf_stmt!(B::LD | B::B | B::ABS, 40);
-            bpf_jump!(B::JMP | B::JEQ | B::K, ND_NEIGHBOR_SOLICIT, 0, 1);
+    fn create_filter() -> Result<BpfProg> {
+        let mut builder = BpfBuilder::new();
+        let reject = builder.label();
 
-            bpf_stmt!(B::RET | B::K, u32::max_value());
+        builder
+            .match_icmpv6_type(IcmpV6Type::NdNeighborSolicit, reject)
+            .match_ipv6_hop_limit(NDP_HOP_LIMIT, reject)
+            .accept()
+            .place(reject)
+            .reject();
 
-            bpf_stmt!(B::RET | B::K, 0);
-        )
+        builder.build()
     }
 
     fn make_input_stream(
-        sock: IPv6PacketSocketAdapter,
-        mtu: usize,
+        sock: IPv6PacketSocketRecvHalf,
+        pool: BufferPool<Option<HwAddr>>,
+        hwaddr: Option<HwAddr>,
         prefixes: Vec<Arc<PrefixConfig>>,
+        foreign_prefixes: Vec<Ipv6Network>,
         if_name: impl AsRef<str>,
         stats: Arc<PacketStats>
     ) -> impl Stream<
@@ -177,21 +265,32 @@ impl Server {
     > {
         let if_name_clone = if_name.as_ref().to_string();
 
-        unfold((sock, mtu), move |(mut sock, mtu)| {
-            Some(sock.recvpacket(mtu, RecvFlags::empty())
-                .map(move |x| (x, (sock, mtu)))
+        let hw_addr_len = hwaddr.as_ref().map(HwAddr::len);
+
+        // the solicitations are parsed in place, so the pool is handed
+        // back to the next receive without copying the frames out
+        unfold((sock, pool), move |(mut sock, pool)| {
+            Some(sock.recvpacket_pool(pool, RecvFlags::empty())
+                .map(move |pool| {
+                    let batch = pool.ipv6_packets()
+                        .map(|(packet, _)| {
+                            Solicitation::parse(&packet, hw_addr_len)
+                        })
+                        .collect::<Vec<_>>();
+                    (batch, (sock, pool))
+                })
                 .map_err(|e| e.into())
             )
-        }).filter_map(move |(packet, _)| {
+        })
+        .map(iter_ok::<_, ::failure::Error>)
+        .flatten()
+        .filter_map(move |solicit| {
             // validate common solicitation features
             debug!("received a packet on {}", if_name.as_ref());
 
             stats.add_received();
 
-            let solicit = match Solicitation::parse(&packet) {
-                Some(s) => s,
-                None => return None
-            };
+            let solicit = solicit?;
 
             // TODO: use source macaddr to validate the multicast macs
             // to correspond to the ipv6 multicast address
@@ -224,10 +323,26 @@ impl Server {
                 break;
             }
 
-            match prefix {
-                Some(p) => Some((solicit, p)),
-                None => None
+            let prefix = prefix?;
+
+            // the target is reachable through the other uplink, not us
+            let foreign = find_foreign_prefix(
+                &foreign_prefixes,
+                solicit.target,
+                &prefix.prefix
+            );
+            if let Some(x) = foreign {
+                debug!(
+                    "target {} belongs to prefix {} served by another \
+                        interface, not answering on {}",
+                    solicit.target,
+                    x,
+                    if_name.as_ref()
+                );
+                return None;
             }
+
+            Some((solicit, prefix))
         }).filter_map(move |(solicit, prefix_conf)| {
             // validate type-specific solicitation features
             debug!(
@@ -237,7 +352,8 @@ impl Server {
 
             let is_ll_res = Self::validate_ll_address_resolution(
                 &solicit,
-                &prefix_conf
+                &prefix_conf,
+                hwaddr.is_some()
             );
             let is_nud = Self::validate_neighbor_unreachability_detection(
                 &solicit,
@@ -251,12 +367,17 @@ impl Server {
         })
     }
 
+    pub fn get_stats(&self) -> Arc<PacketStats> {
+        self.stats.clone()
+    }
+
     fn validate_ll_address_resolution(
         solicit: &Solicitation,
-        prefix_conf: &PrefixConfig
+        prefix_conf: &PrefixConfig,
+        link_has_hwaddr: bool
     ) -> bool {
         if !is_solicited_node_multicast(&solicit.dst)
-                || solicit.ll_addr_opt.is_none() {
+                || (link_has_hwaddr && solicit.ll_addr_opt.is_none()) {
             return false;
         }
 
@@ -293,11 +414,42 @@ impl Server {
     }
 }
 
+// None if there are more than MAX_SOLICITED_NODE_GROUPS of them.
+// A group covers the addresses sharing the low 24 bits, so prefixes
+// up to /122 fit and a /64 is left to allmulti: the proxied addresses
+// are not known in advance and its 2^24 groups can not all be joined.
+fn solicited_node_groups(prefixes: &[Arc<PrefixConfig>])
+        -> Option<Vec<Ipv6Addr>> {
+    let mut groups = Vec::new();
+    for i in prefixes {
+        let host_bits = 128 - u32::from(i.prefix.netmask());
+        if host_bits > 24 {
+            return None;
+        }
+        let count = 1_u128 << host_bits;
+        if groups.len() as u128 + count > MAX_SOLICITED_NODE_GROUPS as u128 {
+            return None;
+        }
+
+        let network = u128::from(i.prefix.network_address());
+        groups.extend((0 .. count)
+            .map(|x| make_solicited_node_multicast(
+                &Ipv6Addr::from(network | x)
+            ))
+        );
+    }
+
+    groups.sort();
+    groups.dedup();
+    Some(groups)
+}
+
 impl Drop for Server {
     fn drop(&mut self) {
+        self.memberships.clear();
         if self.drop_allmulti.into() {
             ::util::log_if_err(
-                self.recv_sock.set_allmulti(false, &self.ifname)
+                self.packet_sock.set_allmulti(false, &self.ifname)
                     .context("error returning allmilti flag to the previous \
                         state")
                     .map_err(|e| e.into())
@@ -329,79 +481,222 @@ impl Future for Server {
                     .map_err(|e| log_err(e.into()))? {
                 debug!("received a signal, quitting");
                 // active = true;
-                match qk.expect("a quit signal") {
+                match qk {
                     // the distinction will be important when implementing
                     // querying the target network's interface
                     // currently queued packets are purposefully omitted
-                    ::QuitKind::Fast | ::QuitKind::Normal =>
-                        return Ok(Async::Ready(()))
+                    Some(::QuitKind::Fast) | Some(::QuitKind::Normal) =>
+                        return Ok(Async::Ready(())),
+                    // the setup of another server failed
+                    None => return Ok(Async::Ready(()))
                 }
             }
 
-            if let Async::Ready(Some((solicit, prefix_conf)))
-                    = self.input.poll().map_err(log_err)? {
-                debug!(
-                    "the solicitation received on {} must be proxied, \
-                        the solicitation is {:?}",
-                    self.ifname,
-                    solicit
-                );
+            let mut new_role = None;
+            if let Some(ref mut ha) = self.ha {
+                // the receiver is polled again until it is not ready,
+                // to have the task woken up by the next role change
+                if let Async::Ready(Some(role)) = ha.poll()
+                        .map_err(|e| log_err(e.into()))? {
+                    active = true;
+                    if role != self.role {
+                        new_role = Some(role);
+                    }
+                }
+            }
+            if let Some(role) = new_role {
+                self.role = role;
+                if let HaRole::Active = role {
+                    info!("taking over interface {}", self.ifname);
+                    self.send_takeover_adverts();
+                }
+            }
+
+            // answer a whole batch of solicitations with a single send
+            let mut received = 0;
+            while received < RECV_BATCH_SIZE {
+                match self.input.poll().map_err(log_err)? {
+                    Async::Ready(Some((solicit, prefix_conf))) => {
+                        received += 1;
+                        self.handle_solicitation(solicit, &prefix_conf);
+                    },
+                    _ => break
+                }
+            }
+            if received > 0 {
                 active = true;
+            }
 
-                let adv = Advertisement {
-                    src: solicit.target,
-                    dst: solicit.src,
-                    target: solicit.target,
-                    ll_addr_opt: Some(self.recv_sock.get_interface_mac())
-                };
-                let adv_packet = adv.solicited_to_packet(
-                    prefix_conf.override_flag,
-                    prefix_conf.router_flag
-                );
+            if self.poll_sends() {
+                active = true;
+            }
+        }
 
-                let queued_sends = self.queued_sends.clone();
+        Ok(Async::NotReady)
+    }
+}
 
-                let queued = queued_sends.fetch_add(1, Ordering::Relaxed);
-                if queued >= self.max_queued {
-                    warn!(
-                        "Maximum queued packet number ({}) \
-                            for interface {} exceeded.",
-                        self.max_queued,
+impl Server {
+    fn handle_solicitation(
+        &mut self,
+        solicit: Solicitation,
+        prefix_conf: &Arc<PrefixConfig>
+    ) {
+        debug!(
+            "the solicitation received on {} must be proxied, \
+                the solicitation is {:?}",
+            self.ifname,
+            solicit
+        );
+
+        self.remember_target(solicit.target, prefix_conf);
+        if let HaRole::Standby = self.role {
+            debug!(
+                "standing by, the solicitation on {} is not answered",
+                self.ifname
+            );
+            return;
+        }
+
+        let adv = Advertisement {
+            src: solicit.target,
+            dst: solicit.src,
+            target: solicit.target,
+            ll_addr_opt: self.packet_sock.get_ref().get_interface_hwaddr()
+        };
+        let adv_packet = adv.solicited_to_packet(
+            prefix_conf.override_flag,
+            prefix_conf.router_flag
+        );
+
+        self.queue_send(adv_packet, solicit.src);
+    }
+
+    fn queue_send(&mut self, packet: Bytes, dst_addr: Ipv6Addr) {
+        if self.outgoing.len() + self.in_flight.len() >= self.max_queued {
+            warn!(
+                "Maximum queued packet number ({}) \
+                    for interface {} exceeded.",
+                self.max_queued,
+                self.ifname
+            );
+            return;
+        }
+        self.push_send(packet, dst_addr);
+    }
+
+    // queues past max_queued, for the bursts sent once per takeover
+    #[allow(clippy::cast_sign_loss)]
+    fn push_send(&mut self, packet: Bytes, dst_addr: Ipv6Addr) {
+        let dst = SocketAddrV6::new(
+            dst_addr,
+            0,
+            0,
+            self.packet_sock.get_ref().get_interface_index() as u32
+        );
+        self.outgoing.push((packet, dst));
+        debug!("advertisement queued on {}", self.ifname);
+    }
+
+    // sends everything queued since the previous batch completed,
+    // returns true if any progress was made
+    fn poll_sends(&mut self) -> bool {
+        let finished = match self.sending {
+            Some(ref mut sending) => match sending.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(report)) => {
+                    // the rest of the batch went out past the failures
+                    for (i, e) in report.failed {
+                        warn!(
+                            "Failed to send an advertisement to {} on {}.",
+                            self.in_flight[i],
+                            self.ifname
+                        );
+                        log_err(Error::LinuxNetworkError(e).into());
+                    }
+                    self.stats.add_sent(report.sent);
+                    debug!(
+                        "{} advertisements sent on {}",
+                        report.sent,
                         self.ifname
                     );
-                    queued_sends.fetch_sub(1, Ordering::Relaxed);
-                    continue;
+                    true
+                },
+                Err(e) => {
+                    log_err(Error::LinuxNetworkError(e).into());
+                    true
                 }
+            },
+            None => true
+        };
+        if !finished {
+            return false;
+        }
 
-                let dst = SocketAddrV6::new(
-                    solicit.src,
-                    0,
-                    0,
-                    self.recv_sock.get_interface_index() as u32
-                );
-                ::tokio::spawn(
-                    self.send_sock.sendto(
-                        adv_packet,
-                        dst,
-                        SendFlags::empty()
-                    ).map(
-                        |_| ()
-                    ).map_err(
-                        |e| log_err(Error::LinuxNetworkError(e).into())
-                    ).then(move |x| {
-                        queued_sends.fetch_sub(1, Ordering::Relaxed);
-                        x
-                    })
-                );
-                self.stats.add_sent();
-                debug!("advertisement queued on {}", self.ifname);
-            }
+        let progressed = self.sending.take().is_some();
+        self.in_flight.clear();
+        if self.outgoing.is_empty() {
+            return progressed;
         }
 
-        Ok(Async::NotReady)
+        let batch = ::std::mem::replace(
+            &mut self.outgoing,
+            Vec::with_capacity(self.max_queued)
+        );
+        self.in_flight.extend(batch.iter().map(|&(_, dst)| dst));
+        self.sending = Some(
+            self.send_sock.sendto_batch(batch, SendFlags::empty())
+        );
+        true
+    }
+
+    fn remember_target(
+        &mut self,
+        target: Ipv6Addr,
+        prefix_conf: &Arc<PrefixConfig>
+    ) {
+        if self.ha.is_none()
+                || self.takeover_targets.iter().any(|&(x, _)| x == target) {
+            return;
+        }
+
+        if self.takeover_targets.len() >= self.max_takeover_targets {
+            self.takeover_targets.pop_front();
+        }
+        if self.max_takeover_targets > 0 {
+            self.takeover_targets.push_back((target, prefix_conf.clone()));
+        }
+    }
+
+    fn send_takeover_adverts(&mut self) {
+        // the neighbors may still have the previous active instance's
+        // link-layer address cached for the targets seen recently
+        let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        let targets = self.takeover_targets.clone();
+        for (target, prefix_conf) in targets {
+            let adv = Advertisement {
+                src: target,
+                dst: all_nodes,
+                target,
+                ll_addr_opt: self.packet_sock.get_ref().get_interface_hwaddr()
+            };
+            let adv_packet = adv.unsolicited_to_packet(
+                prefix_conf.router_flag
+            );
+            // takeover-adverts may be above max_queued
+            self.push_send(adv_packet, all_nodes);
+        }
     }
 }
 
+// the receivers drop the ndp packets with any other hop limit,
+// the unsolicited advertisements to ff02::1 included
+fn set_ndp_hop_limits<T: SocketCommon>(sock: &mut T) -> Result<()> {
+    sock.setsockopt(&SockOpts::UnicastHops::new(&NDP_HOP_LIMIT.into()))?;
+    sock.setsockopt(&SockOpts::MulticastHops::new(&NDP_HOP_LIMIT.into()))?;
+    Ok(())
+}
+
 impl PacketStats {
     fn new() -> Self {
         Self {
@@ -414,15 +709,83 @@ impl PacketStats {
         self.packets_received.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn add_sent(&self) {
-        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+    fn add_sent(&self, count: usize) {
+        self.packets_sent.fetch_add(count, Ordering::Relaxed);
     }
 
-    fn get_received(&self) -> usize {
+    pub fn get_received(&self) -> usize {
         self.packets_received.load(Ordering::Relaxed)
     }
 
-    fn get_sent(&self) -> usize {
+    pub fn get_sent(&self) -> usize {
         self.packets_sent.load(Ordering::Relaxed)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::linux_network::test_util::*;
+
+    fn make_prefixes(prefixes: &[(Ipv6Addr, u8)]) -> Vec<Arc<PrefixConfig>> {
+        prefixes.iter()
+            .map(|&(addr, netmask)| Arc::new(PrefixConfig {
+                prefix: Ipv6Network::new(addr, netmask).unwrap(),
+                router_flag: Router::Yes,
+                override_flag: Override::No,
+                reply_unconditionally: false
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn solicited_node_groups_of_small_prefixes() {
+        let prefixes = make_prefixes(&[
+            ("2001:db8::4".parse().unwrap(), 126),
+            // the same low 24 bits as the first one
+            ("2001:db8:1::4".parse().unwrap(), 127)
+        ]);
+
+        assert_eq!(
+            solicited_node_groups(&prefixes).unwrap(),
+            (4 .. 8)
+                .map(|x| Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, x))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn solicited_node_groups_limit() {
+        let fits = make_prefixes(&[("2001:db8::".parse().unwrap(), 122)]);
+        assert_eq!(
+            solicited_node_groups(&fits).unwrap().len(),
+            MAX_SOLICITED_NODE_GROUPS
+        );
+
+        let too_many = make_prefixes(&[
+            ("2001:db8::".parse().unwrap(), 122),
+            ("2001:db8::100".parse().unwrap(), 127)
+        ]);
+        assert!(solicited_node_groups(&too_many).is_none());
+
+        let subnet = make_prefixes(&[("2001:db8::".parse().unwrap(), 64)]);
+        assert!(solicited_node_groups(&subnet).is_none());
+    }
+
+    #[test]
+    fn takeover_advert_hop_limit() {
+        // needs no privileges, unlike the raw socket the server sends from
+        let mut sock = make_test_socket();
+        set_ndp_hop_limits(&mut sock).unwrap();
+
+        // the takeover advertisements go to ff02::1
+        assert_eq!(
+            sock.getsockopt::<SockOpts::MulticastHops>().unwrap(),
+            NDP_HOP_LIMIT.into()
+        );
+        assert_eq!(
+            sock.getsockopt::<SockOpts::UnicastHops>().unwrap(),
+            NDP_HOP_LIMIT.into()
+        );
+    }
+}
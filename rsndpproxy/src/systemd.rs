@@ -0,0 +1,234 @@
+// Native systemd service support: readiness and status notifications
+// through $NOTIFY_SOCKET, the service watchdog and journal-friendly logging.
+// See sd_notify(3) and sd-daemon(3) for the protocols.
+
+use ::std::env;
+use ::std::ffi::OsStr;
+use ::std::os::unix::prelude::*;
+use ::std::sync::Arc;
+use ::std::time::*;
+
+use ::log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord};
+use ::nix::sys::socket::*;
+use ::nix::unistd::close;
+use ::tokio::prelude::*;
+use ::tokio::timer::Interval;
+
+use ::broadcast::Receiver;
+use ::errors::Result;
+use ::server::PacketStats;
+use ::util::*;
+
+// how often the status is updated without the watchdog
+const STATUS_UPDATE_PERIOD: u64 = 10;
+
+pub struct Notifier {
+    fd: RawFd,
+    addr: SockAddr
+}
+
+impl Notifier {
+    pub fn from_env() -> Result<Option<Self>> {
+        match env::var_os("NOTIFY_SOCKET") {
+            Some(path) => Ok(Some(Self::new(path)?)),
+            None => Ok(None)
+        }
+    }
+
+    // a leading '@' denotes a socket in the abstract namespace
+    pub fn new<T>(path: T) -> Result<Self> where T: AsRef<OsStr> {
+        let path_bytes = path.as_ref().as_bytes();
+        let addr = if path_bytes.first() == Some(&b'@') {
+            UnixAddr::new_abstract(&path_bytes[1..])?
+        } else {
+            UnixAddr::new(path.as_ref())?
+        };
+
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::empty(),
+            0
+        )?;
+
+        Ok(Self {
+            fd,
+            addr: SockAddr::Unix(addr)
+        })
+    }
+
+    pub fn notify<T>(&self, state: T) -> Result<()> where T: AsRef<str> {
+        sendto(
+            self.fd,
+            state.as_ref().as_bytes(),
+            &self.addr,
+            MsgFlags::empty()
+        )?;
+        Ok(())
+    }
+
+    pub fn ready(&self) -> Result<()> {
+        self.notify("READY=1")
+    }
+
+    pub fn stopping(&self) -> Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    pub fn status<T>(&self, status: T) -> Result<()> where T: AsRef<str> {
+        self.notify(format!("STATUS={}", status.as_ref()))
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        log_if_err(close(self.fd).map_err(|e| e.into()));
+    }
+}
+
+pub fn watchdog_period() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?;
+    let pid = env::var("WATCHDOG_PID").ok();
+    parse_watchdog_period(&usec, pid.as_ref().map(String::as_str),
+        i64::from(::std::process::id()))
+}
+
+fn parse_watchdog_period(usec: &str, pid: Option<&str>, own_pid: i64)
+        -> Option<Duration> {
+    // the watchdog may be meant for another process in the service
+    if let Some(pid) = pid {
+        if pid.parse::<i64>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    match usec.parse::<u64>().ok()? {
+        0 => None,
+        x => Some(Duration::from_micros(x))
+    }
+}
+
+pub fn spawn_supervisor(
+    notifier: Arc<Notifier>,
+    stats: Vec<(String, Arc<PacketStats>)>,
+    mut quit: Receiver<::QuitKind>
+) {
+    let watchdog = watchdog_period();
+    let period = match watchdog {
+        // pinging twice per period is what sd_watchdog_enabled(3) suggests
+        Some(x) => {
+            debug!("systemd watchdog period is {:?}", x);
+            x / 2
+        },
+        None => Duration::from_secs(STATUS_UPDATE_PERIOD)
+    };
+    let mut timer = Interval::new(Instant::now() + period, period);
+
+    ::tokio::spawn(future::poll_fn(move || {
+        loop {
+            if let Async::Ready(_) = quit.poll()
+                    .map_err(|e| log_err(e.into()))? {
+                debug!("systemd supervisor quitting");
+                return Ok(Async::Ready(()));
+            }
+
+            match timer.poll().map_err(|e| log_err(e.into()))? {
+                Async::Ready(Some(_)) => (),
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => return Ok(Async::NotReady)
+            }
+
+            let mut state = String::new();
+            if watchdog.is_some() {
+                state += "WATCHDOG=1\n";
+            }
+            state += "STATUS=";
+            state += &format_status(&stats);
+            log_if_err(notifier.notify(state));
+        }
+    }));
+}
+
+fn format_status(stats: &[(String, Arc<PacketStats>)]) -> String {
+    stats.iter()
+        .map(|&(ref name, ref x)| format!(
+            "{}: received {}, sent {}",
+            name,
+            x.get_received(),
+            x.get_sent()
+        )).collect::<Vec<String>>()
+        .join("; ")
+}
+
+// the journal takes the priority from the sd-daemon(3) "<N>" line prefixes
+struct JournalLogger;
+
+impl ::log::Log for JournalLogger {
+    fn enabled(&self, _: &LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &LogRecord) {
+        let priority = match record.level() {
+            LogLevel::Error => 3,
+            LogLevel::Warn => 4,
+            LogLevel::Info => 6,
+            LogLevel::Debug | LogLevel::Trace => 7
+        };
+
+        let msg = format!("{}", record.args());
+        for i in msg.lines() {
+            eprintln!("<{}>{}", priority, i);
+        }
+    }
+}
+
+pub fn init_journal_logging(level: LogLevelFilter) -> Result<()> {
+    ::log::set_logger(|max_level| {
+        max_level.set(level);
+        Box::new(JournalLogger)
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn notify_fake_socket() {
+        let path = env::temp_dir().join(
+            format!("rsndpproxy-notify-test-{}", ::std::process::id())
+        );
+        let _ = ::std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::new(&path).unwrap();
+        notifier.ready().unwrap();
+        notifier.status("all good").unwrap();
+
+        let mut buf = [0; 64];
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=all good");
+
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn watchdog_period_parse() {
+        assert_eq!(
+            parse_watchdog_period("3000000", None, 42),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            parse_watchdog_period("3000000", Some("42"), 42),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(parse_watchdog_period("3000000", Some("43"), 42), None);
+        assert_eq!(parse_watchdog_period("0", None, 42), None);
+        assert_eq!(parse_watchdog_period("nonsense", None, 42), None);
+    }
+}